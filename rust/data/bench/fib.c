@@ -0,0 +1,11 @@
+// 递归调用: 每次调用都有比较跳转, 两次递归和一次加法
+int fib(int n) {
+	if (n < 2)
+		return n;
+	return fib(n - 1) + fib(n - 2);
+}
+
+int main() {
+	printf("fib(27) = %d\n", fib(27));
+	return 0;
+}
@@ -0,0 +1,73 @@
+// 太阳和四颗外行星的 n 体模拟: 结构体成员读写和乘除法.
+// 没有浮点数, 用 16.16 定点数: 长度单位是天文单位, 时间单位是年, 1.0 是 65536
+struct body {
+	long x, y, z;
+	long vx, vy, vz;
+	long mass;
+};
+
+struct body bodies[5] = {
+	{0, 0, 0, 0, 0, 0, 2587258},
+	{317288, -76043, -6791, 39736, 184286, -1653, 2470},
+	{546791, 270323, -26445, -66242, 119647, 552, 740},
+	{845045, -990324, -14635, 70962, 56932, -710, 113},
+	{1007924, -1698648, 11748, 64166, 38974, -2278, 133},
+};
+
+long isqrt(long n) {
+	if (n < 2)
+		return n;
+	long x = n, y = (n + 1) / 2;
+	for (; y < x;) {
+		x = y;
+		y = (x + n / x) / 2;
+	}
+	return x;
+}
+
+void advance(long dt) {
+	for (int i = 0; i < 5; i++) {
+		struct body *a = &bodies[i];
+		for (int j = i + 1; j < 5; j++) {
+			struct body *b = &bodies[j];
+			long dx = a->x - b->x, dy = a->y - b->y, dz = a->z - b->z;
+			long d2 = (dx * dx + dy * dy + dz * dz) >> 16;
+			long d3 = d2 * isqrt(d2 << 16) >> 16;
+			a->vx -= dx * b->mass / d3 * dt >> 16;
+			a->vy -= dy * b->mass / d3 * dt >> 16;
+			a->vz -= dz * b->mass / d3 * dt >> 16;
+			b->vx += dx * a->mass / d3 * dt >> 16;
+			b->vy += dy * a->mass / d3 * dt >> 16;
+			b->vz += dz * a->mass / d3 * dt >> 16;
+		}
+	}
+	for (int i = 0; i < 5; i++) {
+		struct body *a = &bodies[i];
+		a->x += a->vx * dt >> 16;
+		a->y += a->vy * dt >> 16;
+		a->z += a->vz * dt >> 16;
+	}
+}
+
+// 让总动量为0, 太阳不漂移
+void offset_momentum() {
+	long px = 0, py = 0, pz = 0;
+	for (int i = 1; i < 5; i++) {
+		px += bodies[i].vx * bodies[i].mass;
+		py += bodies[i].vy * bodies[i].mass;
+		pz += bodies[i].vz * bodies[i].mass;
+	}
+	bodies[0].vx = -px / bodies[0].mass;
+	bodies[0].vy = -py / bodies[0].mass;
+	bodies[0].vz = -pz / bodies[0].mass;
+}
+
+int main() {
+	offset_momentum();
+	// 步长 0.01 年
+	for (int step = 0; step < 4000; step++)
+		advance(655);
+	for (int i = 0; i < 5; i++)
+		printf("%ld %ld %ld\n", bodies[i].x, bodies[i].y, bodies[i].z);
+	return 0;
+}
@@ -0,0 +1,24 @@
+// 埃拉托斯特尼筛法: 数组读写和嵌套循环
+char composite[200000];
+
+int sieve(int n) {
+	int count = 0;
+	for (int i = 2; i < n; i++)
+		composite[i] = 0;
+	for (int i = 2; i < n; i++) {
+		if (composite[i])
+			continue;
+		count++;
+		for (int j = i + i; j < n; j += i)
+			composite[j] = 1;
+	}
+	return count;
+}
+
+int main() {
+	int count = 0;
+	for (int round = 0; round < 2; round++)
+		count = sieve(200000);
+	printf("primes below 200000: %d\n", count);
+	return 0;
+}
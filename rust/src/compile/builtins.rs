@@ -0,0 +1,254 @@
+//! 内置函数, 对应c/src/ci.c中加入符号表的库函数, 另外加上读标准输入的getchar
+
+use serde::{Deserialize, Serialize};
+
+use super::types::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Builtin {
+	Fopen,
+	Fread,
+	Fclose,
+	Printf,
+	Malloc,
+	Memset,
+	Memcmp,
+	Exit,
+	Free,
+	Getchar,
+}
+
+impl Builtin {
+	pub const ALL: [Builtin; 10] = [
+		Builtin::Fopen,
+		Builtin::Fread,
+		Builtin::Fclose,
+		Builtin::Printf,
+		Builtin::Malloc,
+		Builtin::Memset,
+		Builtin::Memcmp,
+		Builtin::Exit,
+		Builtin::Free,
+		Builtin::Getchar,
+	];
+
+	pub fn from_name(name: &str) -> Option<Self> {
+		Self::ALL.into_iter().find(|builtin| builtin.name() == name)
+	}
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			Builtin::Fopen => "fopen",
+			Builtin::Fread => "fread",
+			Builtin::Fclose => "fclose",
+			Builtin::Printf => "printf",
+			Builtin::Malloc => "malloc",
+			Builtin::Memset => "memset",
+			Builtin::Memcmp => "memcmp",
+			Builtin::Exit => "exit",
+			Builtin::Free => "free",
+			Builtin::Getchar => "getchar",
+		}
+	}
+
+	/// 函数原型. FILE * 和 size_t 分别用 void * 和 int 代替
+	pub fn ctype(&self) -> Func {
+		let void_ptr = || TYPE_VOID.into_pointer();
+		let char_ptr = || TYPE_CHAR.into_pointer();
+		let (return_type, params, is_variadic) = match self {
+			Builtin::Fopen => (void_ptr(), vec![char_ptr(), char_ptr()], false),
+			Builtin::Fread => (TYPE_INT, vec![void_ptr(), TYPE_INT, TYPE_INT, void_ptr()], false),
+			Builtin::Fclose => (TYPE_INT, vec![void_ptr()], false),
+			Builtin::Printf => (TYPE_INT, vec![char_ptr()], true),
+			Builtin::Malloc => (void_ptr(), vec![TYPE_INT], false),
+			Builtin::Memset => (void_ptr(), vec![void_ptr(), TYPE_INT, TYPE_INT], false),
+			Builtin::Memcmp => (TYPE_INT, vec![void_ptr(), void_ptr(), TYPE_INT], false),
+			Builtin::Exit => (TYPE_VOID, vec![TYPE_INT], false),
+			Builtin::Free => (TYPE_VOID, vec![void_ptr()], false),
+			Builtin::Getchar => (TYPE_INT, vec![], false),
+		};
+		let param_list = params.into_iter().map(|ctype| TypeIdentifier::new(ctype, None)).collect();
+		Func { return_type: Box::new(return_type), param_list, is_variadic, is_unspecified: false }
+	}
+}
+
+/// 宽度和精度的上限, 与C中int的范围相同
+const MAX_WIDTH: usize = i32::MAX as usize;
+
+/// printf的格式化. 支持 %d %i %u %x %X %c %s %p %%, 标志 `- + space # 0`, 宽度和精度(可以是 `*`),
+/// 整数的长度修饰符 hh h l ll, 没有时是int. string按地址读出 %s 的字符串
+pub fn format<E>(
+	fmt: &[u8],
+	args: &[i64],
+	string: impl Fn(i64) -> Result<Vec<u8>, E>,
+) -> Result<Vec<u8>, FormatError<E>> {
+	let mut out = vec![];
+	let mut args = args.iter().copied();
+	let mut next_arg = || args.next().ok_or(FormatError::TooFewArguments);
+	let mut i = 0;
+	while i < fmt.len() {
+		if fmt[i] != b'%' {
+			out.push(fmt[i]);
+			i += 1;
+			continue;
+		}
+		i += 1;
+		let mut spec = Spec::default();
+		while let Some(flag @ (b'-' | b'+' | b' ' | b'#' | b'0')) = fmt.get(i) {
+			match flag {
+				b'-' => spec.left = true,
+				b'+' => spec.plus = true,
+				b' ' => spec.space = true,
+				b'#' => spec.alternate = true,
+				_ => spec.zero = true,
+			}
+			i += 1;
+		}
+		// 负数宽度表示左对齐
+		match fmt.get(i) {
+			Some(b'*') => {
+				i += 1;
+				let width = next_arg()? as i32;
+				spec.left |= width < 0;
+				spec.width = width.unsigned_abs() as usize;
+			}
+			_ => spec.width = digits(fmt, &mut i),
+		}
+		if fmt.get(i) == Some(&b'.') {
+			i += 1;
+			spec.precision = match fmt.get(i) {
+				Some(b'*') => {
+					i += 1;
+					usize::try_from(next_arg()? as i32).ok()
+				}
+				_ => Some(digits(fmt, &mut i)),
+			};
+		}
+		if spec.width > MAX_WIDTH || spec.precision.is_some_and(|precision| precision > MAX_WIDTH) {
+			return Err(FormatError::WidthTooLarge);
+		}
+		// 整数参数的位数
+		let bits = match (fmt.get(i), fmt.get(i + 1)) {
+			(Some(b'h'), Some(b'h')) => 8,
+			(Some(b'h'), _) => 16,
+			(Some(b'l'), _) => 64,
+			_ => 32,
+		};
+		while let Some(b'h' | b'l') = fmt.get(i) {
+			i += 1;
+		}
+		// 按位数截断, 有符号的符号扩展, 无符号的零扩展
+		let shift = 64 - bits;
+		let signed = |v: i64| v << shift >> shift;
+		let unsigned = |v: i64| (v as u64) << shift >> shift;
+		let conversion = *fmt.get(i).ok_or(FormatError::InvalidConversion(None))?;
+		i += 1;
+		let (prefix, body) = match conversion {
+			b'%' => {
+				out.push(b'%');
+				continue;
+			}
+			b'd' | b'i' => {
+				let v = signed(next_arg()?);
+				let sign = match (v < 0, spec.plus, spec.space) {
+					(true, _, _) => "-",
+					(false, true, _) => "+",
+					(false, false, true) => " ",
+					_ => "",
+				};
+				(sign.to_string(), spec.digits(v.unsigned_abs().to_string()))
+			}
+			b'u' => (String::new(), spec.digits(unsigned(next_arg()?).to_string())),
+			b'x' | b'X' => {
+				let v = unsigned(next_arg()?);
+				let prefix = if spec.alternate && v != 0 { "0x" } else { "" };
+				let (prefix, body) = (prefix.to_string(), spec.digits(format!("{:x}", v)));
+				match conversion {
+					b'X' => (prefix.to_uppercase(), body.to_uppercase()),
+					_ => (prefix, body),
+				}
+			}
+			b'p' => ("0x".to_string(), format!("{:x}", next_arg()? as u64)),
+			b'c' => {
+				spec.zero = false;
+				spec.pad(&mut out, String::new(), vec![next_arg()? as u8]);
+				continue;
+			}
+			b's' => {
+				spec.zero = false;
+				let mut bytes = string(next_arg()?).map_err(FormatError::String)?;
+				if let Some(precision) = spec.precision {
+					bytes.truncate(precision);
+				}
+				spec.pad(&mut out, String::new(), bytes);
+				continue;
+			}
+			c => return Err(FormatError::InvalidConversion(Some(c as char))),
+		};
+		spec.pad(&mut out, prefix, body.into_bytes());
+	}
+	Ok(out)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormatError<E> {
+	TooFewArguments,
+	/// 不支持的转换说明符, None表示格式串在 % 之后结束
+	InvalidConversion(Option<char>),
+	/// 宽度或者精度超出int的范围
+	WidthTooLarge,
+	/// 读取 %s 的字符串出错
+	String(E),
+}
+
+/// 一个转换说明的标志, 宽度和精度
+#[derive(Debug, Default)]
+struct Spec {
+	left: bool,
+	plus: bool,
+	space: bool,
+	alternate: bool,
+	zero: bool,
+	width: usize,
+	precision: Option<usize>,
+}
+
+impl Spec {
+	/// 整数的精度是最少的数字个数, 指定精度时忽略0标志
+	fn digits(&mut self, digits: String) -> String {
+		match self.precision {
+			Some(precision) => {
+				self.zero = false;
+				match (precision, digits.as_str()) {
+					(0, "0") => String::new(),
+					_ => {
+						std::iter::repeat_n('0', precision.saturating_sub(digits.len())).chain(digits.chars()).collect()
+					}
+				}
+			}
+			None => digits,
+		}
+	}
+
+	/// 按宽度填充: 左对齐时右边补空格, 0标志在符号或前缀之后补0, 否则左边补空格
+	fn pad(&self, out: &mut Vec<u8>, prefix: String, body: Vec<u8>) {
+		let fill = self.width.saturating_sub(prefix.len() + body.len());
+		if self.left {
+			out.extend(prefix.bytes().chain(body).chain(std::iter::repeat_n(b' ', fill)));
+		} else if self.zero {
+			out.extend(prefix.bytes().chain(std::iter::repeat_n(b'0', fill)).chain(body));
+		} else {
+			out.extend(std::iter::repeat_n(b' ', fill).chain(prefix.bytes()).chain(body));
+		}
+	}
+}
+
+/// 十进制的宽度或者精度, 太大时饱和, 由调用者报错
+fn digits(fmt: &[u8], i: &mut usize) -> usize {
+	let mut n: usize = 0;
+	while let Some(d @ b'0'..=b'9') = fmt.get(*i) {
+		n = n.saturating_mul(10).saturating_add((d - b'0') as usize);
+		*i += 1;
+	}
+	n
+}
@@ -0,0 +1,567 @@
+//! 类型检查: 计算每个表达式的类型和值类别, 插入隐式类型转换.
+//!
+//! 检查之后的表达式树满足:
+//! - 整数提升, 寻常算术转换, 赋值转换都以 Cast 节点的形式显式插入
+//! - 作为值使用的数组和函数转换为指针, 同样是 Cast 节点
+//! - 指针加减整数时, 整数已经乘以所指类型的大小; 指针相减的结果已经除以所指类型的大小
+//! - 下标 a[i] 改写为 *(a + i)
+//! - 作用于指针的 ++ -- 和 += -=, 步长是所指类型的大小
+
+use super::{
+	errors::{Diagnostic, ParseError},
+	token::{Const, Punct, Span},
+	types::*,
+};
+
+/// Err(None)表示错误已经报告过了, 如解析时留下的 Expr::Error
+type Checked = Result<(), Option<Diagnostic>>;
+
+/// 检查整个翻译单元, 返回所有错误. 出错的语句跳过, 继续检查后面的语句.
+/// 标识符的类型来自名字解析得到的符号表
+pub fn check(unit: &mut TranslationUnit) -> Vec<Diagnostic> {
+	let TranslationUnit { global_variables, functions, symbols } = unit;
+	let mut checker = Checker { symbols, diagnostics: vec![], return_type: TYPE_VOID };
+	for var in global_variables.iter_mut() {
+		checker.variable(var);
+	}
+	for func in functions.iter_mut().filter(|func| func.is_definition) {
+		checker.function(func);
+	}
+	checker.diagnostics
+}
+
+struct Checker<'a> {
+	symbols: &'a [Symbol],
+	diagnostics: Vec<Diagnostic>,
+	/// 当前函数的返回类型
+	return_type: Type,
+}
+
+impl Checker<'_> {
+	fn report(&mut self, result: Checked) {
+		if let Err(Some(diagnostic)) = result {
+			self.diagnostics.push(diagnostic);
+		}
+	}
+
+	fn variable(&mut self, var: &mut Variable) {
+		if var.is_enum_constant {
+			return;
+		}
+		if let Some(init) = var.init_value.as_mut() {
+			let result = self.initializer(&var.ctype, init);
+			self.report(result);
+		}
+	}
+
+	fn function(&mut self, func: &mut Function) {
+		self.return_type = (*func.ctype.return_type).clone();
+		for param in func.ctype.param_list.iter_mut() {
+			let result = self.vla_type(&mut param.ctype);
+			self.report(result);
+		}
+		self.stmt(&mut func.stmts);
+	}
+
+	fn stmt(&mut self, stmt: &mut Statement) {
+		match stmt {
+			Statement::Empty | Statement::BreakStmt | Statement::ContinueStmt | Statement::Error => (),
+			Statement::ExprStmt(expr) => {
+				let result = self.rvalue(expr);
+				self.report(result);
+			}
+			Statement::ReturnStmt(Some(expr), span) => {
+				let return_type = self.return_type.clone();
+				let result = self.rvalue(expr).and_then(|_| match return_type {
+					Type::Void => fail(*span, ParseError::General("return with a value in void function")),
+					_ => assign_convert(expr, &return_type),
+				});
+				self.report(result);
+			}
+			Statement::ReturnStmt(None, span) if self.return_type != TYPE_VOID => {
+				let result = fail(*span, ParseError::General("return with no value in non-void function"));
+				self.report(result);
+			}
+			Statement::ReturnStmt(None, _) => (),
+			Statement::IfStmt(cond, then_stmt, else_stmt) => {
+				let result = self.condition(cond);
+				self.report(result);
+				self.stmt(then_stmt);
+				if let Some(else_stmt) = else_stmt {
+					self.stmt(else_stmt);
+				}
+			}
+			Statement::ForStmt(init, cond, end, body) => {
+				for expr in [init.as_mut(), end.as_mut()].into_iter().flatten() {
+					let result = self.rvalue(expr);
+					self.report(result);
+				}
+				let result = self.condition(cond);
+				self.report(result);
+				self.stmt(body);
+			}
+			Statement::SwitchStmt(cond, body) => {
+				let result = self.rvalue(cond).and_then(|_| expect_integer(cond, "switch"));
+				promote(cond);
+				self.report(result);
+				self.stmt(body);
+			}
+			Statement::CaseStmt(_, stmt) | Statement::DefaultStmt(stmt) => self.stmt(stmt),
+			Statement::CompoundStmt(stmts) => {
+				for stmt in stmts {
+					self.stmt(stmt);
+				}
+			}
+			Statement::DeclStmt(vars) => {
+				for var in vars {
+					self.variable(var);
+				}
+			}
+		}
+	}
+
+	fn initializer(&mut self, ctype: &Type, init: &mut Initializer) -> Checked {
+		match (ctype, init) {
+			(_, Initializer::Expr(expr)) => {
+				self.rvalue(expr)?;
+				assign_convert(expr, ctype)
+			}
+			(Type::Array(Array { base_type, .. }), Initializer::List(list)) => {
+				for elem in list.iter_mut().flatten() {
+					self.initializer(base_type, elem)?;
+				}
+				Ok(())
+			}
+			(Type::Struct(st), Initializer::List(list)) => {
+				for (member, elem) in st.members().iter().zip(list.iter_mut()) {
+					if let Some(elem) = elem {
+						self.initializer(&member.ctype, elem)?;
+					}
+				}
+				Ok(())
+			}
+			(_, Initializer::List(_)) => Ok(()),
+		}
+	}
+
+	/// 变长数组的长度必须是整数
+	fn vla_type(&mut self, ctype: &mut Type) -> Checked {
+		match ctype {
+			Type::Array(Array { base_type, size_expr, .. }) => {
+				if let Some(expr) = size_expr.as_deref_mut().filter(|expr| !matches!(expr.kind, ExprKind::Const(_))) {
+					self.rvalue(expr)?;
+					expect_integer(expr, "array size")?;
+					promote(expr);
+				}
+				self.vla_type(base_type)
+			}
+			Type::Ptr(Ptr { base_type }) => self.vla_type(base_type),
+			_ => Ok(()),
+		}
+	}
+
+	/// if, for 的条件
+	fn condition(&mut self, expr: &mut Expr) -> Checked {
+		self.rvalue(expr)?;
+		expect_scalar(expr, "condition")
+	}
+
+	/// 作为值使用的表达式: 数组和函数转换为指针
+	fn rvalue(&mut self, expr: &mut Expr) -> Checked {
+		self.expr(expr)?;
+		match type_of(expr) {
+			Type::Array(Array { base_type, .. }) => convert(expr, base_type.into_pointer()),
+			ctype @ Type::Func(_) => convert(expr, ctype.into_pointer()),
+			_ => (),
+		}
+		Ok(())
+	}
+
+	fn expr(&mut self, expr: &mut Expr) -> Checked {
+		let span = expr.span;
+		let (ctype, category) = match &mut expr.kind {
+			ExprKind::Error => return Err(None),
+			ExprKind::Const(c) => (c.ctype(), ValueCategory::RValue),
+			ExprKind::StringLiteral(str) => {
+				(TYPE_CHAR.into_array(None).with_array_length(str.len() + 1), ValueCategory::LValue)
+			}
+			ExprKind::Id(name, symbol) => match symbol.map(|symbol| &self.symbols[symbol.0]) {
+				Some(Symbol { kind: SymbolKind::Global | SymbolKind::Local | SymbolKind::Param, ctype, .. }) => {
+					(ctype.clone(), ValueCategory::LValue)
+				}
+				Some(Symbol { ctype, .. }) => (ctype.clone(), ValueCategory::RValue),
+				None => return fail(span, ParseError::Undeclared(name.clone())),
+			},
+			ExprKind::BinOp(_) => return self.binary(expr),
+			ExprKind::UnaryOp(UnaryOp { op, expr: operand }) => match op {
+				Punct::Add | Punct::Sub | Punct::Tilde => {
+					self.rvalue(operand)?;
+					expect_arithmetic(operand, *op)?;
+					promote(operand);
+					(type_of(operand), ValueCategory::RValue)
+				}
+				Punct::Not => {
+					self.rvalue(operand)?;
+					expect_scalar(operand, *op)?;
+					(TYPE_INT, ValueCategory::RValue)
+				}
+				Punct::And => {
+					self.expr(operand)?;
+					let ctype = type_of(operand);
+					if !operand.is_lvalue() && !matches!(ctype, Type::Func(_)) {
+						return fail(span, ParseError::NotLvalue(op.to_string()));
+					}
+					(ctype.into_pointer(), ValueCategory::RValue)
+				}
+				Punct::Mul => {
+					self.rvalue(operand)?;
+					match type_of(operand).pointee() {
+						Some(Type::Void) | None => return fail(span, ParseError::InvalidOperands(op.to_string())),
+						Some(ctype @ Type::Func(_)) => (ctype.clone(), ValueCategory::RValue),
+						Some(ctype) => (ctype.clone(), ValueCategory::LValue),
+					}
+				}
+				// ++i --i
+				_ => {
+					self.expr(operand)?;
+					expect_modifiable(operand, *op)?;
+					expect_scalar(operand, *op)?;
+					(type_of(operand), ValueCategory::RValue)
+				}
+			},
+			ExprKind::Postfix(PostfixOP { op, expr: operand }) => {
+				self.expr(operand)?;
+				expect_modifiable(operand, *op)?;
+				expect_scalar(operand, *op)?;
+				(type_of(operand), ValueCategory::RValue)
+			}
+			ExprKind::AssignExpr(AssignExpr { left, assign, right }) => {
+				self.expr(left)?;
+				expect_modifiable(left, *assign)?;
+				self.rvalue(right)?;
+				let ctype = type_of(left);
+				match assign {
+					Punct::Assign => assign_convert(right, &ctype)?,
+					Punct::AssignAdd | Punct::AssignSub if ctype.is_pointer() => {
+						expect_integer(right, *assign)?;
+						promote(right);
+						scale(right, pointee_size(&ctype, span));
+					}
+					Punct::AssignMul | Punct::AssignDiv | Punct::AssignAdd | Punct::AssignSub => {
+						expect_arithmetic(left, *assign)?;
+						expect_arithmetic(right, *assign)?;
+						promote(right);
+					}
+					_ => {
+						expect_integer(left, *assign)?;
+						expect_integer(right, *assign)?;
+						promote(right);
+					}
+				}
+				(ctype, ValueCategory::RValue)
+			}
+			ExprKind::CondExpr(CondExpr { cond, left, right }) => {
+				self.condition(cond)?;
+				self.rvalue(left)?;
+				self.rvalue(right)?;
+				let (lt, rt) = (type_of(left), type_of(right));
+				let ctype = if lt.is_arithmetic() && rt.is_arithmetic() {
+					arith_conv(left, right)
+				} else if lt.is_pointer() && is_null_pointer(right) {
+					convert(right, lt.clone());
+					lt
+				} else if rt.is_pointer() && is_null_pointer(left) {
+					convert(left, rt.clone());
+					rt
+				} else if lt == rt || (lt.is_pointer() && rt.is_pointer()) {
+					lt
+				} else {
+					return fail(span, ParseError::InvalidOperands("?:".into()));
+				};
+				(ctype, ValueCategory::RValue)
+			}
+			ExprKind::CommaExpr(CommaExpr { left, right }) => {
+				self.rvalue(left)?;
+				self.rvalue(right)?;
+				(type_of(right), ValueCategory::RValue)
+			}
+			ExprKind::FunctionCall(callee, args) => {
+				self.rvalue(callee)?;
+				let func = match type_of(callee).pointee() {
+					Some(Type::Func(func)) => func.clone(),
+					_ => return fail(span, ParseError::NotFunction),
+				};
+				// int f() 没有原型, 不检查参数
+				let params = func.param_list.len();
+				if !func.is_unspecified && (args.len() < params || (args.len() > params && !func.is_variadic)) {
+					return fail(span, ParseError::ArgumentCount(params, args.len()));
+				}
+				for (i, arg) in args.iter_mut().enumerate() {
+					self.rvalue(arg)?;
+					match func.param_list.get(i) {
+						Some(param) => assign_convert(arg, &param.ctype)?,
+						// 默认实参提升
+						None => promote(arg),
+					}
+				}
+				(*func.return_type, ValueCategory::RValue)
+			}
+			ExprKind::MemberAccess(base, name) => {
+				self.expr(base)?;
+				let ctype =
+					member_type(&type_of(base), name).ok_or_else(|| error(span, ParseError::NoMember(name.clone())))?;
+				(ctype, base.category)
+			}
+			ExprKind::MemberAccessP(base, name) => {
+				self.rvalue(base)?;
+				let ctype = type_of(base).pointee().cloned().unwrap_or(TYPE_VOID);
+				let ctype = member_type(&ctype, name).ok_or_else(|| error(span, ParseError::NoMember(name.clone())))?;
+				(ctype, ValueCategory::LValue)
+			}
+			ExprKind::Cast(ctype, operand) => {
+				self.vla_type(ctype)?;
+				self.rvalue(operand)?;
+				if *ctype != TYPE_VOID && !(ctype.is_scalar() && type_of(operand).is_scalar()) {
+					return fail(span, ParseError::InvalidOperands("cast".into()));
+				}
+				(ctype.clone(), ValueCategory::RValue)
+			}
+			// sizeof的类型是size_t, 即unsigned long
+			ExprKind::SizeOfType(ctype) => {
+				self.vla_type(ctype)?;
+				(TYPE_ULONG, ValueCategory::RValue)
+			}
+			ExprKind::SizeOfExpr(operand) => {
+				self.expr(operand)?;
+				(TYPE_ULONG, ValueCategory::RValue)
+			}
+			ExprKind::CompoundLiteral(ctype, init) => {
+				if ctype.is_vla() {
+					return fail(span, ParseError::VariablyModified("compound literal".into()));
+				}
+				self.initializer(ctype, init)?;
+				(ctype.clone(), ValueCategory::LValue)
+			}
+		};
+		expr.ctype = Some(ctype);
+		expr.category = category;
+		Ok(())
+	}
+
+	fn binary(&mut self, expr: &mut Expr) -> Checked {
+		let span = expr.span;
+		let ExprKind::BinOp(BinOp { left, op, right }) = &mut expr.kind else { unreachable!() };
+		self.rvalue(left)?;
+		self.rvalue(right)?;
+		let op_name = op.to_string();
+		let invalid = || fail(span, ParseError::InvalidOperands(op_name.clone()));
+		let (lt, rt) = (type_of(left), type_of(right));
+		let ctype = match op {
+			Punct::Add | Punct::Sub | Punct::BrakL if lt.is_arithmetic() && rt.is_arithmetic() => {
+				if *op == Punct::BrakL {
+					return invalid();
+				}
+				arith_conv(left, right)
+			}
+			// 指针 ± 整数, 整数 + 指针, 下标
+			Punct::Add | Punct::Sub | Punct::BrakL if lt.is_pointer() != rt.is_pointer() => {
+				if lt.is_integer() {
+					if *op == Punct::Sub {
+						return invalid();
+					}
+					std::mem::swap(left, right);
+				}
+				let ctype = type_of(left);
+				expect_integer(right, *op)?;
+				promote(right);
+				scale(right, pointee_size(&ctype, span));
+				ctype
+			}
+			// 指针 - 指针, 结果是ptrdiff_t, 即long
+			Punct::Sub if lt.is_pointer() && rt.is_pointer() => {
+				let size = pointee_size(&lt, span);
+				expr.ctype = Some(TYPE_LONG);
+				let diff = std::mem::replace(expr, Expr::error());
+				let quotient = ExprKind::BinOp(BinOp { left: Box::new(diff), op: Punct::Div, right: Box::new(size) });
+				*expr = typed(quotient, TYPE_LONG, span);
+				return Ok(());
+			}
+			Punct::Mul | Punct::Div | Punct::Mod if lt.is_arithmetic() && rt.is_arithmetic() => {
+				if *op == Punct::Mod {
+					expect_integer(left, *op)?;
+					expect_integer(right, *op)?;
+				}
+				arith_conv(left, right)
+			}
+			Punct::And | Punct::Or | Punct::Xor if lt.is_integer() && rt.is_integer() => arith_conv(left, right),
+			// 移位的两个操作数分别提升, 结果是左操作数的类型
+			Punct::Shl | Punct::Shr if lt.is_integer() && rt.is_integer() => {
+				promote(left);
+				promote(right);
+				type_of(left)
+			}
+			Punct::Lt | Punct::Gt | Punct::Le | Punct::Ge | Punct::Eq | Punct::Ne => {
+				if lt.is_arithmetic() && rt.is_arithmetic() {
+					arith_conv(left, right);
+				} else if lt.is_pointer() && is_null_pointer(right) {
+					convert(right, lt);
+				} else if rt.is_pointer() && is_null_pointer(left) {
+					convert(left, rt);
+				} else if !(lt.is_pointer() && rt.is_pointer()) {
+					return invalid();
+				}
+				TYPE_INT
+			}
+			Punct::Lan | Punct::Lor if lt.is_scalar() && rt.is_scalar() => TYPE_INT,
+			_ => return invalid(),
+		};
+		if *op == Punct::BrakL {
+			*op = Punct::Add;
+			let (elem_type, category) = match ctype.pointee() {
+				Some(Type::Void) | None => return invalid(),
+				Some(elem_type @ Type::Func(_)) => (elem_type.clone(), ValueCategory::RValue),
+				Some(elem_type) => (elem_type.clone(), ValueCategory::LValue),
+			};
+			expr.ctype = Some(ctype);
+			let addr = std::mem::replace(expr, Expr::error());
+			*expr = Expr::new_unary(Punct::Mul, addr);
+			expr.ctype = Some(elem_type);
+			expr.category = category;
+			expr.span = span;
+			return Ok(());
+		}
+		expr.ctype = Some(ctype);
+		expr.category = ValueCategory::RValue;
+		Ok(())
+	}
+}
+
+fn error(span: Span, error: ParseError) -> Option<Diagnostic> {
+	Some(Diagnostic::error(span, error))
+}
+
+fn fail(span: Span, e: ParseError) -> Checked {
+	Err(error(span, e))
+}
+
+/// 已经检查过的表达式的类型
+fn type_of(expr: &Expr) -> Type {
+	expr.ctype.clone().unwrap_or(TYPE_VOID)
+}
+
+fn typed(kind: ExprKind, ctype: Type, span: Span) -> Expr {
+	Expr { kind, ctype: Some(ctype), category: ValueCategory::RValue, span }
+}
+
+fn long_const(n: usize, span: Span) -> Expr {
+	typed(ExprKind::Const(Const::Integer(format!("{}L", n))), TYPE_LONG, span)
+}
+
+/// 插入到目标类型的转换
+fn convert(expr: &mut Expr, ctype: Type) {
+	if expr.ctype.as_ref() == Some(&ctype) {
+		return;
+	}
+	let span = expr.span;
+	let operand = std::mem::replace(expr, Expr::error());
+	*expr = typed(ExprKind::Cast(ctype.clone(), Box::new(operand)), ctype, span);
+}
+
+/// 整数提升: 比int小的整数类型转换为int
+fn promote(expr: &mut Expr) {
+	if type_of(expr).is_integer() {
+		convert(expr, type_of(expr).promoted());
+	}
+}
+
+/// 寻常算术转换, 返回两个操作数转换后的共同类型
+fn arith_conv(left: &mut Expr, right: &mut Expr) -> Type {
+	let ctype = type_of(left).common(&type_of(right));
+	convert(left, ctype.clone());
+	convert(right, ctype.clone());
+	ctype
+}
+
+/// 整数转换为long之后乘以指针所指类型的大小
+fn scale(expr: &mut Expr, size: Expr) {
+	convert(expr, TYPE_LONG);
+	if size.fold_const() != Some(1) {
+		let span = expr.span;
+		let ctype = type_of(expr);
+		let n = std::mem::replace(expr, Expr::error());
+		*expr = typed(ExprKind::BinOp(BinOp { left: Box::new(n), op: Punct::Mul, right: Box::new(size) }), ctype, span);
+	}
+}
+
+/// 指针所指类型的大小. void * 按1字节计算, 和gcc一样; 指向变长数组时是运行时求值的sizeof
+fn pointee_size(ctype: &Type, span: Span) -> Expr {
+	match ctype.pointee() {
+		Some(pointee) if pointee.is_vla() => typed(ExprKind::SizeOfType(pointee.clone()), TYPE_LONG, span),
+		pointee => long_const(pointee.map(|t| t.size()).filter(|size| *size > 0).unwrap_or(1), span),
+	}
+}
+
+fn is_null_pointer(expr: &Expr) -> bool {
+	type_of(expr).is_integer() && expr.fold_const() == Some(0)
+}
+
+/// 赋值, 初始化, 传参和返回时的隐式转换
+fn assign_convert(expr: &mut Expr, ctype: &Type) -> Checked {
+	let from = type_of(expr);
+	let compatible = match (ctype, &from) {
+		(to, from) if to.is_arithmetic() && from.is_arithmetic() => true,
+		(Type::Bool, from) => from.is_scalar(),
+		// void * 可以和任何对象指针互相转换, 其他指针要求所指的类型兼容
+		(Type::Ptr(to), Type::Ptr(from)) => {
+			*to.base_type == TYPE_VOID
+				|| *from.base_type == TYPE_VOID
+				|| to.base_type.composite(&from.base_type).is_some()
+		}
+		(Type::Ptr(_), _) => is_null_pointer(expr),
+		(to, from) => to == from,
+	};
+	if !compatible {
+		return fail(expr.span, ParseError::InvalidOperands(format!("assignment: {} from {}", ctype, from)));
+	}
+	convert(expr, ctype.clone());
+	Ok(())
+}
+
+fn expect_modifiable(expr: &Expr, op: impl ToString) -> Checked {
+	match type_of(expr) {
+		Type::Array(_) | Type::Func(_) => fail(expr.span, ParseError::NotLvalue(op.to_string())),
+		_ if !expr.is_lvalue() => fail(expr.span, ParseError::NotLvalue(op.to_string())),
+		_ => Ok(()),
+	}
+}
+
+fn expect_scalar(expr: &Expr, op: impl ToString) -> Checked {
+	if type_of(expr).is_scalar() {
+		Ok(())
+	} else {
+		fail(expr.span, ParseError::InvalidOperands(op.to_string()))
+	}
+}
+
+fn expect_arithmetic(expr: &Expr, op: impl ToString) -> Checked {
+	if type_of(expr).is_arithmetic() {
+		Ok(())
+	} else {
+		fail(expr.span, ParseError::InvalidOperands(op.to_string()))
+	}
+}
+
+fn expect_integer(expr: &Expr, op: impl ToString) -> Checked {
+	if type_of(expr).is_integer() {
+		Ok(())
+	} else {
+		fail(expr.span, ParseError::InvalidOperands(op.to_string()))
+	}
+}
+
+fn member_type(ctype: &Type, name: &str) -> Option<Type> {
+	match ctype {
+		Type::Struct(st) => st.member(name).map(|m| m.ctype.clone()),
+		_ => None,
+	}
+}
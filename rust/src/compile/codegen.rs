@@ -0,0 +1,981 @@
+//! 把类型检查之后的语法树翻译成栈式虚拟机的字节码, 指令集参照 c/src/vm.c.
+//! 和c4一样有一个累加器a和一个操作数栈: 二元运算的左操作数在栈顶, 右操作数在a中, 结果写回a.
+//! 局部变量在解释器内存的栈上, 每次调用用 ENT 分配整个栈帧, 用 LEA 按帧基址bp加偏移取地址
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+	builtins::Builtin,
+	data::{DataSegment, RelocTarget},
+	errors::{ConstError, ParseError},
+	eval::compound_op,
+	memory::{DATA_BASE, FUNCTION_BASE},
+	token::{Const, Punct, Span},
+	types::*,
+};
+
+/// 指令读写和运算的标量类型, 指针按unsigned long处理
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scalar {
+	Bool,
+	I8,
+	U8,
+	I16,
+	U16,
+	I32,
+	U32,
+	I64,
+	U64,
+}
+
+impl Scalar {
+	pub fn of(ctype: &Type) -> Scalar {
+		match ctype {
+			Type::Bool => Scalar::Bool,
+			Type::Char => Scalar::I8,
+			Type::UChar => Scalar::U8,
+			Type::Short => Scalar::I16,
+			Type::UShort => Scalar::U16,
+			Type::Int => Scalar::I32,
+			Type::UInt => Scalar::U32,
+			Type::Long => Scalar::I64,
+			_ => Scalar::U64,
+		}
+	}
+
+	pub fn ctype(self) -> Type {
+		match self {
+			Scalar::Bool => Type::Bool,
+			Scalar::I8 => Type::Char,
+			Scalar::U8 => Type::UChar,
+			Scalar::I16 => Type::Short,
+			Scalar::U16 => Type::UShort,
+			Scalar::I32 => Type::Int,
+			Scalar::U32 => Type::UInt,
+			Scalar::I64 => Type::Long,
+			Scalar::U64 => Type::ULong,
+		}
+	}
+}
+
+/// 虚拟机的指令. 跳转的目标是指令的下标
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+	/// a = bp + n, 局部变量的地址
+	Lea(usize),
+	/// a = n
+	Imm(i64),
+	Jmp(usize),
+	/// 调用函数表中的第n个函数, 实参个数. 实参从左到右压栈, 返回后由调用者用 ADJ 弹出
+	Jsr(usize, usize),
+	/// 调用函数指针, 实参个数. 函数的地址在实参之下
+	Jsri(usize),
+	/// a为0时跳转
+	Bz(usize),
+	/// a不为0时跳转
+	Bnz(usize),
+	/// 分配n个字节的栈帧
+	Ent(usize),
+	/// 弹出n个值
+	Adj(usize),
+	/// a = 数据段基地址 + n, 全局变量和字符串字面量的地址
+	Lgb(usize),
+	/// 返回, 返回值在a中
+	Lev,
+	/// a = 第n个实参
+	Arg(usize),
+	/// a = *a
+	Load(Scalar),
+	/// *pop = a, a转换为写入的值
+	Store(Scalar),
+	/// 从a复制n个字节到pop, a = 目标地址. 用于结构体
+	Copy(usize),
+	/// a处的n个字节清零
+	Zero(usize),
+	/// 在栈上分配a个字节, 按n对齐, a = 地址. 用于变长数组
+	Alloca(usize),
+	/// 把内存的栈顶保存到 bp + n
+	Ssp(usize),
+	/// 恢复保存在 bp + n 的栈顶, 释放之后分配的变长数组
+	Rsp(usize),
+	/// a转换为类型
+	Cast(Scalar),
+	/// 把a压栈
+	Psh,
+
+	Or(Scalar),
+	Xor(Scalar),
+	And(Scalar),
+	Eq(Scalar),
+	Ne(Scalar),
+	Lt(Scalar),
+	Gt(Scalar),
+	Le(Scalar),
+	Ge(Scalar),
+	Shl(Scalar),
+	Shr(Scalar),
+	Add(Scalar),
+	Sub(Scalar),
+	Mul(Scalar),
+	Div(Scalar),
+	Mod(Scalar),
+
+	/// 调用内置函数, 实参个数
+	Sys(Builtin, usize),
+}
+
+impl Opcode {
+	/// 二元运算的指令
+	pub(super) fn binary(op: Punct, scalar: Scalar) -> Option<Opcode> {
+		Some(match op {
+			Punct::Or => Opcode::Or(scalar),
+			Punct::Xor => Opcode::Xor(scalar),
+			Punct::And => Opcode::And(scalar),
+			Punct::Eq => Opcode::Eq(scalar),
+			Punct::Ne => Opcode::Ne(scalar),
+			Punct::Lt => Opcode::Lt(scalar),
+			Punct::Gt => Opcode::Gt(scalar),
+			Punct::Le => Opcode::Le(scalar),
+			Punct::Ge => Opcode::Ge(scalar),
+			Punct::Shl => Opcode::Shl(scalar),
+			Punct::Shr => Opcode::Shr(scalar),
+			Punct::Add => Opcode::Add(scalar),
+			Punct::Sub => Opcode::Sub(scalar),
+			Punct::Mul => Opcode::Mul(scalar),
+			Punct::Div => Opcode::Div(scalar),
+			Punct::Mod => Opcode::Mod(scalar),
+			_ => return None,
+		})
+	}
+
+	/// 二元运算指令对应的运算符和类型
+	pub fn operator(&self) -> Option<(Punct, Scalar)> {
+		Some(match *self {
+			Opcode::Or(s) => (Punct::Or, s),
+			Opcode::Xor(s) => (Punct::Xor, s),
+			Opcode::And(s) => (Punct::And, s),
+			Opcode::Eq(s) => (Punct::Eq, s),
+			Opcode::Ne(s) => (Punct::Ne, s),
+			Opcode::Lt(s) => (Punct::Lt, s),
+			Opcode::Gt(s) => (Punct::Gt, s),
+			Opcode::Le(s) => (Punct::Le, s),
+			Opcode::Ge(s) => (Punct::Ge, s),
+			Opcode::Shl(s) => (Punct::Shl, s),
+			Opcode::Shr(s) => (Punct::Shr, s),
+			Opcode::Add(s) => (Punct::Add, s),
+			Opcode::Sub(s) => (Punct::Sub, s),
+			Opcode::Mul(s) => (Punct::Mul, s),
+			Opcode::Div(s) => (Punct::Div, s),
+			Opcode::Mod(s) => (Punct::Mod, s),
+			_ => return None,
+		})
+	}
+}
+
+/// 函数表中的一项, 只有声明的函数没有入口
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FunctionInfo {
+	pub name: String,
+	pub entry: Option<usize>,
+	pub params: usize,
+	pub is_variadic: bool,
+	/// 返回结构体时结构体的大小和对齐, 返回前复制到调用者的栈上
+	pub returns_struct: Option<(usize, usize)>,
+}
+
+/// 函数地址 FUNCTION_BASE + 符号编号 指向的函数
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Callee {
+	Function(usize),
+	Builtin(Builtin),
+}
+
+/// 翻译的结果: 代码, 函数表和初始的数据段
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Program {
+	pub code: Vec<Opcode>,
+	/// 每条指令对应的表达式的位置, 报告运行时错误
+	pub spans: Vec<Option<Span>>,
+	pub functions: Vec<FunctionInfo>,
+	/// 函数的符号编号到函数, 用于通过函数指针调用
+	pub callees: HashMap<usize, Callee>,
+	/// 全局变量的初始值, 地址已经填好, 后面是字符串字面量
+	pub data: Vec<u8>,
+	/// 数据段中字符串字面量开始的偏移
+	pub string_pool: usize,
+	/// main在函数表中的下标
+	pub main: Option<usize>,
+	/// 数据段中的全局变量和字符串字面量, 名字和偏移. 反汇编时标注地址
+	pub symbols: Vec<(String, usize)>,
+}
+
+/// break和continue跳转的目标
+struct Target {
+	is_loop: bool,
+	breaks: Vec<usize>,
+	continues: Vec<usize>,
+	/// switch中的case标签和default的位置
+	cases: Vec<(i64, usize)>,
+	default: Option<usize>,
+	/// 进入时外层有几个分配变长数组的块, 跳出时要恢复栈顶
+	vla_blocks: usize,
+}
+
+impl Target {
+	fn new(is_loop: bool, vla_blocks: usize) -> Self {
+		Target { is_loop, breaks: vec![], continues: vec![], cases: vec![], default: None, vla_blocks }
+	}
+}
+
+struct Codegen<'a> {
+	symbols: &'a [Symbol],
+	program: Program,
+	/// 全局变量在数据段中的偏移
+	globals: HashMap<SymbolId, usize>,
+	strings: HashMap<String, usize>,
+	/// 函数的符号编号到函数表的下标
+	function_index: HashMap<SymbolId, usize>,
+	/// 当前函数的参数和局部变量在栈帧中的偏移, 变长数组的位置保存的是数组的地址
+	locals: HashMap<SymbolId, usize>,
+	frame_size: usize,
+	targets: Vec<Target>,
+	/// 分配变长数组的块保存栈顶的位置
+	vla_blocks: Vec<usize>,
+	/// 正在翻译的表达式的位置
+	span: Option<Span>,
+}
+
+fn unsupported(what: &str) -> ParseError {
+	ParseError::Unexpected(what.to_string())
+}
+
+/// 直接调用的函数或者内置函数的符号: 函数名经过到指针的转换
+pub(super) fn direct_callee(expr: &Expr) -> Option<SymbolId> {
+	match &expr.kind {
+		ExprKind::Cast(_, operand) => direct_callee(operand),
+		ExprKind::Id(_, id) => *id,
+		_ => None,
+	}
+}
+
+/// 块中是否直接声明了变长数组
+pub(super) fn declares_vla(stmts: &[Statement]) -> bool {
+	stmts.iter().any(|stmt| matches!(stmt, Statement::DeclStmt(vars) if vars.iter().any(|var| var.ctype.is_vla())))
+}
+
+impl<'a> Codegen<'a> {
+	fn emit(&mut self, op: Opcode) -> usize {
+		self.program.code.push(op);
+		self.program.spans.push(self.span);
+		self.program.code.len() - 1
+	}
+
+	fn here(&self) -> usize {
+		self.program.code.len()
+	}
+
+	/// 把跳转指令的目标改为target
+	fn patch(&mut self, at: usize, target: usize) {
+		match &mut self.program.code[at] {
+			Opcode::Jmp(n) | Opcode::Bz(n) | Opcode::Bnz(n) | Opcode::Ent(n) => *n = target,
+			op => unreachable!("patching {:?}", op),
+		}
+	}
+
+	/// 在栈帧中分配一块空间, 返回偏移
+	fn slot(&mut self, size: usize, align: usize) -> usize {
+		let offset = align_to(self.frame_size, align.max(1));
+		self.frame_size = offset + size;
+		offset
+	}
+
+	/// 表达式的类型. 变长数组长度中的隐藏变量没有经过类型检查, 使用符号的类型
+	fn type_of(&self, expr: &'a Expr) -> Result<&'a Type, ParseError> {
+		match (&expr.ctype, &expr.kind) {
+			(Some(ctype), _) => Ok(ctype),
+			(None, ExprKind::Id(_, Some(id))) => Ok(&self.symbols[id.0].ctype),
+			_ => Err(unsupported("expression that is not type checked")),
+		}
+	}
+
+	/// 读出a处的值, 数组, 结构体和函数的值是地址本身
+	fn load(&mut self, ctype: &Type) {
+		if !matches!(ctype, Type::Array(_) | Type::Struct(_) | Type::Func(_)) {
+			self.emit(Opcode::Load(Scalar::of(ctype)));
+		}
+	}
+
+	/// 把a写入栈顶的地址
+	fn store(&mut self, ctype: &Type) {
+		match ctype {
+			Type::Struct(_) => self.emit(Opcode::Copy(ctype.size())),
+			_ => self.emit(Opcode::Store(Scalar::of(ctype))),
+		};
+	}
+
+	fn string(&mut self, str: &str) -> usize {
+		match self.strings.get(str) {
+			Some(offset) => *offset,
+			None => {
+				let offset = self.program.data.len();
+				self.program.data.extend_from_slice(str.as_bytes());
+				self.program.data.push(0);
+				self.strings.insert(str.to_string(), offset);
+				self.program.symbols.push((format!("{:?}", str), offset));
+				offset
+			}
+		}
+	}
+
+	/// 运行时的类型大小, 变长数组的长度保存在隐藏的局部变量中
+	fn size_of(&mut self, ctype: &'a Type) -> Result<(), ParseError> {
+		match ctype {
+			Type::Array(Array { base_type, size_expr: Some(len), .. }) if ctype.is_vla() => {
+				match len.fold_const() {
+					Some(n) => {
+						self.emit(Opcode::Imm(n));
+					}
+					None => self.expr(len)?,
+				}
+				self.emit(Opcode::Psh);
+				self.size_of(base_type)?;
+				self.emit(Opcode::Mul(Scalar::U64));
+			}
+			ctype => {
+				self.emit(Opcode::Imm(ctype.size() as i64));
+			}
+		}
+		Ok(())
+	}
+
+	/// ++ -- 的步长, 指针按所指类型的大小
+	fn step(&mut self, ctype: &'a Type) -> Result<(), ParseError> {
+		match ctype.pointee() {
+			Some(pointee) if pointee.is_vla() => self.size_of(pointee),
+			Some(pointee) => {
+				self.emit(Opcode::Imm(pointee.size().max(1) as i64));
+				Ok(())
+			}
+			None => {
+				self.emit(Opcode::Imm(1));
+				Ok(())
+			}
+		}
+	}
+
+	/// 左值表达式指代的对象的地址
+	fn address(&mut self, expr: &'a Expr) -> Result<(), ParseError> {
+		match &expr.kind {
+			ExprKind::Id(name, Some(id)) => match self.symbols[id.0].kind {
+				SymbolKind::Global => {
+					let offset = self.globals.get(id).ok_or_else(|| unsupported(name))?;
+					self.emit(Opcode::Lgb(*offset));
+				}
+				SymbolKind::Local | SymbolKind::Param => {
+					let offset = self.locals.get(id).ok_or_else(|| ParseError::Undeclared(name.clone()))?;
+					self.emit(Opcode::Lea(*offset));
+					if self.symbols[id.0].ctype.is_vla() {
+						self.emit(Opcode::Load(Scalar::U64));
+					}
+				}
+				SymbolKind::Function | SymbolKind::Builtin(_) => {
+					self.emit(Opcode::Imm((FUNCTION_BASE + id.0) as i64));
+				}
+				SymbolKind::EnumConst(_) => return Err(ParseError::NotLvalue(name.clone())),
+			},
+			ExprKind::Id(name, None) => return Err(ParseError::Undeclared(name.clone())),
+			ExprKind::UnaryOp(UnaryOp { op: Punct::Mul, expr: pointer }) => self.expr(pointer)?,
+			ExprKind::MemberAccess(base, name) | ExprKind::MemberAccessP(base, name) => {
+				// 结构体的值和指针的值都是结构体的地址
+				self.expr(base)?;
+				let st = match (&expr.kind, self.type_of(base)?) {
+					(ExprKind::MemberAccess(..), ctype) => ctype,
+					(_, ctype) => ctype.pointee().unwrap_or(ctype),
+				};
+				let member = match st {
+					Type::Struct(st) => st.member(name),
+					_ => None,
+				};
+				let member = member.ok_or_else(|| ParseError::NoMember(name.clone()))?;
+				if member.offset != 0 {
+					self.emit(Opcode::Psh);
+					self.emit(Opcode::Imm(member.offset as i64));
+					self.emit(Opcode::Add(Scalar::U64));
+				}
+			}
+			ExprKind::StringLiteral(str) => {
+				let offset = self.string(str);
+				self.emit(Opcode::Lgb(offset));
+			}
+			// 复合字面量在栈帧中
+			ExprKind::CompoundLiteral(ctype, init) => {
+				let offset = self.slot(ctype.size(), ctype.align());
+				self.local_init(offset, ctype, Some(init))?;
+				self.emit(Opcode::Lea(offset));
+			}
+			_ => return Err(unsupported(&format!("{} as lvalue", expr))),
+		}
+		Ok(())
+	}
+
+	/// 自增自减. 比int小的整数按int运算, 写入时转换; 后缀形式的值由新值减去步长得到
+	fn increment(&mut self, operand: &'a Expr, op: Punct, postfix: bool) -> Result<(), ParseError> {
+		let ctype = self.type_of(operand)?;
+		let scalar = Scalar::of(ctype);
+		let promoted = Scalar::of(&ctype.promoted());
+		let (op, undo) = if op == Punct::Inc { (Punct::Add, Punct::Sub) } else { (Punct::Sub, Punct::Add) };
+		if postfix && scalar == Scalar::Bool {
+			// _Bool的旧值不能由新值推出, 地址和旧值先保存在栈帧中
+			let temp = self.slot(16, 8);
+			self.emit(Opcode::Lea(temp));
+			self.emit(Opcode::Psh);
+			self.address(operand)?;
+			self.emit(Opcode::Store(Scalar::U64));
+			self.emit(Opcode::Lea(temp + 8));
+			self.emit(Opcode::Psh);
+			self.emit(Opcode::Lea(temp));
+			self.emit(Opcode::Load(Scalar::U64));
+			self.emit(Opcode::Load(scalar));
+			self.emit(Opcode::Store(Scalar::I64));
+			self.emit(Opcode::Lea(temp));
+			self.emit(Opcode::Load(Scalar::U64));
+			self.emit(Opcode::Psh);
+			self.emit(Opcode::Lea(temp + 8));
+			self.emit(Opcode::Load(Scalar::I64));
+			self.emit(Opcode::Psh);
+			self.emit(Opcode::Imm(1));
+			self.emit(Opcode::binary(op, promoted).unwrap_or(Opcode::Add(promoted)));
+			self.emit(Opcode::Store(scalar));
+			self.emit(Opcode::Lea(temp + 8));
+			self.emit(Opcode::Load(Scalar::I64));
+			return Ok(());
+		}
+		self.address(operand)?;
+		self.emit(Opcode::Psh);
+		self.emit(Opcode::Load(scalar));
+		self.emit(Opcode::Psh);
+		self.step(ctype)?;
+		self.emit(Opcode::binary(op, promoted).unwrap_or(Opcode::Add(promoted)));
+		self.emit(Opcode::Store(scalar));
+		if postfix {
+			self.emit(Opcode::Psh);
+			self.step(ctype)?;
+			self.emit(Opcode::binary(undo, promoted).unwrap_or(Opcode::Sub(promoted)));
+			self.emit(Opcode::Cast(scalar));
+		}
+		Ok(())
+	}
+
+	/// 翻译表达式, 值在a中
+	fn expr(&mut self, expr: &'a Expr) -> Result<(), ParseError> {
+		let outer = self.span;
+		// 省略的表达式(如 for(;;) 的条件)没有位置
+		if expr.span.line > 0 {
+			self.span = Some(expr.span);
+		}
+		let result = self.expr_at(expr);
+		self.span = outer;
+		result
+	}
+
+	fn expr_at(&mut self, expr: &'a Expr) -> Result<(), ParseError> {
+		match &expr.kind {
+			ExprKind::Const(Const::Empty) => {
+				self.emit(Opcode::Imm(0));
+			}
+			ExprKind::Const(Const::Integer(ints)) => {
+				let (value, _) = Const::integer(ints).ok_or(ParseError::ConstError(ConstError::Overflow))?;
+				self.emit(Opcode::Imm(value));
+			}
+			ExprKind::Const(Const::Character(c)) => {
+				self.emit(Opcode::Imm(*c as i64));
+			}
+			ExprKind::Id(_, Some(id)) if matches!(self.symbols[id.0].kind, SymbolKind::EnumConst(_)) => {
+				if let SymbolKind::EnumConst(value) = self.symbols[id.0].kind {
+					self.emit(Opcode::Imm(value));
+				}
+			}
+			// 对象的值从内存中读出
+			ExprKind::Id(..)
+			| ExprKind::UnaryOp(UnaryOp { op: Punct::Mul, .. })
+			| ExprKind::MemberAccess(..)
+			| ExprKind::MemberAccessP(..)
+			| ExprKind::StringLiteral(_)
+			| ExprKind::CompoundLiteral(..) => {
+				self.address(expr)?;
+				self.load(self.type_of(expr)?);
+			}
+			ExprKind::SizeOfType(ctype) => self.size_of(ctype)?,
+			ExprKind::SizeOfExpr(operand) => match &operand.ctype {
+				Some(ctype) => self.size_of(ctype)?,
+				None => {
+					let size = expr.fold_const().ok_or_else(|| unsupported("sizeof"))?;
+					self.emit(Opcode::Imm(size));
+				}
+			},
+			ExprKind::Cast(ctype, operand) => {
+				self.expr(operand)?;
+				if ctype.is_integer() {
+					self.emit(Opcode::Cast(Scalar::of(ctype)));
+				}
+			}
+			ExprKind::UnaryOp(UnaryOp { op, expr: operand }) => match op {
+				Punct::Add => self.expr(operand)?,
+				Punct::Sub => {
+					let scalar = Scalar::of(self.type_of(operand)?);
+					self.emit(Opcode::Imm(0));
+					self.emit(Opcode::Psh);
+					self.expr(operand)?;
+					self.emit(Opcode::Sub(scalar));
+				}
+				Punct::Tilde => {
+					self.expr(operand)?;
+					self.emit(Opcode::Psh);
+					self.emit(Opcode::Imm(-1));
+					self.emit(Opcode::Xor(Scalar::of(self.type_of(operand)?)));
+				}
+				Punct::Not => {
+					self.expr(operand)?;
+					self.emit(Opcode::Psh);
+					self.emit(Opcode::Imm(0));
+					self.emit(Opcode::Eq(Scalar::I64));
+				}
+				Punct::And => self.address(operand)?,
+				// ++i --i
+				Punct::Inc | Punct::Dec => self.increment(operand, *op, false)?,
+				_ => return Err(unsupported(&format!("operator {}", op))),
+			},
+			ExprKind::Postfix(PostfixOP { op, expr: operand }) => self.increment(operand, *op, true)?,
+			ExprKind::BinOp(BinOp { left, op: op @ (Punct::Lan | Punct::Lor), right }) => {
+				// 短路求值, 结果是0或1
+				let branch = |target| if *op == Punct::Lan { Opcode::Bz(target) } else { Opcode::Bnz(target) };
+				self.expr(left)?;
+				let first = self.emit(branch(0));
+				self.expr(right)?;
+				let second = self.emit(branch(0));
+				self.emit(Opcode::Imm((*op == Punct::Lan) as i64));
+				let end = self.emit(Opcode::Jmp(0));
+				let short = self.here();
+				self.patch(first, short);
+				self.patch(second, short);
+				self.emit(Opcode::Imm((*op == Punct::Lor) as i64));
+				let here = self.here();
+				self.patch(end, here);
+			}
+			// 类型检查已经把两个操作数转换为共同类型, 移位和指针运算时左操作数的类型是结果的类型
+			ExprKind::BinOp(BinOp { left, op, right }) => {
+				let scalar = Scalar::of(self.type_of(left)?);
+				let op = Opcode::binary(*op, scalar).ok_or_else(|| unsupported(&format!("operator {}", op)))?;
+				self.expr(left)?;
+				self.emit(Opcode::Psh);
+				self.expr(right)?;
+				self.emit(op);
+			}
+			ExprKind::AssignExpr(AssignExpr { left, assign, right }) => {
+				let ctype = self.type_of(left)?;
+				self.address(left)?;
+				self.emit(Opcode::Psh);
+				if *assign != Punct::Assign {
+					// 按寻常算术转换之后的类型运算, 写入时转换为左边的类型
+					let op = compound_op(*assign);
+					let op_type = match (ctype.is_integer(), op) {
+						(true, Punct::Shl | Punct::Shr) => ctype.promoted(),
+						(true, _) => ctype.common(self.type_of(right)?),
+						(false, _) => ctype.clone(),
+					};
+					let scalar = Scalar::of(&op_type);
+					let op = Opcode::binary(op, scalar).ok_or_else(|| unsupported(&format!("operator {}", assign)))?;
+					// 栈顶的地址留给写入, a中的地址用来读出旧值
+					self.emit(Opcode::Load(Scalar::of(ctype)));
+					if scalar != Scalar::of(ctype) {
+						self.emit(Opcode::Cast(scalar));
+					}
+					self.emit(Opcode::Psh);
+					self.expr(right)?;
+					if scalar != Scalar::of(self.type_of(right)?) {
+						self.emit(Opcode::Cast(scalar));
+					}
+					self.emit(op);
+				} else {
+					self.expr(right)?;
+				}
+				self.store(ctype);
+			}
+			ExprKind::CondExpr(CondExpr { cond, left, right }) => {
+				self.expr(cond)?;
+				let branch = self.emit(Opcode::Bz(0));
+				self.expr(left)?;
+				let end = self.emit(Opcode::Jmp(0));
+				let here = self.here();
+				self.patch(branch, here);
+				self.expr(right)?;
+				let here = self.here();
+				self.patch(end, here);
+			}
+			ExprKind::CommaExpr(CommaExpr { left, right }) => {
+				self.expr(left)?;
+				self.expr(right)?;
+			}
+			ExprKind::FunctionCall(callee, args) => {
+				let direct = direct_callee(callee).and_then(|id| match self.symbols[id.0].kind {
+					SymbolKind::Builtin(builtin) => Some(Callee::Builtin(builtin)),
+					SymbolKind::Function => self.function_index.get(&id).map(|index| Callee::Function(*index)),
+					_ => None,
+				});
+				if direct.is_none() {
+					self.expr(callee)?;
+					self.emit(Opcode::Psh);
+				}
+				for arg in args {
+					self.expr(arg)?;
+					self.emit(Opcode::Psh);
+				}
+				match direct {
+					Some(Callee::Builtin(builtin)) => self.emit(Opcode::Sys(builtin, args.len())),
+					Some(Callee::Function(index)) => self.emit(Opcode::Jsr(index, args.len())),
+					None => self.emit(Opcode::Jsri(args.len())),
+				};
+				let pushed = args.len() + direct.is_none() as usize;
+				if pushed > 0 {
+					self.emit(Opcode::Adj(pushed));
+				}
+			}
+			ExprKind::Error => return Err(unsupported("expression with errors")),
+		}
+		Ok(())
+	}
+
+	/// 初始化栈帧中的对象. 没有初始化器或者初始化列表时先清零, 没有初始化的子对象保持为0
+	fn local_init(&mut self, offset: usize, ctype: &Type, init: Option<&'a Initializer>) -> Result<(), ParseError> {
+		if !matches!(init, Some(Initializer::Expr(_))) {
+			self.emit(Opcode::Lea(offset));
+			self.emit(Opcode::Zero(ctype.size()));
+		}
+		match init {
+			Some(init) => self.init(offset, ctype, init),
+			None => Ok(()),
+		}
+	}
+
+	fn init(&mut self, offset: usize, ctype: &Type, init: &'a Initializer) -> Result<(), ParseError> {
+		match (ctype, init) {
+			(Type::Array(Array { base_type, .. }), Initializer::List(list)) => {
+				for (i, elem) in list.iter().enumerate() {
+					if let Some(elem) = elem {
+						self.init(offset + i * base_type.size(), base_type, elem)?;
+					}
+				}
+				Ok(())
+			}
+			(Type::Struct(st), Initializer::List(list)) => {
+				for (member, elem) in st.members().iter().zip(list.iter()) {
+					if let Some(elem) = elem {
+						self.init(offset + member.offset, &member.ctype, elem)?;
+					}
+				}
+				Ok(())
+			}
+			(_, Initializer::List(list)) => match list.first() {
+				Some(Some(elem)) => self.init(offset, ctype, elem),
+				_ => Ok(()),
+			},
+			(_, Initializer::Expr(expr)) => {
+				self.emit(Opcode::Lea(offset));
+				self.emit(Opcode::Psh);
+				self.expr(expr)?;
+				self.store(ctype);
+				Ok(())
+			}
+		}
+	}
+
+	/// 局部变量: 变长数组在声明处按长度分配, 地址保存在栈帧中
+	fn declare(&mut self, var: &'a Variable) -> Result<(), ParseError> {
+		let id = var.symbol.ok_or_else(|| ParseError::Undeclared(var.name.clone()))?;
+		let offset = *self.locals.get(&id).ok_or_else(|| ParseError::Undeclared(var.name.clone()))?;
+		if var.ctype.is_vla() {
+			self.emit(Opcode::Lea(offset));
+			self.emit(Opcode::Psh);
+			self.size_of(&var.ctype)?;
+			self.emit(Opcode::Alloca(var.ctype.align()));
+			self.emit(Opcode::Store(Scalar::U64));
+			return Ok(());
+		}
+		self.local_init(offset, &var.ctype, var.init_value.as_ref())
+	}
+
+	/// 跳出循环或者switch之前释放其中分配的变长数组
+	fn leave_vla_blocks(&mut self, depth: usize) {
+		if let Some(offset) = self.vla_blocks.get(depth).copied() {
+			self.emit(Opcode::Rsp(offset));
+		}
+	}
+
+	fn block(&mut self, stmts: &'a [Statement]) -> Result<(), ParseError> {
+		if !declares_vla(stmts) {
+			return stmts.iter().try_for_each(|stmt| self.stmt(stmt));
+		}
+		let offset = self.slot(8, 8);
+		self.emit(Opcode::Ssp(offset));
+		self.vla_blocks.push(offset);
+		let result = stmts.iter().try_for_each(|stmt| self.stmt(stmt));
+		self.vla_blocks.pop();
+		self.emit(Opcode::Rsp(offset));
+		result
+	}
+
+	fn stmt(&mut self, stmt: &'a Statement) -> Result<(), ParseError> {
+		// 语句本身没有位置
+		self.span = None;
+		match stmt {
+			Statement::Empty => (),
+			Statement::CompoundStmt(stmts) => self.block(stmts)?,
+			Statement::ExprStmt(expr) => self.expr(expr)?,
+			Statement::IfStmt(cond, then, else_stmt) => {
+				self.expr(cond)?;
+				let branch = self.emit(Opcode::Bz(0));
+				self.stmt(then)?;
+				match else_stmt {
+					Some(else_stmt) => {
+						let end = self.emit(Opcode::Jmp(0));
+						let here = self.here();
+						self.patch(branch, here);
+						self.stmt(else_stmt)?;
+						let here = self.here();
+						self.patch(end, here);
+					}
+					None => {
+						let here = self.here();
+						self.patch(branch, here);
+					}
+				}
+			}
+			Statement::ReturnStmt(expr, _) => {
+				match expr {
+					Some(expr) => self.expr(expr)?,
+					None => {
+						self.emit(Opcode::Imm(0));
+					}
+				}
+				self.emit(Opcode::Lev);
+			}
+			Statement::DeclStmt(vars) => {
+				for var in vars.iter().filter(|var| !var.is_enum_constant) {
+					self.declare(var)?;
+				}
+			}
+			Statement::ForStmt(init, cond, step, body) => {
+				if let Some(init) = init {
+					self.expr(init)?;
+				}
+				let start = self.here();
+				self.expr(cond)?;
+				let exit = self.emit(Opcode::Bz(0));
+				self.targets.push(Target::new(true, self.vla_blocks.len()));
+				let result = self.stmt(body);
+				let target = self.targets.pop().expect("loop target");
+				result?;
+				let next = self.here();
+				if let Some(step) = step {
+					self.expr(step)?;
+				}
+				self.emit(Opcode::Jmp(start));
+				let end = self.here();
+				self.patch(exit, end);
+				for at in target.continues {
+					self.patch(at, next);
+				}
+				for at in target.breaks {
+					self.patch(at, end);
+				}
+			}
+			Statement::SwitchStmt(cond, body) => {
+				// 条件的值保存在栈帧中, 和每个case的值比较
+				let value = self.slot(8, 8);
+				self.emit(Opcode::Lea(value));
+				self.emit(Opcode::Psh);
+				self.expr(cond)?;
+				self.emit(Opcode::Store(Scalar::I64));
+				let dispatch = self.emit(Opcode::Jmp(0));
+				self.targets.push(Target::new(false, self.vla_blocks.len()));
+				let result = self.stmt(body);
+				let target = self.targets.pop().expect("switch target");
+				result?;
+				let end = self.emit(Opcode::Jmp(0));
+				let here = self.here();
+				self.patch(dispatch, here);
+				for (case, at) in target.cases {
+					self.emit(Opcode::Lea(value));
+					self.emit(Opcode::Load(Scalar::I64));
+					self.emit(Opcode::Psh);
+					self.emit(Opcode::Imm(case));
+					self.emit(Opcode::Eq(Scalar::I64));
+					self.emit(Opcode::Bnz(at));
+				}
+				// 没有匹配的case也没有default时跳过switch体
+				let here = self.here() + 1;
+				self.emit(Opcode::Jmp(target.default.unwrap_or(here)));
+				self.patch(end, here);
+				for at in target.breaks {
+					self.patch(at, here);
+				}
+			}
+			Statement::BreakStmt => {
+				let depth = self.targets.last().map(|target| target.vla_blocks);
+				let depth = depth.ok_or(ParseError::General("break statement not within loop or switch"))?;
+				self.leave_vla_blocks(depth);
+				let at = self.emit(Opcode::Jmp(0));
+				if let Some(target) = self.targets.last_mut() {
+					target.breaks.push(at);
+				}
+			}
+			Statement::ContinueStmt => {
+				let depth = self.targets.iter().rev().find(|target| target.is_loop).map(|target| target.vla_blocks);
+				let depth = depth.ok_or(ParseError::General("continue statement not within a loop"))?;
+				self.leave_vla_blocks(depth);
+				let at = self.emit(Opcode::Jmp(0));
+				if let Some(target) = self.targets.iter_mut().rev().find(|target| target.is_loop) {
+					target.continues.push(at);
+				}
+			}
+			Statement::CaseStmt(value, stmt) => {
+				let here = self.here();
+				if let Some(target) = self.targets.iter_mut().rev().find(|target| !target.is_loop) {
+					target.cases.push((*value, here));
+				}
+				self.stmt(stmt)?;
+			}
+			Statement::DefaultStmt(stmt) => {
+				let here = self.here();
+				if let Some(target) = self.targets.iter_mut().rev().find(|target| !target.is_loop) {
+					target.default = Some(here);
+				}
+				self.stmt(stmt)?;
+			}
+			Statement::Error => return Err(unsupported("statement with errors")),
+		}
+		Ok(())
+	}
+
+	/// 函数: 分配栈帧, 把实参写入参数, 执行函数体, 没有return语句时返回0
+	fn function(&mut self, func: &'a Function) -> Result<usize, ParseError> {
+		self.locals.clear();
+		self.frame_size = 0;
+		for id in func.locals.iter() {
+			let ctype = &self.symbols[id.0].ctype;
+			let offset = match ctype.is_vla() {
+				true => self.slot(8, 8),
+				false => self.slot(ctype.size(), ctype.align()),
+			};
+			self.locals.insert(*id, offset);
+		}
+		self.span = None;
+		let entry = self.emit(Opcode::Ent(0));
+		for (i, (param, id)) in func.ctype.param_list.iter().zip(func.locals.iter()).enumerate() {
+			self.emit(Opcode::Lea(self.locals[id]));
+			self.emit(Opcode::Psh);
+			self.emit(Opcode::Arg(i));
+			self.store(&param.ctype);
+		}
+		match &func.stmts {
+			Statement::CompoundStmt(stmts) => self.block(stmts)?,
+			stmt => self.stmt(stmt)?,
+		}
+		self.span = None;
+		self.emit(Opcode::Imm(0));
+		self.emit(Opcode::Lev);
+		let frame_size = align_to(self.frame_size, 16);
+		self.patch(entry, frame_size);
+		Ok(entry)
+	}
+}
+
+/// 翻译之前确定的布局: 还没有代码的程序(初始的数据段, 函数表, 函数地址表), 全局变量在数据段中的偏移,
+/// 函数的符号编号到函数表的下标. IR 的翻译使用同样的布局
+pub(super) struct Layout {
+	pub program: Program,
+	pub globals: HashMap<SymbolId, usize>,
+	pub function_index: HashMap<SymbolId, usize>,
+}
+
+/// 数据段按全局变量的布局排列, 全局变量和函数的地址在翻译时就已经确定
+pub(super) fn layout(functions: &[Function], symbols: &[Symbol], globals: &[Variable]) -> Result<Layout, ParseError> {
+	let data = DataSegment::layout(globals, functions)?;
+	let mut bytes = data.bytes.clone();
+	for reloc in data.relocations.iter() {
+		let addr = match &reloc.target {
+			RelocTarget::Data(n) => DATA_BASE + n,
+			RelocTarget::Function(id) => FUNCTION_BASE + id.0,
+		};
+		bytes[reloc.offset..reloc.offset + 8].copy_from_slice(&(addr as u64).to_le_bytes());
+	}
+
+	let string_pool = bytes.len();
+	let mut program = Program { data: bytes, string_pool, symbols: data.symbols.clone(), ..Default::default() };
+	let mut function_index = HashMap::new();
+	for func in functions {
+		let index = program.functions.len();
+		if let Some(id) = func.symbol {
+			// 同一个函数有多项时使用有定义的那一项
+			match function_index.get(&id) {
+				Some(_) if !func.is_definition => continue,
+				_ => function_index.insert(id, index),
+			};
+		}
+		program.functions.push(FunctionInfo {
+			name: func.name.clone(),
+			entry: None,
+			params: func.ctype.param_list.len(),
+			is_variadic: func.ctype.is_variadic,
+			returns_struct: match func.ctype.return_type.as_ref() {
+				ctype @ Type::Struct(_) => Some((ctype.size(), ctype.align())),
+				_ => None,
+			},
+		});
+	}
+	for (id, symbol) in symbols.iter().enumerate() {
+		match symbol.kind {
+			SymbolKind::Builtin(builtin) => {
+				program.callees.insert(id, Callee::Builtin(builtin));
+			}
+			SymbolKind::Function => {
+				if let Some(index) = function_index.get(&SymbolId(id)) {
+					program.callees.insert(id, Callee::Function(*index));
+				}
+			}
+			_ => (),
+		}
+	}
+
+	let globals = data.offsets.clone();
+	Ok(Layout { program, globals, function_index })
+}
+
+/// 翻译全部函数
+pub fn compile(functions: &[Function], symbols: &[Symbol], globals: &[Variable]) -> Result<Program, ParseError> {
+	let Layout { program, globals, function_index } = layout(functions, symbols, globals)?;
+	let mut codegen = Codegen {
+		symbols,
+		program,
+		globals,
+		strings: HashMap::new(),
+		function_index,
+		locals: HashMap::new(),
+		frame_size: 0,
+		targets: vec![],
+		vla_blocks: vec![],
+		span: None,
+	};
+	for func in functions.iter().filter(|func| func.is_definition) {
+		let index = func.symbol.and_then(|id| codegen.function_index.get(&id).copied());
+		let entry = codegen.function(func)?;
+		if let Some(index) = index {
+			codegen.program.functions[index].entry = Some(entry);
+		}
+	}
+	let mut program = codegen.program;
+	program.main = program.functions.iter().position(|func| func.name == "main" && func.entry.is_some());
+	Ok(program)
+}
@@ -0,0 +1,150 @@
+//! 整数常量表达式 (6.6) 的求值, 用于数组长度, case标签和枚举常量.
+//!
+//! 常量表达式的类型都是int: 运算结果超出int的范围是溢出, 除以0也是错误;
+//! 包含变量, 函数调用, 赋值, 逗号运算符或者变长数组的sizeof时不是常量, 如变长数组的长度.
+//! && || ?: 不求值的操作数中的错误不影响结果
+
+use super::{
+	errors::ConstError,
+	token::{Const, Punct},
+	types::*,
+};
+
+/// 常量表达式中标识符的含义
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Binding {
+	EnumConst(i64),
+	/// 变量或者函数, 只能作为sizeof的操作数
+	Object(Type),
+}
+
+/// 按照名字查找标识符, 找不到时返回None
+pub type Lookup<'a> = &'a dyn Fn(&str) -> Option<Binding>;
+
+pub fn eval(expr: &Expr, lookup: Lookup) -> Result<i64, ConstError> {
+	match &expr.kind {
+		ExprKind::Const(Const::Integer(i)) => {
+			let (v, _) = Const::integer(i).ok_or(ConstError::Overflow)?;
+			int(Some(v))
+		}
+		ExprKind::Const(Const::Character(c)) => Ok(*c as i64),
+		ExprKind::Id(name, _) => match lookup(name) {
+			Some(Binding::EnumConst(v)) => Ok(v),
+			_ => Err(ConstError::NotConstant),
+		},
+		ExprKind::SizeOfType(ctype) => size_of(ctype),
+		ExprKind::SizeOfExpr(operand) => size_of(&type_of(operand, lookup).ok_or(ConstError::NotConstant)?),
+		ExprKind::Cast(ctype, operand) => {
+			let v = eval(operand, lookup)?;
+			match ctype.is_integer() {
+				true => Ok(ctype.convert(v)),
+				false => Err(ConstError::NotConstant),
+			}
+		}
+		ExprKind::UnaryOp(UnaryOp { op, expr: operand }) => {
+			let v = eval(operand, lookup)?;
+			match op {
+				Punct::Add => Ok(v),
+				Punct::Sub => int(v.checked_neg()),
+				Punct::Tilde => Ok(!v),
+				Punct::Not => Ok((v == 0) as i64),
+				_ => Err(ConstError::NotConstant),
+			}
+		}
+		ExprKind::BinOp(BinOp { left, op: Punct::Lan, right }) => match eval(left, lookup)? {
+			0 => Ok(0),
+			_ => Ok((eval(right, lookup)? != 0) as i64),
+		},
+		ExprKind::BinOp(BinOp { left, op: Punct::Lor, right }) => match eval(left, lookup)? {
+			0 => Ok((eval(right, lookup)? != 0) as i64),
+			_ => Ok(1),
+		},
+		ExprKind::BinOp(BinOp { left, op, right }) => binary(*op, eval(left, lookup)?, eval(right, lookup)?),
+		ExprKind::CondExpr(CondExpr { cond, left, right }) => match eval(cond, lookup)? {
+			0 => eval(right, lookup),
+			_ => eval(left, lookup),
+		},
+		_ => Err(ConstError::NotConstant),
+	}
+}
+
+/// 结果必须在int的范围内
+fn int(v: Option<i64>) -> Result<i64, ConstError> {
+	match v {
+		Some(v) if i32::try_from(v).is_ok() => Ok(v),
+		_ => Err(ConstError::Overflow),
+	}
+}
+
+fn binary(op: Punct, l: i64, r: i64) -> Result<i64, ConstError> {
+	match op {
+		Punct::Add => int(l.checked_add(r)),
+		Punct::Sub => int(l.checked_sub(r)),
+		Punct::Mul => int(l.checked_mul(r)),
+		Punct::Div | Punct::Mod if r == 0 => Err(ConstError::DivisionByZero),
+		// INT_MIN / -1 溢出
+		Punct::Div => int(l.checked_div(r)),
+		Punct::Mod => int(l.checked_div(r)).and(int(l.checked_rem(r))),
+		// 移位的位数不能是负数或者不小于int的位数, 有符号数左移不能溢出
+		Punct::Shl | Punct::Shr if !(0..32).contains(&r) => Err(ConstError::Overflow),
+		Punct::Shl if l < 0 => Err(ConstError::Overflow),
+		Punct::Shl => int(Some(l << r)),
+		Punct::Shr => Ok(l >> r),
+		Punct::And => Ok(l & r),
+		Punct::Or => Ok(l | r),
+		Punct::Xor => Ok(l ^ r),
+		Punct::Eq => Ok((l == r) as i64),
+		Punct::Ne => Ok((l != r) as i64),
+		Punct::Lt => Ok((l < r) as i64),
+		Punct::Gt => Ok((l > r) as i64),
+		Punct::Le => Ok((l <= r) as i64),
+		Punct::Ge => Ok((l >= r) as i64),
+		_ => Err(ConstError::NotConstant),
+	}
+}
+
+/// 变长数组的大小在运行时才能确定
+fn size_of(ctype: &Type) -> Result<i64, ConstError> {
+	match ctype.is_vla() {
+		true => Err(ConstError::NotConstant),
+		false => Ok(ctype.size() as i64),
+	}
+}
+
+/// sizeof的操作数不求值, 只需要它的类型. 类型检查之后直接使用标注的类型
+fn type_of(expr: &Expr, lookup: Lookup) -> Option<Type> {
+	if let Some(ctype) = &expr.ctype {
+		return Some(ctype.clone());
+	}
+	match &expr.kind {
+		ExprKind::Id(name, _) => match lookup(name)? {
+			Binding::EnumConst(_) => Some(TYPE_INT),
+			Binding::Object(ctype) => Some(ctype),
+		},
+		ExprKind::UnaryOp(UnaryOp { op: Punct::Mul, expr }) => element_type(type_of(expr, lookup)?),
+		ExprKind::UnaryOp(UnaryOp { op: Punct::And, expr }) => Some(type_of(expr, lookup)?.into_pointer()),
+		ExprKind::BinOp(BinOp { left, op: Punct::BrakL, right }) => {
+			element_type(type_of(left, lookup)?).or_else(|| element_type(type_of(right, lookup)?))
+		}
+		ExprKind::MemberAccess(base, name) => member_type(type_of(base, lookup)?, name),
+		ExprKind::MemberAccessP(base, name) => member_type(element_type(type_of(base, lookup)?)?, name),
+		ExprKind::AssignExpr(AssignExpr { left, .. }) => type_of(left, lookup),
+		ExprKind::CommaExpr(CommaExpr { right, .. }) => type_of(right, lookup),
+		_ => expr.literal_type(),
+	}
+}
+
+/// 指针指向的类型或者数组元素的类型
+fn element_type(ctype: Type) -> Option<Type> {
+	match ctype {
+		Type::Ptr(Ptr { base_type }) | Type::Array(Array { base_type, .. }) => Some(*base_type),
+		_ => None,
+	}
+}
+
+fn member_type(ctype: Type, name: &str) -> Option<Type> {
+	match ctype {
+		Type::Struct(st) => st.member(name).map(|m| m.ctype.clone()),
+		_ => None,
+	}
+}
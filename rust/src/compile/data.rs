@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+
+use super::{
+	consteval,
+	errors::{ConstError, ParseError},
+	token::Punct,
+	types::*,
+};
+
+/// 静态存储期对象的初始值按字节排列在数据段中.
+/// 地址常量(字符串字面量, 全局变量和函数的地址)在加载之前还不确定, 记录在relocations中.
+/// 全局变量和函数按名字解析得到的符号查找, 没有解析过的标识符不是地址常量
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DataSegment {
+	pub bytes: Vec<u8>,
+	/// 全局变量名, 在数据段中的偏移, 用于显示
+	pub symbols: Vec<(String, usize)>,
+	/// 全局变量的符号, 在数据段中的偏移
+	pub offsets: HashMap<SymbolId, usize>,
+	pub relocations: Vec<Relocation>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+	/// 需要写入地址的位置, 大小为指针大小
+	pub offset: usize,
+	pub target: RelocTarget,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RelocTarget {
+	/// 数据段基地址 + 偏移
+	Data(usize),
+	Function(SymbolId),
+}
+
+enum StaticValue {
+	Int(i64),
+	Address(RelocTarget),
+}
+
+impl DataSegment {
+	pub fn layout(globals: &[Variable], functions: &[Function]) -> Result<Self, ParseError> {
+		let (seg, invalid) = Self::layout_all(globals, functions);
+		match invalid.into_iter().next() {
+			Some((_, e)) => Err(e),
+			None => Ok(seg),
+		}
+	}
+
+	/// 排列全部全局变量, 同时返回初始值不是常量表达式或者地址常量的变量和错误, 它们的初始值保持为0.
+	/// 溢出和除以0报告常量表达式本身的错误
+	pub fn layout_all<'a>(globals: &'a [Variable], functions: &[Function]) -> (Self, Vec<(&'a Variable, ParseError)>) {
+		let mut seg = DataSegment::default();
+		let mut invalid = vec![];
+		let mut offset = 0;
+		let mut placed = vec![];
+		for var in globals.iter().filter(|var| !var.is_enum_constant) {
+			offset = align_to(offset, var.ctype.align());
+			seg.symbols.push((var.name.clone(), offset));
+			if let Some(id) = var.symbol {
+				seg.offsets.insert(id, offset);
+			}
+			placed.push((var, offset));
+			offset += var.ctype.size();
+		}
+		seg.bytes.resize(offset, 0);
+
+		for (var, offset) in placed {
+			let result = match &var.init_value {
+				Some(init) => seg.write_init(globals, functions, &var.ctype, init, offset),
+				None => Ok(()),
+			};
+			match result {
+				Ok(()) => (),
+				Err(ConstError::NotConstant) => invalid.push((var, ParseError::NotConstant(var.name.clone()))),
+				Err(e) => invalid.push((var, ParseError::ConstError(e))),
+			}
+		}
+		(seg, invalid)
+	}
+
+	pub fn offset_of(&self, id: SymbolId) -> Option<usize> {
+		self.offsets.get(&id).copied()
+	}
+
+	fn write_init(
+		&mut self,
+		globals: &[Variable],
+		functions: &[Function],
+		ctype: &Type,
+		init: &Initializer,
+		offset: usize,
+	) -> Result<(), ConstError> {
+		match (ctype, init) {
+			(Type::Array(Array { base_type, .. }), Initializer::List(list)) => {
+				for (i, elem) in list.iter().enumerate() {
+					if let Some(elem) = elem {
+						self.write_init(globals, functions, base_type, elem, offset + i * base_type.size())?;
+					}
+				}
+				Ok(())
+			}
+			(Type::Struct(st), Initializer::List(list)) => {
+				for (member, elem) in st.members().iter().zip(list.iter()) {
+					if let Some(elem) = elem {
+						self.write_init(globals, functions, &member.ctype, elem, offset + member.offset)?;
+					}
+				}
+				Ok(())
+			}
+			(Type::Array(_) | Type::Struct(_), Initializer::Expr(_)) => Err(ConstError::NotConstant),
+			(_, Initializer::List(list)) => match list.first() {
+				Some(Some(elem)) => self.write_init(globals, functions, ctype, elem, offset),
+				_ => Ok(()),
+			},
+			(_, Initializer::Expr(expr)) => match self.static_value(globals, functions, expr)? {
+				StaticValue::Int(v) => {
+					let size = ctype.size();
+					self.bytes[offset..offset + size].copy_from_slice(&v.to_le_bytes()[..size]);
+					Ok(())
+				}
+				StaticValue::Address(target) => {
+					self.relocations.push(Relocation { offset, target });
+					Ok(())
+				}
+			},
+		}
+	}
+
+	/// 常量表达式或者地址常量
+	fn static_value(
+		&mut self,
+		globals: &[Variable],
+		functions: &[Function],
+		expr: &Expr,
+	) -> Result<StaticValue, ConstError> {
+		match consteval::eval(expr, &|_| None) {
+			Ok(v) => return Ok(StaticValue::Int(v)),
+			Err(ConstError::NotConstant) => (),
+			Err(e) => return Err(e),
+		}
+		match &expr.kind {
+			ExprKind::Cast(_, expr) => self.static_value(globals, functions, expr),
+			// 地址 ± 整数常量
+			ExprKind::BinOp(BinOp { left, op: op @ (Punct::Add | Punct::Sub), right }) => {
+				let n = consteval::eval(right, &|_| None)?;
+				let n = if *op == Punct::Add { n } else { -n };
+				match self.address_of(globals, functions, left, true)? {
+					(RelocTarget::Data(base), elem_size) => {
+						// 类型检查之后整数已经乘过元素大小
+						let elem_size = if expr.ctype.is_some() { 1 } else { elem_size as i64 };
+						let offset = usize::try_from(base as i64 + n * elem_size).map_err(|_| ConstError::Overflow)?;
+						Ok(StaticValue::Address(RelocTarget::Data(offset)))
+					}
+					(RelocTarget::Function(_), _) => Err(ConstError::NotConstant),
+				}
+			}
+			_ => self.address_of(globals, functions, expr, true).map(|(target, _)| StaticValue::Address(target)),
+		}
+	}
+
+	/// 地址常量, 以及指向的元素的大小(用于指针运算). decay为true时数组名和函数名是地址
+	fn address_of(
+		&mut self,
+		globals: &[Variable],
+		functions: &[Function],
+		expr: &Expr,
+		decay: bool,
+	) -> Result<(RelocTarget, usize), ConstError> {
+		match &expr.kind {
+			ExprKind::StringLiteral(str) if decay => {
+				let offset = self.bytes.len();
+				self.bytes.extend(str.bytes().chain([0]));
+				Ok((RelocTarget::Data(offset), 1))
+			}
+			ExprKind::Id(_, Some(id)) if functions.iter().any(|func| func.symbol == Some(*id)) => {
+				Ok((RelocTarget::Function(*id), 1))
+			}
+			ExprKind::Id(_, Some(id)) => {
+				let var = globals.iter().find(|var| var.symbol == Some(*id)).ok_or(ConstError::NotConstant)?;
+				let offset = self.offset_of(*id).ok_or(ConstError::NotConstant)?;
+				match &var.ctype {
+					Type::Array(Array { base_type, .. }) if decay => Ok((RelocTarget::Data(offset), base_type.size())),
+					ctype if !decay => Ok((RelocTarget::Data(offset), ctype.size())),
+					_ => Err(ConstError::NotConstant),
+				}
+			}
+			// 类型检查插入的数组和函数到指针的转换
+			ExprKind::Cast(_, expr) if decay => self.address_of(globals, functions, expr, true),
+			ExprKind::UnaryOp(UnaryOp { op: Punct::And, expr }) => self.address_of(globals, functions, expr, false),
+			// 类型检查把 a[1] 改写成了 *(a + 4)
+			ExprKind::UnaryOp(UnaryOp { op: Punct::Mul, expr: addr }) if !decay => {
+				match self.static_value(globals, functions, addr)? {
+					StaticValue::Address(target) => Ok((target, expr.ctype.as_ref().map_or(1, |t| t.size()))),
+					StaticValue::Int(_) => Err(ConstError::NotConstant),
+				}
+			}
+			// &a[1]
+			ExprKind::BinOp(BinOp { left, op: Punct::BrakL, right }) if !decay => {
+				let n = consteval::eval(right, &|_| None)?;
+				match self.address_of(globals, functions, left, true)? {
+					(RelocTarget::Data(base), elem_size) => Ok((
+						RelocTarget::Data(
+							usize::try_from(base as i64 + n * elem_size as i64).map_err(|_| ConstError::Overflow)?,
+						),
+						elem_size,
+					)),
+					_ => Err(ConstError::NotConstant),
+				}
+			}
+			_ => Err(ConstError::NotConstant),
+		}
+	}
+}
+
+impl std::fmt::Display for DataSegment {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		for (name, offset) in self.symbols.iter() {
+			writeln!(f, "{:04x}: {}", offset, name)?;
+		}
+		for (i, line) in self.bytes.chunks(16).enumerate() {
+			write!(f, "{:04x}:", i * 16)?;
+			for b in line {
+				write!(f, " {:02x}", b)?;
+			}
+			writeln!(f)?;
+		}
+		for Relocation { offset, target } in self.relocations.iter() {
+			match target {
+				RelocTarget::Data(n) => writeln!(f, "{:04x} -> data + {:04x}", offset, n)?,
+				RelocTarget::Function(id) => writeln!(f, "{:04x} -> function #{}", offset, id.0)?,
+			}
+		}
+		Ok(())
+	}
+}
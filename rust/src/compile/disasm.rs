@@ -0,0 +1,142 @@
+//! 字节码的文本形式. 指令的格式和 c/src/vm.c 的 dump_instruction 一样: 助记符补齐到4个字符, 后面是操作数
+
+use std::fmt::{Display, Write};
+
+use super::{
+	builtins::Builtin,
+	codegen::{Opcode, Program, Scalar},
+};
+
+impl Display for Scalar {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.pad(match self {
+			Scalar::Bool => "bool",
+			Scalar::I8 => "i8",
+			Scalar::U8 => "u8",
+			Scalar::I16 => "i16",
+			Scalar::U16 => "u16",
+			Scalar::I32 => "i32",
+			Scalar::U32 => "u32",
+			Scalar::I64 => "i64",
+			Scalar::U64 => "u64",
+		})
+	}
+}
+
+/// c4的内置函数指令的名字
+fn mnemonic(builtin: Builtin) -> &'static str {
+	match builtin {
+		Builtin::Fopen => "OPEN",
+		Builtin::Fread => "READ",
+		Builtin::Fclose => "CLOS",
+		Builtin::Printf => "PRTF",
+		Builtin::Malloc => "MALC",
+		Builtin::Memset => "MSET",
+		Builtin::Memcmp => "MCMP",
+		Builtin::Exit => "EXIT",
+		Builtin::Free => "FREE",
+		Builtin::Getchar => "GETC",
+	}
+}
+
+impl Display for Opcode {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let (name, operand) = match *self {
+			Opcode::Lea(n) => ("LEA", n.to_string()),
+			Opcode::Imm(n) => ("IMM", n.to_string()),
+			Opcode::Jmp(n) => ("JMP", n.to_string()),
+			Opcode::Jsr(function, argc) => ("JSR", format!("{}, {}", function, argc)),
+			Opcode::Jsri(argc) => ("JSRI", argc.to_string()),
+			Opcode::Bz(n) => ("BZ", n.to_string()),
+			Opcode::Bnz(n) => ("BNZ", n.to_string()),
+			Opcode::Ent(n) => ("ENT", n.to_string()),
+			Opcode::Adj(n) => ("ADJ", n.to_string()),
+			Opcode::Lgb(n) => ("LGB", n.to_string()),
+			Opcode::Lev => ("LEV", String::new()),
+			Opcode::Arg(n) => ("ARG", n.to_string()),
+			Opcode::Load(s) => ("LD", s.to_string()),
+			Opcode::Store(s) => ("ST", s.to_string()),
+			Opcode::Copy(n) => ("COPY", n.to_string()),
+			Opcode::Zero(n) => ("ZERO", n.to_string()),
+			Opcode::Alloca(n) => ("ALCA", n.to_string()),
+			Opcode::Ssp(n) => ("SSP", n.to_string()),
+			Opcode::Rsp(n) => ("RSP", n.to_string()),
+			Opcode::Cast(s) => ("CAST", s.to_string()),
+			Opcode::Psh => ("PSH", String::new()),
+			Opcode::Or(s) => ("OR", s.to_string()),
+			Opcode::Xor(s) => ("XOR", s.to_string()),
+			Opcode::And(s) => ("AND", s.to_string()),
+			Opcode::Eq(s) => ("EQ", s.to_string()),
+			Opcode::Ne(s) => ("NE", s.to_string()),
+			Opcode::Lt(s) => ("LT", s.to_string()),
+			Opcode::Gt(s) => ("GT", s.to_string()),
+			Opcode::Le(s) => ("LE", s.to_string()),
+			Opcode::Ge(s) => ("GE", s.to_string()),
+			Opcode::Shl(s) => ("SHL", s.to_string()),
+			Opcode::Shr(s) => ("SHR", s.to_string()),
+			Opcode::Add(s) => ("ADD", s.to_string()),
+			Opcode::Sub(s) => ("SUB", s.to_string()),
+			Opcode::Mul(s) => ("MUL", s.to_string()),
+			Opcode::Div(s) => ("DIV", s.to_string()),
+			Opcode::Mod(s) => ("MOD", s.to_string()),
+			Opcode::Sys(builtin, argc) => (mnemonic(builtin), argc.to_string()),
+		};
+		// 先拼成字符串, 让 {:<12} 这样的宽度对整条指令生效
+		match operand.is_empty() {
+			true => f.pad(name),
+			false => f.pad(&format!("{:<4} {}", name, operand)),
+		}
+	}
+}
+
+impl Program {
+	/// 数据段中偏移n处的变量或者字符串字面量, 不在开头时带上偏移
+	fn data_symbol(&self, n: usize) -> Option<String> {
+		let (name, offset) =
+			self.symbols.iter().filter(|(_, offset)| *offset <= n).max_by_key(|(_, offset)| *offset)?;
+		Some(match n - offset {
+			0 => name.clone(),
+			k => format!("{}+{}", name, k),
+		})
+	}
+
+	/// 指令后面的注释: 调用的函数名, 取地址的全局变量或者字符串字面量
+	fn annotation(&self, op: &Opcode) -> Option<String> {
+		match *op {
+			Opcode::Jsr(function, _) => self.functions.get(function).map(|func| func.name.clone()),
+			Opcode::Lgb(n) => self.data_symbol(n),
+			_ => None,
+		}
+	}
+
+	/// 按函数分组列出全部指令. 指令对应的源码行第一次出现时, 把这一行插在指令之前
+	pub fn disassemble(&self, src: &str) -> String {
+		let lines: Vec<&str> = src.lines().collect();
+		let mut entries: Vec<(usize, &str)> =
+			self.functions.iter().filter_map(|func| Some((func.entry?, func.name.as_str()))).collect();
+		entries.sort();
+
+		let mut s = String::new();
+		for (i, (entry, name)) in entries.iter().enumerate() {
+			let end = entries.get(i + 1).map_or(self.code.len(), |(next, _)| *next);
+			if i > 0 {
+				s.push('\n');
+			}
+			writeln!(s, "{}:", name).unwrap();
+			let mut line = 0;
+			for pc in *entry..end {
+				if let Some(span) = self.spans[pc].filter(|span| span.line != line && span.line > 0) {
+					line = span.line;
+					let text = lines.get(line - 1).map_or("", |text| text.trim());
+					writeln!(s, "{:>8} | {}", line, text).unwrap();
+				}
+				let op = self.code[pc];
+				match self.annotation(&op) {
+					Some(note) => writeln!(s, "{:>6}: {:<12} ; {}", pc, op, note).unwrap(),
+					None => writeln!(s, "{:>6}: {}", pc, op).unwrap(),
+				}
+			}
+		}
+		s
+	}
+}
@@ -1,6 +1,8 @@
 use std::fmt::Display;
 
-#[derive(Debug, PartialEq, Eq)]
+use super::token::Span;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum LexError {
 	InvalidChar(char),
 	UnexpectedEof,
@@ -33,9 +35,250 @@ impl Display for LexError {
 	}
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// 整数常量表达式求值的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConstError {
+	/// 不是常量表达式, 如包含变量. 作为数组长度时是变长数组
+	NotConstant,
+	Overflow,
+	DivisionByZero,
+}
+
+impl Display for ConstError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			ConstError::NotConstant => "expression is not an integer constant expression",
+			ConstError::Overflow => "integer overflow in constant expression",
+			ConstError::DivisionByZero => "division by zero in constant expression",
+		})
+	}
+}
+
+/// 解释器运行时的错误
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuntimeError {
+	DivisionByZero,
+	/// 打开溢出检查时, 有符号整数运算的结果超出类型的范围
+	SignedOverflow,
+	NullPointer,
+	/// 地址, 访问的字节数
+	OutOfBounds(usize, usize),
+	/// free的地址不是malloc返回的, 或者已经释放过
+	InvalidFree(usize),
+	/// 栈空间用完
+	StackOverflow,
+	/// 执行的步数达到上限
+	StepLimit(u64),
+	/// 调用深度达到上限
+	CallDepthLimit(usize),
+	/// 堆超过上限: 申请的字节数, 已经占用的字节数
+	HeapLimit(usize, usize),
+	/// 运行超时: 用掉的时间, 执行的步数
+	Timeout(std::time::Duration, u64),
+	/// 调用的值不是函数的地址
+	NotFunction(i64),
+	/// 参数个数不对: 期望, 实际
+	ArgumentCount(usize, usize),
+	/// 调用只有声明没有定义的函数
+	UndefinedFunction(String),
+	MissingMain,
+	/// 整数常量超出范围
+	InvalidConstant(String),
+	/// printf的格式串有错
+	Format(String),
+	Io(String),
+	/// 解释器不支持的语法, 或者没有通过检查的语法树
+	Unsupported(String),
+	/// 不是错误: exit(code) 从任意深度结束程序
+	Exit(i64),
+}
+
+impl RuntimeError {
+	/// 数据段之前的地址按空指针处理
+	pub fn invalid_address(addr: usize, size: usize) -> Self {
+		match addr < super::memory::FUNCTION_BASE {
+			true => RuntimeError::NullPointer,
+			false => RuntimeError::OutOfBounds(addr, size),
+		}
+	}
+}
+
+impl Display for RuntimeError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			RuntimeError::DivisionByZero => f.write_str("division by zero"),
+			RuntimeError::SignedOverflow => f.write_str("signed integer overflow"),
+			RuntimeError::NullPointer => f.write_str("null pointer dereference"),
+			RuntimeError::OutOfBounds(addr, size) => write!(f, "out-of-bounds access of {} bytes at {:#x}", size, addr),
+			RuntimeError::InvalidFree(addr) => write!(f, "free of invalid pointer {:#x}", addr),
+			RuntimeError::StackOverflow => f.write_str("stack overflow"),
+			RuntimeError::StepLimit(steps) => write!(f, "step limit exceeded after {} steps", steps),
+			RuntimeError::CallDepthLimit(depth) => write!(f, "call depth limit exceeded at depth {}", depth),
+			RuntimeError::HeapLimit(size, used) => {
+				write!(f, "heap limit exceeded: allocating {} bytes with {} bytes in use", size, used)
+			}
+			RuntimeError::Timeout(elapsed, steps) => {
+				write!(f, "time limit exceeded after {:.3}s and {} steps", elapsed.as_secs_f64(), steps)
+			}
+			RuntimeError::NotFunction(value) => write!(f, "called object {:#x} is not a function", value),
+			RuntimeError::ArgumentCount(expected, given) => {
+				write!(f, "expected {} arguments, have {}", expected, given)
+			}
+			RuntimeError::UndefinedFunction(name) => write!(f, "undefined reference to {}", name),
+			RuntimeError::MissingMain => f.write_str("main not found"),
+			RuntimeError::InvalidConstant(s) => write!(f, "integer constant is too large: {}", s),
+			RuntimeError::Format(s) => write!(f, "printf: {}", s),
+			RuntimeError::Io(s) => write!(f, "io error: {}", s),
+			RuntimeError::Unsupported(s) => write!(f, "not supported: {}", s),
+			RuntimeError::Exit(code) => write!(f, "exit({})", code),
+		}
+	}
+}
+
+impl std::error::Error for RuntimeError {}
+
+/// 运行时错误, 出错的表达式的位置, 以及出错时的调用栈
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trap {
+	pub error: RuntimeError,
+	pub span: Option<Span>,
+	/// 从出错的函数到main, 每一项是函数名和调用它的位置, main没有调用位置
+	pub backtrace: Vec<(String, Option<Span>)>,
+}
+
+impl Trap {
+	/// 合并调用栈中连续重复的项(递归), 每一项带重复的次数
+	pub fn collapsed_backtrace(&self) -> Vec<(&str, Option<Span>, usize)> {
+		let mut frames: Vec<(&str, Option<Span>, usize)> = vec![];
+		for (function, call_site) in self.backtrace.iter() {
+			match frames.last_mut() {
+				Some((f, c, n)) if f == function && c == call_site => *n += 1,
+				_ => frames.push((function, *call_site, 1)),
+			}
+		}
+		frames
+	}
+}
+
+impl From<RuntimeError> for Trap {
+	fn from(error: RuntimeError) -> Self {
+		Trap { error, span: None, backtrace: vec![] }
+	}
+}
+
+impl Display for Trap {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self.span {
+			Some(span) => write!(f, "{}: runtime error: {}", span, self.error)?,
+			None => write!(f, "runtime error: {}", self.error)?,
+		}
+		for (function, call_site, times) in self.collapsed_backtrace() {
+			match call_site {
+				Some(span) => write!(f, "\n    in {}, called at {}", function, span)?,
+				None => write!(f, "\n    in {}", function)?,
+			}
+			if times > 1 {
+				write!(f, " ({} times)", times)?;
+			}
+		}
+		Ok(())
+	}
+}
+
+impl std::error::Error for Trap {}
+
+/// 读取编译好的 .tbc 文件的错误
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ObjectError {
+	/// 文件开头不是 .tbc 的标记
+	BadMagic,
+	/// 文件的格式版本, 当前支持的版本
+	Version(u32, u32),
+	/// 文件在读完之前结束
+	Truncated,
+	UnknownOpcode(u8),
+	/// 元数据不能解析
+	Metadata(String),
+	/// 各部分之间不一致, 如源码行表和指令的个数不同
+	Malformed(String),
+}
+
+impl Display for ObjectError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ObjectError::BadMagic => f.write_str("not a bytecode file"),
+			ObjectError::Version(found, supported) => {
+				write!(f, "bytecode file version {} is not supported, expected {}", found, supported)
+			}
+			ObjectError::Truncated => f.write_str("bytecode file is truncated"),
+			ObjectError::UnknownOpcode(tag) => write!(f, "unknown opcode {:#04x}", tag),
+			ObjectError::Metadata(s) => write!(f, "invalid metadata: {}", s),
+			ObjectError::Malformed(s) => write!(f, "malformed bytecode file: {}", s),
+		}
+	}
+}
+
+impl std::error::Error for ObjectError {}
+
+/// 字节码没有通过校验, 第一个数是出错的指令的位置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyError {
+	/// 函数的入口不在代码中, 源码行表和指令的个数不同等整体的错误
+	Malformed(String),
+	/// 函数的第一条指令不是 ENT, 或者 ENT 出现在函数中间
+	MisplacedEnt(usize),
+	/// 函数的最后一条指令之后会接着执行下一个函数
+	FallsThrough(usize),
+	/// 跳转的目标不在同一个函数中
+	JumpOutOfFunction(usize, usize),
+	/// 从不同的路径到达同一条指令时操作数栈的深度不同
+	StackMismatch(usize, usize, usize),
+	/// 弹出的值比操作数栈中的多
+	StackUnderflow(usize),
+	/// 返回时操作数栈中还留有值
+	UnbalancedReturn(usize, usize),
+	/// 局部变量的偏移超出 ENT 分配的栈帧
+	FrameOutOfBounds(usize, usize),
+	/// 数据段中没有这个偏移
+	DataOutOfBounds(usize, usize),
+	/// 函数表中没有这个函数
+	UnknownFunction(usize, usize),
+	/// 读取的实参超出函数的参数个数
+	ArgumentOutOfRange(usize, usize),
+}
+
+impl Display for VerifyError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			VerifyError::Malformed(s) => write!(f, "invalid bytecode: {}", s),
+			VerifyError::MisplacedEnt(pc) => write!(f, "{}: ENT must be the first instruction of a function", pc),
+			VerifyError::FallsThrough(pc) => write!(f, "{}: control falls off the end of the function", pc),
+			VerifyError::JumpOutOfFunction(pc, target) => {
+				write!(f, "{}: jump target {} is outside the function", pc, target)
+			}
+			VerifyError::StackMismatch(pc, depth, other) => {
+				write!(f, "{}: operand stack depth is {} on one path and {} on another", pc, depth, other)
+			}
+			VerifyError::StackUnderflow(pc) => write!(f, "{}: operand stack underflow", pc),
+			VerifyError::UnbalancedReturn(pc, depth) => {
+				write!(f, "{}: returning with {} values on the stack", pc, depth)
+			}
+			VerifyError::FrameOutOfBounds(pc, offset) => {
+				write!(f, "{}: local offset {} is outside the frame", pc, offset)
+			}
+			VerifyError::DataOutOfBounds(pc, offset) => write!(f, "{}: data offset {} is out of bounds", pc, offset),
+			VerifyError::UnknownFunction(pc, index) => write!(f, "{}: no function {}", pc, index),
+			VerifyError::ArgumentOutOfRange(pc, n) => write!(f, "{}: argument {} is out of range", pc, n),
+		}
+	}
+}
+
+impl std::error::Error for VerifyError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ParseError {
 	LexError(LexError),
+	ConstError(ConstError),
 	Unexpected(String),
 	General(&'static str),
 	EndOfToken,
@@ -43,6 +286,30 @@ pub enum ParseError {
 	NoMatchFound,
 	NotType,
 	NotIdentifier,
+	/// 静态存储期对象的初始值不是常量表达式
+	NotConstant(String),
+	Redefinition(String),
+	/// 同一个标识符的多次声明类型不兼容
+	ConflictingTypes(String),
+	/// 要求读完全部输入时, 剩余token的范围
+	TrailingTokens(Span),
+	Undeclared(String),
+	/// 赋值, 取地址, ++ -- 的操作数不是(可修改的)左值
+	NotLvalue(String),
+	NotFunction,
+	NoMember(String),
+	/// 参数个数不对: 期望, 实际
+	ArgumentCount(usize, usize),
+	/// 操作数类型不符合运算符的要求
+	InvalidOperands(String),
+	/// 标识符在后面才声明
+	UsedBeforeDeclaration(String),
+	/// 内层作用域的声明隐藏了外层的同名标识符
+	Shadows(String),
+	/// C89的隐式函数声明
+	ImplicitDeclaration(String),
+	/// 不允许变长数组的地方: 文件作用域, 结构体成员, 初始化
+	VariablyModified(String),
 }
 
 impl Display for ParseError {
@@ -53,15 +320,111 @@ impl Display for ParseError {
 				s = format!("Lex Error: {}", e);
 				&s
 			}
+			ParseError::ConstError(e) => {
+				s = e.to_string();
+				&s
+			}
 			ParseError::Unexpected(s) => s.as_str(),
+			ParseError::Redefinition(name) => {
+				s = format!("redefinition of {}", name);
+				&s
+			}
+			ParseError::ConflictingTypes(name) => {
+				s = format!("conflicting types for {}", name);
+				&s
+			}
+			ParseError::NotConstant(name) => {
+				s = format!("initializer element is not constant: {}", name);
+				&s
+			}
+			ParseError::TrailingTokens(span) => {
+				s = format!("unexpected trailing tokens at {}", span);
+				&s
+			}
+			ParseError::Undeclared(name) => {
+				s = format!("use of undeclared identifier {}", name);
+				&s
+			}
+			ParseError::NotLvalue(op) => {
+				s = format!("lvalue required as operand of {}", op);
+				&s
+			}
+			ParseError::NotFunction => "called object is not a function or function pointer",
+			ParseError::NoMember(name) => {
+				s = format!("no member named {}", name);
+				&s
+			}
+			ParseError::ArgumentCount(expected, given) => {
+				s = format!("expected {} arguments, have {}", expected, given);
+				&s
+			}
+			ParseError::InvalidOperands(op) => {
+				s = format!("invalid operands to {}", op);
+				&s
+			}
+			ParseError::UsedBeforeDeclaration(name) => {
+				s = format!("{} used before its declaration", name);
+				&s
+			}
+			ParseError::Shadows(name) => {
+				s = format!("declaration of {} shadows a previous declaration", name);
+				&s
+			}
+			ParseError::ImplicitDeclaration(name) => {
+				s = format!("implicit declaration of function {}", name);
+				&s
+			}
+			ParseError::VariablyModified(name) => {
+				s = format!("variably modified type not allowed: {}", name);
+				&s
+			}
 			ParseError::General(s) => s,
-			ParseError::EndOfToken => "EndOfToken",
-			ParseError::NoMoreExpr => "NoMoreExpr",
-			ParseError::NotIdentifier => "NotIdentifier",
-			ParseError::NotType => "NotType",
-			ParseError::NoMatchFound => "NoMatchFound",
+			ParseError::EndOfToken => "unexpected end of input",
+			ParseError::NoMoreExpr => "expected expression",
+			ParseError::NotIdentifier => "expected identifier",
+			ParseError::NotType => "expected type specifier",
+			ParseError::NoMatchFound => "unmatched (",
 		})
 	}
 }
 
 impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+	Error,
+	/// 不影响编译结果
+	Warning,
+}
+
+/// 带位置的错误信息
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+	pub span: Span,
+	pub error: ParseError,
+	pub severity: Severity,
+}
+
+impl Diagnostic {
+	pub fn error(span: Span, error: ParseError) -> Self {
+		Diagnostic { span, error, severity: Severity::Error }
+	}
+
+	pub fn warning(span: Span, error: ParseError) -> Self {
+		Diagnostic { span, error, severity: Severity::Warning }
+	}
+
+	pub fn is_error(&self) -> bool {
+		self.severity == Severity::Error
+	}
+}
+
+impl Display for Diagnostic {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let severity = match self.severity {
+			Severity::Error => "error",
+			Severity::Warning => "warning",
+		};
+		write!(f, "{}: {}: {}", self.span, severity, self.error)
+	}
+}
@@ -1,70 +1,933 @@
+//! 遍历类型检查之后的语法树求值. 对象保存在按字节寻址的内存中: 全局变量在数据段, 局部变量在栈上.
+//! 表达式的值是i64, 数组, 结构体和函数类型的表达式的值是它们的地址
+
 use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
 
 use super::{
-	errors::ParseError,
-	token::Const,
-	types::{Expr, Function, Statement, Variable},
+	builtins::{self, Builtin, FormatError},
+	codegen::{self, Program},
+	data::{DataSegment, RelocTarget, Relocation},
+	errors::{ParseError, RuntimeError, Trap},
+	ir,
+	memory::{address, Memory, DATA_BASE, FUNCTION_BASE},
+	token::{Const, Punct, Span},
+	types::*,
 };
 
 pub struct Env {
-	pub global_variables: HashMap<String, Variable>,
+	pub global_variables: Vec<Variable>,
+	pub(super) memory: Memory,
+	/// 全局变量的地址
+	globals: HashMap<SymbolId, usize>,
+	/// 函数体中的字符串字面量在数据段中的地址
+	strings: HashMap<String, usize>,
+	/// 调用栈, 最后一个是当前函数的栈帧
+	frames: Vec<Frame>,
+	/// printf的输出, None时写到标准输出
+	output: Option<Vec<u8>>,
+	/// getchar的输入, None时从标准输入读
+	input: Option<std::io::Cursor<Vec<u8>>>,
+	/// fopen打开的文件, FILE * 是下标加1
+	files: Vec<Option<std::fs::File>>,
+	/// 有符号整数运算溢出时报错, 而不是回绕
+	pub(super) trap_overflow: bool,
+	pub(super) limits: Limits,
+	/// 已经执行的步数: 每个语句和表达式算一步
+	steps: u64,
+	/// main开始执行的时间
+	started: Option<Instant>,
+}
+
+/// 运行不可信的程序时的资源限制, None表示不限制
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+	/// 执行的语句和求值的表达式的个数
+	pub max_steps: Option<u64>,
+	pub max_call_depth: usize,
+	/// 堆占用的字节数
+	pub max_heap: Option<usize>,
+	/// 从main开始执行算起的时间
+	pub timeout: Option<Duration>,
+}
+
+impl Default for Limits {
+	fn default() -> Self {
+		Limits { max_steps: None, max_call_depth: MAX_CALL_DEPTH, max_heap: None, timeout: None }
+	}
+}
+
+/// 每执行这么多步检查一次是否超时
+const CLOCK_INTERVAL: u64 = 1024;
+
+/// 一次函数调用的参数和局部变量的地址
+#[derive(Debug, Default)]
+pub struct Frame {
+	function: String,
+	/// 调用表达式的位置, main没有
+	call_site: Option<Span>,
+	locals: HashMap<SymbolId, usize>,
+}
+
+impl Frame {
+	fn new(function: &str, call_site: Option<Span>) -> Self {
+		Frame { function: function.to_string(), call_site, locals: HashMap::new() }
+	}
+}
+
+/// 默认的调用深度上限
+pub const MAX_CALL_DEPTH: usize = 10_000;
+
+impl Env {
+	pub fn new(global_variables: Vec<Variable>) -> Self {
+		Env {
+			global_variables,
+			memory: Memory::new(vec![]),
+			globals: HashMap::new(),
+			strings: HashMap::new(),
+			frames: vec![],
+			output: None,
+			input: None,
+			files: vec![],
+			trap_overflow: false,
+			limits: Limits::default(),
+			steps: 0,
+			started: None,
+		}
+	}
+
+	/// 设置步数, 调用深度, 堆和时间的限制, 超过时报错
+	pub fn set_limits(&mut self, limits: Limits) {
+		self.limits = limits;
+	}
+
+	/// 把printf的输出保存起来, 而不是写到标准输出
+	pub fn capture_output(&mut self) {
+		self.output = Some(vec![]);
+	}
+
+	pub fn take_output(&mut self) -> Vec<u8> {
+		self.output.as_mut().map(std::mem::take).unwrap_or_default()
+	}
+
+	/// 有符号整数运算溢出时报错, 类似gcc的 -ftrapv
+	pub fn trap_signed_overflow(&mut self) {
+		self.trap_overflow = true;
+	}
+
+	/// 用给定的字节代替标准输入
+	pub fn set_input(&mut self, input: Vec<u8>) {
+		self.input = Some(std::io::Cursor::new(input));
+	}
+
+	/// 读一个字节, 输入结束时返回None
+	fn read_byte(&mut self) -> Result<Option<u8>, RuntimeError> {
+		let mut byte = [0];
+		let n = match &mut self.input {
+			Some(input) => input.read(&mut byte),
+			None => std::io::stdin().read(&mut byte),
+		};
+		match n.map_err(|e| RuntimeError::Io(e.to_string()))? {
+			0 => Ok(None),
+			_ => Ok(Some(byte[0])),
+		}
+	}
+
+	pub(super) fn write(&mut self, bytes: &[u8]) -> Result<(), RuntimeError> {
+		match &mut self.output {
+			Some(output) => output.extend_from_slice(bytes),
+			None => {
+				let mut stdout = std::io::stdout();
+				stdout.write_all(bytes).and_then(|_| stdout.flush()).map_err(|e| RuntimeError::Io(e.to_string()))?;
+			}
+		}
+		Ok(())
+	}
+
+	/// 装入初始的内存, 开始计步和计时
+	pub(super) fn start(&mut self, memory: Memory) {
+		self.memory = memory;
+		self.steps = 0;
+		self.started = Some(Instant::now());
+	}
+
+	/// 命令行参数和argv数组追加到数据段, 返回argv的地址. argv[argc] 是空指针
+	fn append_argv(&mut self, args: &[String]) -> usize {
+		let argv: Vec<_> = args.iter().map(|arg| self.memory.append_data(&[arg.as_bytes(), &[0]].concat())).collect();
+		let bytes: Vec<_> = argv.iter().chain([&0]).flat_map(|addr| (*addr as u64).to_le_bytes()).collect();
+		self.memory.append_data(&bytes)
+	}
+
+	/// main的实参 argc 和 argv, 按main的参数个数截断
+	pub(super) fn enter_main(&mut self, args: &[String], params: usize) -> Vec<i64> {
+		let argv = self.append_argv(args);
+		let mut main_args = vec![args.len() as i64, argv as i64];
+		main_args.truncate(params);
+		main_args
+	}
+
+	/// 调用之前的检查: 实参个数和形参相同(可变参数的函数可以更多), 已有depth层调用时没有超过调用深度的上限
+	pub(super) fn check_call(
+		&self,
+		params: usize,
+		is_variadic: bool,
+		argc: usize,
+		depth: usize,
+	) -> Result<(), RuntimeError> {
+		if argc < params || (argc > params && !is_variadic) {
+			return Err(RuntimeError::ArgumentCount(params, argc));
+		}
+		if depth >= self.limits.max_call_depth {
+			return Err(RuntimeError::CallDepthLimit(depth));
+		}
+		Ok(())
+	}
+
+	/// 函数返回时释放sp之上的栈空间. 返回的结构体在被调用者的栈帧中,
+	/// 释放之前复制到调用者的栈上, 返回值改成复制之后的地址
+	pub(super) fn return_value(
+		&mut self,
+		value: i64,
+		returns_struct: Option<(usize, usize)>,
+		sp: usize,
+	) -> Result<i64, RuntimeError> {
+		let Some((size, align)) = returns_struct else {
+			self.memory.pop_to(sp);
+			return Ok(value);
+		};
+		let bytes = self.memory.read(address(value), size)?.to_vec();
+		self.memory.pop_to(sp);
+		let addr = self.memory.push(size, align)?;
+		self.memory.write(addr, &bytes)?;
+		Ok(addr as i64)
+	}
+
+	/// 计一步, 检查步数和时间的限制
+	pub(super) fn tick(&mut self) -> Result<(), RuntimeError> {
+		if self.limits.max_steps.is_some_and(|max| self.steps >= max) {
+			return Err(RuntimeError::StepLimit(self.steps));
+		}
+		self.steps += 1;
+		if let (Some(timeout), Some(started), 0) = (self.limits.timeout, self.started, self.steps % CLOCK_INTERVAL) {
+			let elapsed = started.elapsed();
+			if elapsed > timeout {
+				return Err(RuntimeError::Timeout(elapsed, self.steps));
+			}
+		}
+		Ok(())
+	}
+
+	/// 内置函数, 参数个数已经在类型检查时检查过
+	pub(super) fn call_builtin(&mut self, builtin: Builtin, args: &[i64]) -> Result<i64, RuntimeError> {
+		let arg = |i: usize| args.get(i).copied().ok_or(RuntimeError::ArgumentCount(i + 1, args.len()));
+		match builtin {
+			Builtin::Printf => {
+				let fmt = self.memory.read_c_string(address(arg(0)?))?;
+				let memory = &self.memory;
+				let out = builtins::format(&fmt, &args[1..], |addr| memory.read_c_string(address(addr)));
+				let out = out.map_err(|e| match e {
+					FormatError::TooFewArguments => RuntimeError::Format("too few arguments".to_string()),
+					FormatError::InvalidConversion(c) => {
+						RuntimeError::Format(format!("invalid conversion %{}", c.unwrap_or_default()))
+					}
+					FormatError::WidthTooLarge => RuntimeError::Format("width or precision too large".to_string()),
+					FormatError::String(e) => e,
+				})?;
+				self.write(&out)?;
+				Ok(out.len() as i64)
+			}
+			Builtin::Malloc => match usize::try_from(arg(0)?) {
+				Ok(size) => {
+					let used = self.memory.heap_size();
+					if self.limits.max_heap.is_some_and(|max| used.saturating_add(size) > max) {
+						return Err(RuntimeError::HeapLimit(size, used));
+					}
+					Ok(self.memory.malloc(size) as i64)
+				}
+				Err(_) => Ok(0),
+			},
+			Builtin::Free => match arg(0)? {
+				0 => Ok(0),
+				ptr => self.memory.free(address(ptr)).map(|_| 0),
+			},
+			Builtin::Memset => {
+				let (ptr, size) = (arg(0)?, arg(2)?.max(0) as usize);
+				self.memory.fill(address(ptr), size, arg(1)? as u8)?;
+				Ok(ptr)
+			}
+			Builtin::Memcmp => {
+				let size = arg(2)?.max(0) as usize;
+				let a = self.memory.read(address(arg(0)?), size)?;
+				let b = self.memory.read(address(arg(1)?), size)?;
+				Ok(a.iter().zip(b).find(|(x, y)| x != y).map_or(0, |(x, y)| *x as i64 - *y as i64))
+			}
+			Builtin::Getchar => Ok(self.read_byte()?.map_or(-1, |b| b as i64)),
+			Builtin::Exit => Err(RuntimeError::Exit(arg(0)? as i32 as i64)),
+			// 只支持读文件
+			Builtin::Fopen => {
+				let path = self.memory.read_c_string(address(arg(0)?))?;
+				let mode = self.memory.read_c_string(address(arg(1)?))?;
+				if !mode.starts_with(b"r") {
+					return Ok(0);
+				}
+				match std::fs::File::open(String::from_utf8_lossy(&path).as_ref()) {
+					Ok(file) => {
+						self.files.push(Some(file));
+						Ok(self.files.len() as i64)
+					}
+					Err(_) => Ok(0),
+				}
+			}
+			Builtin::Fread => {
+				let (ptr, size, count) = (arg(0)?, arg(1)?.max(0) as usize, arg(2)?.max(0) as usize);
+				let file = usize::try_from(arg(3)? - 1).ok().and_then(|i| self.files.get_mut(i)?.as_mut());
+				let file = file.ok_or(RuntimeError::Unsupported("fread of an invalid FILE pointer".to_string()))?;
+				let mut buf = vec![0; size * count];
+				let mut len = 0;
+				while len < buf.len() {
+					match file.read(&mut buf[len..]) {
+						Ok(0) | Err(_) => break,
+						Ok(n) => len += n,
+					}
+				}
+				self.memory.write(address(ptr), &buf[..len])?;
+				Ok(len.checked_div(size).unwrap_or(0) as i64)
+			}
+			Builtin::Fclose => {
+				let file = usize::try_from(arg(0)? - 1).ok().and_then(|i| self.files.get_mut(i));
+				match file.and_then(Option::take) {
+					Some(_) => Ok(0),
+					None => Ok(-1),
+				}
+			}
+		}
+	}
+
+	fn frame(&self) -> Option<&Frame> {
+		self.frames.last()
+	}
+
+	/// 从当前函数到main的调用栈
+	fn backtrace(&self) -> Vec<(String, Option<Span>)> {
+		self.frames.iter().rev().map(|frame| (frame.function.clone(), frame.call_site)).collect()
+	}
+}
+
+/// 语句执行之后的去向
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Flow {
+	Next,
+	Break,
+	Continue,
+	Return(i64),
+}
+
+/// switch跳转的目标
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Label {
+	Case(i64),
+	Default,
 }
 
 pub struct VM {
-	functions: HashMap<String, Function>,
+	functions: Vec<Function>,
+	symbols: Vec<Symbol>,
+}
+
+fn unsupported(what: &str) -> RuntimeError {
+	RuntimeError::Unsupported(what.to_string())
+}
+
+/// 值就是地址的类型
+fn is_object_address(ctype: &Type) -> bool {
+	matches!(ctype, Type::Array(_) | Type::Struct(_) | Type::Func(_))
 }
 
 impl VM {
-	pub fn new(functions: HashMap<String, Function>) -> Self {
-		VM { functions }
+	pub fn new(functions: Vec<Function>, symbols: Vec<Symbol>) -> Self {
+		VM { functions, symbols }
 	}
 
-	pub fn eval_expr(&self, expr: &Expr, env: &mut Env) -> i64 {
-		match expr {
-			Expr::Const(Const::Empty) => 0,
-			Expr::Const(Const::Integer(ints)) => ints.parse().unwrap_or(0),
-			Expr::Const(Const::Character(c)) => *c as i64,
-			_ => 0,
+	/// 表达式的类型. 变长数组长度中的隐藏变量没有经过类型检查, 使用符号的类型
+	fn type_of<'a>(&'a self, expr: &'a Expr) -> Result<&'a Type, RuntimeError> {
+		match (&expr.ctype, &expr.kind) {
+			(Some(ctype), _) => Ok(ctype),
+			(None, ExprKind::Id(_, Some(id))) => Ok(&self.symbols[id.0].ctype),
+			_ => Err(unsupported("expression that is not type checked")),
 		}
 	}
 
-	pub fn eval_stmt(&self, stmt: &Statement, env: &mut Env) {
-		match stmt {
-			Statement::Empty => (),
-			Statement::CompoundStmt(stmts) => {
-				for stmt in stmts {
-					self.eval_stmt(stmt, env)
+	/// 读出地址处的值, 数组和结构体的值是地址本身
+	fn load(&self, addr: usize, ctype: &Type, env: &Env) -> Result<i64, Trap> {
+		match is_object_address(ctype) {
+			true => Ok(addr as i64),
+			false => Ok(env.memory.load(addr, ctype)?),
+		}
+	}
+
+	/// 写入地址, 结构体赋值时value是源对象的地址
+	fn store(&self, addr: usize, ctype: &Type, value: i64, env: &mut Env) -> Result<(), Trap> {
+		match ctype {
+			Type::Struct(_) => env.memory.copy(addr, address(value), ctype.size()),
+			_ => env.memory.store(addr, ctype, value),
+		}
+		.map_err(Trap::from)
+	}
+
+	/// 左值表达式指代的对象的地址
+	fn address(&self, expr: &Expr, env: &mut Env) -> Result<usize, Trap> {
+		match &expr.kind {
+			ExprKind::Id(name, Some(id)) => match self.symbols[id.0].kind {
+				SymbolKind::Global => env.globals.get(id).copied().ok_or_else(|| unsupported(name).into()),
+				SymbolKind::Local | SymbolKind::Param => env
+					.frame()
+					.and_then(|frame| frame.locals.get(id))
+					.copied()
+					.ok_or_else(|| unsupported(&format!("{} used before its declaration is executed", name)).into()),
+				SymbolKind::Function | SymbolKind::Builtin(_) => Ok(FUNCTION_BASE + id.0),
+				SymbolKind::EnumConst(_) => Err(unsupported(&format!("assignment to enumerator {}", name)).into()),
+			},
+			ExprKind::Id(name, None) => Err(unsupported(&format!("undeclared {}", name)).into()),
+			ExprKind::UnaryOp(UnaryOp { op: Punct::Mul, expr: pointer }) => Ok(address(self.eval_expr(pointer, env)?)),
+			ExprKind::MemberAccess(base, name) | ExprKind::MemberAccessP(base, name) => {
+				// 结构体的值和指针的值都是结构体的地址
+				let base_addr = address(self.eval_expr(base, env)?);
+				let st = match (&expr.kind, self.type_of(base)?) {
+					(ExprKind::MemberAccess(..), ctype) => ctype,
+					(_, ctype) => ctype.pointee().unwrap_or(ctype),
+				};
+				let member = match st {
+					Type::Struct(st) => st.member(name),
+					_ => None,
+				};
+				let member = member.ok_or_else(|| unsupported(&format!("no member named {}", name)))?;
+				Ok(base_addr.wrapping_add(member.offset))
+			}
+			ExprKind::StringLiteral(str) => Ok(match env.strings.get(str) {
+				Some(addr) => *addr,
+				None => {
+					let addr = env.memory.append_data(&[str.as_bytes(), &[0]].concat());
+					env.strings.insert(str.clone(), addr);
+					addr
 				}
+			}),
+			// 块作用域中的复合字面量在栈上, 离开块时释放
+			ExprKind::CompoundLiteral(ctype, init) => {
+				let addr = env.memory.push(ctype.size(), ctype.align())?;
+				self.init(addr, ctype, init, env)?;
+				Ok(addr)
+			}
+			_ => Err(unsupported(&format!("{} as lvalue", expr)).into()),
+		}
+	}
+
+	/// 运行时的类型大小, 变长数组的长度保存在隐藏的局部变量中
+	fn size_of(&self, ctype: &Type, env: &mut Env) -> Result<i64, Trap> {
+		match ctype {
+			Type::Array(Array { base_type, size_expr: Some(len), .. }) if ctype.is_vla() => {
+				let length = match len.fold_const() {
+					Some(n) => n,
+					None => self.eval_expr(len, env)?,
+				};
+				Ok(length * self.size_of(base_type, env)?)
 			}
+			ctype => Ok(ctype.size() as i64),
+		}
+	}
+
+	/// ++ -- 的步长, 指针按所指类型的大小
+	fn step(&self, ctype: &Type, env: &mut Env) -> Result<i64, Trap> {
+		match ctype.pointee() {
+			Some(pointee) => Ok(self.size_of(pointee, env)?.max(1)),
+			None => Ok(1),
+		}
+	}
+
+	/// 自增自减, 返回新值和旧值. 比int小的整数按int运算, 写入时转换
+	fn increment(&self, operand: &Expr, op: Punct, env: &mut Env) -> Result<(i64, i64), Trap> {
+		let ctype = self.type_of(operand)?;
+		let addr = self.address(operand, env)?;
+		let step = self.step(ctype, env)?;
+		let old = self.load(addr, ctype, env)?;
+		let op = if op == Punct::Inc { Punct::Add } else { Punct::Sub };
+		let new = binary(op, &ctype.promoted(), old, step, env.trap_overflow)?;
+		self.store(addr, ctype, new, env)?;
+		Ok((self.load(addr, ctype, env)?, old))
+	}
+
+	/// 求值出错时记下最内层出错的表达式的位置和这时的调用栈
+	fn eval_expr(&self, expr: &Expr, env: &mut Env) -> Result<i64, Trap> {
+		let value = env.tick().map_err(Trap::from).and_then(|_| self.eval_expr_at(expr, env));
+		value.map_err(|trap| match trap.span {
+			// 省略的表达式(如 for(;;) 的条件)没有位置
+			None => Trap { span: (expr.span.line > 0).then_some(expr.span), backtrace: env.backtrace(), ..trap },
+			Some(_) => trap,
+		})
+	}
+
+	fn eval_expr_at(&self, expr: &Expr, env: &mut Env) -> Result<i64, Trap> {
+		Ok(match &expr.kind {
+			ExprKind::Const(Const::Empty) => 0,
+			ExprKind::Const(Const::Integer(ints)) => {
+				Const::integer(ints).ok_or_else(|| RuntimeError::InvalidConstant(ints.clone()))?.0
+			}
+			ExprKind::Const(Const::Character(c)) => *c as i64,
+			// 对象的值从内存中读出
+			ExprKind::Id(..)
+			| ExprKind::UnaryOp(UnaryOp { op: Punct::Mul, .. })
+			| ExprKind::MemberAccess(..)
+			| ExprKind::MemberAccessP(..)
+			| ExprKind::StringLiteral(_)
+			| ExprKind::CompoundLiteral(..) => {
+				let addr = self.address(expr, env)?;
+				self.load(addr, self.type_of(expr)?, env)?
+			}
+			ExprKind::SizeOfType(ctype) => self.size_of(ctype, env)?,
+			ExprKind::SizeOfExpr(operand) => match &operand.ctype {
+				Some(ctype) => self.size_of(ctype, env)?,
+				None => expr.fold_const().ok_or_else(|| unsupported("sizeof"))?,
+			},
+			ExprKind::Cast(ctype, operand) => ctype.convert(self.eval_expr(operand, env)?),
+			ExprKind::UnaryOp(UnaryOp { op, expr: operand }) => match op {
+				Punct::Add => self.eval_expr(operand, env)?,
+				Punct::Sub => {
+					let value = self.eval_expr(operand, env)?;
+					binary(Punct::Sub, self.type_of(operand)?, 0, value, env.trap_overflow)?
+				}
+				Punct::Tilde => self.type_of(operand)?.convert(!self.eval_expr(operand, env)?),
+				Punct::Not => (self.eval_expr(operand, env)? == 0) as i64,
+				Punct::And => self.address(operand, env)? as i64,
+				// ++i --i
+				Punct::Inc | Punct::Dec => self.increment(operand, *op, env)?.0,
+				_ => return Err(unsupported(&format!("operator {}", op)).into()),
+			},
+			ExprKind::Postfix(PostfixOP { op, expr: operand }) => self.increment(operand, *op, env)?.1,
+			ExprKind::BinOp(BinOp { left, op: Punct::Lan, right }) => {
+				(self.eval_expr(left, env)? != 0 && self.eval_expr(right, env)? != 0) as i64
+			}
+			ExprKind::BinOp(BinOp { left, op: Punct::Lor, right }) => {
+				(self.eval_expr(left, env)? != 0 || self.eval_expr(right, env)? != 0) as i64
+			}
+			// 类型检查已经把两个操作数转换为共同类型, 移位和指针运算时左操作数的类型是结果的类型
+			ExprKind::BinOp(BinOp { left, op, right }) => {
+				let l = self.eval_expr(left, env)?;
+				let r = self.eval_expr(right, env)?;
+				binary(*op, self.type_of(left)?, l, r, env.trap_overflow)?
+			}
+			ExprKind::AssignExpr(AssignExpr { left, assign, right }) => {
+				let ctype = self.type_of(left)?;
+				let addr = self.address(left, env)?;
+				let r = self.eval_expr(right, env)?;
+				let value = match assign {
+					Punct::Assign => r,
+					op => {
+						// 按寻常算术转换之后的类型运算, 写入时转换为左边的类型
+						let op = compound_op(*op);
+						let op_type = match (ctype.is_integer(), op) {
+							(true, Punct::Shl | Punct::Shr) => ctype.promoted(),
+							(true, _) => ctype.common(self.type_of(right)?),
+							(false, _) => ctype.clone(),
+						};
+						let l = op_type.convert(self.load(addr, ctype, env)?);
+						binary(op, &op_type, l, op_type.convert(r), env.trap_overflow)?
+					}
+				};
+				self.store(addr, ctype, value, env)?;
+				// 赋值表达式的值是写入之后左边的值
+				self.load(addr, ctype, env)?
+			}
+			ExprKind::CondExpr(CondExpr { cond, left, right }) => match self.eval_expr(cond, env)? {
+				0 => self.eval_expr(right, env)?,
+				_ => self.eval_expr(left, env)?,
+			},
+			ExprKind::CommaExpr(CommaExpr { left, right }) => {
+				self.eval_expr(left, env)?;
+				self.eval_expr(right, env)?
+			}
+			ExprKind::FunctionCall(callee, args) => {
+				let callee = self.eval_expr(callee, env)?;
+				let id = address(callee).wrapping_sub(FUNCTION_BASE);
+				let symbol = match self.symbols.get(id) {
+					Some(symbol @ Symbol { kind: SymbolKind::Function | SymbolKind::Builtin(_), .. }) => symbol,
+					_ => return Err(RuntimeError::NotFunction(callee).into()),
+				};
+				let mut values = Vec::with_capacity(args.len());
+				for arg in args {
+					values.push(self.eval_expr(arg, env)?);
+				}
+				if let SymbolKind::Builtin(builtin) = symbol.kind {
+					return Ok(env.call_builtin(builtin, &values)?);
+				}
+				match self.functions.iter().find(|func| func.symbol == Some(SymbolId(id))) {
+					Some(func) if func.is_definition => self.call(func, &values, expr.span, env)?,
+					_ => return Err(RuntimeError::UndefinedFunction(symbol.name.clone()).into()),
+				}
+			}
+			ExprKind::Error => return Err(unsupported("expression with errors").into()),
+		})
+	}
+
+	fn eval_stmt(&self, stmt: &Statement, env: &mut Env) -> Result<Flow, Trap> {
+		// 语句没有位置, 只记下调用栈
+		env.tick().map_err(|error| Trap { backtrace: env.backtrace(), ..error.into() })?;
+		match stmt {
+			Statement::Empty => (),
+			Statement::CompoundStmt(stmts) => return self.eval_block(stmts, env),
 			Statement::ExprStmt(expr) => {
-				self.eval_expr(expr, env);
+				self.eval_expr(expr, env)?;
 			}
 			Statement::IfStmt(cond, then, maybe_else) => {
-				let c = self.eval_expr(cond, env);
+				let c = self.eval_expr(cond, env)?;
 				if c != 0 {
-					self.eval_stmt(then, env)
+					return self.eval_stmt(then, env);
 				} else {
 					match maybe_else {
 						None => (),
-						Some(es) => self.eval_stmt(es, env),
+						Some(es) => return self.eval_stmt(es, env),
+					}
+				}
+			}
+			Statement::ReturnStmt(Some(expr), _) => return Ok(Flow::Return(self.eval_expr(expr, env)?)),
+			// void函数的 return; 和执行到函数末尾一样返回0
+			Statement::ReturnStmt(None, _) => return Ok(Flow::Return(0)),
+			Statement::DeclStmt(vars) => {
+				for var in vars.iter().filter(|var| !var.is_enum_constant) {
+					self.declare(var, env)?;
+				}
+			}
+			Statement::ForStmt(init, cond, expr3, body) => {
+				if let Some(init) = init {
+					self.eval_expr(init, env)?;
+				}
+				while self.eval_expr(cond, env)? != 0 {
+					match self.eval_stmt(body, env)? {
+						Flow::Break => break,
+						flow @ Flow::Return(_) => return Ok(flow),
+						Flow::Next | Flow::Continue => (),
+					}
+					if let Some(expr3) = expr3 {
+						self.eval_expr(expr3, env)?;
+					}
+				}
+			}
+			Statement::SwitchStmt(cond, body) => {
+				let value = self.eval_expr(cond, env)?;
+				let label = match contains_label(body, Label::Case(value)) {
+					true => Label::Case(value),
+					false => Label::Default,
+				};
+				let flow = match contains_label(body, label) {
+					true => self.eval_from(body, label, env)?,
+					false => Flow::Next,
+				};
+				return Ok(if flow == Flow::Break { Flow::Next } else { flow });
+			}
+			Statement::BreakStmt => return Ok(Flow::Break),
+			Statement::ContinueStmt => return Ok(Flow::Continue),
+			Statement::CaseStmt(_, stmt) | Statement::DefaultStmt(stmt) => return self.eval_stmt(stmt, env),
+			Statement::Error => return Err(unsupported("statement with errors").into()),
+		}
+		Ok(Flow::Next)
+	}
+
+	/// 块中的局部变量在离开块时释放
+	fn eval_block(&self, stmts: &[Statement], env: &mut Env) -> Result<Flow, Trap> {
+		let sp = env.memory.stack_pointer();
+		let flow = self.eval_stmts(stmts, env);
+		env.memory.pop_to(sp);
+		flow
+	}
+
+	fn eval_stmts(&self, stmts: &[Statement], env: &mut Env) -> Result<Flow, Trap> {
+		for stmt in stmts {
+			match self.eval_stmt(stmt, env)? {
+				Flow::Next => (),
+				flow => return Ok(flow),
+			}
+		}
+		Ok(Flow::Next)
+	}
+
+	/// 从switch体中的标签处开始执行, 跳过标签之前的语句
+	fn eval_from(&self, stmt: &Statement, label: Label, env: &mut Env) -> Result<Flow, Trap> {
+		match stmt {
+			Statement::CaseStmt(v, stmt) if label == Label::Case(*v) => self.eval_stmt(stmt, env),
+			Statement::DefaultStmt(stmt) if label == Label::Default => self.eval_stmt(stmt, env),
+			// case 1: case 2: ...
+			Statement::CaseStmt(_, stmt) | Statement::DefaultStmt(stmt) => self.eval_from(stmt, label, env),
+			Statement::CompoundStmt(stmts) => {
+				let sp = env.memory.stack_pointer();
+				let start = stmts.iter().position(|stmt| contains_label(stmt, label)).unwrap_or(stmts.len());
+				let flow = match self.eval_from(&stmts[start], label, env) {
+					Ok(Flow::Next) => self.eval_stmts(&stmts[start + 1..], env),
+					flow => flow,
+				};
+				env.memory.pop_to(sp);
+				flow
+			}
+			Statement::IfStmt(_, then, _) if contains_label(then, label) => self.eval_from(then, label, env),
+			Statement::IfStmt(_, _, Some(else_stmt)) => self.eval_from(else_stmt, label, env),
+			_ => Err(unsupported("jumping into this statement").into()),
+		}
+	}
+
+	/// 在栈上分配局部变量并初始化, 变长数组的大小在这时确定
+	fn declare(&self, var: &Variable, env: &mut Env) -> Result<(), Trap> {
+		let size = self.size_of(&var.ctype, env)?;
+		let addr = env.memory.push(size as usize, var.ctype.align())?;
+		if let (Some(id), Some(frame)) = (var.symbol, env.frames.last_mut()) {
+			frame.locals.insert(id, addr);
+		}
+		match &var.init_value {
+			Some(init) => self.init(addr, &var.ctype, init, env),
+			None => Ok(()),
+		}
+	}
+
+	/// 按初始化器写入对象, 没有初始化的子对象保持为0
+	fn init(&self, addr: usize, ctype: &Type, init: &Initializer, env: &mut Env) -> Result<(), Trap> {
+		match (ctype, init) {
+			(Type::Array(Array { base_type, .. }), Initializer::List(list)) => {
+				for (i, elem) in list.iter().enumerate() {
+					if let Some(elem) = elem {
+						self.init(addr + i * base_type.size(), base_type, elem, env)?;
+					}
+				}
+				Ok(())
+			}
+			(Type::Struct(st), Initializer::List(list)) => {
+				for (member, elem) in st.members().iter().zip(list.iter()) {
+					if let Some(elem) = elem {
+						self.init(addr + member.offset, &member.ctype, elem, env)?;
 					}
 				}
+				Ok(())
 			}
-			Statement::ReturnStmt(expr) => {
-				let _r = self.eval_expr(expr, env);
+			(_, Initializer::List(list)) => match list.first() {
+				Some(Some(elem)) => self.init(addr, ctype, elem, env),
+				_ => Ok(()),
+			},
+			(_, Initializer::Expr(expr)) => {
+				let value = self.eval_expr(expr, env)?;
+				self.store(addr, ctype, value, env)
 			}
-			Statement::ForStmt(m_init, cond, expr3, body) => {}
 		}
 	}
 
-	pub fn eval_func(&self, func: &Function, env: &mut Env) {
-		self.eval_stmt(&func.stmts, env)
+	/// 在新的栈帧中调用函数, 按位置绑定参数
+	fn call(&self, func: &Function, args: &[i64], call_site: Span, env: &mut Env) -> Result<i64, Trap> {
+		env.check_call(func.ctype.param_list.len(), func.ctype.is_variadic, args.len(), env.frames.len())?;
+		let sp = env.memory.stack_pointer();
+		env.frames.push(Frame::new(&func.name, Some(call_site)));
+		let result = self.bind_params(func, args, env).and_then(|_| self.run(func, env));
+		env.frames.pop();
+		match (result, func.ctype.return_type.as_ref()) {
+			(Ok(value), ctype @ Type::Struct(_)) => {
+				Ok(env.return_value(value, Some((ctype.size(), ctype.align())), sp)?)
+			}
+			(result, _) => {
+				env.memory.pop_to(sp);
+				result
+			}
+		}
 	}
 
-	pub fn eval(&self, env: &mut Env) -> Result<(), ParseError> {
-		match self.functions.get("main") {
-			Some(main) => Ok(self.eval_func(main, env)),
-			None => Err(ParseError::General("main not found")),
+	fn bind_params(&self, func: &Function, args: &[i64], env: &mut Env) -> Result<(), Trap> {
+		for ((param, id), value) in func.ctype.param_list.iter().zip(func.locals.iter()).zip(args.iter()) {
+			let addr = env.memory.push(param.ctype.size(), param.ctype.align())?;
+			if let Some(frame) = env.frames.last_mut() {
+				frame.locals.insert(*id, addr);
+			}
+			self.store(addr, &param.ctype, *value, env)?;
 		}
+		Ok(())
+	}
+
+	/// 在当前栈帧中执行函数体, 没有return语句时返回0. 函数体最外层的块属于栈帧, 由调用者释放
+	fn run(&self, func: &Function, env: &mut Env) -> Result<i64, Trap> {
+		let flow = match &func.stmts {
+			Statement::CompoundStmt(stmts) => self.eval_stmts(stmts, env)?,
+			stmt => self.eval_stmt(stmt, env)?,
+		};
+		match flow {
+			Flow::Return(value) => Ok(value),
+			_ => Ok(0),
+		}
+	}
+
+	/// 按数据段的布局加载全局变量, 填入全局变量和函数的地址
+	fn load_globals(&self, env: &mut Env) -> Result<(), Trap> {
+		let data =
+			DataSegment::layout(&env.global_variables, &self.functions).map_err(|e| unsupported(&e.to_string()))?;
+		let mut memory = Memory::new(data.bytes.clone());
+		let base = DATA_BASE;
+		for Relocation { offset, target } in data.relocations.iter() {
+			let addr = match target {
+				RelocTarget::Data(n) => base + n,
+				RelocTarget::Function(id) => FUNCTION_BASE + id.0,
+			};
+			memory.store(base + offset, &TYPE_VOID.into_pointer(), addr as i64)?;
+		}
+		for (id, offset) in data.offsets.iter() {
+			env.globals.insert(*id, base + offset);
+		}
+		env.start(memory);
+		Ok(())
+	}
+
+	/// 翻译成字节码, 由 BytecodeVm 执行
+	pub fn compile(&self, env: &Env) -> Result<Program, ParseError> {
+		codegen::compile(&self.functions, &self.symbols, &env.global_variables)
+	}
+
+	/// 翻译成三地址的中间表示, 由 IrInterpreter 执行
+	pub fn lower(&self, env: &Env) -> Result<ir::Module, ParseError> {
+		ir::lower(&self.functions, &self.symbols, &env.global_variables)
+	}
+
+	/// 初始化全局变量之后执行main, 返回main的返回值或者exit的参数. main的栈帧留在调用栈中
+	pub fn eval(&self, env: &mut Env) -> Result<i64, Trap> {
+		self.eval_with_args(env, &[])
+	}
+
+	/// 命令行参数作为 main(int argc, char **argv) 的参数, argv[argc] 是空指针
+	pub fn eval_with_args(&self, env: &mut Env, args: &[String]) -> Result<i64, Trap> {
+		self.load_globals(env)?;
+		let result = match self.functions.iter().find(|func| func.name == "main") {
+			Some(main) if main.is_definition => {
+				let main_args = env.enter_main(args, main.ctype.param_list.len());
+				env.frames.push(Frame::new(&main.name, None));
+				self.bind_params(main, &main_args, env).and_then(|_| self.run(main, env))
+			}
+			_ => Err(RuntimeError::MissingMain.into()),
+		};
+		// exit从任意深度的调用中直接结束程序
+		match result {
+			Err(Trap { error: RuntimeError::Exit(code), .. }) => Ok(code),
+			result => result,
+		}
+	}
+
+	/// 对象的值: 数组和结构体按元素展开, 变长数组只显示长度未知
+	fn format_value(&self, addr: usize, ctype: &Type, env: &Env) -> String {
+		match ctype {
+			Type::Array(_) if ctype.is_vla() => "[*]".to_string(),
+			Type::Array(Array { base_type, length, .. }) => {
+				let elems: Vec<_> =
+					(0..*length).map(|i| self.format_value(addr + i * base_type.size(), base_type, env)).collect();
+				format!("[{}]", elems.join(", "))
+			}
+			Type::Struct(st) => {
+				let members: Vec<_> = st
+					.members()
+					.iter()
+					.map(|m| format!("{}: {}", m.name, self.format_value(addr + m.offset, &m.ctype, env)))
+					.collect();
+				format!("{{{}}}", members.join(", "))
+			}
+			ctype => match env.memory.load(addr, ctype) {
+				Ok(value) => value.to_string(),
+				Err(e) => format!("<{}>", e),
+			},
+		}
+	}
+
+	/// 全局变量和当前栈帧中的局部变量的值
+	pub fn dump(&self, env: &Env) -> String {
+		let locals = env.frame().into_iter().flat_map(|frame| frame.locals.iter());
+		let mut variables: Vec<_> = env.globals.iter().chain(locals).collect();
+		variables.sort();
+		variables
+			.iter()
+			.map(|(id, addr)| {
+				let symbol = &self.symbols[id.0];
+				format!("{} = {}\n", symbol.name, self.format_value(**addr, &symbol.ctype, env))
+			})
+			.collect()
+	}
+}
+
+/// 标签是否在语句中, 不包括嵌套的switch中的标签
+fn contains_label(stmt: &Statement, label: Label) -> bool {
+	match stmt {
+		Statement::CaseStmt(v, stmt) => label == Label::Case(*v) || contains_label(stmt, label),
+		Statement::DefaultStmt(stmt) => label == Label::Default || contains_label(stmt, label),
+		Statement::CompoundStmt(stmts) => stmts.iter().any(|stmt| contains_label(stmt, label)),
+		Statement::IfStmt(_, then, else_stmt) => {
+			contains_label(then, label) || else_stmt.as_ref().is_some_and(|stmt| contains_label(stmt, label))
+		}
+		Statement::ForStmt(_, _, _, body) => contains_label(body, label),
+		_ => false,
+	}
+}
+
+/// 字节码和中间表示执行结束: exit从任意深度的调用中直接结束程序, 它的参数是退出码;
+/// 其它错误加上出错的位置和调用栈. calls是每层调用的函数名和调用的位置, 从main开始
+pub(super) fn finish<'a>(
+	result: Result<i64, RuntimeError>,
+	span: Option<Span>,
+	calls: impl DoubleEndedIterator<Item = (&'a str, Option<Span>)>,
+) -> Result<i64, Trap> {
+	match result {
+		Ok(value) | Err(RuntimeError::Exit(value)) => Ok(value),
+		Err(error) => {
+			let backtrace = calls.rev().map(|(name, call_site)| (name.to_string(), call_site)).collect();
+			Err(Trap { error, span, backtrace })
+		}
+	}
+}
+
+/// 复合赋值对应的运算符
+pub(super) fn compound_op(assign: Punct) -> Punct {
+	match assign {
+		Punct::AssignAdd => Punct::Add,
+		Punct::AssignSub => Punct::Sub,
+		Punct::AssignMul => Punct::Mul,
+		Punct::AssignDiv => Punct::Div,
+		Punct::AssignMod => Punct::Mod,
+		Punct::AssignShl => Punct::Shl,
+		Punct::AssignShr => Punct::Shr,
+		Punct::AssignBAnd => Punct::And,
+		Punct::AssignBOr => Punct::Or,
+		Punct::AssignBXor => Punct::Xor,
+		op => op,
+	}
+}
+
+/// 按运算的类型求值, 结果转换为这个类型: 无符号数回绕, 有符号数溢出时回绕或者报错.
+/// 无符号数和指针的除法, 取余, 右移和比较按无符号进行. 移位的位数对类型的位数取模, 和x86一样
+pub(super) fn binary(op: Punct, ctype: &Type, l: i64, r: i64, trap_overflow: bool) -> Result<i64, RuntimeError> {
+	let unsigned = ctype.is_unsigned() || !ctype.is_integer();
+	let bits = if ctype.is_integer() { ctype.size() as u32 * 8 } else { 64 };
+	// 在i128中精确运算, 无符号数按u64解释
+	let wide = |v: i64| if unsigned { v as u64 as i128 } else { v as i128 };
+	let (a, b) = (wide(l), wide(r));
+	let shift = (r as u32) & (bits - 1);
+	let exact = match op {
+		Punct::Add => a + b,
+		Punct::Sub => a - b,
+		// 无符号数只需要低64位
+		Punct::Mul => a.wrapping_mul(b),
+		Punct::Div | Punct::Mod if b == 0 => return Err(RuntimeError::DivisionByZero),
+		Punct::Div => a / b,
+		Punct::Mod => a % b,
+		Punct::Shl => a << shift,
+		Punct::Shr => a >> shift,
+		Punct::And => a & b,
+		Punct::Or => a | b,
+		Punct::Xor => a ^ b,
+		Punct::Eq => return Ok((a == b) as i64),
+		Punct::Ne => return Ok((a != b) as i64),
+		Punct::Lt => return Ok((a < b) as i64),
+		Punct::Gt => return Ok((a > b) as i64),
+		Punct::Le => return Ok((a <= b) as i64),
+		Punct::Ge => return Ok((a >= b) as i64),
+		_ => return Err(unsupported(&format!("operator {}", op))),
+	};
+	let value = ctype.convert(exact as i64);
+	match trap_overflow && !unsigned && value as i128 != exact {
+		true => Err(RuntimeError::SignedOverflow),
+		false => Ok(value),
 	}
 }
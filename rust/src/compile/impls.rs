@@ -22,10 +22,34 @@ impl Display for Type {
 				s = style("char").green().to_string();
 				&s
 			}
+			Self::Short => {
+				s = style("short").green().to_string();
+				&s
+			}
 			Self::Int => {
 				s = style("int").green().to_string();
 				&s
 			}
+			Self::Long => {
+				s = style("long").green().to_string();
+				&s
+			}
+			Self::UChar => {
+				s = style("unsigned char").green().to_string();
+				&s
+			}
+			Self::UShort => {
+				s = style("unsigned short").green().to_string();
+				&s
+			}
+			Self::UInt => {
+				s = style("unsigned int").green().to_string();
+				&s
+			}
+			Self::ULong => {
+				s = style("unsigned long").green().to_string();
+				&s
+			}
 			Self::Ptr(Ptr { base_type }) => {
 				if f.alternate() {
 					s = format!("pointer to: < {:#} >", base_type);
@@ -35,8 +59,12 @@ impl Display for Type {
 				}
 				&s
 			}
-			Self::Array(Array { length, base_type, size_expr: _ }) => {
-				if f.alternate() {
+			Self::Array(Array { length, base_type, size_expr }) => {
+				let is_vla = size_expr.as_ref().is_some_and(|expr| !matches!(expr.kind, ExprKind::Const(_)));
+				if is_vla {
+					// 变长数组, 长度在运行时确定
+					s = format!("[*] -> {}", base_type);
+				} else if f.alternate() {
 					s = format!("array of < {:#} > with size {}", base_type, length);
 				} else {
 					// s = format!("array [size: {}] of ---> {}", length, base_type);
@@ -48,7 +76,11 @@ impl Display for Type {
 				}
 				&s
 			}
-			Self::Func(Func { return_type, param_list, is_variadic: _ }) => {
+			Self::Struct(st) => {
+				s = style(format!("struct {}", st.tag().unwrap_or_default())).green().to_string();
+				&s
+			}
+			Self::Func(Func { return_type, param_list, .. }) => {
 				if f.alternate() {
 					s = format!("function returning < {:#} > with parameters: (", return_type);
 					if let Some((first, remaining)) = param_list.split_first() {
@@ -79,7 +111,8 @@ impl Display for Statement {
 		match self {
 			Self::Empty => writeln!(f, ";"),
 			Self::ExprStmt(expr) => write!(f, "<expr>\n{}", expr),
-			Self::ReturnStmt(expr) => write!(f, "return \n{}", expr),
+			Self::ReturnStmt(Some(expr), _) => write!(f, "return \n{}", expr),
+			Self::ReturnStmt(None, _) => writeln!(f, "return;"),
 			Self::IfStmt(expr, then_stmt, else_stmt) => match else_stmt {
 				None => write!(f, "if (\n{}) {}", expr, then_stmt),
 				Some(es) => write!(f, "if (\n{}) {} else {}", expr, then_stmt, es),
@@ -97,6 +130,11 @@ impl Display for Statement {
 					stmt
 				)
 			}
+			Self::SwitchStmt(expr, stmt) => write!(f, "switch (\n{}) {}", expr, stmt),
+			Self::CaseStmt(value, stmt) => write!(f, "case {}:\n{}", value, stmt),
+			Self::DefaultStmt(stmt) => write!(f, "default:\n{}", stmt),
+			Self::BreakStmt => writeln!(f, "break;"),
+			Self::ContinueStmt => writeln!(f, "continue;"),
 			Self::CompoundStmt(stmts) => {
 				writeln!(f, "{{")?;
 				for stmt in stmts {
@@ -104,6 +142,16 @@ impl Display for Statement {
 				}
 				write!(f, "}}")
 			}
+			Self::DeclStmt(vars) => {
+				for var in vars {
+					writeln!(f, "<decl> {}: {}", var.name, var.ctype)?;
+					if let Some(init) = var.init_value.as_ref() {
+						write!(f, "{}", init)?;
+					}
+				}
+				Ok(())
+			}
+			Self::Error => writeln!(f, "<error>;"),
 		}
 	}
 }
@@ -190,21 +238,42 @@ fn print_func_args(s: &mut String, prev: &str, pos: &NodePos, expr: &[Expr]) {
 	}
 }
 
-fn print_expr_tree(this: &Expr, s: &mut String, prev: &str, pos: &NodePos) {
+fn print_initializer_tree(this: &Initializer, s: &mut String, prev: &str, pos: &NodePos) {
 	match this {
-		Expr::Const(v) => print_leaf(s, prev, pos, v),
+		Initializer::Expr(expr) => print_expr_tree(expr, s, prev, pos),
+		Initializer::List(list) => {
+			print_op(s, prev, pos, "{}");
+			let elems: Vec<_> = list.iter().enumerate().filter_map(|(i, e)| e.as_ref().map(|e| (i, e))).collect();
+			if let Some(((_, last), elems)) = elems.split_last() {
+				let next_prefix = prev.to_owned() + if pos.is_top() || pos.is_middle() { "│   " } else { "    " };
+				for (_, e) in elems {
+					print_initializer_tree(e, s, &next_prefix, &NodePos::Middle);
+				}
+				print_initializer_tree(last, s, &next_prefix, &NodePos::Bottom);
+			}
+		}
+	}
+}
 
-		Expr::StringLiteral(v) => print_leaf_str(s, prev, pos, v),
+fn print_expr_tree(this: &Expr, s: &mut String, prev: &str, pos: &NodePos) {
+	match &this.kind {
+		ExprKind::Const(v) => print_leaf(s, prev, pos, v),
+
+		ExprKind::StringLiteral(v) => print_leaf_str(s, prev, pos, v),
 
-		Expr::Id(v) => print_leaf(s, prev, pos, v),
+		ExprKind::Id(v, _) => print_leaf(s, prev, pos, v),
 
-		Expr::BinOp(BinOp { op, left, right }) => print_binary_node(s, prev, pos, op, left, right),
+		ExprKind::Error => print_leaf(s, prev, pos, "<error>"),
 
-		Expr::AssignExpr(AssignExpr { left, assign, right }) => print_binary_node(s, prev, pos, assign, left, right),
+		ExprKind::BinOp(BinOp { op, left, right }) => print_binary_node(s, prev, pos, op, left, right),
 
-		Expr::CommaExpr(CommaExpr { left, right }) => print_binary_node(s, prev, pos, ",", left, right),
+		ExprKind::AssignExpr(AssignExpr { left, assign, right }) => {
+			print_binary_node(s, prev, pos, assign, left, right)
+		}
 
-		Expr::CondExpr(CondExpr { cond, left, right }) => {
+		ExprKind::CommaExpr(CommaExpr { left, right }) => print_binary_node(s, prev, pos, ",", left, right),
+
+		ExprKind::CondExpr(CondExpr { cond, left, right }) => {
 			let prefix_str = if pos.is_top() || prev.is_empty() { "    " } else { "│   " };
 			print_expr_tree(cond, s, &(prev.to_owned() + prefix_str), &NodePos::Top);
 
@@ -214,31 +283,47 @@ fn print_expr_tree(this: &Expr, s: &mut String, prev: &str, pos: &NodePos) {
 			print_expr_tree(left, s, &next_prefix, &NodePos::Middle);
 			print_expr_tree(right, s, &next_prefix, &NodePos::Bottom);
 		}
-		Expr::FunctionCall(expr, args) => {
+		ExprKind::FunctionCall(expr, args) => {
 			let prefix_str = if pos.is_top() || prev.is_empty() { "    " } else { "│   " };
 			print_expr_tree(expr, s, &(prev.to_owned() + prefix_str), &NodePos::Top);
 			print_op(s, prev, pos, "<fn>()");
 
 			print_func_args(s, prev, pos, args)
 		}
-		Expr::Postfix(PostfixOP { op, expr }) => {
+		ExprKind::Postfix(PostfixOP { op, expr }) => {
 			print_op(s, prev, pos, op);
 
 			let next_prefix = prev.to_owned() + if pos.is_top() { "│   " } else { "    " };
 			print_expr_tree(expr, s, &next_prefix, &NodePos::Bottom);
 		}
-		Expr::UnaryOp(UnaryOp { op, expr }) => {
+		ExprKind::Cast(ctype, expr) => {
+			print_op(s, prev, pos, format!("({})", ctype));
+			let next_prefix = prev.to_owned() + if pos.is_top() { "│   " } else { "    " };
+			print_expr_tree(expr, s, &next_prefix, &NodePos::Bottom);
+		}
+		ExprKind::SizeOfType(ctype) => print_leaf(s, prev, pos, format!("sizeof({})", ctype)),
+		ExprKind::CompoundLiteral(ctype, init) => {
+			print_op(s, prev, pos, format!("({})", ctype));
+			let next_prefix = prev.to_owned() + if pos.is_top() { "│   " } else { "    " };
+			print_initializer_tree(init, s, &next_prefix, &NodePos::Bottom);
+		}
+		ExprKind::SizeOfExpr(expr) => {
+			print_op(s, prev, pos, "sizeof");
+			let next_prefix = prev.to_owned() + if pos.is_top() { "│   " } else { "    " };
+			print_expr_tree(expr, s, &next_prefix, &NodePos::Bottom);
+		}
+		ExprKind::UnaryOp(UnaryOp { op, expr }) => {
 			print_op(s, prev, pos, op);
 			let next_prefix = prev.to_owned() + if pos.is_top() { "│   " } else { "    " };
 			print_expr_tree(expr, s, &next_prefix, &NodePos::Bottom);
 		}
-		Expr::MemberAccess(expr, field) => {
+		ExprKind::MemberAccess(expr, field) => {
 			let prefix_str = if pos.is_top() || prev.is_empty() { "    " } else { "│   " };
 			print_expr_tree(expr, s, &(prev.to_owned() + prefix_str), &NodePos::Top);
 			let op = ".".to_owned() + field;
 			print_op(s, prev, pos, &op);
 		}
-		Expr::MemberAccessP(expr, field) => {
+		ExprKind::MemberAccessP(expr, field) => {
 			let prefix_str = if pos.is_top() || prev.is_empty() { "    " } else { "│   " };
 			print_expr_tree(expr, s, &(prev.to_owned() + prefix_str), &NodePos::Top);
 			let op = "->".to_owned() + field;
@@ -254,3 +339,11 @@ impl Display for Expr {
 		f.write_str(s.as_str())
 	}
 }
+
+impl Display for Initializer {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let mut s = String::new();
+		print_initializer_tree(self, &mut s, "", &NodePos::Init);
+		f.write_str(s.as_str())
+	}
+}
@@ -0,0 +1,979 @@
+//! 三地址形式的中间表示, 比 Statement/Expr 低一层, 之后的优化, 本地代码生成和分析都在它上面做.
+//! 每个函数是一组基本块, 第一个块是入口; 块中是三地址指令, 最后是显式的跳转, 分支或者返回.
+//! 表达式的值放在有类型的虚拟寄存器中. 寄存器不是SSA的: 条件表达式和短路运算的两个分支写同一个寄存器.
+//! 内存布局和字节码相同: 局部变量在栈帧中按偏移取地址, 全局变量和字符串字面量在数据段中, 由 ireval 解释执行
+
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use super::{
+	builtins::Builtin,
+	codegen::{declares_vla, direct_callee, layout, Callee, Layout, Opcode, Scalar},
+	errors::{ConstError, ParseError},
+	eval::compound_op,
+	memory::FUNCTION_BASE,
+	token::{Const, Punct, Span},
+	types::{self, *},
+};
+
+/// 虚拟寄存器, 类型记录在函数的 registers 中
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Reg(pub usize);
+
+/// 基本块在函数中的下标
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BlockId(pub usize);
+
+/// 三地址指令, 第一个寄存器是结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inst {
+	Const(Reg, i64),
+	/// r = bp + n, 栈帧中的对象的地址
+	Local(Reg, usize),
+	/// r = 数据段基地址 + n, 全局变量和字符串字面量的地址
+	Global(Reg, usize),
+	/// r = 第n个实参
+	Arg(Reg, usize),
+	Mov(Reg, Reg),
+	/// r = x 转换为r的类型
+	Cast(Reg, Reg),
+	/// r = x op y, 按指定的类型运算
+	Binary(Punct, Scalar, Reg, Reg, Reg),
+	/// r = *x, 按r的类型读出
+	Load(Reg, Reg),
+	/// *x = y, 按指定的类型写入
+	Store(Scalar, Reg, Reg),
+	/// 从y复制n个字节到x, 用于结构体
+	Copy(Reg, Reg, usize),
+	/// x处的n个字节清零
+	Zero(Reg, usize),
+	/// 在栈上分配x个字节, 按n对齐, r = 地址. 用于变长数组
+	Alloca(Reg, Reg, usize),
+	/// 把内存的栈顶保存到 bp + n
+	SaveStack(usize),
+	/// 恢复保存在 bp + n 的栈顶, 释放之后分配的变长数组
+	RestoreStack(usize),
+	/// 调用函数表中的函数
+	Call(Reg, usize, Vec<Reg>),
+	CallBuiltin(Reg, Builtin, Vec<Reg>),
+	/// 通过函数指针调用
+	CallIndirect(Reg, Reg, Vec<Reg>),
+}
+
+/// 基本块最后的控制转移
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Terminator {
+	Jump(BlockId),
+	/// 条件不为0时到第一个块, 否则到第二个块
+	Branch(Reg, BlockId, BlockId),
+	Return(Reg),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+	pub insts: Vec<Inst>,
+	/// 每条指令对应的表达式的位置, 报告运行时错误
+	pub spans: Vec<Option<Span>>,
+	pub terminator: Terminator,
+	pub span: Option<Span>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+	pub name: String,
+	pub params: usize,
+	pub is_variadic: bool,
+	/// 返回结构体时结构体的大小和对齐, 返回前复制到调用者的栈上
+	pub returns_struct: Option<(usize, usize)>,
+	pub frame_size: usize,
+	/// 每个寄存器的类型. 指针, 数组和结构体的值是地址, 类型是u64
+	pub registers: Vec<Scalar>,
+	/// 按逆后序排列, 第一个块是入口. 只有声明的函数没有块
+	pub blocks: Vec<Block>,
+}
+
+/// 整个程序. 函数表, 函数地址表和数据段与字节码的相同
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Module {
+	pub functions: Vec<Function>,
+	pub callees: HashMap<usize, Callee>,
+	pub data: Vec<u8>,
+	pub main: Option<usize>,
+}
+
+impl Terminator {
+	pub fn successors(&self) -> Vec<BlockId> {
+		match self {
+			Terminator::Jump(target) => vec![*target],
+			Terminator::Branch(_, then, other) => vec![*then, *other],
+			Terminator::Return(_) => vec![],
+		}
+	}
+}
+
+/// 正在翻译的块, 结束之前没有 terminator
+#[derive(Default)]
+struct PendingBlock {
+	insts: Vec<Inst>,
+	spans: Vec<Option<Span>>,
+	end: Option<(Terminator, Option<Span>)>,
+}
+
+/// break和continue跳转到的块
+struct Target {
+	breaks: BlockId,
+	/// switch没有continue的目标
+	continues: Option<BlockId>,
+	/// switch中的case标签和default开始的块
+	cases: Vec<(i64, BlockId)>,
+	default: Option<BlockId>,
+	/// 进入时外层有几个分配变长数组的块, 跳出时要恢复栈顶
+	vla_blocks: usize,
+}
+
+impl Target {
+	fn new(breaks: BlockId, continues: Option<BlockId>, vla_blocks: usize) -> Self {
+		Target { breaks, continues, cases: vec![], default: None, vla_blocks }
+	}
+}
+
+struct Lowering<'a> {
+	symbols: &'a [Symbol],
+	data: Vec<u8>,
+	/// 全局变量在数据段中的偏移
+	globals: HashMap<SymbolId, usize>,
+	strings: HashMap<String, usize>,
+	/// 函数的符号编号到函数表的下标
+	function_index: HashMap<SymbolId, usize>,
+	/// 当前函数的参数和局部变量在栈帧中的偏移, 变长数组的位置保存的是数组的地址
+	locals: HashMap<SymbolId, usize>,
+	frame_size: usize,
+	registers: Vec<Scalar>,
+	blocks: Vec<PendingBlock>,
+	current: BlockId,
+	targets: Vec<Target>,
+	/// 分配变长数组的块保存栈顶的位置
+	vla_blocks: Vec<usize>,
+	/// 正在翻译的表达式的位置
+	span: Option<Span>,
+}
+
+fn unsupported(what: &str) -> ParseError {
+	ParseError::Unexpected(what.to_string())
+}
+
+/// 把到达得了的块按逆后序重新编号, 去掉到达不了的块. 分支的两个后继按先条件成立的顺序排列
+fn reverse_postorder(blocks: Vec<PendingBlock>) -> Vec<Block> {
+	let successors = |block: &PendingBlock| block.end.as_ref().map_or(vec![], |(end, _)| end.successors());
+	let mut visited = vec![false; blocks.len()];
+	let mut postorder = vec![];
+	let mut stack = vec![(BlockId(0), false)];
+	while let Some((block, done)) = stack.pop() {
+		if done {
+			postorder.push(block);
+			continue;
+		}
+		if visited[block.0] {
+			continue;
+		}
+		visited[block.0] = true;
+		stack.push((block, true));
+		let unvisited = successors(&blocks[block.0]).into_iter().filter(|next| !visited[next.0]);
+		stack.extend(unvisited.map(|next| (next, false)));
+	}
+	postorder.reverse();
+
+	let mut numbers = vec![0; blocks.len()];
+	for (i, block) in postorder.iter().enumerate() {
+		numbers[block.0] = i;
+	}
+	let renumber = |block: BlockId| BlockId(numbers[block.0]);
+	let mut blocks: Vec<Option<PendingBlock>> = blocks.into_iter().map(Some).collect();
+	postorder
+		.iter()
+		.map(|id| {
+			let block = blocks[id.0].take().unwrap();
+			let (terminator, span) = block.end.expect("every block is terminated");
+			let terminator = match terminator {
+				Terminator::Jump(target) => Terminator::Jump(renumber(target)),
+				Terminator::Branch(cond, then, other) => Terminator::Branch(cond, renumber(then), renumber(other)),
+				end => end,
+			};
+			Block { insts: block.insts, spans: block.spans, terminator, span }
+		})
+		.collect()
+}
+
+impl<'a> Lowering<'a> {
+	fn emit(&mut self, inst: Inst) {
+		let block = &mut self.blocks[self.current.0];
+		block.insts.push(inst);
+		block.spans.push(self.span);
+	}
+
+	fn reg(&mut self, scalar: Scalar) -> Reg {
+		self.registers.push(scalar);
+		Reg(self.registers.len() - 1)
+	}
+
+	/// 发出结果写到新寄存器的指令
+	fn define(&mut self, scalar: Scalar, inst: impl FnOnce(Reg) -> Inst) -> Reg {
+		let r = self.reg(scalar);
+		self.emit(inst(r));
+		r
+	}
+
+	fn constant(&mut self, scalar: Scalar, n: i64) -> Reg {
+		self.define(scalar, |r| Inst::Const(r, n))
+	}
+
+	fn binary(&mut self, op: Punct, scalar: Scalar, result: Scalar, left: Reg, right: Reg) -> Reg {
+		self.define(result, |r| Inst::Binary(op, scalar, r, left, right))
+	}
+
+	/// 类型不同时转换
+	fn convert(&mut self, value: Reg, scalar: Scalar) -> Reg {
+		match self.registers[value.0] == scalar {
+			true => value,
+			false => self.define(scalar, |r| Inst::Cast(r, value)),
+		}
+	}
+
+	fn new_block(&mut self) -> BlockId {
+		self.blocks.push(PendingBlock::default());
+		BlockId(self.blocks.len() - 1)
+	}
+
+	fn switch_to(&mut self, block: BlockId) {
+		self.current = block;
+	}
+
+	fn terminate(&mut self, terminator: Terminator) {
+		let block = &mut self.blocks[self.current.0];
+		if block.end.is_none() {
+			block.end = Some((terminator, self.span));
+		}
+	}
+
+	fn jump(&mut self, target: BlockId) {
+		self.terminate(Terminator::Jump(target));
+	}
+
+	fn branch(&mut self, cond: Reg, then: BlockId, other: BlockId) {
+		self.terminate(Terminator::Branch(cond, then, other));
+	}
+
+	/// return break continue 之后的语句在一个没有前驱的新块中, 最后被去掉
+	fn unreachable(&mut self) {
+		let block = self.new_block();
+		self.switch_to(block);
+	}
+
+	/// 在栈帧中分配一块空间, 返回偏移
+	fn slot(&mut self, size: usize, align: usize) -> usize {
+		let offset = align_to(self.frame_size, align.max(1));
+		self.frame_size = offset + size;
+		offset
+	}
+
+	/// 表达式的类型. 变长数组长度中的隐藏变量没有经过类型检查, 使用符号的类型
+	fn type_of(&self, expr: &'a Expr) -> Result<&'a Type, ParseError> {
+		match (&expr.ctype, &expr.kind) {
+			(Some(ctype), _) => Ok(ctype),
+			(None, ExprKind::Id(_, Some(id))) => Ok(&self.symbols[id.0].ctype),
+			_ => Err(unsupported("expression that is not type checked")),
+		}
+	}
+
+	fn scalar_of(&self, expr: &'a Expr) -> Result<Scalar, ParseError> {
+		self.type_of(expr).map(Scalar::of)
+	}
+
+	/// 读出addr处的值, 数组, 结构体和函数的值是地址本身
+	fn load(&mut self, ctype: &Type, addr: Reg) -> Reg {
+		match ctype {
+			Type::Array(_) | Type::Struct(_) | Type::Func(_) => addr,
+			ctype => self.define(Scalar::of(ctype), |r| Inst::Load(r, addr)),
+		}
+	}
+
+	/// 写入addr, 返回赋值表达式的值: 转换为写入类型的值, 结构体是目标地址
+	fn store(&mut self, ctype: &Type, addr: Reg, value: Reg) -> Reg {
+		match ctype {
+			Type::Struct(_) => {
+				self.emit(Inst::Copy(addr, value, ctype.size()));
+				addr
+			}
+			ctype => {
+				let scalar = Scalar::of(ctype);
+				self.emit(Inst::Store(scalar, addr, value));
+				self.convert(value, scalar)
+			}
+		}
+	}
+
+	fn string(&mut self, str: &str) -> usize {
+		match self.strings.get(str) {
+			Some(offset) => *offset,
+			None => {
+				let offset = self.data.len();
+				self.data.extend_from_slice(str.as_bytes());
+				self.data.push(0);
+				self.strings.insert(str.to_string(), offset);
+				offset
+			}
+		}
+	}
+
+	/// 运行时的类型大小, 变长数组的长度保存在隐藏的局部变量中
+	fn size_of(&mut self, ctype: &'a Type) -> Result<Reg, ParseError> {
+		match ctype {
+			Type::Array(Array { base_type, size_expr: Some(len), .. }) if ctype.is_vla() => {
+				let len = match len.fold_const() {
+					Some(n) => self.constant(Scalar::U64, n),
+					None => self.expr(len)?,
+				};
+				let size = self.size_of(base_type)?;
+				Ok(self.binary(Punct::Mul, Scalar::U64, Scalar::U64, len, size))
+			}
+			ctype => Ok(self.constant(Scalar::U64, ctype.size() as i64)),
+		}
+	}
+
+	/// ++ -- 的步长, 指针按所指类型的大小
+	fn step(&mut self, ctype: &'a Type, scalar: Scalar) -> Result<Reg, ParseError> {
+		match ctype.pointee() {
+			Some(pointee) if pointee.is_vla() => self.size_of(pointee),
+			Some(pointee) => Ok(self.constant(scalar, pointee.size().max(1) as i64)),
+			None => Ok(self.constant(scalar, 1)),
+		}
+	}
+
+	/// 左值表达式指代的对象的地址
+	fn address(&mut self, expr: &'a Expr) -> Result<Reg, ParseError> {
+		Ok(match &expr.kind {
+			ExprKind::Id(name, Some(id)) => match self.symbols[id.0].kind {
+				SymbolKind::Global => {
+					let offset = *self.globals.get(id).ok_or_else(|| unsupported(name))?;
+					self.define(Scalar::U64, |r| Inst::Global(r, offset))
+				}
+				SymbolKind::Local | SymbolKind::Param => {
+					let offset = *self.locals.get(id).ok_or_else(|| ParseError::Undeclared(name.clone()))?;
+					let addr = self.define(Scalar::U64, |r| Inst::Local(r, offset));
+					match self.symbols[id.0].ctype.is_vla() {
+						true => self.define(Scalar::U64, |r| Inst::Load(r, addr)),
+						false => addr,
+					}
+				}
+				SymbolKind::Function | SymbolKind::Builtin(_) => {
+					self.constant(Scalar::U64, (FUNCTION_BASE + id.0) as i64)
+				}
+				SymbolKind::EnumConst(_) => return Err(ParseError::NotLvalue(name.clone())),
+			},
+			ExprKind::Id(name, None) => return Err(ParseError::Undeclared(name.clone())),
+			ExprKind::UnaryOp(UnaryOp { op: Punct::Mul, expr: pointer }) => self.expr(pointer)?,
+			ExprKind::MemberAccess(base, name) | ExprKind::MemberAccessP(base, name) => {
+				// 结构体的值和指针的值都是结构体的地址
+				let addr = self.expr(base)?;
+				let st = match (&expr.kind, self.type_of(base)?) {
+					(ExprKind::MemberAccess(..), ctype) => ctype,
+					(_, ctype) => ctype.pointee().unwrap_or(ctype),
+				};
+				let member = match st {
+					Type::Struct(st) => st.member(name),
+					_ => None,
+				};
+				let member = member.ok_or_else(|| ParseError::NoMember(name.clone()))?;
+				match member.offset {
+					0 => addr,
+					offset => {
+						let offset = self.constant(Scalar::U64, offset as i64);
+						self.binary(Punct::Add, Scalar::U64, Scalar::U64, addr, offset)
+					}
+				}
+			}
+			ExprKind::StringLiteral(str) => {
+				let offset = self.string(str);
+				self.define(Scalar::U64, |r| Inst::Global(r, offset))
+			}
+			// 复合字面量在栈帧中
+			ExprKind::CompoundLiteral(ctype, init) => {
+				let offset = self.slot(ctype.size(), ctype.align());
+				self.local_init(offset, ctype, Some(init))?;
+				self.define(Scalar::U64, |r| Inst::Local(r, offset))
+			}
+			_ => return Err(unsupported(&format!("{} as lvalue", expr))),
+		})
+	}
+
+	/// 自增自减. 比int小的整数按int运算, 写入时转换; 后缀形式的值是旧值
+	fn increment(&mut self, operand: &'a Expr, op: Punct, postfix: bool) -> Result<Reg, ParseError> {
+		let ctype = self.type_of(operand)?;
+		let scalar = Scalar::of(ctype);
+		let promoted = Scalar::of(&ctype.promoted());
+		let op = if op == Punct::Inc { Punct::Add } else { Punct::Sub };
+		let addr = self.address(operand)?;
+		let old = self.define(scalar, |r| Inst::Load(r, addr));
+		let value = self.convert(old, promoted);
+		let step = self.step(ctype, promoted)?;
+		let new = self.binary(op, promoted, promoted, value, step);
+		self.emit(Inst::Store(scalar, addr, new));
+		Ok(if postfix { old } else { self.convert(new, scalar) })
+	}
+
+	/// 翻译表达式, 返回值所在的寄存器
+	fn expr(&mut self, expr: &'a Expr) -> Result<Reg, ParseError> {
+		let outer = self.span;
+		// 省略的表达式(如 for(;;) 的条件)没有位置
+		if expr.span.line > 0 {
+			self.span = Some(expr.span);
+		}
+		let result = self.expr_at(expr);
+		self.span = outer;
+		result
+	}
+
+	fn expr_at(&mut self, expr: &'a Expr) -> Result<Reg, ParseError> {
+		// 常量的类型: 解析时生成的常量没有经过类型检查
+		let constant_type = expr.ctype.as_ref().map_or(Scalar::I32, Scalar::of);
+		Ok(match &expr.kind {
+			ExprKind::Const(Const::Empty) => self.constant(constant_type, 0),
+			ExprKind::Const(Const::Integer(ints)) => {
+				let (value, _) = Const::integer(ints).ok_or(ParseError::ConstError(ConstError::Overflow))?;
+				self.constant(constant_type, value)
+			}
+			ExprKind::Const(Const::Character(c)) => self.constant(constant_type, *c as i64),
+			ExprKind::Id(_, Some(id)) if matches!(self.symbols[id.0].kind, SymbolKind::EnumConst(_)) => {
+				match self.symbols[id.0].kind {
+					SymbolKind::EnumConst(value) => self.constant(constant_type, value),
+					_ => unreachable!(),
+				}
+			}
+			// 对象的值从内存中读出
+			ExprKind::Id(..)
+			| ExprKind::UnaryOp(UnaryOp { op: Punct::Mul, .. })
+			| ExprKind::MemberAccess(..)
+			| ExprKind::MemberAccessP(..)
+			| ExprKind::StringLiteral(_)
+			| ExprKind::CompoundLiteral(..) => {
+				let addr = self.address(expr)?;
+				self.load(self.type_of(expr)?, addr)
+			}
+			ExprKind::SizeOfType(ctype) => self.size_of(ctype)?,
+			ExprKind::SizeOfExpr(operand) => match &operand.ctype {
+				Some(ctype) => self.size_of(ctype)?,
+				None => {
+					let size = expr.fold_const().ok_or_else(|| unsupported("sizeof"))?;
+					self.constant(Scalar::U64, size)
+				}
+			},
+			ExprKind::Cast(ctype, operand) => {
+				let value = self.expr(operand)?;
+				match ctype.is_scalar() {
+					true => self.convert(value, Scalar::of(ctype)),
+					false => value,
+				}
+			}
+			ExprKind::UnaryOp(UnaryOp { op, expr: operand }) => match op {
+				Punct::Add => self.expr(operand)?,
+				Punct::Sub | Punct::Tilde => {
+					let scalar = self.scalar_of(operand)?;
+					let value = self.expr(operand)?;
+					match op {
+						Punct::Sub => {
+							let zero = self.constant(scalar, 0);
+							self.binary(Punct::Sub, scalar, scalar, zero, value)
+						}
+						_ => {
+							let ones = self.constant(scalar, -1);
+							self.binary(Punct::Xor, scalar, scalar, value, ones)
+						}
+					}
+				}
+				Punct::Not => {
+					let value = self.expr(operand)?;
+					let zero = self.constant(Scalar::I64, 0);
+					self.binary(Punct::Eq, Scalar::I64, Scalar::I32, value, zero)
+				}
+				Punct::And => self.address(operand)?,
+				// ++i --i
+				Punct::Inc | Punct::Dec => self.increment(operand, *op, false)?,
+				_ => return Err(unsupported(&format!("operator {}", op))),
+			},
+			ExprKind::Postfix(PostfixOP { op, expr: operand }) => self.increment(operand, *op, true)?,
+			ExprKind::BinOp(BinOp { left, op: op @ (Punct::Lan | Punct::Lor), right }) => {
+				// 短路求值: 两个分支把1或0写到同一个寄存器
+				let result = self.reg(Scalar::I32);
+				let (rhs, long, short, end) = (self.new_block(), self.new_block(), self.new_block(), self.new_block());
+				let and = *op == Punct::Lan;
+				let left = self.expr(left)?;
+				match and {
+					true => self.branch(left, rhs, short),
+					false => self.branch(left, short, rhs),
+				}
+				self.switch_to(rhs);
+				let right = self.expr(right)?;
+				match and {
+					true => self.branch(right, long, short),
+					false => self.branch(right, short, long),
+				}
+				for (block, value) in [(long, and), (short, !and)] {
+					self.switch_to(block);
+					self.emit(Inst::Const(result, value as i64));
+					self.jump(end);
+				}
+				self.switch_to(end);
+				result
+			}
+			// 类型检查已经把两个操作数转换为共同类型, 移位和指针运算时左操作数的类型是运算的类型
+			ExprKind::BinOp(BinOp { left, op, right }) => {
+				let scalar = self.scalar_of(left)?;
+				if Opcode::binary(*op, scalar).is_none() {
+					return Err(unsupported(&format!("operator {}", op)));
+				}
+				let left = self.expr(left)?;
+				let right = self.expr(right)?;
+				self.binary(*op, scalar, self.scalar_of(expr)?, left, right)
+			}
+			ExprKind::AssignExpr(AssignExpr { left, assign, right }) => {
+				let ctype = self.type_of(left)?;
+				let addr = self.address(left)?;
+				let value = match *assign {
+					Punct::Assign => self.expr(right)?,
+					assign => {
+						// 按寻常算术转换之后的类型运算, 写入时转换为左边的类型
+						let op = compound_op(assign);
+						let op_type = match (ctype.is_integer(), op) {
+							(true, Punct::Shl | Punct::Shr) => ctype.promoted(),
+							(true, _) => ctype.common(self.type_of(right)?),
+							(false, _) => ctype.clone(),
+						};
+						let scalar = Scalar::of(&op_type);
+						if Opcode::binary(op, scalar).is_none() {
+							return Err(unsupported(&format!("operator {}", assign)));
+						}
+						let old = self.load(ctype, addr);
+						let old = self.convert(old, scalar);
+						let right = self.expr(right)?;
+						let right = self.convert(right, scalar);
+						self.binary(op, scalar, scalar, old, right)
+					}
+				};
+				self.store(ctype, addr, value)
+			}
+			ExprKind::CondExpr(CondExpr { cond, left, right }) => {
+				let result = self.reg(self.scalar_of(expr)?);
+				let (then, other, end) = (self.new_block(), self.new_block(), self.new_block());
+				let cond = self.expr(cond)?;
+				self.branch(cond, then, other);
+				for (block, value) in [(then, left), (other, right)] {
+					self.switch_to(block);
+					let value = self.expr(value)?;
+					self.emit(Inst::Mov(result, value));
+					self.jump(end);
+				}
+				self.switch_to(end);
+				result
+			}
+			ExprKind::CommaExpr(CommaExpr { left, right }) => {
+				self.expr(left)?;
+				self.expr(right)?
+			}
+			ExprKind::FunctionCall(callee, args) => {
+				let direct = direct_callee(callee).and_then(|id| match self.symbols[id.0].kind {
+					SymbolKind::Builtin(builtin) => Some(Callee::Builtin(builtin)),
+					SymbolKind::Function => self.function_index.get(&id).map(|index| Callee::Function(*index)),
+					_ => None,
+				});
+				let pointer = match direct {
+					Some(_) => None,
+					None => Some(self.expr(callee)?),
+				};
+				let args = args.iter().map(|arg| self.expr(arg)).collect::<Result<Vec<_>, _>>()?;
+				let result = self.reg(self.scalar_of(expr)?);
+				self.emit(match (direct, pointer) {
+					(Some(Callee::Builtin(builtin)), _) => Inst::CallBuiltin(result, builtin, args),
+					(Some(Callee::Function(index)), _) => Inst::Call(result, index, args),
+					(None, pointer) => Inst::CallIndirect(result, pointer.expect("function pointer"), args),
+				});
+				result
+			}
+			ExprKind::Error => return Err(unsupported("expression with errors")),
+		})
+	}
+
+	/// 初始化栈帧中的对象. 没有初始化器或者初始化列表时先清零, 没有初始化的子对象保持为0
+	fn local_init(&mut self, offset: usize, ctype: &Type, init: Option<&'a Initializer>) -> Result<(), ParseError> {
+		if !matches!(init, Some(Initializer::Expr(_))) {
+			let addr = self.define(Scalar::U64, |r| Inst::Local(r, offset));
+			self.emit(Inst::Zero(addr, ctype.size()));
+		}
+		match init {
+			Some(init) => self.init(offset, ctype, init),
+			None => Ok(()),
+		}
+	}
+
+	fn init(&mut self, offset: usize, ctype: &Type, init: &'a Initializer) -> Result<(), ParseError> {
+		match (ctype, init) {
+			(Type::Array(Array { base_type, .. }), Initializer::List(list)) => {
+				for (i, elem) in list.iter().enumerate() {
+					if let Some(elem) = elem {
+						self.init(offset + i * base_type.size(), base_type, elem)?;
+					}
+				}
+				Ok(())
+			}
+			(Type::Struct(st), Initializer::List(list)) => {
+				for (member, elem) in st.members().iter().zip(list.iter()) {
+					if let Some(elem) = elem {
+						self.init(offset + member.offset, &member.ctype, elem)?;
+					}
+				}
+				Ok(())
+			}
+			(_, Initializer::List(list)) => match list.first() {
+				Some(Some(elem)) => self.init(offset, ctype, elem),
+				_ => Ok(()),
+			},
+			(_, Initializer::Expr(expr)) => {
+				let addr = self.define(Scalar::U64, |r| Inst::Local(r, offset));
+				let value = self.expr(expr)?;
+				self.store(ctype, addr, value);
+				Ok(())
+			}
+		}
+	}
+
+	/// 局部变量: 变长数组在声明处按长度分配, 地址保存在栈帧中
+	fn declare(&mut self, var: &'a Variable) -> Result<(), ParseError> {
+		let id = var.symbol.ok_or_else(|| ParseError::Undeclared(var.name.clone()))?;
+		let offset = *self.locals.get(&id).ok_or_else(|| ParseError::Undeclared(var.name.clone()))?;
+		if var.ctype.is_vla() {
+			let addr = self.define(Scalar::U64, |r| Inst::Local(r, offset));
+			let size = self.size_of(&var.ctype)?;
+			let array = self.define(Scalar::U64, |r| Inst::Alloca(r, size, var.ctype.align()));
+			self.emit(Inst::Store(Scalar::U64, addr, array));
+			return Ok(());
+		}
+		self.local_init(offset, &var.ctype, var.init_value.as_ref())
+	}
+
+	/// 跳出循环或者switch之前释放其中分配的变长数组
+	fn leave_vla_blocks(&mut self, depth: usize) {
+		if let Some(offset) = self.vla_blocks.get(depth).copied() {
+			self.emit(Inst::RestoreStack(offset));
+		}
+	}
+
+	fn block(&mut self, stmts: &'a [Statement]) -> Result<(), ParseError> {
+		if !declares_vla(stmts) {
+			return stmts.iter().try_for_each(|stmt| self.stmt(stmt));
+		}
+		let offset = self.slot(8, 8);
+		self.emit(Inst::SaveStack(offset));
+		self.vla_blocks.push(offset);
+		let result = stmts.iter().try_for_each(|stmt| self.stmt(stmt));
+		self.vla_blocks.pop();
+		self.emit(Inst::RestoreStack(offset));
+		result
+	}
+
+	/// case和default标签开始一个新块, 前面的语句落入这个块
+	fn label(&mut self, value: Option<i64>) {
+		let block = self.new_block();
+		self.jump(block);
+		self.switch_to(block);
+		if let Some(target) = self.targets.iter_mut().rev().find(|target| target.continues.is_none()) {
+			match value {
+				Some(value) => target.cases.push((value, block)),
+				None => target.default = Some(block),
+			}
+		}
+	}
+
+	fn stmt(&mut self, stmt: &'a Statement) -> Result<(), ParseError> {
+		// 语句本身没有位置
+		self.span = None;
+		match stmt {
+			Statement::Empty => (),
+			Statement::CompoundStmt(stmts) => self.block(stmts)?,
+			Statement::ExprStmt(expr) => _ = self.expr(expr)?,
+			Statement::IfStmt(cond, then, else_stmt) => {
+				let cond = self.expr(cond)?;
+				let (then_block, end) = (self.new_block(), self.new_block());
+				let else_block = match else_stmt {
+					Some(_) => self.new_block(),
+					None => end,
+				};
+				self.branch(cond, then_block, else_block);
+				self.switch_to(then_block);
+				self.stmt(then)?;
+				self.jump(end);
+				if let Some(else_stmt) = else_stmt {
+					self.switch_to(else_block);
+					self.stmt(else_stmt)?;
+					self.jump(end);
+				}
+				self.switch_to(end);
+			}
+			Statement::ReturnStmt(expr, _) => {
+				let value = match expr {
+					Some(expr) => self.expr(expr)?,
+					None => self.constant(Scalar::of(&TYPE_VOID), 0),
+				};
+				self.terminate(Terminator::Return(value));
+				self.unreachable();
+			}
+			Statement::DeclStmt(vars) => {
+				for var in vars.iter().filter(|var| !var.is_enum_constant) {
+					self.declare(var)?;
+				}
+			}
+			Statement::ForStmt(init, cond, step, body) => {
+				if let Some(init) = init {
+					self.expr(init)?;
+				}
+				let (head, body_block) = (self.new_block(), self.new_block());
+				let (next, end) = (self.new_block(), self.new_block());
+				self.jump(head);
+				self.switch_to(head);
+				let cond = self.expr(cond)?;
+				self.branch(cond, body_block, end);
+				self.switch_to(body_block);
+				self.targets.push(Target::new(end, Some(next), self.vla_blocks.len()));
+				let result = self.stmt(body);
+				self.targets.pop();
+				result?;
+				self.jump(next);
+				self.switch_to(next);
+				if let Some(step) = step {
+					self.expr(step)?;
+				}
+				self.jump(head);
+				self.switch_to(end);
+			}
+			Statement::SwitchStmt(cond, body) => {
+				// 先执行switch体之后的分派块: 条件的值依次和每个case比较
+				let value = self.expr(cond)?;
+				let (dispatch, end) = (self.new_block(), self.new_block());
+				self.jump(dispatch);
+				self.unreachable();
+				self.targets.push(Target::new(end, None, self.vla_blocks.len()));
+				let result = self.stmt(body);
+				let target = self.targets.pop().expect("switch target");
+				result?;
+				self.jump(end);
+				self.switch_to(dispatch);
+				for (case, block) in target.cases {
+					let case = self.constant(Scalar::I64, case);
+					let matched = self.binary(Punct::Eq, Scalar::I64, Scalar::I32, value, case);
+					let next = self.new_block();
+					self.branch(matched, block, next);
+					self.switch_to(next);
+				}
+				// 没有匹配的case也没有default时跳过switch体
+				self.jump(target.default.unwrap_or(end));
+				self.switch_to(end);
+			}
+			Statement::BreakStmt => {
+				let target = self.targets.last().map(|target| (target.breaks, target.vla_blocks));
+				let (end, depth) = target.ok_or(ParseError::General("break statement not within loop or switch"))?;
+				self.leave_vla_blocks(depth);
+				self.jump(end);
+				self.unreachable();
+			}
+			Statement::ContinueStmt => {
+				let target = self.targets.iter().rev().find_map(|target| Some((target.continues?, target.vla_blocks)));
+				let (next, depth) = target.ok_or(ParseError::General("continue statement not within a loop"))?;
+				self.leave_vla_blocks(depth);
+				self.jump(next);
+				self.unreachable();
+			}
+			Statement::CaseStmt(value, stmt) => {
+				self.label(Some(*value));
+				self.stmt(stmt)?;
+			}
+			Statement::DefaultStmt(stmt) => {
+				self.label(None);
+				self.stmt(stmt)?;
+			}
+			Statement::Error => return Err(unsupported("statement with errors")),
+		}
+		Ok(())
+	}
+
+	/// 函数: 把实参写入参数, 执行函数体, 没有return语句时返回0
+	fn function(&mut self, func: &'a types::Function) -> Result<(usize, Vec<Scalar>, Vec<Block>), ParseError> {
+		self.locals.clear();
+		self.frame_size = 0;
+		for id in func.locals.iter() {
+			let ctype = &self.symbols[id.0].ctype;
+			let offset = match ctype.is_vla() {
+				true => self.slot(8, 8),
+				false => self.slot(ctype.size(), ctype.align()),
+			};
+			self.locals.insert(*id, offset);
+		}
+		self.span = None;
+		let entry = self.new_block();
+		self.switch_to(entry);
+		for (i, (param, id)) in func.ctype.param_list.iter().zip(func.locals.iter()).enumerate() {
+			let offset = self.locals[id];
+			let addr = self.define(Scalar::U64, |r| Inst::Local(r, offset));
+			let arg = self.define(Scalar::of(&param.ctype), |r| Inst::Arg(r, i));
+			self.store(&param.ctype, addr, arg);
+		}
+		match &func.stmts {
+			Statement::CompoundStmt(stmts) => self.block(stmts)?,
+			stmt => self.stmt(stmt)?,
+		}
+		self.span = None;
+		let zero = self.constant(Scalar::of(&func.ctype.return_type), 0);
+		self.terminate(Terminator::Return(zero));
+		let blocks = reverse_postorder(std::mem::take(&mut self.blocks));
+		Ok((align_to(self.frame_size, 16), std::mem::take(&mut self.registers), blocks))
+	}
+}
+
+/// 翻译全部函数, 布局和 codegen::compile 相同
+pub fn lower(functions: &[types::Function], symbols: &[Symbol], globals: &[Variable]) -> Result<Module, ParseError> {
+	let Layout { program, globals, function_index } = layout(functions, symbols, globals)?;
+	let mut module = Module {
+		functions: program
+			.functions
+			.iter()
+			.map(|info| Function {
+				name: info.name.clone(),
+				params: info.params,
+				is_variadic: info.is_variadic,
+				returns_struct: info.returns_struct,
+				frame_size: 0,
+				registers: vec![],
+				blocks: vec![],
+			})
+			.collect(),
+		callees: program.callees,
+		data: vec![],
+		main: None,
+	};
+	let mut lowering = Lowering {
+		symbols,
+		data: program.data,
+		globals,
+		strings: HashMap::new(),
+		function_index,
+		locals: HashMap::new(),
+		frame_size: 0,
+		registers: vec![],
+		blocks: vec![],
+		current: BlockId(0),
+		targets: vec![],
+		vla_blocks: vec![],
+		span: None,
+	};
+	for func in functions.iter().filter(|func| func.is_definition) {
+		let index = func.symbol.and_then(|id| lowering.function_index.get(&id).copied());
+		let (frame_size, registers, blocks) = lowering.function(func)?;
+		if let Some(index) = index {
+			module.functions[index] = Function { frame_size, registers, blocks, ..module.functions[index].clone() };
+		}
+	}
+	module.data = lowering.data;
+	module.main = module.functions.iter().position(|func| func.name == "main" && !func.blocks.is_empty());
+	Ok(module)
+}
+
+fn mnemonic(op: Punct) -> &'static str {
+	match op {
+		Punct::Or => "or",
+		Punct::Xor => "xor",
+		Punct::And => "and",
+		Punct::Eq => "eq",
+		Punct::Ne => "ne",
+		Punct::Lt => "lt",
+		Punct::Gt => "gt",
+		Punct::Le => "le",
+		Punct::Ge => "ge",
+		Punct::Shl => "shl",
+		Punct::Shr => "shr",
+		Punct::Add => "add",
+		Punct::Sub => "sub",
+		Punct::Mul => "mul",
+		Punct::Div => "div",
+		_ => "mod",
+	}
+}
+
+impl Display for Reg {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "%{}", self.0)
+	}
+}
+
+impl Display for BlockId {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "bb{}", self.0)
+	}
+}
+
+impl Display for Terminator {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Terminator::Jump(target) => write!(f, "jmp {}", target),
+			Terminator::Branch(cond, then, other) => write!(f, "br {}, {}, {}", cond, then, other),
+			Terminator::Return(value) => write!(f, "ret {}", value),
+		}
+	}
+}
+
+fn list(regs: &[Reg]) -> String {
+	regs.iter().map(|r| r.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+impl Module {
+	/// 一条指令的文本形式, 定义寄存器的指令写出寄存器的类型
+	fn inst(&self, func: &Function, inst: &Inst) -> String {
+		let define = |r: &Reg, rest: String| format!("{}: {} = {}", r, func.registers[r.0], rest);
+		match inst {
+			Inst::Const(r, n) => define(r, format!("const {}", n)),
+			Inst::Local(r, n) => define(r, format!("local {}", n)),
+			Inst::Global(r, n) => define(r, format!("global {}", n)),
+			Inst::Arg(r, n) => define(r, format!("arg {}", n)),
+			Inst::Mov(r, x) => format!("{} = {}", r, x),
+			Inst::Cast(r, x) => define(r, format!("cast {}", x)),
+			Inst::Binary(op, s, r, x, y) => define(r, format!("{} {} {}, {}", mnemonic(*op), s, x, y)),
+			Inst::Load(r, x) => define(r, format!("load {}", x)),
+			Inst::Store(s, x, y) => format!("store {} {}, {}", s, x, y),
+			Inst::Copy(x, y, n) => format!("copy {}, {}, {}", x, y, n),
+			Inst::Zero(x, n) => format!("zero {}, {}", x, n),
+			Inst::Alloca(r, x, align) => define(r, format!("alloca {}, {}", x, align)),
+			Inst::SaveStack(n) => format!("savesp {}", n),
+			Inst::RestoreStack(n) => format!("restoresp {}", n),
+			Inst::Call(r, index, args) => define(r, format!("call {}({})", self.functions[*index].name, list(args))),
+			Inst::CallBuiltin(r, builtin, args) => define(r, format!("call {}({})", builtin.name(), list(args))),
+			Inst::CallIndirect(r, pointer, args) => define(r, format!("call {}({})", pointer, list(args))),
+		}
+	}
+}
+
+/// 文本形式: 每个有定义的函数一段, 函数头是名字, 参数个数和栈帧大小, 之后是每个基本块
+impl Display for Module {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		for (i, func) in self.functions.iter().filter(|func| !func.blocks.is_empty()).enumerate() {
+			if i > 0 {
+				writeln!(f)?;
+			}
+			let variadic = if func.is_variadic { ", ..." } else { "" };
+			writeln!(f, "fn {}(params {}{}) frame {}:", func.name, func.params, variadic, func.frame_size)?;
+			for (id, block) in func.blocks.iter().enumerate() {
+				writeln!(f, "{}:", BlockId(id))?;
+				for inst in block.insts.iter() {
+					writeln!(f, "\t{}", self.inst(func, inst))?;
+				}
+				writeln!(f, "\t{}", block.terminator)?;
+			}
+		}
+		Ok(())
+	}
+}
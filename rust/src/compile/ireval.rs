@@ -0,0 +1,171 @@
+//! 解释执行 ir 的三地址代码, 用来和语法树的解释器互相验证翻译是否正确.
+//! 每次调用有自己的寄存器数组; 内存, 内置函数和资源限制与其它执行方式共用 Env
+
+use super::{
+	codegen::{Callee, Scalar},
+	errors::{RuntimeError, Trap},
+	eval::{binary, finish, Env},
+	ir::{Inst, Module, Reg, Terminator},
+	memory::{address, Memory, DATA_BASE, FUNCTION_BASE},
+	token::Span,
+};
+
+/// 一次调用: 执行到的位置, 寄存器和实参, 返回值写到调用者的哪个寄存器
+#[derive(Debug)]
+struct CallFrame {
+	function: usize,
+	block: usize,
+	/// 下一条指令在块中的下标, 等于指令数时下一步执行块的 terminator
+	index: usize,
+	registers: Vec<i64>,
+	args: Vec<i64>,
+	result: Reg,
+	bp: usize,
+	/// 调用之前内存的栈顶, 返回时释放之后的空间
+	sp: usize,
+	call_site: Option<Span>,
+}
+
+pub struct IrInterpreter<'a> {
+	module: &'a Module,
+}
+
+impl<'a> IrInterpreter<'a> {
+	pub fn new(module: &'a Module) -> Self {
+		IrInterpreter { module }
+	}
+
+	/// 初始化数据段之后执行main, 返回main的返回值或者exit的参数
+	pub fn run(&self, env: &mut Env, args: &[String]) -> Result<i64, Trap> {
+		env.start(Memory::new(self.module.data.clone()));
+		let mut frames = vec![];
+		let result = match self.module.main {
+			Some(main) => {
+				let main_args = env.enter_main(args, self.module.functions[main].params);
+				self.call(main, main_args, Reg(0), None, env, &mut frames).and_then(|_| self.execute(env, &mut frames))
+			}
+			None => Err(RuntimeError::MissingMain),
+		};
+		let calls = frames.iter().map(|frame| (self.module.functions[frame.function].name.as_str(), frame.call_site));
+		finish(result, self.span(&frames), calls)
+	}
+
+	/// 出错的指令或者 terminator 对应的位置, index已经指向下一条
+	fn span(&self, frames: &[CallFrame]) -> Option<Span> {
+		let frame = frames.last()?;
+		let block = &self.module.functions[frame.function].blocks[frame.block];
+		match frame.index.checked_sub(1) {
+			Some(i) if i < block.insts.len() => block.spans[i],
+			_ => block.span,
+		}
+	}
+
+	/// 进入函数并分配栈帧
+	fn call(
+		&self,
+		function: usize,
+		args: Vec<i64>,
+		result: Reg,
+		call_site: Option<Span>,
+		env: &mut Env,
+		frames: &mut Vec<CallFrame>,
+	) -> Result<(), RuntimeError> {
+		let func = &self.module.functions[function];
+		if func.blocks.is_empty() {
+			return Err(RuntimeError::UndefinedFunction(func.name.clone()));
+		}
+		env.check_call(func.params, func.is_variadic, args.len(), frames.len())?;
+		let sp = env.memory.stack_pointer();
+		let registers = vec![0; func.registers.len()];
+		frames.push(CallFrame { function, block: 0, index: 0, registers, args, result, bp: 0, sp, call_site });
+		let bp = env.memory.push(func.frame_size, 16)?;
+		if let Some(frame) = frames.last_mut() {
+			frame.bp = bp;
+		}
+		Ok(())
+	}
+
+	/// 逐条执行, 直到main返回
+	fn execute(&self, env: &mut Env, frames: &mut Vec<CallFrame>) -> Result<i64, RuntimeError> {
+		loop {
+			env.tick()?;
+			let frame = frames.last_mut().expect("a frame is running");
+			let func = &self.module.functions[frame.function];
+			let block = &func.blocks[frame.block];
+			let index = frame.index;
+			frame.index += 1;
+			let Some(inst) = block.insts.get(index) else {
+				match block.terminator {
+					Terminator::Jump(target) => (frame.block, frame.index) = (target.0, 0),
+					Terminator::Branch(cond, then, other) => {
+						let target = if frame.registers[cond.0] != 0 { then } else { other };
+						(frame.block, frame.index) = (target.0, 0);
+					}
+					Terminator::Return(value) => {
+						let value = frame.registers[value.0];
+						let frame = frames.pop().expect("a frame is running");
+						let value = env.return_value(value, func.returns_struct, frame.sp)?;
+						match frames.last_mut() {
+							Some(caller) => caller.registers[frame.result.0] = value,
+							None => return Ok(value),
+						}
+					}
+				}
+				continue;
+			};
+			let CallFrame { registers, args, bp, .. } = frame;
+			let bp = *bp;
+			let r = |reg: &Reg| reg.0;
+			match inst {
+				Inst::Const(dst, n) => registers[r(dst)] = *n,
+				Inst::Local(dst, n) => registers[r(dst)] = (bp + n) as i64,
+				Inst::Global(dst, n) => registers[r(dst)] = (DATA_BASE + n) as i64,
+				Inst::Arg(dst, n) => registers[r(dst)] = args[*n],
+				Inst::Mov(dst, x) => registers[r(dst)] = registers[r(x)],
+				Inst::Cast(dst, x) => registers[r(dst)] = func.registers[r(dst)].ctype().convert(registers[r(x)]),
+				Inst::Binary(op, scalar, dst, x, y) => {
+					let (left, right) = (registers[r(x)], registers[r(y)]);
+					registers[r(dst)] = binary(*op, &scalar.ctype(), left, right, env.trap_overflow)?;
+				}
+				Inst::Load(dst, x) => {
+					registers[r(dst)] = env.memory.load(address(registers[r(x)]), &func.registers[r(dst)].ctype())?
+				}
+				Inst::Store(scalar, x, y) => {
+					env.memory.store(address(registers[r(x)]), &scalar.ctype(), registers[r(y)])?
+				}
+				Inst::Copy(x, y, size) => env.memory.copy(address(registers[r(x)]), address(registers[r(y)]), *size)?,
+				Inst::Zero(x, size) => env.memory.fill(address(registers[r(x)]), *size, 0)?,
+				Inst::Alloca(dst, x, align) => {
+					registers[r(dst)] = env.memory.push(address(registers[r(x)]), *align)? as i64
+				}
+				Inst::SaveStack(n) => {
+					let sp = env.memory.stack_pointer() as i64;
+					env.memory.store(bp + n, &Scalar::U64.ctype(), sp)?;
+				}
+				Inst::RestoreStack(n) => {
+					let sp = env.memory.load(bp + n, &Scalar::U64.ctype())?;
+					env.memory.pop_to(address(sp));
+				}
+				Inst::Call(dst, function, call_args) => {
+					let values = call_args.iter().map(|arg| registers[r(arg)]).collect();
+					self.call(*function, values, *dst, block.spans[index], env, frames)?;
+				}
+				Inst::CallBuiltin(dst, builtin, call_args) => {
+					let values: Vec<i64> = call_args.iter().map(|arg| registers[r(arg)]).collect();
+					registers[r(dst)] = env.call_builtin(*builtin, &values)?;
+				}
+				Inst::CallIndirect(dst, pointer, call_args) => {
+					let callee = registers[r(pointer)];
+					let values: Vec<i64> = call_args.iter().map(|arg| registers[r(arg)]).collect();
+					match self.module.callees.get(&address(callee).wrapping_sub(FUNCTION_BASE)) {
+						Some(Callee::Function(function)) => {
+							self.call(*function, values, *dst, block.spans[index], env, frames)?
+						}
+						Some(Callee::Builtin(builtin)) => registers[r(dst)] = env.call_builtin(*builtin, &values)?,
+						None => return Err(RuntimeError::NotFunction(callee)),
+					}
+				}
+			}
+		}
+	}
+}
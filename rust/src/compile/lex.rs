@@ -2,13 +2,18 @@ use crate::compile::errors::*;
 use itertools::Itertools;
 use std::str::Chars;
 
-use super::token::{Const, Keyword, Punct, Token};
+use super::token::{Const, Keyword, Punct, Span, Token};
 
 #[derive(Debug)]
 pub struct TokenApi {
 	/// 当前行号
 	line: isize,
 	token_count: isize,
+	input_len: usize,
+	/// 当前行开始处的字节偏移
+	line_start: usize,
+	/// 当前token开始处的字节偏移
+	token_start: usize,
 }
 
 type LexResult = Option<Result<Token, LexError>>;
@@ -72,12 +77,20 @@ impl TokenApi {
 		}))
 	}
 
-	/// const处理, 应该包含int, float, char
+	/// const处理, 应该包含int, float, char. 整数的后缀 u l ll 保留在常量中, 类型检查时确定类型
 	fn try_decimal(&mut self, iter: &mut Chars, c: char) -> LexResult {
 		let mut str = String::from(c);
 		while let Some(nc) = iter.peeking_take_while(is_digit).next() {
 			str.push(nc);
 		}
+		let mut suffix = String::new();
+		while let Some(nc) = iter.peeking_take_while(is_id_char).next() {
+			suffix.push(nc);
+		}
+		if !matches!(suffix.to_ascii_lowercase().as_str(), "" | "u" | "l" | "ll" | "ul" | "lu" | "ull" | "llu") {
+			return Some(Err(LexError::InvalidChar(suffix.chars().next().unwrap_or(c))));
+		}
+		str.push_str(&suffix);
 		Some(Ok(Token::Const(Const::Integer(str))))
 	}
 
@@ -168,15 +181,17 @@ impl TokenApi {
 	/// 标识符
 	fn try_next_token(&mut self, iter: &mut Chars) -> LexResult {
 		// 不可以使用for in, into iter 会move走迭代器,就不能手动控制了
-		while let Some(c) = iter.next() {
+		loop {
+			self.token_start = self.offset(iter);
+			let c = iter.next()?;
 			match c {
 				' ' | '\t' => {} // skip 空白
 				'\r' => {
 					// 处理换行
 					iter.peeking_take_while(|&x| x == '\n').next();
-					self.line += 1;
+					self.new_line(iter);
 				}
-				'\n' => self.line += 1,
+				'\n' => self.new_line(iter),
 				// 跳过 # 和换行之间的内容,预处理.
 				'#' => while iter.peeking_take_while(is_not_new_line).next().is_some() {},
 				'/' => {
@@ -380,31 +395,53 @@ impl TokenApi {
 				_ => return Some(Err(LexError::InvalidChar(c))),
 			}
 		}
-		None
+	}
+
+	#[inline]
+	fn offset(&self, iter: &Chars) -> usize {
+		self.input_len - iter.as_str().len()
+	}
+
+	fn new_line(&mut self, iter: &Chars) {
+		self.line += 1;
+		self.line_start = self.offset(iter);
 	}
 
 	/// 对输入字符串进行词法解析,得到一组token list,或者错误信息
 	/// TODO 修改接口,把迭代器放到结构体中
+	#[cfg(test)]
 	pub fn parse_all(input: &str) -> Result<Vec<Token>, LexError> {
+		Self::parse_all_with_span(input).map(|(token_list, _)| token_list)
+	}
+
+	/// 同时返回每个token在源码中的位置
+	pub fn parse_all_with_span(input: &str) -> Result<(Vec<Token>, Vec<Span>), LexError> {
 		let mut token_list = vec![];
-		let mut lex_state = TokenApi { line: 1, token_count: 0 };
+		let mut spans = vec![];
+		let mut lex_state = TokenApi { line: 1, token_count: 0, input_len: input.len(), line_start: 0, token_start: 0 };
 		let mut iter = input.chars();
 		while let Some(result) = lex_state.try_next_token(&mut iter) {
 			token_list.push(result?);
+			spans.push(Span {
+				start: lex_state.token_start,
+				end: lex_state.offset(&iter),
+				line: lex_state.line as usize,
+				column: lex_state.token_start - lex_state.line_start + 1,
+			});
 			lex_state.token_count += 1;
 		}
-		Ok(token_list)
+		Ok((token_list, spans))
 	}
 }
 
 #[inline]
 fn is_digit(c: &char) -> bool {
-	('0'..='9').contains(c)
+	c.is_ascii_digit()
 }
 
 #[inline]
 fn is_id_initial_char(c: &char) -> bool {
-	('a'..='z').contains(c) || ('A'..='Z').contains(c) || *c == '_'
+	c.is_ascii_alphabetic() || *c == '_'
 }
 
 #[inline]
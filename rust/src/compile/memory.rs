@@ -0,0 +1,192 @@
+//! 解释器的内存: 平坦的地址空间分成数据段, 栈和堆三段, 按字节寻址.
+//! 数据段之前的地址不属于任何段, 其中的一部分用作函数的地址, 只能调用不能读写
+
+use std::collections::BTreeMap;
+
+use super::{errors::RuntimeError, types::*};
+
+/// 值作为地址使用, 负数是很大的地址, 访问时报告越界
+pub fn address(value: i64) -> usize {
+	value as u64 as usize
+}
+
+/// 函数的地址是 FUNCTION_BASE + 符号的编号
+pub const FUNCTION_BASE: usize = 0x1000;
+pub const DATA_BASE: usize = 0x10_0000;
+pub const STACK_BASE: usize = 0x1000_0000;
+pub const HEAP_BASE: usize = 0x2000_0000;
+/// 栈的最大字节数, 超过是栈溢出
+pub const STACK_LIMIT: usize = 8 << 20;
+
+#[derive(Debug)]
+pub struct Segment {
+	base: usize,
+	bytes: Vec<u8>,
+}
+
+impl Segment {
+	fn new(base: usize, bytes: Vec<u8>) -> Self {
+		Segment { base, bytes }
+	}
+
+	/// 地址范围在段内时返回段内的偏移
+	fn offset(&self, addr: usize, size: usize) -> Option<usize> {
+		let offset = addr.checked_sub(self.base)?;
+		(offset.checked_add(size)? <= self.bytes.len()).then_some(offset)
+	}
+}
+
+#[derive(Debug)]
+pub struct Memory {
+	data: Segment,
+	/// 栈向高地址增长, 段的长度就是栈顶
+	stack: Segment,
+	/// 堆只增长不回收, 只有已分配且没有释放的块可以访问
+	heap: Segment,
+	/// 堆中的块: 地址, 大小
+	blocks: BTreeMap<usize, usize>,
+	/// 还没有释放的块的总字节数
+	heap_in_use: usize,
+}
+
+impl Memory {
+	pub fn new(data: Vec<u8>) -> Self {
+		Memory {
+			data: Segment::new(DATA_BASE, data),
+			stack: Segment::new(STACK_BASE, vec![]),
+			heap: Segment::new(HEAP_BASE, vec![]),
+			blocks: BTreeMap::new(),
+			heap_in_use: 0,
+		}
+	}
+
+	/// 地址范围是否在一个堆块中
+	fn in_block(&self, addr: usize, size: usize) -> bool {
+		match self.blocks.range(..=addr).next_back() {
+			Some((base, len)) => addr.checked_add(size).is_some_and(|end| end <= base + len),
+			None => false,
+		}
+	}
+
+	fn segment(&self, addr: usize, size: usize) -> Result<(&Segment, usize), RuntimeError> {
+		if addr >= HEAP_BASE && !self.in_block(addr, size) {
+			return Err(RuntimeError::invalid_address(addr, size));
+		}
+		[&self.data, &self.stack, &self.heap]
+			.into_iter()
+			.find_map(|seg| seg.offset(addr, size).map(|offset| (seg, offset)))
+			.ok_or_else(|| RuntimeError::invalid_address(addr, size))
+	}
+
+	fn segment_mut(&mut self, addr: usize, size: usize) -> Result<(&mut Segment, usize), RuntimeError> {
+		if addr >= HEAP_BASE && !self.in_block(addr, size) {
+			return Err(RuntimeError::invalid_address(addr, size));
+		}
+		[&mut self.data, &mut self.stack, &mut self.heap]
+			.into_iter()
+			.find_map(|seg| seg.offset(addr, size).map(|offset| (seg, offset)))
+			.ok_or_else(|| RuntimeError::invalid_address(addr, size))
+	}
+
+	pub fn read(&self, addr: usize, size: usize) -> Result<&[u8], RuntimeError> {
+		let (seg, offset) = self.segment(addr, size)?;
+		Ok(&seg.bytes[offset..offset + size])
+	}
+
+	pub fn write(&mut self, addr: usize, bytes: &[u8]) -> Result<(), RuntimeError> {
+		let (seg, offset) = self.segment_mut(addr, bytes.len())?;
+		seg.bytes[offset..offset + bytes.len()].copy_from_slice(bytes);
+		Ok(())
+	}
+
+	/// 把一段内存填成同一个字节. 先检查范围, 不按大小分配临时的缓冲区
+	pub fn fill(&mut self, addr: usize, size: usize, byte: u8) -> Result<(), RuntimeError> {
+		let (seg, offset) = self.segment_mut(addr, size)?;
+		seg.bytes[offset..offset + size].fill(byte);
+		Ok(())
+	}
+
+	/// 读出以0结尾的字符串, 不包括结尾的0
+	pub fn read_c_string(&self, addr: usize) -> Result<Vec<u8>, RuntimeError> {
+		let mut bytes = vec![];
+		loop {
+			match self.read(addr + bytes.len(), 1)?[0] {
+				0 => return Ok(bytes),
+				b => bytes.push(b),
+			}
+		}
+	}
+
+	pub fn copy(&mut self, dst: usize, src: usize, size: usize) -> Result<(), RuntimeError> {
+		let bytes = self.read(src, size)?.to_vec();
+		self.write(dst, &bytes)
+	}
+
+	/// 按类型读出标量: 有符号整数符号扩展, 无符号整数和指针零扩展
+	pub fn load(&self, addr: usize, ctype: &Type) -> Result<i64, RuntimeError> {
+		let bytes = self.read(addr, ctype.size())?;
+		let mut buf = [0; 8];
+		let len = bytes.len().min(8);
+		buf[..len].copy_from_slice(&bytes[..len]);
+		Ok(ctype.convert(i64::from_le_bytes(buf)))
+	}
+
+	/// 按类型写入标量, 截断到类型的大小
+	pub fn store(&mut self, addr: usize, ctype: &Type, value: i64) -> Result<(), RuntimeError> {
+		let value = match ctype {
+			Type::Bool => (value != 0) as i64,
+			_ => value,
+		};
+		self.write(addr, &value.to_le_bytes()[..ctype.size()])
+	}
+
+	/// 数据段末尾追加只读数据(如字符串字面量), 返回地址
+	pub fn append_data(&mut self, bytes: &[u8]) -> usize {
+		let addr = self.data.base + self.data.bytes.len();
+		self.data.bytes.extend_from_slice(bytes);
+		addr
+	}
+
+	pub fn stack_pointer(&self) -> usize {
+		self.stack.base + self.stack.bytes.len()
+	}
+
+	/// 在栈顶分配清零的空间, 返回地址
+	pub fn push(&mut self, size: usize, align: usize) -> Result<usize, RuntimeError> {
+		let addr = align_to(self.stack_pointer(), align.max(1));
+		let len = (addr - self.stack.base).checked_add(size).filter(|&len| len <= STACK_LIMIT);
+		self.stack.bytes.resize(len.ok_or(RuntimeError::StackOverflow)?, 0);
+		Ok(addr)
+	}
+
+	/// 堆中还没有释放的块的字节数
+	pub fn heap_size(&self) -> usize {
+		self.heap_in_use
+	}
+
+	/// 释放sp之上的栈空间, 之后再访问是越界
+	pub fn pop_to(&mut self, sp: usize) {
+		self.stack.bytes.truncate(sp - self.stack.base);
+	}
+
+	/// 在堆上分配清零的块, 按16字节对齐
+	pub fn malloc(&mut self, size: usize) -> usize {
+		let addr = align_to(self.heap.base + self.heap.bytes.len(), 16);
+		// malloc(0) 也返回不同的地址
+		self.heap.bytes.resize(addr - self.heap.base + size.max(1), 0);
+		self.blocks.insert(addr, size);
+		self.heap_in_use += size;
+		addr
+	}
+
+	/// 释放堆块, 地址必须是malloc返回的并且没有释放过
+	pub fn free(&mut self, addr: usize) -> Result<(), RuntimeError> {
+		match self.blocks.remove(&addr) {
+			Some(size) => {
+				self.heap_in_use -= size;
+				Ok(())
+			}
+			None => Err(RuntimeError::InvalidFree(addr)),
+		}
+	}
+}
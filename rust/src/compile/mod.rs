@@ -1,9 +1,24 @@
+pub mod builtins;
+pub mod check;
+pub mod codegen;
+pub mod consteval;
+pub mod data;
+pub mod disasm;
 pub mod errors;
 mod impls;
+pub mod ir;
+pub mod ireval;
+pub mod memory;
+pub mod object;
 pub mod parse;
+pub mod regcode;
+pub mod regvm;
+pub mod resolve;
 pub mod types;
 pub mod token;
 pub mod tree;
+pub mod verify;
+pub mod vm;
 
 pub mod lex;
 pub mod token_impl;
@@ -0,0 +1,303 @@
+//! 编译好的字节码文件 .tbc, 保存之后不用重新解析就能执行.
+//!
+//! 文件以 MAGIC 和格式版本开头, 后面依次是五个部分, 每部分是4字节的长度加内容, 整数都是小端序:
+//! 元数据(json: 源文件名, 函数表, 函数地址表, main, 数据段的符号表), 指令, 全局变量的初始值,
+//! 字符串字面量, 源码行表(每条指令的 行 列 起止偏移)
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+	builtins::Builtin,
+	codegen::{Callee, FunctionInfo, Opcode, Program, Scalar},
+	errors::ObjectError,
+	token::{Punct, Span},
+};
+
+pub const MAGIC: [u8; 4] = *b"\x7fTBC";
+/// 格式改变时加一, 不读取其它版本的文件
+pub const VERSION: u32 = 1;
+
+const SCALARS: [Scalar; 9] = [
+	Scalar::Bool,
+	Scalar::I8,
+	Scalar::U8,
+	Scalar::I16,
+	Scalar::U16,
+	Scalar::I32,
+	Scalar::U32,
+	Scalar::I64,
+	Scalar::U64,
+];
+
+/// 二元运算指令的编码是 BINARY + 运算符在这里的下标, 后面是类型
+const BINARY: u8 = 0x20;
+const OPERATORS: [Punct; 16] = [
+	Punct::Or,
+	Punct::Xor,
+	Punct::And,
+	Punct::Eq,
+	Punct::Ne,
+	Punct::Lt,
+	Punct::Gt,
+	Punct::Le,
+	Punct::Ge,
+	Punct::Shl,
+	Punct::Shr,
+	Punct::Add,
+	Punct::Sub,
+	Punct::Mul,
+	Punct::Div,
+	Punct::Mod,
+];
+
+/// 一个 .tbc 文件: 编译好的程序和它的源文件名, 源文件用来报告运行时错误的位置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Object {
+	pub source: String,
+	pub program: Program,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Metadata {
+	source: String,
+	functions: Vec<FunctionInfo>,
+	/// 按符号编号排序, 保证同一个程序写出的文件相同
+	callees: Vec<(usize, Callee)>,
+	main: Option<usize>,
+	symbols: Vec<(String, usize)>,
+}
+
+fn put_usize(out: &mut Vec<u8>, n: usize) {
+	out.extend_from_slice(&(n as u64).to_le_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, n: usize) {
+	out.extend_from_slice(&(n as u32).to_le_bytes());
+}
+
+fn scalar(s: Scalar) -> u8 {
+	SCALARS.iter().position(|x| *x == s).unwrap() as u8
+}
+
+fn encode(op: Opcode, out: &mut Vec<u8>) {
+	if let Some((punct, s)) = op.operator() {
+		let index = OPERATORS.iter().position(|x| *x == punct).unwrap();
+		out.extend_from_slice(&[BINARY + index as u8, scalar(s)]);
+		return;
+	}
+	let (tag, operands) = match op {
+		Opcode::Lea(n) => (0, vec![n]),
+		Opcode::Imm(n) => (1, vec![n as usize]),
+		Opcode::Jmp(n) => (2, vec![n]),
+		Opcode::Jsr(function, argc) => (3, vec![function, argc]),
+		Opcode::Jsri(argc) => (4, vec![argc]),
+		Opcode::Bz(n) => (5, vec![n]),
+		Opcode::Bnz(n) => (6, vec![n]),
+		Opcode::Ent(n) => (7, vec![n]),
+		Opcode::Adj(n) => (8, vec![n]),
+		Opcode::Lgb(n) => (9, vec![n]),
+		Opcode::Lev => (10, vec![]),
+		Opcode::Arg(n) => (11, vec![n]),
+		Opcode::Copy(n) => (12, vec![n]),
+		Opcode::Zero(n) => (13, vec![n]),
+		Opcode::Alloca(n) => (14, vec![n]),
+		Opcode::Ssp(n) => (15, vec![n]),
+		Opcode::Rsp(n) => (16, vec![n]),
+		Opcode::Psh => (17, vec![]),
+		Opcode::Sys(builtin, argc) => {
+			let index = Builtin::ALL.iter().position(|x| *x == builtin).unwrap();
+			(18, vec![index, argc])
+		}
+		Opcode::Load(s) => return out.extend_from_slice(&[19, scalar(s)]),
+		Opcode::Store(s) => return out.extend_from_slice(&[20, scalar(s)]),
+		Opcode::Cast(s) => return out.extend_from_slice(&[21, scalar(s)]),
+		_ => unreachable!("binary operators are encoded above"),
+	};
+	out.push(tag);
+	for n in operands {
+		put_usize(out, n);
+	}
+}
+
+/// 按顺序读取文件的内容, 读过头时报告 Truncated
+struct Reader<'a> {
+	bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+	fn take(&mut self, n: usize) -> Result<&'a [u8], ObjectError> {
+		if self.bytes.len() < n {
+			return Err(ObjectError::Truncated);
+		}
+		let (head, tail) = self.bytes.split_at(n);
+		self.bytes = tail;
+		Ok(head)
+	}
+
+	fn u8(&mut self) -> Result<u8, ObjectError> {
+		Ok(self.take(1)?[0])
+	}
+
+	fn u32(&mut self) -> Result<usize, ObjectError> {
+		Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()) as usize)
+	}
+
+	fn usize(&mut self) -> Result<usize, ObjectError> {
+		Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()) as usize)
+	}
+
+	fn section(&mut self) -> Result<Reader<'a>, ObjectError> {
+		let len = self.u32()?;
+		Ok(Reader { bytes: self.take(len)? })
+	}
+
+	fn scalar(&mut self) -> Result<Scalar, ObjectError> {
+		let index = self.u8()?;
+		SCALARS.get(index as usize).copied().ok_or_else(|| malformed(format!("unknown type {}", index)))
+	}
+
+	fn opcode(&mut self) -> Result<Opcode, ObjectError> {
+		let tag = self.u8()?;
+		Ok(match tag {
+			0 => Opcode::Lea(self.usize()?),
+			1 => Opcode::Imm(self.usize()? as i64),
+			2 => Opcode::Jmp(self.usize()?),
+			3 => Opcode::Jsr(self.usize()?, self.usize()?),
+			4 => Opcode::Jsri(self.usize()?),
+			5 => Opcode::Bz(self.usize()?),
+			6 => Opcode::Bnz(self.usize()?),
+			7 => Opcode::Ent(self.usize()?),
+			8 => Opcode::Adj(self.usize()?),
+			9 => Opcode::Lgb(self.usize()?),
+			10 => Opcode::Lev,
+			11 => Opcode::Arg(self.usize()?),
+			12 => Opcode::Copy(self.usize()?),
+			13 => Opcode::Zero(self.usize()?),
+			14 => Opcode::Alloca(self.usize()?),
+			15 => Opcode::Ssp(self.usize()?),
+			16 => Opcode::Rsp(self.usize()?),
+			17 => Opcode::Psh,
+			18 => {
+				let index = self.usize()?;
+				let builtin = Builtin::ALL.get(index).ok_or_else(|| malformed(format!("unknown builtin {}", index)))?;
+				Opcode::Sys(*builtin, self.usize()?)
+			}
+			19 => Opcode::Load(self.scalar()?),
+			20 => Opcode::Store(self.scalar()?),
+			21 => Opcode::Cast(self.scalar()?),
+			_ => match OPERATORS.get(tag.wrapping_sub(BINARY) as usize) {
+				Some(punct) => Opcode::binary(*punct, self.scalar()?).unwrap(),
+				None => return Err(ObjectError::UnknownOpcode(tag)),
+			},
+		})
+	}
+}
+
+fn malformed(what: String) -> ObjectError {
+	ObjectError::Malformed(what)
+}
+
+impl Object {
+	pub fn new(source: &str, program: Program) -> Self {
+		Object { source: source.to_string(), program }
+	}
+
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let program = &self.program;
+		let mut callees: Vec<_> = program.callees.iter().map(|(id, callee)| (*id, *callee)).collect();
+		callees.sort_by_key(|(id, _)| *id);
+		let metadata = Metadata {
+			source: self.source.clone(),
+			functions: program.functions.clone(),
+			callees,
+			main: program.main,
+			symbols: program.symbols.clone(),
+		};
+		let mut code = vec![];
+		for op in program.code.iter() {
+			encode(*op, &mut code);
+		}
+		let mut lines = vec![];
+		for span in program.spans.iter() {
+			// 没有位置的指令行号是0
+			let span = span.unwrap_or_default();
+			for n in [span.line, span.column, span.start, span.end] {
+				put_u32(&mut lines, n);
+			}
+		}
+		let (data, strings) = program.data.split_at(program.string_pool);
+
+		let mut out = MAGIC.to_vec();
+		put_u32(&mut out, VERSION as usize);
+		let sections = [serde_json::to_vec(&metadata).unwrap(), code, data.to_vec(), strings.to_vec(), lines];
+		for section in sections {
+			put_u32(&mut out, section.len());
+			out.extend_from_slice(&section);
+		}
+		out
+	}
+
+	/// 读取 to_bytes 写出的内容, 检查格式版本和各部分是否一致
+	pub fn from_bytes(bytes: &[u8]) -> Result<Object, ObjectError> {
+		let mut reader = Reader { bytes };
+		if reader.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+			return Err(ObjectError::BadMagic);
+		}
+		let version = reader.u32()? as u32;
+		if version != VERSION {
+			return Err(ObjectError::Version(version, VERSION));
+		}
+		let metadata = reader.section()?;
+		let metadata: Metadata =
+			serde_json::from_slice(metadata.bytes).map_err(|e| ObjectError::Metadata(e.to_string()))?;
+		let mut code = vec![];
+		let mut code_section = reader.section()?;
+		while !code_section.bytes.is_empty() {
+			code.push(code_section.opcode()?);
+		}
+		let data = reader.section()?.bytes;
+		let strings = reader.section()?.bytes;
+		let mut lines = reader.section()?;
+		let mut spans = vec![];
+		while !lines.bytes.is_empty() {
+			let span = Span { line: lines.u32()?, column: lines.u32()?, start: lines.u32()?, end: lines.u32()? };
+			if span.start > span.end {
+				return Err(malformed(format!("line {} ends before it starts", span.line)));
+			}
+			spans.push((span.line > 0).then_some(span));
+		}
+		if !reader.bytes.is_empty() {
+			return Err(malformed(format!("{} trailing bytes", reader.bytes.len())));
+		}
+
+		if spans.len() != code.len() {
+			return Err(malformed(format!("{} lines for {} instructions", spans.len(), code.len())));
+		}
+		let functions = metadata.functions.len();
+		if metadata.main.is_some_and(|main| main >= functions) {
+			return Err(malformed("main is not in the function table".to_string()));
+		}
+		for func in metadata.functions.iter() {
+			if func.entry.is_some_and(|entry| entry >= code.len()) {
+				return Err(malformed(format!("entry of {} is out of code", func.name)));
+			}
+		}
+		for (id, callee) in metadata.callees.iter() {
+			if matches!(callee, Callee::Function(index) if *index >= functions) {
+				return Err(malformed(format!("function address {} is not in the function table", id)));
+			}
+		}
+
+		let program = Program {
+			code,
+			spans,
+			functions: metadata.functions,
+			callees: metadata.callees.into_iter().collect(),
+			data: [data, strings].concat(),
+			string_pool: data.len(),
+			main: metadata.main,
+			symbols: metadata.symbols,
+		};
+		Ok(Object { source: metadata.source, program })
+	}
+}
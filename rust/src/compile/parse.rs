@@ -3,17 +3,107 @@ use std::collections::HashMap;
 use console::style;
 
 use super::{
+	check,
+	consteval::{self, Binding},
+	data::DataSegment,
 	errors::*,
 	eval::{Env, VM},
-	token::{Const, Keyword, Precedence, Punct, Token, TokenList},
+	resolve,
+	token::{Const, Keyword, Precedence, Punct, Span, Token, TokenList},
 	types::*,
 };
 
+/// 块作用域
+#[derive(Default)]
+struct Scope {
+	/// 结构体和枚举标签. 枚举类型就是int
+	tags: HashMap<String, Type>,
+	/// 普通标识符, 用于在解析时计算常量表达式. 名字解析另外由resolve完成
+	names: HashMap<String, Binding>,
+}
+
+/// 组合出整数类型的说明符, 如 unsigned long int, 顺序任意
+#[derive(Debug, Default, PartialEq, Eq)]
+struct IntSpec {
+	/// signed 或 unsigned
+	sign: Option<Keyword>,
+	char: bool,
+	short: bool,
+	int: bool,
+	/// long long 和 long 相同
+	long: usize,
+}
+
+impl IntSpec {
+	/// 重复的说明符返回false
+	fn add(&mut self, keyword: Keyword) -> bool {
+		let repeated = match keyword {
+			Keyword::Signed | Keyword::Unsigned => self.sign.replace(keyword).is_some(),
+			Keyword::Char => std::mem::replace(&mut self.char, true),
+			Keyword::Short => std::mem::replace(&mut self.short, true),
+			Keyword::Int => std::mem::replace(&mut self.int, true),
+			_ => {
+				self.long += 1;
+				self.long > 2
+			}
+		};
+		!repeated
+	}
+
+	/// 不合法的组合, 如 short long, long char, 返回None
+	fn ctype(&self) -> Option<Type> {
+		let unsigned = self.sign == Some(Keyword::Unsigned);
+		Some(match (self.char, self.short, self.long) {
+			(true, false, 0) if !self.int => {
+				if unsigned {
+					TYPE_UCHAR
+				} else {
+					TYPE_CHAR
+				}
+			}
+			(false, true, 0) => {
+				if unsigned {
+					TYPE_USHORT
+				} else {
+					TYPE_SHORT
+				}
+			}
+			(false, false, 0) => {
+				if unsigned {
+					TYPE_UINT
+				} else {
+					TYPE_INT
+				}
+			}
+			(false, false, _) => {
+				if unsigned {
+					TYPE_ULONG
+				} else {
+					TYPE_LONG
+				}
+			}
+			_ => return None,
+		})
+	}
+}
+
 pub struct Parser {
 	token_list: TokenList,
 	index: usize,
-	global_variables: HashMap<String, Variable>,
-	functions: HashMap<String, Function>,
+	unit: TranslationUnit,
+	/// 最后一个是当前作用域
+	scopes: Vec<Scope>,
+	/// 外层到内层的switch语句中已有的case标签, None是default
+	switch_labels: Vec<Vec<Option<i64>>>,
+	/// 所在的循环和switch语句的层数, 为0时不能break
+	breakable_depth: usize,
+	/// 所在的循环的层数, 为0时不能continue
+	loop_depth: usize,
+	/// declspec中定义的枚举常量, 由所在的声明加入全局变量或者DeclStmt
+	enum_constants: Vec<Variable>,
+	diagnostics: Vec<Diagnostic>,
+	/// 报告错误之后, 在成功解析一个声明或语句之前, 不再报告新的错误, 避免连锁错误
+	panic_mode: bool,
 }
 
 impl Parser {
@@ -98,8 +188,11 @@ impl Parser {
 	}
 
 	fn expect_punct(&mut self, punct: Punct) -> Result<(), ParseError> {
-		match self.next()? {
-			Token::Punct(p) if p == punct => Ok(()),
+		match self.must_peek_next()? {
+			Token::Punct(p) if p == punct => {
+				self.advance();
+				Ok(())
+			}
 			other => Err(ParseError::Unexpected(format!("expecting {}, but {}", punct, other))),
 		}
 	}
@@ -117,12 +210,23 @@ impl Parser {
 }
 
 fn expect_string(str: Option<String>) -> Result<String, ParseError> {
-	str.map_or(Err(ParseError::General("identifier should not be empty")), Ok)
+	str.ok_or(ParseError::General("identifier should not be empty"))
 }
 
 impl Parser {
 	fn new(token_list: TokenList) -> Self {
-		Parser { token_list, index: 0, global_variables: HashMap::new(), functions: HashMap::new() }
+		Parser {
+			token_list,
+			index: 0,
+			unit: TranslationUnit::default(),
+			scopes: vec![Scope::default()],
+			switch_labels: vec![],
+			breakable_depth: 0,
+			loop_depth: 0,
+			enum_constants: vec![],
+			diagnostics: vec![],
+			panic_mode: false,
+		}
 	}
 
 	pub fn from_str(input: &str) -> Result<Self, ParseError> {
@@ -141,68 +245,379 @@ impl Parser {
 	// 			init-declarator-list: init-declarator,
 	// 			init-declarator: declarator = initializer
 	pub fn parse(&mut self) -> Result<(), ParseError> {
-		self.global_variables.clear();
-		self.functions.clear();
+		self.translation_unit();
+		match self.diagnostics.first() {
+			Some(diagnostic) => Err(diagnostic.error.clone()),
+			None => Ok(()),
+		}
+	}
+
+	/// parse() 出错之后, 跳过到同步点继续解析所记录的全部错误
+	pub fn diagnostics(&self) -> &[Diagnostic] {
+		&self.diagnostics
+	}
+
+	/// 解析得到的翻译单元. 出错的部分用 Statement::Error 和 Expr::Error 代替
+	pub fn into_unit(self) -> TranslationUnit {
+		self.unit
+	}
+
+	/// 名字解析和类型检查, 给每个标识符绑定符号, 给每个表达式标注类型和值类别.
+	/// 错误和警告追加到诊断信息中, 只有错误导致失败
+	pub fn check(&mut self) -> Result<(), ParseError> {
+		let mut diagnostics = resolve::resolve(&mut self.unit);
+		if !diagnostics.iter().any(Diagnostic::is_error) {
+			diagnostics.extend(check::check(&mut self.unit));
+		}
+		// 静态存储期对象的初始值必须是常量表达式或者地址常量
+		if !diagnostics.iter().any(Diagnostic::is_error) {
+			let (_, invalid) = DataSegment::layout_all(&self.unit.global_variables, &self.unit.functions);
+			diagnostics.extend(invalid.into_iter().map(|(var, e)| Diagnostic::error(var.span, e)));
+		}
+		let result = match diagnostics.iter().find(|diagnostic| diagnostic.is_error()) {
+			Some(diagnostic) => Err(diagnostic.error.clone()),
+			None => Ok(()),
+		};
+		self.diagnostics.extend(diagnostics);
+		result
+	}
+
+	/// 以下几个入口要求恰好读完全部token, 否则报告剩余token的位置
+	pub fn parse_translation_unit(&mut self) -> Result<&TranslationUnit, ParseError> {
+		self.parse()?;
+		Ok(&self.unit)
+	}
+
+	pub fn parse_expression(&mut self) -> Result<Expr, ParseError> {
+		self.parse_whole(|p| p.expect_expr(Precedence::P1Comma))
+	}
+
+	pub fn parse_statement(&mut self) -> Result<Statement, ParseError> {
+		self.parse_whole(Self::parse_stmt)
+	}
+
+	pub fn parse_type_name(&mut self) -> Result<Type, ParseError> {
+		self.parse_whole(Self::type_name)
+	}
+
+	fn parse_whole<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T, ParseError>) -> Result<T, ParseError> {
+		self.diagnostics.clear();
+		self.panic_mode = false;
+		let result = f(self)?;
+		// 语句和初始化器中的错误已经恢复, 这里仍然算作失败
+		if let Some(diagnostic) = self.diagnostics.first() {
+			return Err(diagnostic.error.clone());
+		}
+		if self.is_not_eof() {
+			return Err(ParseError::TrailingTokens(self.leftover_span()));
+		}
+		Ok(result)
+	}
+
+	/// 从第start个token到上一个token的范围
+	fn span_since(&self, start: usize) -> Span {
+		let spans = &self.token_list.spans;
+		match (spans.get(start), self.index.checked_sub(1).and_then(|last| spans.get(last))) {
+			(Some(first), Some(last)) => Span { end: last.end, ..*first },
+			_ => Span::default(),
+		}
+	}
+
+	/// 从当前token到最后一个token的范围
+	fn leftover_span(&self) -> Span {
+		let spans = &self.token_list.spans[self.index..];
+		match (spans.first(), spans.last()) {
+			(Some(first), Some(last)) => Span { end: last.end, ..*first },
+			_ => self.current_span(),
+		}
+	}
+
+	fn translation_unit(&mut self) {
+		self.unit = TranslationUnit::default();
+		self.diagnostics.clear();
+		self.panic_mode = false;
+		self.scopes = vec![Scope::default()];
 
 		while self.is_not_eof() {
-			let base_type = self.declspec()?;
+			let start = self.index;
+			match self.external_declaration() {
+				Ok(()) => self.panic_mode = false,
+				Err(e) => {
+					self.report(e);
+					self.scopes.truncate(1);
+					self.switch_labels.clear();
+					self.breakable_depth = 0;
+					self.loop_depth = 0;
+					self.enum_constants.clear();
+					self.sync_external_declaration(start);
+					// 同步之后是新的声明, 其中的错误照常报告
+					self.panic_mode = false;
+				}
+			}
+		}
+	}
+
+	fn external_declaration(&mut self) -> Result<(), ParseError> {
+		let mut attr = VarAttr::default();
+		let base_type = self.declspec_attr(Some(&mut attr))?;
+		self.global_enum_constants()?;
 
-			// 不支持 可选的 declarator, 也就是说 `int ;`会报错.
+		// 没有declarator的声明, 如 `struct st { int x; };`, 以及合法但无意义的 `int;`
+		if self.peek_next_punct(Punct::Semicolon) {
+			self.advance();
+			return Ok(());
+		}
 
-			let declarator = self.declarator(base_type.clone())?;
+		let start = self.index;
+		let declarator = self.declarator(base_type.clone())?;
+		let span = self.span_since(start);
 
-			let is_compound_stmt_start = self.peek_next_punct(Punct::BracesL);
+		let is_compound_stmt_start = self.peek_next_punct(Punct::BracesL);
 
-			let maybe_func = declarator.ctype.get_func();
-			match maybe_func {
-				Some(func) if is_compound_stmt_start => {
-					let name = expect_string(declarator.name)?;
-					let func = self.parse_function(name.clone(), func)?;
-					self.functions.insert(name, func);
+		let maybe_func = declarator.ctype.get_func();
+		match maybe_func {
+			Some(func) if is_compound_stmt_start => {
+				let name = expect_string(declarator.name)?;
+				let func = self.parse_function(name, func, span)?;
+				self.new_function(func)?;
+			}
+			_ => {
+				self.new_global_declaration(declarator, &attr, span)?;
+				loop {
+					let punct = self.next_punct()?;
+					if punct == Punct::Semicolon {
+						break;
+					} else if punct == Punct::Comma {
+						let start = self.index;
+						let var = self.declarator(base_type.clone())?;
+						let span = self.span_since(start);
+						self.new_global_declaration(var, &attr, span)?;
+					} else {
+						return Err(ParseError::General("unexpected token"));
+					}
 				}
-				_ => {
-					self.new_global_declaration(declarator)?;
-					loop {
-						let punct = self.next_punct()?;
-						if punct == Punct::Semicolon {
-							break;
-						} else if punct == Punct::Comma {
-							let var = self.declarator(base_type.clone())?;
-							self.new_global_declaration(var)?;
-						} else {
-							return Err(ParseError::General("unexpected token"));
-						}
+			}
+		}
+
+		// 参数列表中定义的枚举常量
+		self.global_enum_constants()
+	}
+
+	fn report(&mut self, error: ParseError) {
+		let span = self.current_span();
+		self.report_at(span, error);
+	}
+
+	fn report_at(&mut self, span: Span, error: ParseError) {
+		if !self.panic_mode {
+			self.diagnostics.push(Diagnostic::error(span, error));
+		}
+		self.panic_mode = true;
+	}
+
+	/// 声明之间的冲突在后一个声明符的位置报告, 而不是读到的当前token. 返回的错误照常向上传递
+	fn declaration_error(&mut self, span: Span, error: ParseError) -> ParseError {
+		self.report_at(span, error.clone());
+		error
+	}
+
+	/// 当前token的位置, 已经读完时是最后一个token的位置
+	fn current_span(&self) -> Span {
+		let spans = &self.token_list.spans;
+		spans.get(self.index).or_else(|| spans.last()).copied().unwrap_or_default()
+	}
+
+	// 同步点: 顶层的 ; 之后, 与 { 匹配的 } 之后, 或者下一个顶层声明的开始
+	fn sync_external_declaration(&mut self, start: usize) {
+		if self.index == start && self.is_not_eof() {
+			self.advance();
+		}
+		let mut depth = 0;
+		while let Some(token) = self.peek_next() {
+			match token {
+				Token::Punct(Punct::BracesL) => depth += 1,
+				Token::Punct(Punct::BracesR) if depth <= 1 => {
+					self.advance();
+					return;
+				}
+				Token::Punct(Punct::BracesR) => depth -= 1,
+				Token::Punct(Punct::Semicolon) if depth == 0 => {
+					self.advance();
+					return;
+				}
+				_ if depth == 0 && token.is_type_keyword() => return,
+				_ => (),
+			}
+			self.advance();
+		}
+	}
+
+	// 同步点: 语句结尾的 ; 之后, 完整的 { } 之后, 或者所在块的 } 之前
+	fn sync_statement(&mut self, start: usize) {
+		let mut depth = 0;
+		while let Some(token) = self.peek_next() {
+			match token {
+				Token::Punct(Punct::BracesL) => depth += 1,
+				Token::Punct(Punct::BracesR) if depth == 0 => {
+					if self.index == start {
+						self.advance();
+					}
+					return;
+				}
+				Token::Punct(Punct::BracesR) => {
+					depth -= 1;
+					if depth == 0 {
+						self.advance();
+						return;
 					}
 				}
+				Token::Punct(Punct::Semicolon) if depth == 0 => {
+					self.advance();
+					return;
+				}
+				_ => (),
 			}
+			self.advance();
 		}
+	}
 
-		Ok(())
+	/// 跳过出错的表达式, 停在同一层括号中的 ; , ) ] } 之前
+	fn sync_expr(&mut self) {
+		let mut depth = 0;
+		while let Some(token) = self.peek_next() {
+			match token {
+				Token::Punct(Punct::ParentheseL | Punct::BrakL | Punct::BracesL) => depth += 1,
+				Token::Punct(Punct::ParentheseR | Punct::BrakR | Punct::BracesR) if depth == 0 => return,
+				Token::Punct(Punct::ParentheseR | Punct::BrakR | Punct::BracesR) => depth -= 1,
+				Token::Punct(Punct::Semicolon | Punct::Comma) if depth == 0 => return,
+				_ => (),
+			}
+			self.advance();
+		}
 	}
 
-	fn parse_function(&mut self, name: String, return_type: Func) -> Result<Function, ParseError> {
-		let stmts = self.parse_stmt()?;
+	/// 表达式出错时记录错误, 返回 Expr::Error
+	fn expr_or_error(&mut self, precedence: Precedence) -> Result<Expr, ParseError> {
+		match self.expect_expr(precedence) {
+			Err(ParseError::EndOfToken) => Err(ParseError::EndOfToken),
+			Err(e) => {
+				self.report(e);
+				self.sync_expr();
+				Ok(Expr::error())
+			}
+			ok => ok,
+		}
+	}
 
-		Ok(Function { name, ctype: return_type, locals: vec![], stmts, stack_size: 0, is_definition: true })
+	fn parse_function(&mut self, name: String, return_type: Func, span: Span) -> Result<Function, ParseError> {
+		self.enter_scope();
+		for param in return_type.param_list.iter() {
+			if let Some(name) = &param.name {
+				self.declare_name(name, Binding::Object(param.ctype.clone()));
+			}
+		}
+		let stmts = self.parse_stmt();
+		self.leave_scope();
+		let stmts = stmts?;
+
+		Ok(Function {
+			name,
+			ctype: return_type,
+			locals: vec![],
+			stmts,
+			stack_size: 0,
+			is_definition: true,
+			decl_span: span,
+			def_span: span,
+			symbol: None,
+		})
+	}
+
+	fn new_prototype(&mut self, name: String, ctype: Func, span: Span) -> Result<(), ParseError> {
+		let stmts = Statement::Empty;
+		self.new_function(Function {
+			name,
+			ctype,
+			locals: vec![],
+			stmts,
+			stack_size: 0,
+			is_definition: false,
+			decl_span: span,
+			def_span: span,
+			symbol: None,
+		})
+	}
+
+	/// 同名函数的多次声明合并为一个, 类型取合成类型, 最多只能有一个定义
+	fn new_function(&mut self, func: Function) -> Result<(), ParseError> {
+		let span = func.decl_span;
+		if self.unit.global_variables.iter().any(|var| var.name == func.name) {
+			return Err(self.declaration_error(span, ParseError::ConflictingTypes(func.name)));
+		}
+		let prev = match self.unit.functions.iter_mut().find(|prev| prev.name == func.name) {
+			None => {
+				self.unit.functions.push(func);
+				return Ok(());
+			}
+			Some(prev) => prev,
+		};
+		if prev.is_definition && func.is_definition {
+			return Err(self.declaration_error(span, ParseError::Redefinition(func.name)));
+		}
+		// 参数名以定义为准
+		let (merged, other) = if func.is_definition { (&func, &*prev) } else { (&*prev, &func) };
+		// int f() { ... } 这样的定义没有参数, 与有参数的原型不兼容
+		let empty_definition =
+			merged.is_definition && merged.ctype.is_unspecified && !other.ctype.param_list.is_empty();
+		let ctype = match Type::Func(merged.ctype.clone()).composite(&Type::Func(other.ctype.clone())) {
+			Some(Type::Func(ctype)) if !empty_definition => ctype,
+			_ => return Err(self.declaration_error(span, ParseError::ConflictingTypes(func.name))),
+		};
+		if func.is_definition {
+			let decl_span = prev.decl_span;
+			*prev = func;
+			prev.decl_span = decl_span;
+		}
+		prev.ctype = ctype;
+		Ok(())
 	}
 
 	fn parse_stmt(&mut self) -> Result<Statement, ParseError> {
 		Ok(match self.must_peek_next()? {
 			Token::Punct(Punct::BracesL) => {
 				self.advance();
+				self.enter_scope();
 				let mut stmts = vec![];
 				while Token::Punct(Punct::BracesR) != self.must_peek_next()? {
-					let stmt = self.parse_stmt()?;
-					stmts.push(stmt);
+					let start = self.index;
+					match self.parse_stmt() {
+						Ok(stmt) => {
+							self.panic_mode = false;
+							stmts.push(stmt);
+						}
+						Err(ParseError::EndOfToken) => return Err(ParseError::EndOfToken),
+						Err(e) => {
+							self.report(e);
+							self.sync_statement(start);
+							self.panic_mode = false;
+							stmts.push(Statement::Error);
+						}
+					}
 				}
 				self.advance();
+				self.leave_scope();
 				Statement::CompoundStmt(stmts)
 			}
+			token if token.is_type_keyword() => self.local_declaration()?,
 			Token::Keyword(Keyword::Return) => {
+				let start = self.index;
 				self.advance(); // skip return
-				let expr = self.expect_expr(Precedence::P1Comma)?;
+				let expr = match self.must_peek_next()? {
+					Token::Punct(Punct::Semicolon) => None,
+					_ => Some(self.expr_or_error(Precedence::P1Comma)?),
+				};
 				self.expect_punct(Punct::Semicolon)?;
-				Statement::ReturnStmt(expr)
+				Statement::ReturnStmt(expr, self.span_since(start))
 			}
 			Token::Punct(Punct::Semicolon) => {
 				self.advance();
@@ -211,7 +626,7 @@ impl Parser {
 			Token::Keyword(Keyword::If) => {
 				self.advance();
 				self.expect_punct(Punct::ParentheseL)?;
-				let cond = self.expect_expr(Precedence::P1Comma)?;
+				let cond = self.expr_or_error(Precedence::P1Comma)?;
 				self.expect_punct(Punct::ParentheseR)?;
 				let then_stmt = self.parse_stmt()?;
 				let m_else_stmt = if let Some(next) = self.peek_next() {
@@ -230,69 +645,480 @@ impl Parser {
 			Token::Keyword(Keyword::For) => {
 				self.advance();
 				self.expect_punct(Punct::ParentheseL)?;
+				// C99: for (int i = 0; ...) 声明的作用域只包括这个循环, 相当于 { int i = 0; for (; ...) }
+				if self.must_peek_next()?.is_type_keyword() {
+					self.enter_scope();
+					let stmts = self.local_declaration().and_then(|decl| Ok(vec![decl, self.for_stmt(None)?]));
+					self.leave_scope();
+					return Ok(Statement::CompoundStmt(stmts?));
+				}
 
 				let init = if Token::Punct(Punct::Semicolon) == self.must_peek_next()? {
 					self.advance();
 					None
 				} else {
-					let expr = self.expect_expr(Precedence::P1Comma)?;
+					let expr = self.expr_or_error(Precedence::P1Comma)?;
 					self.expect_punct(Punct::Semicolon)?;
 					Some(expr)
 				};
-
-				let cond = if Token::Punct(Punct::Semicolon) == self.must_peek_next()? {
-					self.advance();
-					Expr::Const(Const::Integer("1".to_string()))
-				} else {
-					let expr = self.expect_expr(Precedence::P1Comma)?;
-					self.expect_punct(Punct::Semicolon)?;
-					expr
-				};
-
-				let end = if Token::Punct(Punct::ParentheseR) == self.must_peek_next()? {
-					self.advance();
-					None
-				} else {
-					let expr = self.expect_expr(Precedence::P1Comma)?;
-					self.expect_punct(Punct::ParentheseR)?;
-					Some(expr)
+				self.for_stmt(init)?
+			}
+			Token::Keyword(Keyword::Switch) => {
+				self.advance();
+				self.expect_punct(Punct::ParentheseL)?;
+				let cond = self.expr_or_error(Precedence::P1Comma)?;
+				self.expect_punct(Punct::ParentheseR)?;
+				self.switch_labels.push(vec![]);
+				let body = self.breakable_stmt();
+				self.switch_labels.pop();
+				Statement::SwitchStmt(cond, Box::new(body?))
+			}
+			Token::Keyword(keyword @ (Keyword::Case | Keyword::Default)) => {
+				self.advance();
+				let value = match keyword {
+					Keyword::Case => Some(self.expect_const()?),
+					_ => None,
 				};
-
-				let stmt = self.parse_stmt()?;
-				Statement::ForStmt(init, cond, end, Box::new(stmt))
+				self.expect_punct(Punct::Colon)?;
+				match self.switch_labels.last_mut() {
+					None => return Err(ParseError::General("case label not within a switch statement")),
+					Some(labels) if labels.contains(&value) => {
+						return Err(ParseError::Unexpected(match value {
+							Some(v) => format!("duplicate case value {}", v),
+							None => "multiple default labels in one switch".to_owned(),
+						}))
+					}
+					Some(labels) => labels.push(value),
+				}
+				let stmt = Box::new(self.parse_stmt()?);
+				match value {
+					Some(v) => Statement::CaseStmt(v, stmt),
+					None => Statement::DefaultStmt(stmt),
+				}
+			}
+			Token::Keyword(Keyword::Break) => {
+				self.advance();
+				self.expect_punct(Punct::Semicolon)?;
+				if self.breakable_depth == 0 {
+					return Err(ParseError::General("break statement not within loop or switch"));
+				}
+				Statement::BreakStmt
+			}
+			Token::Keyword(Keyword::Continue) => {
+				self.advance();
+				self.expect_punct(Punct::Semicolon)?;
+				if self.loop_depth == 0 {
+					return Err(ParseError::General("continue statement not within a loop"));
+				}
+				Statement::ContinueStmt
 			}
 			_ => {
-				let expr = self.expect_expr(Precedence::P1Comma)?;
+				let expr = self.expr_or_error(Precedence::P1Comma)?;
 				self.expect_punct(Punct::Semicolon)?;
 				Statement::ExprStmt(expr)
 			}
 		})
 	}
 
+	/// for语句中初始化部分之后的条件, 步进和循环体
+	fn for_stmt(&mut self, init: Option<Expr>) -> Result<Statement, ParseError> {
+		let cond = if Token::Punct(Punct::Semicolon) == self.must_peek_next()? {
+			self.advance();
+			Expr::constant(Const::Integer("1".to_string()))
+		} else {
+			let expr = self.expr_or_error(Precedence::P1Comma)?;
+			self.expect_punct(Punct::Semicolon)?;
+			expr
+		};
+
+		let end = if Token::Punct(Punct::ParentheseR) == self.must_peek_next()? {
+			self.advance();
+			None
+		} else {
+			let expr = self.expr_or_error(Precedence::P1Comma)?;
+			self.expect_punct(Punct::ParentheseR)?;
+			Some(expr)
+		};
+
+		self.loop_depth += 1;
+		let stmt = self.breakable_stmt();
+		self.loop_depth -= 1;
+		Ok(Statement::ForStmt(init, cond, end, Box::new(stmt?)))
+	}
+
+	/// 循环体和switch语句体, 其中可以break
+	fn breakable_stmt(&mut self) -> Result<Statement, ParseError> {
+		self.breakable_depth += 1;
+		let stmt = self.parse_stmt();
+		self.breakable_depth -= 1;
+		stmt
+	}
+
 	pub fn declaration(&mut self) -> Result<TypeIdentifier, ParseError> {
 		let base_type = self.declspec()?;
 		self.declarator(base_type)
 	}
 
-	fn get_optional_initializer(&mut self) -> Result<Option<Expr>, ParseError> {
-		Ok(if self.peek_next_punct(Punct::Assign) {
+	// type-name: specifier-qualifier-list abstract-declarator opt
+	// abstract-declarator和declarator相同,只是没有标识符, 如 int (*)(void)
+	pub fn type_name(&mut self) -> Result<Type, ParseError> {
+		let base_type = self.declspec()?;
+		let declarator = self.declarator(base_type)?;
+		match declarator.name {
+			None => Ok(declarator.ctype),
+			Some(name) => Err(ParseError::Unexpected(format!("type name should not have identifier: {}", name))),
+		}
+	}
+
+	/// `(` 之后是类型关键字, 即 (type-name)
+	fn is_type_name_in_parentheses(&self) -> bool {
+		self.peek_next_punct(Punct::ParentheseL) && self.peek_next_n(1).is_some_and(|tk| tk.is_type_keyword())
+	}
+
+	// postfix-expression: ( type-name ) { initializer-list }
+	fn compound_literal(&mut self, ctype: Type) -> Result<Expr, ParseError> {
+		let (ctype, init) = self.initializer(ctype)?;
+		Ok(ExprKind::CompoundLiteral(ctype, Box::new(init)).into())
+	}
+
+	fn parenthesized_type_name(&mut self) -> Result<Type, ParseError> {
+		self.expect_punct(Punct::ParentheseL)?;
+		let ctype = self.type_name()?;
+		self.expect_punct(Punct::ParentheseR)?;
+		Ok(ctype)
+	}
+
+	// declaration: declaration-specifiers init-declarator-list opt ;
+	fn local_declaration(&mut self) -> Result<Statement, ParseError> {
+		let base_type = self.declspec()?;
+		let mut vars = std::mem::take(&mut self.enum_constants);
+		if !self.peek_next_punct(Punct::Semicolon) {
+			loop {
+				let start = self.index;
+				let declarator = self.declarator(base_type.clone())?;
+				let span = self.span_since(start);
+				let name = expect_string(declarator.name)?;
+				if let Type::Func(func) = declarator.ctype {
+					// 块作用域中的函数声明
+					self.new_prototype(name, func, span)?;
+				} else {
+					// 变长数组在运行时分配, 不能初始化
+					if declarator.ctype.is_vla() && self.peek_next_punct(Punct::Assign) {
+						return Err(ParseError::VariablyModified(format!("initializer of {}", name)));
+					}
+					self.declare_name(&name, Binding::Object(declarator.ctype.clone()));
+					let (ctype, init_value) = self.get_optional_initializer(declarator.ctype)?;
+					vars.push(Variable {
+						name,
+						ctype,
+						init_value,
+						is_local: true,
+						is_tentative: false,
+						is_enum_constant: false,
+						span,
+						symbol: None,
+					});
+				}
+				if !self.peek_next_punct(Punct::Comma) {
+					break;
+				}
+				self.advance();
+			}
+		}
+		self.expect_punct(Punct::Semicolon)?;
+		Ok(Statement::DeclStmt(vars))
+	}
+
+	fn get_optional_initializer(&mut self, ctype: Type) -> Result<(Type, Option<Initializer>), ParseError> {
+		if self.peek_next_punct(Punct::Assign) {
 			self.advance();
-			Some(self.expect_expr(Precedence::P2Assign)?)
+			let (ctype, init) = self.initializer(ctype)?;
+			Ok((ctype, Some(init)))
 		} else {
-			None
-		})
+			Ok((ctype, None))
+		}
 	}
 
-	fn new_global_declaration(&mut self, var: TypeIdentifier) -> Result<(), ParseError> {
+	fn new_global_declaration(&mut self, var: TypeIdentifier, attr: &VarAttr, span: Span) -> Result<(), ParseError> {
 		let name = expect_string(var.name)?;
-		let init_value = self.get_optional_initializer()?;
-		self.global_variables.insert(
-			name.clone(),
-			Variable { ctype: var.ctype, name, init_value, is_local: false, is_tentative: false },
-		);
+		if let Type::Func(func) = var.ctype {
+			return self.new_prototype(name, func, span);
+		}
+		if self.unit.functions.iter().any(|func| func.name == name) {
+			return Err(self.declaration_error(span, ParseError::ConflictingTypes(name)));
+		}
+		if var.ctype.is_variably_modified() {
+			return Err(ParseError::VariablyModified(format!("{} at file scope", name)));
+		}
+
+		self.declare_name(&name, Binding::Object(var.ctype.clone()));
+		let (ctype, init_value) = self.get_optional_initializer(var.ctype)?;
+		// 没有初始化器的文件作用域对象声明是暂定定义 (6.9.2)
+		let is_tentative = init_value.is_none() && !attr.is_extern;
+		let var = Variable {
+			ctype,
+			name,
+			init_value,
+			is_local: false,
+			is_tentative,
+			is_enum_constant: false,
+			span,
+			symbol: None,
+		};
+
+		match self.unit.global_variables.iter_mut().find(|prev| prev.name == var.name) {
+			None => self.unit.global_variables.push(var),
+			Some(prev) if prev.is_enum_constant => {
+				return Err(self.declaration_error(span, ParseError::Redefinition(var.name)));
+			}
+			Some(prev) => {
+				if prev.init_value.is_some() && var.init_value.is_some() {
+					return Err(self.declaration_error(span, ParseError::Redefinition(var.name)));
+				}
+				match prev.ctype.composite(&var.ctype) {
+					Some(ctype) => prev.ctype = ctype,
+					None => return Err(self.declaration_error(span, ParseError::ConflictingTypes(var.name))),
+				}
+				if var.init_value.is_some() {
+					prev.init_value = var.init_value;
+				}
+				prev.is_tentative = prev.init_value.is_none() && (prev.is_tentative || var.is_tentative);
+			}
+		}
+		Ok(())
+	}
+
+	// initializer:
+	// 	assignment-expression
+	// 	{ initializer-list }
+	// 	{ initializer-list , }
+	// initializer-list:
+	// 	designation opt initializer
+	// 	initializer-list , designation opt initializer
+	// designation: designator-list =
+	// designator:
+	// 	[ constant-expression ]
+	// 	. identifier
+	//
+	// 返回的类型中, 未指定长度的数组已经根据初始化器补全
+	fn initializer(&mut self, ctype: Type) -> Result<(Type, Initializer), ParseError> {
+		// 结构体可以用同类型的表达式初始化, 不展开
+		if matches!(ctype, Type::Struct(_)) && !self.peek_next_punct(Punct::BracesL) {
+			return Ok((ctype, Initializer::Expr(self.expect_expr(Precedence::P2Assign)?)));
+		}
+		if ctype.is_incomplete_array()
+			&& !self.peek_next_punct(Punct::BracesL)
+			&& !(ctype.is_char_array() && self.peek_string_literal())
+		{
+			return Err(ParseError::General("array initializer must be an initializer list"));
+		}
+
+		let mut init = None;
+		self.initializer2(&ctype, &mut init)?;
+		let init = init.unwrap_or(Initializer::List(vec![]));
+		if ctype.is_incomplete_array() {
+			let length = match &init {
+				Initializer::List(list) => list.len(),
+				Initializer::Expr(_) => 1,
+			};
+			Ok((ctype.with_array_length(length), init))
+		} else {
+			Ok((ctype, init))
+		}
+	}
+
+	fn initializer2(&mut self, ctype: &Type, slot: &mut Option<Initializer>) -> Result<(), ParseError> {
+		match ctype {
+			Type::Array(array) => {
+				if ctype.is_char_array() && self.peek_string_literal() {
+					self.string_initializer(array, slot)
+				} else if self.peek_next_punct(Punct::BracesL) {
+					self.advance();
+					if ctype.is_char_array() && self.peek_string_literal() {
+						// char s[] = { "hi" };
+						self.string_initializer(array, slot)?;
+						self.skip_trailing_comma();
+						self.expect_punct(Punct::BracesR)
+					} else {
+						self.array_initializer1(array, ctype.is_incomplete_array(), slot)
+					}
+				} else {
+					self.array_initializer2(array, slot)
+				}
+			}
+			Type::Struct(st) => {
+				if self.peek_next_punct(Punct::BracesL) {
+					self.advance();
+					self.struct_initializer1(st, slot)
+				} else {
+					self.struct_initializer2(st, slot)
+				}
+			}
+			_ => {
+				if self.peek_next_punct(Punct::BracesL) {
+					// 标量也可以用花括号包围: int i = { 1 };
+					self.advance();
+					self.initializer2(ctype, slot)?;
+					self.skip_trailing_comma();
+					self.expect_punct(Punct::BracesR)
+				} else {
+					*slot = Some(Initializer::Expr(self.expr_or_error(Precedence::P2Assign)?));
+					Ok(())
+				}
+			}
+		}
+	}
+
+	fn string_initializer(&mut self, array: &Array, slot: &mut Option<Initializer>) -> Result<(), ParseError> {
+		let str = match self.next()? {
+			Token::StringLiteral(str) => str,
+			_ => return Err(ParseError::General("expecting string literal")),
+		};
+		let mut chars: Vec<_> = str
+			.bytes()
+			.chain([0])
+			.map(|c| Some(Initializer::Expr(Expr::constant(Const::Character(c as char)))))
+			.collect();
+		// char s[2] = "hi"; 合法, 丢掉结尾的0. 再短就是错误
+		if array.size_expr.is_some() && chars.len() > array.length {
+			if chars.len() > array.length + 1 {
+				return Err(ParseError::General("initializer-string for char array is too long"));
+			}
+			chars.truncate(array.length);
+		}
+		*slot = Some(Initializer::List(chars));
 		Ok(())
 	}
 
+	/// 已经读取了 {
+	fn array_initializer1(
+		&mut self,
+		array: &Array,
+		is_incomplete: bool,
+		slot: &mut Option<Initializer>,
+	) -> Result<(), ParseError> {
+		let mut i = 0;
+		while !self.peek_next_punct(Punct::BracesR) {
+			if self.peek_designator() {
+				i = self.designation(&Type::Array(array.clone()), slot)?;
+			} else {
+				if !is_incomplete && i >= array.length {
+					return Err(ParseError::General("excess elements in array initializer"));
+				}
+				self.initializer2(&array.base_type, element_slot(list_slot(slot), i))?;
+			}
+			i += 1;
+			if !self.peek_next_punct(Punct::BracesR) {
+				self.expect_punct(Punct::Comma)?;
+			}
+		}
+		self.advance();
+		// {} 也要得到一个空的列表
+		list_slot(slot);
+		Ok(())
+	}
+
+	/// 省略了花括号的数组, 最多读取数组长度个元素
+	fn array_initializer2(&mut self, array: &Array, slot: &mut Option<Initializer>) -> Result<(), ParseError> {
+		for i in 0..array.length {
+			if i > 0 {
+				if !self.continues_elided_list() {
+					break;
+				}
+				self.advance();
+			}
+			self.initializer2(&array.base_type, element_slot(list_slot(slot), i))?;
+		}
+		Ok(())
+	}
+
+	/// 已经读取了 {
+	fn struct_initializer1(&mut self, st: &Struct, slot: &mut Option<Initializer>) -> Result<(), ParseError> {
+		let members = st.members();
+		let mut i = 0;
+		while !self.peek_next_punct(Punct::BracesR) {
+			if self.peek_designator() {
+				i = self.designation(&Type::Struct(st.clone()), slot)?;
+			} else {
+				let member = members.get(i).ok_or(ParseError::General("excess elements in struct initializer"))?;
+				self.initializer2(&member.ctype, element_slot(list_slot(slot), i))?;
+			}
+			i += 1;
+			if !self.peek_next_punct(Punct::BracesR) {
+				self.expect_punct(Punct::Comma)?;
+			}
+		}
+		self.advance();
+		list_slot(slot);
+		Ok(())
+	}
+
+	fn struct_initializer2(&mut self, st: &Struct, slot: &mut Option<Initializer>) -> Result<(), ParseError> {
+		for (i, member) in st.members().iter().enumerate() {
+			if i > 0 {
+				if !self.continues_elided_list() {
+					break;
+				}
+				self.advance();
+			}
+			self.initializer2(&member.ctype, element_slot(list_slot(slot), i))?;
+		}
+		Ok(())
+	}
+
+	/// 解析一个指示符, 以及之后的指示符或者 = initializer, 返回指示的元素在当前聚合类型中的位置
+	fn designation(&mut self, ctype: &Type, slot: &mut Option<Initializer>) -> Result<usize, ParseError> {
+		let (index, sub_type) = match (self.next_punct()?, ctype) {
+			(Punct::BrakL, Type::Array(array)) => {
+				let index = self.expect_const()?;
+				self.expect_punct(Punct::BrakR)?;
+				if index < 0 || (array.size_expr.is_some() && index as usize >= array.length) {
+					return Err(ParseError::General("array index in initializer exceeds array bounds"));
+				}
+				(index as usize, *array.base_type.clone())
+			}
+			(Punct::Dot, Type::Struct(st)) => {
+				let name = self.expect_identifier()?;
+				let index = st
+					.members()
+					.iter()
+					.position(|m| m.name == name)
+					.ok_or_else(|| ParseError::Unexpected(format!("no member named {}", name)))?;
+				(index, st.members()[index].ctype.clone())
+			}
+			_ => return Err(ParseError::General("designator does not match the initialized type")),
+		};
+
+		let sub_slot = element_slot(list_slot(slot), index);
+		if self.peek_designator() {
+			self.designation(&sub_type, sub_slot)?;
+		} else {
+			self.expect_punct(Punct::Assign)?;
+			self.initializer2(&sub_type, sub_slot)?;
+		}
+		Ok(index)
+	}
+
+	fn peek_designator(&self) -> bool {
+		self.peek_next_punct(Punct::BrakL) || self.peek_next_punct(Punct::Dot)
+	}
+
+	fn peek_string_literal(&self) -> bool {
+		matches!(self.peek_next(), Some(Token::StringLiteral(_)))
+	}
+
+	/// 省略花括号时, 遇到 `,}` 或者 `, 指示符` 说明当前子对象已经结束, 交给外层处理
+	fn continues_elided_list(&self) -> bool {
+		self.peek_next_punct(Punct::Comma)
+			&& !matches!(self.peek_next_n(1), None | Some(Token::Punct(Punct::BracesR | Punct::BrakL | Punct::Dot)))
+	}
+
+	fn skip_trailing_comma(&mut self) {
+		if self.peek_next_punct(Punct::Comma) {
+			self.advance();
+		}
+	}
+
 	pub fn show_token_list(&self) {
 		self.show_parse_state(0);
 	}
@@ -326,22 +1152,247 @@ impl Parser {
 		println!();
 	}
 
-	// fn enter_scope(&mut self) {} fn leave_scope(&mut self) {}
+	fn enter_scope(&mut self) {
+		self.scopes.push(Scope::default());
+	}
 
-	fn declspec(&mut self) -> Result<Type, ParseError> {
-		while let Some(token) = self.peek_next() {
-			if token.is_type_keyword() {
+	fn leave_scope(&mut self) {
+		self.scopes.pop();
+	}
+
+	fn find_tag(&self, tag: &str) -> Option<Type> {
+		self.scopes.iter().rev().find_map(|scope| scope.tags.get(tag)).cloned()
+	}
+
+	fn push_tag(&mut self, tag: String, ctype: Type) {
+		if let Some(scope) = self.scopes.last_mut() {
+			scope.tags.insert(tag, ctype);
+		}
+	}
+
+	fn declare_name(&mut self, name: &str, binding: Binding) {
+		if let Some(scope) = self.scopes.last_mut() {
+			scope.names.insert(name.to_owned(), binding);
+		}
+	}
+
+	/// 用当前可见的枚举常量和变量类型计算常量表达式
+	fn eval_const(&self, expr: &Expr) -> Result<i64, ConstError> {
+		consteval::eval(expr, &|name| self.scopes.iter().rev().find_map(|scope| scope.names.get(name)).cloned())
+	}
+
+	/// 必须是常量的表达式, 如case标签
+	fn expect_const(&mut self) -> Result<i64, ParseError> {
+		let expr = self.expect_expr(Precedence::P3Cond)?;
+		self.eval_const(&expr).map_err(ParseError::ConstError)
+	}
+
+	// struct-or-union-specifier:
+	// 	struct identifier opt { struct-declaration-list }
+	// 	struct identifier
+	// struct-declaration: specifier-qualifier-list struct-declarator-list ;
+	fn struct_decl(&mut self) -> Result<Type, ParseError> {
+		let tag = match self.peek_next() {
+			Some(Token::Id(id)) => {
 				self.advance();
-				return match token {
-					Token::Keyword(Keyword::Void) => Ok(TYPE_VOID),
-					Token::Keyword(Keyword::Bool) => Ok(TYPE_BOOL),
-					Token::Keyword(Keyword::Char) => Ok(TYPE_CHAR),
-					Token::Keyword(Keyword::Int) => Ok(TYPE_INT),
-					_ => Err(ParseError::NotType),
-				};
+				Some(id)
+			}
+			_ => None,
+		};
+
+		if !self.peek_next_punct(Punct::BracesL) {
+			let tag = tag.ok_or(ParseError::General("struct without tag or body"))?;
+			let st = match self.find_tag(&tag) {
+				Some(Type::Struct(st)) => st,
+				Some(_) => return Err(ParseError::Unexpected(format!("{} defined as wrong kind of tag", tag))),
+				None => {
+					let st = Struct::new_incomplete(Some(tag.clone()));
+					self.push_tag(tag, Type::Struct(st.clone()));
+					st
+				}
+			};
+			return Ok(Type::Struct(st));
+		}
+		self.advance();
+
+		let st = match tag {
+			// 当前作用域中已经前置声明过, 补全同一个类型
+			Some(tag) => match self.scopes.last().and_then(|scope| scope.tags.get(&tag)).cloned() {
+				Some(Type::Struct(st)) if st.is_complete() => {
+					return Err(ParseError::Unexpected(format!("redefinition of struct {}", tag)))
+				}
+				Some(Type::Struct(st)) => st,
+				Some(_) => return Err(ParseError::Unexpected(format!("{} defined as wrong kind of tag", tag))),
+				None => {
+					let st = Struct::new_incomplete(Some(tag.clone()));
+					self.push_tag(tag, Type::Struct(st.clone()));
+					st
+				}
+			},
+			None => Struct::new_incomplete(None),
+		};
+
+		let mut members = vec![];
+		while !self.peek_next_punct(Punct::BracesR) {
+			let base_type = self.declspec()?;
+			loop {
+				let member = self.declarator(base_type.clone())?;
+				if member.ctype.is_variably_modified() {
+					return Err(ParseError::VariablyModified(format!("member {}", member.name.unwrap_or_default())));
+				}
+				members.push(member);
+				if !self.peek_next_punct(Punct::Comma) {
+					break;
+				}
+				self.advance();
+			}
+			self.expect_punct(Punct::Semicolon)?;
+		}
+		self.advance();
+
+		st.complete(members).map_err(ParseError::Unexpected)?;
+		Ok(Type::Struct(st))
+	}
+
+	// enum-specifier:
+	// 	enum identifier opt { enumerator-list }
+	// 	enum identifier opt { enumerator-list , }
+	// 	enum identifier
+	// enumerator:
+	// 	enumeration-constant
+	// 	enumeration-constant = constant-expression
+	// 枚举常量的值在解析时计算, 省略时是前一个常量加1
+	fn enum_decl(&mut self) -> Result<Type, ParseError> {
+		let tag = match self.peek_next() {
+			Some(Token::Id(id)) => {
+				self.advance();
+				Some(id)
+			}
+			_ => None,
+		};
+
+		if !self.peek_next_punct(Punct::BracesL) {
+			let tag = tag.ok_or(ParseError::General("enum without tag or body"))?;
+			return match self.find_tag(&tag) {
+				Some(Type::Struct(_)) => Err(ParseError::Unexpected(format!("{} defined as wrong kind of tag", tag))),
+				Some(ctype) => Ok(ctype),
+				None => Err(ParseError::Unexpected(format!("use of undeclared enum {}", tag))),
+			};
+		}
+		self.advance();
+
+		if let Some(tag) = tag {
+			if self.scopes.last().is_some_and(|scope| scope.tags.contains_key(&tag)) {
+				return Err(ParseError::Unexpected(format!("redefinition of enum {}", tag)));
+			}
+			self.push_tag(tag, TYPE_INT);
+		}
+
+		let mut next = Some(0);
+		while !self.peek_next_punct(Punct::BracesR) {
+			let start = self.index;
+			let name = self.expect_identifier()?;
+			let span = self.span_since(start);
+			let value = if self.peek_next_punct(Punct::Assign) {
+				self.advance();
+				self.expect_const()?
+			} else {
+				next.ok_or(ParseError::ConstError(ConstError::Overflow))?
+			};
+			next = value.checked_add(1).filter(|v| i32::try_from(*v).is_ok());
+			self.declare_name(&name, Binding::EnumConst(value));
+			self.enum_constants.push(Variable {
+				name,
+				ctype: TYPE_INT,
+				init_value: Some(Initializer::Expr(Expr::constant(Const::Integer(value.to_string())))),
+				is_local: self.scopes.len() > 1,
+				is_tentative: false,
+				is_enum_constant: true,
+				span,
+				symbol: None,
+			});
+			if !self.peek_next_punct(Punct::Comma) {
+				break;
+			}
+			self.advance();
+		}
+		self.expect_punct(Punct::BracesR)?;
+		Ok(TYPE_INT)
+	}
+
+	/// 文件作用域中定义的枚举常量加入全局变量
+	fn global_enum_constants(&mut self) -> Result<(), ParseError> {
+		for var in std::mem::take(&mut self.enum_constants) {
+			if self.unit.global_variables.iter().any(|prev| prev.name == var.name)
+				|| self.unit.functions.iter().any(|func| func.name == var.name)
+			{
+				return Err(ParseError::Redefinition(var.name));
 			}
+			self.unit.global_variables.push(var);
+		}
+		Ok(())
+	}
+
+	fn declspec(&mut self) -> Result<Type, ParseError> {
+		self.declspec_attr(None)
+	}
+
+	// declaration-specifiers:
+	// 	storage-class-specifier: typedef extern static auto register
+	// 	type-specifier
+	// 	type-qualifier: const restrict volatile
+	// 	function-specifier: inline
+	// 只有声明中可以出现存储类说明符, 此时attr不为None
+	fn declspec_attr(&mut self, mut attr: Option<&mut VarAttr>) -> Result<Type, ParseError> {
+		let mut ctype = None;
+		let mut int_spec = IntSpec::default();
+		let conflict = ParseError::General("two or more data types in declaration specifiers");
+		while let Some(Token::Keyword(keyword)) = self.peek_next() {
+			match keyword {
+				Keyword::Typedef | Keyword::Static | Keyword::Extern | Keyword::Inline => {
+					let attr = attr
+						.as_deref_mut()
+						.ok_or(ParseError::General("storage class specifier is not allowed in this context"))?;
+					match keyword {
+						Keyword::Typedef => return Err(ParseError::General("typedef is not supported")),
+						Keyword::Static => attr.is_static = true,
+						Keyword::Extern => attr.is_extern = true,
+						_ => attr.is_inline = true,
+					}
+					if attr.is_static && attr.is_extern {
+						return Err(ParseError::General("multiple storage classes in declaration specifiers"));
+					}
+					self.advance();
+				}
+				// 限定符不影响这里的类型
+				Keyword::Const | Keyword::Volatile | Keyword::Restrict | Keyword::Auto | Keyword::Register => {
+					self.advance();
+				}
+				Keyword::Char | Keyword::Short | Keyword::Int | Keyword::Long | Keyword::Signed | Keyword::Unsigned => {
+					if ctype.is_some() || !int_spec.add(keyword) {
+						return Err(conflict);
+					}
+					self.advance();
+				}
+				Keyword::Void | Keyword::Bool | Keyword::Struct | Keyword::Enum => {
+					if ctype.is_some() || int_spec != IntSpec::default() {
+						return Err(conflict);
+					}
+					self.advance();
+					ctype = Some(match keyword {
+						Keyword::Void => TYPE_VOID,
+						Keyword::Bool => TYPE_BOOL,
+						Keyword::Enum => self.enum_decl()?,
+						_ => self.struct_decl()?,
+					});
+				}
+				_ => break,
+			}
+		}
+		match int_spec == IntSpec::default() {
+			true => ctype.ok_or(ParseError::NotType),
+			false => int_spec.ctype().ok_or(conflict),
 		}
-		Err(ParseError::NotType)
 	}
 
 	fn func_params(&mut self, base_type: Type) -> Result<Type, ParseError> {
@@ -352,28 +1403,55 @@ impl Parser {
 			}
 		}
 
-		let mut params = vec![];
+		let mut param_list = vec![];
+		let mut is_variadic = false;
 		loop {
-			let token = self.must_peek_next()?;
-			if token == Token::Punct(Punct::ParentheseR) {
+			if self.must_peek_next()? == Token::Punct(Punct::ParentheseR) {
 				self.advance();
 				break;
-			} else if token == Token::Punct(Punct::Comma) {
+			}
+			if !param_list.is_empty() {
+				self.expect_punct(Punct::Comma)?;
+			}
+			if self.peek_next_punct(Punct::VarArg) {
 				self.advance();
+				self.expect_punct(Punct::ParentheseR)?;
+				is_variadic = true;
+				break;
 			}
 
 			let param_type = self.declspec()?;
-			params.push(self.declarator(param_type)?);
+			let TypeIdentifier { name, ctype } = self.declarator(param_type)?;
+			// 数组和函数类型的参数调整为指针 (6.7.5.3)
+			let ctype = match ctype {
+				Type::Array(Array { base_type, .. }) => base_type.into_pointer(),
+				Type::Func(_) => ctype.into_pointer(),
+				_ => ctype,
+			};
+			param_list.push(TypeIdentifier::new(ctype, name));
 		}
 
-		Ok(base_type.into_function_with_param(params))
+		if param_list.is_empty() && !is_variadic {
+			return Ok(base_type.into_unspecified_function());
+		}
+		Ok(Type::Func(Func { return_type: Box::new(base_type), param_list, is_variadic, is_unspecified: false }))
 	}
 
 	fn array_dimensions(&mut self, mut base_type: Type) -> Result<Type, ParseError> {
 		let maybe_expr = self.parse_expr(Precedence::P2Assign)?;
 		self.expect_punct(Punct::BrakR)?;
 		base_type = self.type_suffix(base_type)?;
-		Ok(base_type.into_array(maybe_expr))
+		// 长度不是常量表达式时是变长数组
+		let size_expr = match maybe_expr {
+			Some(expr) => match self.eval_const(&expr) {
+				Ok(n) if n < 0 => return Err(ParseError::General("size of array is negative")),
+				Ok(n) => Some(Expr::constant(Const::Integer(n.to_string()))),
+				Err(ConstError::NotConstant) => Some(expr),
+				Err(e) => return Err(ParseError::ConstError(e)),
+			},
+			None => None,
+		};
+		Ok(base_type.into_array(size_expr))
 	}
 
 	fn type_suffix(&mut self, base_type: Type) -> Result<Type, ParseError> {
@@ -401,7 +1479,10 @@ impl Parser {
 		}
 
 		if let Some(token) = self.peek_next() {
-			if token == Token::Punct(Punct::ParentheseL) {
+			// 抽象声明符中, ( 之后是类型或者 ) 时是函数的参数列表, 而不是括号
+			let is_param_list =
+				self.peek_next_n(1).is_some_and(|tk| tk.is_type_keyword() || tk == Token::Punct(Punct::ParentheseR));
+			if token == Token::Punct(Punct::ParentheseL) && !is_param_list {
 				let pos1 = self.advance();
 
 				self.skip_after_matching()?;
@@ -425,7 +1506,8 @@ impl Parser {
 				Ok(TypeIdentifier::new(base_type, name))
 			}
 		} else {
-			Err(ParseError::EndOfToken)
+			// 抽象声明符可以为空, 如 type-name 中的 int
+			Ok(TypeIdentifier::new(base_type, None))
 		}
 	}
 
@@ -441,17 +1523,26 @@ impl Parser {
 			match tk {
 				Token::Const(c) => {
 					self.advance();
-					Ok(Some(Expr::Const(c)))
+					Ok(Some(Expr::constant(c)))
 				}
 				Token::StringLiteral(str) => {
 					self.advance();
-					Ok(Some(Expr::StringLiteral(str)))
+					Ok(Some(ExprKind::StringLiteral(str).into()))
 				}
 				Token::Id(id) => {
 					self.advance();
-					Ok(Some(Expr::Id(id)))
+					Ok(Some(Expr::id(&id)))
 				}
 				Token::Punct(punct) => match punct {
+					Punct::ParentheseL if self.is_type_name_in_parentheses() => {
+						let ctype = self.parenthesized_type_name()?;
+						if self.peek_next_punct(Punct::BracesL) {
+							Ok(Some(self.compound_literal(ctype)?))
+						} else {
+							let expr = self.expect_expr(Precedence::P14Unary)?;
+							Ok(Some(Expr::new_cast(ctype, expr)))
+						}
+					}
 					Punct::ParentheseL => {
 						self.advance();
 						match self.parse_expr(Precedence::P1Comma)? {
@@ -479,9 +1570,17 @@ impl Parser {
 				Token::Keyword(keyword) => match keyword {
 					Keyword::SizeOf => {
 						self.advance();
-						let expr = self.expect_expr(Precedence::P14Unary)?;
-						// sizeof -> ?
-						Ok(Some(Expr::new_unary(Punct::Cond, expr)))
+						if self.is_type_name_in_parentheses() {
+							let ctype = self.parenthesized_type_name()?;
+							if self.peek_next_punct(Punct::BracesL) {
+								Ok(Some(Expr::new_sizeof_expr(self.compound_literal(ctype)?)))
+							} else {
+								Ok(Some(ExprKind::SizeOfType(ctype).into()))
+							}
+						} else {
+							let expr = self.expect_expr(Precedence::P14Unary)?;
+							Ok(Some(Expr::new_sizeof_expr(expr)))
+						}
 					}
 					_ => Ok(None),
 				},
@@ -492,10 +1591,12 @@ impl Parser {
 	}
 
 	pub fn parse_expr(&mut self, precedence: Precedence) -> Result<Option<Expr>, ParseError> {
+		let start = self.index;
 		let mut first = match self.parse_leaf()? {
 			Some(leaf) => leaf,
 			None => return Ok(None),
 		};
+		first.span = self.span_since(start);
 
 		while let Some(ntk) = self.peek_next() {
 			let ntk_precedence = ntk.precedence();
@@ -521,22 +1622,21 @@ impl Parser {
 						},
 						Punct::ParentheseL => {
 							let mut argument_expr_list = vec![];
-							while let Some(tk) = self.peek_next() {
-								if tk == Token::Punct(Punct::ParentheseR) {
-									self.advance();
-									break;
-								} else if tk == Token::Punct(Punct::Comma) {
-									self.advance();
+							while !self.peek_next_punct(Punct::ParentheseR) {
+								if !argument_expr_list.is_empty() {
+									self.expect_punct(Punct::Comma)?;
 								}
 								argument_expr_list.push(self.expect_expr(Precedence::P2Assign)?);
 							}
+							self.expect_punct(Punct::ParentheseR)?;
 							first = Expr::new_func_call(first, argument_expr_list)
 						}
 						_ if p.is_binary_op() => match self.parse_expr(ntk_precedence.next_level())? {
 							Some(second) => first = Expr::new_binary(first, p, second),
 							None => return Err(ParseError::NoMoreExpr),
 						},
-						_ if p.is_assign() => match self.parse_expr(ntk_precedence.next_level())? {
+						// 赋值是右结合的: a = b = c 即 a = (b = c)
+						_ if p.is_assign() => match self.parse_expr(ntk_precedence)? {
 							Some(second) => first = Expr::new_assign(first, p, second),
 							None => return Err(ParseError::NoMoreExpr),
 						},
@@ -547,7 +1647,8 @@ impl Parser {
 						Punct::Cond => match self.parse_expr(Precedence::P1Comma)? {
 							Some(left) => {
 								self.expect_punct(Punct::Colon)?;
-								match self.parse_expr(ntk_precedence.next_level())? {
+								// 右结合: a ? b : c ? d : e
+								match self.parse_expr(ntk_precedence)? {
 									Some(right) => {
 										first = Expr::new_cond(first, left, right);
 									}
@@ -560,6 +1661,7 @@ impl Parser {
 					},
 					_ => unreachable!(),
 				}
+				first.span = self.span_since(start);
 			} else {
 				break;
 			}
@@ -571,21 +1673,51 @@ impl Parser {
 
 impl Parser {
 	pub fn display(&self) {
-		for var in self.global_variables.values() {
+		for var in self.unit.global_variables.iter() {
 			println!("{}: {}", var.name, var.ctype);
-			if let Some(e) = var.init_value.as_ref() {
-				println!(" = \n{}", e)
+			if let Some(init) = var.init_value.as_ref() {
+				println!(" = \n{}", init)
 			}
 		}
-		for func in self.functions.values() {
+		match self.data_segment() {
+			Ok(data) => println!("data:\n{}", data),
+			Err(e) => println!("data: {}", e),
+		}
+		for func in self.unit.functions.iter() {
 			println!("name: {}\t\ttype: {}", func.name, Type::Func(func.ctype.clone()));
+			for symbol in func.locals.iter().map(|id| self.unit.symbol(*id)) {
+				println!("local: {}: {}\t{}", symbol.name, symbol.ctype, symbol.span);
+			}
 			println!("stmts:\n{}", func.stmts);
 		}
 	}
 
+	pub fn data_segment(&self) -> Result<DataSegment, ParseError> {
+		DataSegment::layout(&self.unit.global_variables, &self.unit.functions)
+	}
+
 	pub fn into_vm(self) -> (Env, VM) {
-		let env = Env { global_variables: self.global_variables };
-		let vm = VM::new(self.functions);
+		let unit = self.into_unit();
+		let env = Env::new(unit.global_variables);
+		let vm = VM::new(unit.functions, unit.symbols);
 		(env, vm)
 	}
 }
+
+/// 把slot变成初始化列表(如果还不是), 返回列表
+fn list_slot(slot: &mut Option<Initializer>) -> &mut Vec<Option<Initializer>> {
+	if !matches!(slot, Some(Initializer::List(_))) {
+		*slot = Some(Initializer::List(vec![]));
+	}
+	match slot {
+		Some(Initializer::List(list)) => list,
+		_ => unreachable!(),
+	}
+}
+
+fn element_slot(list: &mut Vec<Option<Initializer>>, index: usize) -> &mut Option<Initializer> {
+	if list.len() <= index {
+		list.resize(index + 1, None);
+	}
+	&mut list[index]
+}
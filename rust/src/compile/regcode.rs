@@ -0,0 +1,493 @@
+//! 把校验过的栈式字节码翻译成寄存器机器的三地址指令, 由 regvm::RegisterVm 执行.
+//! 每个函数的栈帧有一组虚拟寄存器: r0 是栈式机器的累加器a, 操作数栈的第i个值放在 r(i+1).
+//! 翻译时记住a和栈中的每个值是常量, 局部变量的地址还是已经在寄存器中, 用到时才写进寄存器.
+//! 这样 IMM PSH, LEA LOAD 之类的指令序列变成一条带立即数或者局部变量偏移的指令,
+//! 比较之后的 BZ BNZ 合并成一条比较跳转, ADJ 不需要指令
+
+use std::collections::HashMap;
+
+use super::{
+	builtins::Builtin,
+	codegen::{Callee, FunctionInfo, Opcode, Program, Scalar},
+	errors::VerifyError,
+	eval::binary,
+	memory::DATA_BASE,
+	token::{Punct, Span},
+	verify::stack_depths,
+};
+
+/// 指令的源操作数: 寄存器或者立即数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+	Reg(usize),
+	Imm(i64),
+}
+
+/// 寄存器机器的指令. 第一个寄存器是结果, 寄存器的编号在所在的栈帧中. 跳转的目标是指令的下标
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegOp {
+	Mov(usize, Operand),
+	/// r = bp + n
+	Lea(usize, usize),
+	/// r = 第n个实参
+	Arg(usize, usize),
+	/// r = *x
+	Load(usize, Operand, Scalar),
+	/// r = *(bp + n), 读局部变量
+	LoadLocal(usize, usize, Scalar),
+	/// *x = y
+	Store(Operand, Operand, Scalar),
+	/// *(bp + n) = x, 写局部变量
+	StoreLocal(usize, Operand, Scalar),
+	/// 从y复制n个字节到x
+	Copy(Operand, Operand, usize),
+	/// x处的n个字节清零
+	Zero(Operand, usize),
+	/// 在栈上分配x个字节, 按n对齐
+	Alloca(usize, Operand, usize),
+	Ssp(usize),
+	Rsp(usize),
+	Cast(usize, Operand, Scalar),
+	/// r = x op y
+	Binary(Punct, Scalar, usize, Operand, Operand),
+	Jmp(usize),
+	/// x为0时跳转
+	Bz(Operand, usize),
+	Bnz(Operand, usize),
+	/// 比较跳转: x op y 成立时跳转
+	Branch(Punct, Scalar, Operand, Operand, usize),
+	/// 调用函数表中的函数, 实参在从第三个寄存器开始的n个寄存器中
+	Call(usize, usize, usize, usize),
+	/// 调用第二个寄存器中的函数指针, 实参在它之后的n个寄存器中
+	CallIndirect(usize, usize, usize),
+	/// 调用内置函数, 实参在从第三个寄存器开始的n个寄存器中
+	Sys(usize, Builtin, usize, usize),
+	Ent(usize),
+	Lev(Operand),
+}
+
+/// 翻译的结果, 函数表中的入口已经换成新的位置
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RegisterProgram {
+	pub code: Vec<RegOp>,
+	pub spans: Vec<Option<Span>>,
+	pub functions: Vec<FunctionInfo>,
+	/// 每个函数的栈帧用到的寄存器个数
+	pub registers: Vec<usize>,
+	pub callees: HashMap<usize, Callee>,
+	pub data: Vec<u8>,
+	pub main: Option<usize>,
+}
+
+/// 累加器a所在的寄存器
+const ACC: usize = 0;
+
+/// 操作数栈的第i个值所在的寄存器
+fn slot(i: usize) -> usize {
+	i + 1
+}
+
+/// 翻译时a和操作数栈中的值
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Value {
+	Reg(usize),
+	Const(i64),
+	/// 局部变量的地址 bp + n
+	Local(usize),
+}
+
+/// 跳转条件取反, 用于把 比较 BZ 合并成比较跳转
+fn negate(op: Punct) -> Punct {
+	match op {
+		Punct::Eq => Punct::Ne,
+		Punct::Ne => Punct::Eq,
+		Punct::Lt => Punct::Ge,
+		Punct::Ge => Punct::Lt,
+		Punct::Gt => Punct::Le,
+		_ => Punct::Gt,
+	}
+}
+
+/// 指令的后继: 跳转目标和下一条指令
+fn successors(op: Opcode, pc: usize) -> Vec<usize> {
+	match op {
+		Opcode::Jmp(target) => vec![target],
+		Opcode::Bz(target) | Opcode::Bnz(target) => vec![target, pc + 1],
+		Opcode::Lev => vec![],
+		_ => vec![pc + 1],
+	}
+}
+
+/// 执行每条指令之前a中的值之后还会不会被读
+fn accumulator_liveness(program: &Program, depths: &[Option<usize>]) -> Vec<bool> {
+	let mut live = vec![false; program.code.len()];
+	let mut changed = true;
+	while changed {
+		changed = false;
+		for pc in (0..program.code.len()).rev().filter(|pc| depths[*pc].is_some()) {
+			let op = program.code[pc];
+			let reads = match op {
+				Opcode::Bz(_) | Opcode::Bnz(_) | Opcode::Lev | Opcode::Psh => true,
+				Opcode::Load(_) | Opcode::Store(_) | Opcode::Copy(_) | Opcode::Zero(_) => true,
+				Opcode::Alloca(_) | Opcode::Cast(_) => true,
+				op => op.operator().is_some(),
+			};
+			let writes =
+				matches!(
+					op,
+					Opcode::Imm(_)
+						| Opcode::Lea(_) | Opcode::Lgb(_)
+						| Opcode::Arg(_) | Opcode::Jsr(..)
+						| Opcode::Jsri(_) | Opcode::Sys(..)
+				);
+			let after = successors(op, pc).into_iter().any(|next| live[next]);
+			let before = reads || (!writes && after);
+			if before != live[pc] {
+				live[pc] = before;
+				changed = true;
+			}
+		}
+	}
+	live
+}
+
+struct Translator<'a> {
+	program: &'a Program,
+	depths: Vec<Option<usize>>,
+	live: Vec<bool>,
+	/// 基本块的开头: 函数入口和跳转目标. 从不同的路径进入时所有的值都要在自己的寄存器中
+	leaders: Vec<bool>,
+	/// 原来的指令在新代码中的位置
+	targets: Vec<usize>,
+	code: Vec<RegOp>,
+	spans: Vec<Option<Span>>,
+	span: Option<Span>,
+	acc: Value,
+	stack: Vec<Value>,
+	/// 上一条指令把结果写到了 r0, 紧接着的 PSH 可以改成直接写到栈的寄存器中
+	pending: Option<usize>,
+}
+
+impl<'a> Translator<'a> {
+	fn emit(&mut self, op: RegOp) -> usize {
+		self.code.push(op);
+		self.spans.push(self.span);
+		self.pending = None;
+		self.code.len() - 1
+	}
+
+	/// 发出结果写到 r0 的指令, a在 r0 中
+	fn emit_result(&mut self, op: RegOp) {
+		self.pending = Some(self.emit(op));
+		self.acc = Value::Reg(ACC);
+	}
+
+	/// 把值写到寄存器r中
+	fn materialize(&mut self, value: Value, r: usize) {
+		match value {
+			Value::Reg(x) if x == r => (),
+			Value::Reg(x) => _ = self.emit(RegOp::Mov(r, Operand::Reg(x))),
+			Value::Const(n) => _ = self.emit(RegOp::Mov(r, Operand::Imm(n))),
+			Value::Local(n) => _ = self.emit(RegOp::Lea(r, n)),
+		};
+	}
+
+	/// 值作为操作数, 局部变量的地址先写到寄存器r中
+	fn operand(&mut self, value: Value, r: usize) -> Operand {
+		match value {
+			Value::Reg(x) => Operand::Reg(x),
+			Value::Const(n) => Operand::Imm(n),
+			Value::Local(_) => {
+				self.materialize(value, r);
+				Operand::Reg(r)
+			}
+		}
+	}
+
+	fn acc_operand(&mut self) -> Operand {
+		let operand = self.operand(self.acc, ACC);
+		if let Operand::Reg(r) = operand {
+			self.acc = Value::Reg(r);
+		}
+		operand
+	}
+
+	/// 弹出栈顶作为操作数
+	fn pop_operand(&mut self) -> Operand {
+		let i = self.stack.len() - 1;
+		let operand = self.operand(self.stack[i], slot(i));
+		self.stack.pop();
+		operand
+	}
+
+	/// 把栈中从第from个开始的值写到各自的寄存器中, 作为调用的实参
+	fn spill(&mut self, from: usize) {
+		for i in from..self.stack.len() {
+			self.materialize(self.stack[i], slot(i));
+			self.stack[i] = Value::Reg(slot(i));
+		}
+	}
+
+	/// 基本块结束: 栈中的值都放到各自的寄存器中, 之后还会用到的a放到 r0
+	fn flush(&mut self, acc_live: bool) {
+		self.spill(0);
+		if acc_live {
+			self.materialize(self.acc, ACC);
+		}
+		self.acc = Value::Reg(ACC);
+	}
+
+	/// 翻译 [entry, end) 中的一个函数, 返回用到的寄存器个数
+	fn function(&mut self, entry: usize, end: usize) -> usize {
+		let code = &self.program.code;
+		let registers = self.depths[entry..end].iter().flatten().max().map_or(0, |depth| depth + 2);
+		// 上一条指令执行完会接着执行这一条
+		let mut falls = false;
+		let mut pc = entry;
+		while pc < end {
+			let Some(depth) = self.depths[pc] else {
+				falls = false;
+				pc += 1;
+				continue;
+			};
+			if self.leaders[pc] {
+				if falls {
+					self.flush(self.live[pc]);
+				}
+				self.stack = (0..depth).map(|i| Value::Reg(slot(i))).collect();
+				self.acc = Value::Reg(ACC);
+				self.pending = None;
+			}
+			self.targets[pc] = self.code.len();
+			self.span = self.program.spans[pc];
+			falls = true;
+			match code[pc] {
+				Opcode::Lea(n) => self.acc = Value::Local(n),
+				Opcode::Imm(n) => self.acc = Value::Const(n),
+				Opcode::Lgb(n) => self.acc = Value::Const((DATA_BASE + n) as i64),
+				Opcode::Arg(n) => self.emit_result(RegOp::Arg(ACC, n)),
+				Opcode::Jmp(target) => {
+					self.flush(self.live[target]);
+					self.emit(RegOp::Jmp(target));
+					falls = false;
+				}
+				op @ (Opcode::Bz(target) | Opcode::Bnz(target)) => {
+					let cond = self.acc_operand();
+					self.flush(self.live[target] || self.live[pc + 1]);
+					match op {
+						Opcode::Bz(_) => self.emit(RegOp::Bz(cond, target)),
+						_ => self.emit(RegOp::Bnz(cond, target)),
+					};
+				}
+				Opcode::Jsr(function, argc) => {
+					let from = depth - argc;
+					self.spill(from);
+					self.emit_result(RegOp::Call(ACC, function, slot(from), argc));
+				}
+				Opcode::Jsri(argc) => {
+					let from = depth - argc - 1;
+					self.spill(from);
+					self.emit_result(RegOp::CallIndirect(ACC, slot(from), argc));
+				}
+				Opcode::Sys(builtin, argc) => {
+					let from = depth - argc;
+					self.spill(from);
+					self.emit_result(RegOp::Sys(ACC, builtin, slot(from), argc));
+				}
+				Opcode::Ent(n) => _ = self.emit(RegOp::Ent(n)),
+				Opcode::Adj(n) => self.stack.truncate(depth - n),
+				Opcode::Lev => {
+					let value = self.acc_operand();
+					self.emit(RegOp::Lev(value));
+					falls = false;
+				}
+				Opcode::Load(s) => match self.acc {
+					Value::Local(n) => self.emit_result(RegOp::LoadLocal(ACC, n, s)),
+					_ => {
+						let addr = self.acc_operand();
+						self.emit_result(RegOp::Load(ACC, addr, s));
+					}
+				},
+				Opcode::Store(s) => {
+					let value = self.acc_operand();
+					match self.stack[depth - 1] {
+						Value::Local(n) => {
+							self.stack.pop();
+							self.emit(RegOp::StoreLocal(n, value, s));
+						}
+						_ => {
+							let addr = self.pop_operand();
+							self.emit(RegOp::Store(addr, value, s));
+						}
+					}
+					// 赋值表达式的值是转换为左边类型之后的值, 大多数时候不会用到
+					match value {
+						Operand::Imm(n) => self.acc = Value::Const(s.ctype().convert(n)),
+						Operand::Reg(_) if self.live[pc + 1] => self.emit_result(RegOp::Cast(ACC, value, s)),
+						Operand::Reg(_) => self.acc = Value::Reg(ACC),
+					}
+				}
+				Opcode::Copy(n) => {
+					let src = self.acc_operand();
+					let dst = self.pop_operand();
+					self.emit(RegOp::Copy(dst, src, n));
+					self.acc = match dst {
+						Operand::Reg(r) => Value::Reg(r),
+						Operand::Imm(n) => Value::Const(n),
+					};
+				}
+				Opcode::Zero(n) => {
+					let addr = self.acc_operand();
+					self.emit(RegOp::Zero(addr, n));
+				}
+				Opcode::Alloca(align) => {
+					let size = self.acc_operand();
+					self.emit_result(RegOp::Alloca(ACC, size, align));
+				}
+				Opcode::Ssp(n) => _ = self.emit(RegOp::Ssp(n)),
+				Opcode::Rsp(n) => _ = self.emit(RegOp::Rsp(n)),
+				// 转换不会出错, 结果不再用到时省掉
+				Opcode::Cast(_) if !self.live[pc + 1] => self.acc = Value::Reg(ACC),
+				Opcode::Cast(s) => match self.acc {
+					Value::Const(n) => self.acc = Value::Const(s.ctype().convert(n)),
+					_ => {
+						let value = self.acc_operand();
+						self.emit_result(RegOp::Cast(ACC, value, s));
+					}
+				},
+				Opcode::Psh => match (self.acc, self.pending) {
+					(Value::Reg(ACC), Some(at)) => {
+						set_result(&mut self.code[at], slot(depth));
+						self.acc = Value::Reg(slot(depth));
+						self.stack.push(self.acc);
+						self.pending = None;
+					}
+					(Value::Reg(_), _) => {
+						self.materialize(self.acc, slot(depth));
+						self.acc = Value::Reg(slot(depth));
+						self.stack.push(self.acc);
+					}
+					_ => self.stack.push(self.acc),
+				},
+				op => {
+					let (punct, s) = op.operator().expect("binary operator");
+					if let (Value::Const(left), Value::Const(right)) = (self.stack[depth - 1], self.acc) {
+						// 打开溢出检查也不出错时才在翻译时算出结果
+						if let Ok(value) = binary(punct, &s.ctype(), left, right, true) {
+							self.stack.pop();
+							self.acc = Value::Const(value);
+							pc += 1;
+							continue;
+						}
+					}
+					let right = self.acc_operand();
+					let left = self.pop_operand();
+					match self.fused_branch(pc, punct) {
+						Some((cond, target)) => {
+							self.flush(false);
+							self.emit(RegOp::Branch(cond, s, left, right, target));
+							// 跳过合并进来的 BZ BNZ
+							pc += 1;
+						}
+						None => self.emit_result(RegOp::Binary(punct, s, ACC, left, right)),
+					}
+				}
+			}
+			pc += 1;
+		}
+		registers
+	}
+
+	/// 比较之后紧跟着 BZ BNZ, 并且两个后继都不再读比较的结果时, 合并成比较跳转
+	fn fused_branch(&self, pc: usize, op: Punct) -> Option<(Punct, usize)> {
+		let comparison = matches!(op, Punct::Eq | Punct::Ne | Punct::Lt | Punct::Gt | Punct::Le | Punct::Ge);
+		if !comparison || self.leaders[pc + 1] {
+			return None;
+		}
+		let (cond, target) = match self.program.code[pc + 1] {
+			Opcode::Bz(target) => (negate(op), target),
+			Opcode::Bnz(target) => (op, target),
+			_ => return None,
+		};
+		(!self.live[target] && !self.live[pc + 2]).then_some((cond, target))
+	}
+}
+
+/// 把指令的结果寄存器改成r
+fn set_result(op: &mut RegOp, r: usize) {
+	match op {
+		RegOp::Mov(dst, _)
+		| RegOp::Lea(dst, _)
+		| RegOp::Arg(dst, _)
+		| RegOp::Load(dst, ..)
+		| RegOp::LoadLocal(dst, ..)
+		| RegOp::Alloca(dst, ..)
+		| RegOp::Cast(dst, ..)
+		| RegOp::Binary(_, _, dst, ..)
+		| RegOp::Call(dst, ..)
+		| RegOp::CallIndirect(dst, ..)
+		| RegOp::Sys(dst, ..) => *dst = r,
+		_ => unreachable!("{:?} has no result register", op),
+	}
+}
+
+impl RegisterProgram {
+	/// 校验之后翻译整个程序, 只翻译执行得到的指令
+	pub fn translate(program: &Program) -> Result<RegisterProgram, VerifyError> {
+		let depths = stack_depths(program)?;
+		let live = accumulator_liveness(program, &depths);
+		let mut leaders = vec![false; program.code.len()];
+		for (pc, op) in program.code.iter().enumerate().filter(|(pc, _)| depths[*pc].is_some()) {
+			if let Opcode::Jmp(target) | Opcode::Bz(target) | Opcode::Bnz(target) = op {
+				leaders[*target] = true;
+			}
+			if let Opcode::Ent(_) = op {
+				leaders[pc] = true;
+			}
+		}
+		let mut translator = Translator {
+			program,
+			depths,
+			live,
+			leaders,
+			targets: vec![0; program.code.len()],
+			code: vec![],
+			spans: vec![],
+			span: None,
+			acc: Value::Reg(ACC),
+			stack: vec![],
+			pending: None,
+		};
+
+		let mut functions = program.functions.clone();
+		let mut registers = vec![0; functions.len()];
+		let mut entries: Vec<(usize, usize)> =
+			functions.iter().enumerate().filter_map(|(i, func)| Some((func.entry?, i))).collect();
+		entries.sort();
+		for (i, (entry, index)) in entries.iter().enumerate() {
+			let end = entries.get(i + 1).map_or(program.code.len(), |(next, _)| *next);
+			registers[*index] = translator.function(*entry, end);
+		}
+		let targets = &translator.targets;
+		for func in functions.iter_mut() {
+			func.entry = func.entry.map(|entry| targets[entry]);
+		}
+		for op in translator.code.iter_mut() {
+			match op {
+				RegOp::Jmp(target) | RegOp::Bz(_, target) | RegOp::Bnz(_, target) | RegOp::Branch(.., target) => {
+					*target = targets[*target]
+				}
+				_ => (),
+			}
+		}
+		Ok(RegisterProgram {
+			code: translator.code,
+			spans: translator.spans,
+			functions,
+			registers,
+			callees: program.callees.clone(),
+			data: program.data.clone(),
+			main: program.main,
+		})
+	}
+}
@@ -0,0 +1,212 @@
+//! 执行 regcode 翻译出的寄存器指令. 所有栈帧的寄存器放在同一个数组中, 调用时被调用者的寄存器
+//! 接在调用者的之后, 实参直接从调用者的寄存器中读. 内存, 内置函数和资源限制与其它两种执行方式共用 Env
+
+use super::{
+	codegen::{Callee, Scalar},
+	errors::{RuntimeError, Trap},
+	eval::{binary, finish, Env},
+	memory::{address, Memory, FUNCTION_BASE},
+	regcode::{Operand, RegOp, RegisterProgram},
+	token::Span,
+};
+
+/// 一次调用: 返回地址, 结果写到哪个寄存器, 寄存器和实参在寄存器数组中的位置, 栈帧的基地址
+#[derive(Debug)]
+struct CallFrame {
+	function: usize,
+	return_pc: usize,
+	result: usize,
+	base: usize,
+	args: usize,
+	bp: usize,
+	/// 调用之前内存的栈顶, 返回时释放之后的空间
+	sp: usize,
+	call_site: Option<Span>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+	pc: usize,
+	registers: Vec<i64>,
+	frames: Vec<CallFrame>,
+}
+
+pub struct RegisterVm<'a> {
+	program: &'a RegisterProgram,
+}
+
+fn get(registers: &[i64], base: usize, operand: Operand) -> i64 {
+	match operand {
+		Operand::Reg(r) => registers[base + r],
+		Operand::Imm(n) => n,
+	}
+}
+
+impl<'a> RegisterVm<'a> {
+	pub fn new(program: &'a RegisterProgram) -> Self {
+		RegisterVm { program }
+	}
+
+	/// 初始化数据段之后执行main, 返回main的返回值或者exit的参数
+	pub fn run(&self, env: &mut Env, args: &[String]) -> Result<i64, Trap> {
+		env.start(Memory::new(self.program.data.clone()));
+		let mut state = State::default();
+		let result = match self.program.main {
+			Some(main) => {
+				let main_args = env.enter_main(args, self.program.functions[main].params);
+				let argc = main_args.len();
+				state.registers.extend(main_args);
+				self.call(main, 0, argc, 0, argc, None, env, &mut state).and_then(|_| self.execute(env, &mut state))
+			}
+			None => Err(RuntimeError::MissingMain),
+		};
+		let calls =
+			state.frames.iter().map(|frame| (self.program.functions[frame.function].name.as_str(), frame.call_site));
+		finish(result, self.span(state.pc), calls)
+	}
+
+	/// 出错的指令对应的位置, pc已经指向下一条指令
+	fn span(&self, pc: usize) -> Option<Span> {
+		pc.checked_sub(1).and_then(|pc| self.program.spans.get(pc).copied().flatten())
+	}
+
+	/// 进入函数, argc个实参在寄存器数组中从args开始, 被调用者的寄存器从base开始
+	#[allow(clippy::too_many_arguments)]
+	fn call(
+		&self,
+		function: usize,
+		args: usize,
+		argc: usize,
+		result: usize,
+		base: usize,
+		call_site: Option<Span>,
+		env: &mut Env,
+		state: &mut State,
+	) -> Result<(), RuntimeError> {
+		let func = &self.program.functions[function];
+		let entry = func.entry.ok_or_else(|| RuntimeError::UndefinedFunction(func.name.clone()))?;
+		env.check_call(func.params, func.is_variadic, argc, state.frames.len())?;
+		let size = base + self.program.registers[function];
+		if state.registers.len() < size {
+			state.registers.resize(size, 0);
+		}
+		let sp = env.memory.stack_pointer();
+		let frame = CallFrame { function, return_pc: state.pc, result, base, args, bp: 0, sp, call_site };
+		state.frames.push(frame);
+		state.pc = entry;
+		Ok(())
+	}
+
+	/// 取指令, 分派, 直到main返回. 当前栈帧的寄存器位置, 实参位置和基地址放在局部变量中
+	fn execute(&self, env: &mut Env, state: &mut State) -> Result<i64, RuntimeError> {
+		let code = &self.program.code;
+		let (mut base, mut args, mut bp) = (0, 0, 0);
+		macro_rules! reload {
+			() => {
+				if let Some(frame) = state.frames.last() {
+					(base, args, bp) = (frame.base, frame.args, frame.bp);
+				}
+			};
+		}
+		reload!();
+		loop {
+			env.tick()?;
+			let op = code[state.pc];
+			state.pc += 1;
+			let registers = &mut state.registers;
+			match op {
+				RegOp::Mov(r, x) => registers[base + r] = get(registers, base, x),
+				RegOp::Lea(r, n) => registers[base + r] = (bp + n) as i64,
+				RegOp::Arg(r, n) => registers[base + r] = registers[args + n],
+				RegOp::Load(r, x, s) => {
+					registers[base + r] = env.memory.load(address(get(registers, base, x)), &s.ctype())?
+				}
+				RegOp::LoadLocal(r, n, s) => registers[base + r] = env.memory.load(bp + n, &s.ctype())?,
+				RegOp::Store(x, y, s) => {
+					let addr = address(get(registers, base, x));
+					env.memory.store(addr, &s.ctype(), get(registers, base, y))?;
+				}
+				RegOp::StoreLocal(n, x, s) => env.memory.store(bp + n, &s.ctype(), get(registers, base, x))?,
+				RegOp::Copy(x, y, size) => {
+					let (dst, src) = (address(get(registers, base, x)), address(get(registers, base, y)));
+					env.memory.copy(dst, src, size)?;
+				}
+				RegOp::Zero(x, size) => env.memory.fill(address(get(registers, base, x)), size, 0)?,
+				RegOp::Alloca(r, x, align) => {
+					registers[base + r] = env.memory.push(address(get(registers, base, x)), align)? as i64
+				}
+				RegOp::Ssp(n) => {
+					let sp = env.memory.stack_pointer() as i64;
+					env.memory.store(bp + n, &Scalar::U64.ctype(), sp)?;
+				}
+				RegOp::Rsp(n) => {
+					let sp = env.memory.load(bp + n, &Scalar::U64.ctype())?;
+					env.memory.pop_to(address(sp));
+				}
+				RegOp::Cast(r, x, s) => registers[base + r] = s.ctype().convert(get(registers, base, x)),
+				RegOp::Binary(op, s, r, x, y) => {
+					let (left, right) = (get(registers, base, x), get(registers, base, y));
+					registers[base + r] = binary(op, &s.ctype(), left, right, env.trap_overflow)?;
+				}
+				RegOp::Jmp(target) => state.pc = target,
+				RegOp::Bz(x, target) => {
+					if get(registers, base, x) == 0 {
+						state.pc = target;
+					}
+				}
+				RegOp::Bnz(x, target) => {
+					if get(registers, base, x) != 0 {
+						state.pc = target;
+					}
+				}
+				RegOp::Branch(op, s, x, y, target) => {
+					let (left, right) = (get(registers, base, x), get(registers, base, y));
+					if binary(op, &s.ctype(), left, right, false)? != 0 {
+						state.pc = target;
+					}
+				}
+				RegOp::Call(r, function, from, argc) => {
+					let span = self.span(state.pc);
+					let next = base + self.program.registers[state.frames.last().unwrap().function];
+					self.call(function, base + from, argc, base + r, next, span, env, state)?;
+					reload!();
+				}
+				RegOp::CallIndirect(r, callee, argc) => {
+					let value = registers[base + callee];
+					let from = base + callee + 1;
+					match self.program.callees.get(&address(value).wrapping_sub(FUNCTION_BASE)) {
+						Some(Callee::Function(function)) => {
+							let span = self.span(state.pc);
+							let next = base + self.program.registers[state.frames.last().unwrap().function];
+							self.call(*function, from, argc, base + r, next, span, env, state)?;
+							reload!();
+						}
+						Some(Callee::Builtin(builtin)) => {
+							registers[base + r] = env.call_builtin(*builtin, &registers[from..from + argc])?;
+						}
+						None => return Err(RuntimeError::NotFunction(value)),
+					}
+				}
+				RegOp::Sys(r, builtin, from, argc) => {
+					let from = base + from;
+					registers[base + r] = env.call_builtin(builtin, &registers[from..from + argc])?;
+				}
+				RegOp::Ent(size) => {
+					bp = env.memory.push(size, 16)?;
+					state.frames.last_mut().unwrap().bp = bp;
+				}
+				RegOp::Lev(x) => {
+					let frame = state.frames.pop().unwrap();
+					let returns_struct = self.program.functions[frame.function].returns_struct;
+					let value = env.return_value(get(registers, base, x), returns_struct, frame.sp)?;
+					if state.frames.is_empty() {
+						return Ok(value);
+					}
+					registers[frame.result] = value;
+					state.pc = frame.return_pc;
+					reload!();
+				}
+			}
+		}
+	}
+}
@@ -0,0 +1,334 @@
+//! 名字解析: 按照C的作用域规则把每个标识符绑定到符号表中的符号.
+//!
+//! - 文件作用域的标识符从声明处开始可见, 在声明之前使用是错误
+//! - 块作用域中重复声明是错误, 隐藏外层的同名标识符给出警告
+//! - 调用未声明的函数时按C89的规则隐式声明, 内置函数不需要声明
+//! - 枚举常量的值在解析时已经求出, 对它的引用替换为整数常量
+//! - 局部变长数组的每个变长维度在声明处求值一次, 保存在隐藏的局部变量 `名字.len维度` 中,
+//!   类型中的长度表达式替换为这个变量, 之后的sizeof和下标运算都使用它
+
+use std::collections::HashMap;
+
+use super::{
+	builtins::Builtin,
+	errors::{Diagnostic, ParseError},
+	token::{Const, Span},
+	types::*,
+};
+
+/// 解析整个翻译单元, 填入符号表, 返回所有错误和警告
+pub fn resolve(unit: &mut TranslationUnit) -> Vec<Diagnostic> {
+	let mut resolver =
+		Resolver { symbols: vec![], scopes: vec![HashMap::new()], file_scope: HashMap::new(), diagnostics: vec![] };
+
+	// 文件作用域的声明按照出现的位置排序, 函数体在函数声明之后
+	let mut events = vec![];
+	for (i, var) in unit.global_variables.iter_mut().enumerate() {
+		let kind = if var.is_enum_constant { SymbolKind::EnumConst(0) } else { SymbolKind::Global };
+		var.symbol = Some(resolver.new_symbol(&var.name, kind, var.ctype.clone(), var.span));
+		events.push((var.span.start, 0, Event::Variable(i)));
+	}
+	for (i, func) in unit.functions.iter_mut().enumerate() {
+		let kind = match Builtin::from_name(&func.name) {
+			Some(builtin) if !func.is_definition => SymbolKind::Builtin(builtin),
+			_ => SymbolKind::Function,
+		};
+		func.symbol = Some(resolver.new_symbol(&func.name, kind, Type::Func(func.ctype.clone()), func.decl_span));
+		events.push((func.decl_span.start, 0, Event::Function(i)));
+		if func.is_definition {
+			events.push((func.def_span.start, 1, Event::Body(i)));
+		}
+	}
+	resolver.file_scope =
+		resolver.symbols.iter().enumerate().map(|(i, symbol)| (symbol.name.clone(), SymbolId(i))).collect();
+	events.sort_by_key(|(start, order, _)| (*start, *order));
+
+	for (_, _, event) in events {
+		match event {
+			Event::Variable(i) => resolver.variable(&mut unit.global_variables[i]),
+			Event::Function(i) => {
+				let func = &unit.functions[i];
+				resolver.declare(&func.name, func.symbol, func.decl_span);
+			}
+			Event::Body(i) => resolver.function(&mut unit.functions[i]),
+		}
+	}
+
+	unit.symbols = resolver.symbols;
+	resolver.diagnostics
+}
+
+enum Event {
+	Variable(usize),
+	Function(usize),
+	Body(usize),
+}
+
+struct Resolver {
+	symbols: Vec<Symbol>,
+	/// 当前可见的标识符, 第一个是文件作用域, 最后一个是当前作用域
+	scopes: Vec<HashMap<String, SymbolId>>,
+	/// 文件作用域中的所有标识符, 包括还没有声明的
+	file_scope: HashMap<String, SymbolId>,
+	diagnostics: Vec<Diagnostic>,
+}
+
+impl Resolver {
+	fn new_symbol(&mut self, name: &str, kind: SymbolKind, ctype: Type, span: Span) -> SymbolId {
+		self.symbols.push(Symbol { name: name.to_owned(), kind, ctype, span });
+		SymbolId(self.symbols.len() - 1)
+	}
+
+	fn declare(&mut self, name: &str, symbol: Option<SymbolId>, span: Span) {
+		let Some(symbol) = symbol else {
+			return;
+		};
+		let is_file_scope = self.scopes.len() == 1;
+		let Some(scope) = self.scopes.last_mut() else {
+			return;
+		};
+		// 隐式声明过的函数, 再次声明时是同一个符号
+		if scope.insert(name.to_owned(), symbol).is_some_and(|prev| prev != symbol) {
+			self.diagnostics.push(Diagnostic::error(span, ParseError::Redefinition(name.to_owned())));
+		} else if !is_file_scope && self.scopes.iter().rev().skip(1).any(|scope| scope.contains_key(name)) {
+			self.diagnostics.push(Diagnostic::warning(span, ParseError::Shadows(name.to_owned())));
+		}
+	}
+
+	fn lookup(&self, name: &str) -> Option<SymbolId> {
+		self.scopes.iter().rev().find_map(|scope| scope.get(name)).copied()
+	}
+
+	/// 全局变量或者局部变量的声明. 枚举常量的作用域从它的定义之后开始, 其余的从声明符之后开始
+	fn variable(&mut self, var: &mut Variable) {
+		if var.is_enum_constant {
+			if let Some(Initializer::Expr(expr)) = var.init_value.as_mut() {
+				self.expr(expr);
+				// 枚举常量的类型是int
+				match (expr.fold_const().and_then(|v| i32::try_from(v).ok()), var.symbol) {
+					(Some(v), Some(symbol)) => self.symbols[symbol.0].kind = SymbolKind::EnumConst(v.into()),
+					_ => self.diagnostics.push(Diagnostic::error(var.span, ParseError::NotConstant(var.name.clone()))),
+				}
+			}
+			self.declare(&var.name, var.symbol, var.span);
+		} else {
+			self.declare(&var.name, var.symbol, var.span);
+			if let Some(init) = var.init_value.as_mut() {
+				self.initializer(init);
+			}
+		}
+	}
+
+	fn function(&mut self, func: &mut Function) {
+		self.scopes.push(HashMap::new());
+		for param in func.ctype.param_list.iter_mut() {
+			// 参数中的变长数组可以使用前面的参数, 如 int f(int n, int a[n])
+			self.vla_type(&mut param.ctype);
+			match &param.name {
+				Some(name) => {
+					let symbol = self.new_symbol(name, SymbolKind::Param, param.ctype.clone(), func.def_span);
+					func.locals.push(symbol);
+					self.declare(name, Some(symbol), func.def_span);
+				}
+				None => self
+					.diagnostics
+					.push(Diagnostic::error(func.def_span, ParseError::General("parameter name omitted"))),
+			}
+		}
+		// 参数和函数体最外层的块在同一个作用域中
+		match &mut func.stmts {
+			Statement::CompoundStmt(stmts) => {
+				for stmt in stmts {
+					self.stmt(stmt, &mut func.locals);
+				}
+			}
+			stmt => self.stmt(stmt, &mut func.locals),
+		}
+		self.scopes.pop();
+	}
+
+	fn stmt(&mut self, stmt: &mut Statement, locals: &mut Vec<SymbolId>) {
+		match stmt {
+			Statement::Empty | Statement::BreakStmt | Statement::ContinueStmt | Statement::Error => (),
+			Statement::ReturnStmt(None, _) => (),
+			Statement::ExprStmt(expr) | Statement::ReturnStmt(Some(expr), _) => self.expr(expr),
+			Statement::IfStmt(cond, then_stmt, else_stmt) => {
+				self.expr(cond);
+				self.stmt(then_stmt, locals);
+				if let Some(else_stmt) = else_stmt {
+					self.stmt(else_stmt, locals);
+				}
+			}
+			Statement::ForStmt(init, cond, end, body) => {
+				if let Some(init) = init {
+					self.expr(init);
+				}
+				self.expr(cond);
+				if let Some(end) = end {
+					self.expr(end);
+				}
+				self.stmt(body, locals);
+			}
+			Statement::SwitchStmt(cond, body) => {
+				self.expr(cond);
+				self.stmt(body, locals);
+			}
+			Statement::CaseStmt(_, stmt) | Statement::DefaultStmt(stmt) => self.stmt(stmt, locals),
+			Statement::CompoundStmt(stmts) => {
+				self.scopes.push(HashMap::new());
+				for stmt in stmts {
+					self.stmt(stmt, locals);
+				}
+				self.scopes.pop();
+			}
+			Statement::DeclStmt(vars) => {
+				for mut var in std::mem::take(vars) {
+					let kind = if var.is_enum_constant { SymbolKind::EnumConst(0) } else { SymbolKind::Local };
+					let mut ctype = std::mem::replace(&mut var.ctype, TYPE_VOID);
+					self.vla_lengths(&mut ctype, &var, 0, vars, locals);
+					var.ctype = ctype;
+					let symbol = self.new_symbol(&var.name, kind, var.ctype.clone(), var.span);
+					var.symbol = Some(symbol);
+					if !var.is_enum_constant {
+						locals.push(symbol);
+					}
+					self.variable(&mut var);
+					vars.push(var);
+				}
+			}
+		}
+	}
+
+	/// 把var的类型中每个变长维度的长度提到一个隐藏的int变量中, 加到vars里var的前面
+	fn vla_lengths(
+		&mut self,
+		ctype: &mut Type,
+		var: &Variable,
+		dimension: usize,
+		vars: &mut Vec<Variable>,
+		locals: &mut Vec<SymbolId>,
+	) {
+		match ctype {
+			Type::Array(Array { base_type, size_expr: Some(expr), .. }) if !matches!(expr.kind, ExprKind::Const(_)) => {
+				self.expr(expr);
+				let name = format!("{}.len{}", var.name, dimension);
+				let symbol = self.new_symbol(&name, SymbolKind::Local, TYPE_INT, expr.span);
+				locals.push(symbol);
+				let span = expr.span;
+				let length = std::mem::replace(&mut **expr, Expr::id(&name));
+				expr.kind = ExprKind::Id(name.clone(), Some(symbol));
+				expr.span = span;
+				vars.push(Variable {
+					name,
+					ctype: TYPE_INT,
+					init_value: Some(Initializer::Expr(length)),
+					is_local: true,
+					is_tentative: false,
+					is_enum_constant: false,
+					span,
+					symbol: Some(symbol),
+				});
+				self.vla_lengths(base_type, var, dimension + 1, vars, locals);
+			}
+			Type::Array(Array { base_type, .. }) => self.vla_lengths(base_type, var, dimension + 1, vars, locals),
+			Type::Ptr(Ptr { base_type }) => self.vla_lengths(base_type, var, dimension, vars, locals),
+			_ => (),
+		}
+	}
+
+	/// 类型名中的变长数组长度, 在使用处求值
+	fn vla_type(&mut self, ctype: &mut Type) {
+		match ctype {
+			Type::Array(Array { base_type, size_expr, .. }) => {
+				if let Some(expr) = size_expr {
+					self.expr(expr);
+				}
+				self.vla_type(base_type);
+			}
+			Type::Ptr(Ptr { base_type }) => self.vla_type(base_type),
+			_ => (),
+		}
+	}
+
+	fn initializer(&mut self, init: &mut Initializer) {
+		match init {
+			Initializer::Expr(expr) => self.expr(expr),
+			Initializer::List(list) => {
+				for elem in list.iter_mut().flatten() {
+					self.initializer(elem);
+				}
+			}
+		}
+	}
+
+	fn expr(&mut self, expr: &mut Expr) {
+		match &mut expr.kind {
+			ExprKind::Const(_) | ExprKind::StringLiteral(_) | ExprKind::Error => (),
+			ExprKind::SizeOfType(ctype) => self.vla_type(ctype),
+			ExprKind::Id(name, symbol) => match self.lookup(name) {
+				Some(id) => match self.symbols[id.0].kind {
+					SymbolKind::EnumConst(v) => expr.kind = ExprKind::Const(Const::Integer(v.to_string())),
+					_ => *symbol = Some(id),
+				},
+				None => {
+					let error = match self.file_scope.contains_key(name) {
+						true => ParseError::UsedBeforeDeclaration(name.clone()),
+						false => ParseError::Undeclared(name.clone()),
+					};
+					self.diagnostics.push(Diagnostic::error(expr.span, error));
+				}
+			},
+			ExprKind::FunctionCall(callee, args) => {
+				match &mut callee.kind {
+					ExprKind::Id(name, symbol) if self.lookup(name).is_none() => {
+						*symbol = Some(self.implicit_function(name, callee.span))
+					}
+					_ => self.expr(callee),
+				}
+				for arg in args {
+					self.expr(arg);
+				}
+			}
+			ExprKind::MemberAccess(base, _) | ExprKind::MemberAccessP(base, _) => self.expr(base),
+			ExprKind::Postfix(PostfixOP { expr: operand, .. })
+			| ExprKind::UnaryOp(UnaryOp { expr: operand, .. })
+			| ExprKind::SizeOfExpr(operand) => self.expr(operand),
+			ExprKind::Cast(ctype, operand) => {
+				self.vla_type(ctype);
+				self.expr(operand);
+			}
+			ExprKind::CompoundLiteral(ctype, init) => {
+				self.vla_type(ctype);
+				self.initializer(init);
+			}
+			ExprKind::BinOp(BinOp { left, right, .. })
+			| ExprKind::AssignExpr(AssignExpr { left, right, .. })
+			| ExprKind::CommaExpr(CommaExpr { left, right }) => {
+				self.expr(left);
+				self.expr(right);
+			}
+			ExprKind::CondExpr(CondExpr { cond, left, right }) => {
+				self.expr(cond);
+				self.expr(left);
+				self.expr(right);
+			}
+		}
+	}
+
+	/// 调用未声明的函数. 内置函数直接绑定; 否则视为在文件作用域声明了 int f(),
+	/// 如果后面定义了同名函数就绑定到它
+	fn implicit_function(&mut self, name: &str, span: Span) -> SymbolId {
+		let symbol = match Builtin::from_name(name) {
+			Some(builtin) => self.new_symbol(name, SymbolKind::Builtin(builtin), Type::Func(builtin.ctype()), span),
+			None => {
+				self.diagnostics.push(Diagnostic::warning(span, ParseError::ImplicitDeclaration(name.to_owned())));
+				match self.file_scope.get(name) {
+					Some(symbol) if self.symbols[symbol.0].kind == SymbolKind::Function => *symbol,
+					_ => self.new_symbol(name, SymbolKind::Function, TYPE_INT.into_unspecified_function(), span),
+				}
+			}
+		};
+		self.scopes[0].insert(name.to_owned(), symbol);
+		symbol
+	}
+}
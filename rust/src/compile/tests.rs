@@ -1,4 +1,6 @@
-use super::errors::{LexError, ParseError};
+use super::errors::{ConstError, LexError, ParseError, RuntimeError, Trap};
+use super::token::{Const, Punct};
+use super::types::*;
 use crate::compile::parse::*;
 
 fn test_declaration(input: &str) {
@@ -124,7 +126,8 @@ fn parse(input: &str) {
 	Parser::from_str(input)
 		.and_then(|mut p| {
 			p.parse()?;
-			Ok(p.display())
+			p.display();
+			Ok(())
 		})
 		.unwrap_or(())
 }
@@ -179,11 +182,15 @@ fn parse_eval(input: &str) {
 	println!("\n------");
 	match Parser::from_str(input).and_then(|mut p| {
 		p.parse()?;
+		p.check()?;
 		p.display();
 		let (mut env, vm) = p.into_vm();
-		vm.eval(&mut env)
+		Ok(match vm.eval(&mut env) {
+			Ok(_) => vm.dump(&env),
+			Err(trap) => trap.to_string(),
+		})
 	}) {
-		Ok(_) => println!("eval ok"),
+		Ok(dump) => println!("eval ok\n{}", dump),
 		Err(e) => println!("\t[error]\t{}", e),
 	}
 }
@@ -201,3 +208,1234 @@ int main(void) {
 "##,
 	);
 }
+
+fn expr_of(input: &str) -> Expr {
+	Parser::from_str(input).and_then(|mut p| p.parse_expression()).unwrap()
+}
+
+fn type_name_of(input: &str) -> Type {
+	Parser::from_str(input).and_then(|mut p| p.parse_type_name()).unwrap()
+}
+
+#[test]
+fn test_cast_sizeof() {
+	assert_eq!(type_name_of("int"), TYPE_INT);
+	assert_eq!(type_name_of("char *"), TYPE_CHAR.into_pointer());
+	assert_eq!(type_name_of("int (*)(void)"), TYPE_INT.into_function().into_pointer());
+	assert_eq!(type_name_of("int [4]").size(), 16);
+
+	assert_eq!(expr_of("(char)i"), Expr::new_cast(TYPE_CHAR, Expr::id("i")));
+	assert_eq!(
+		expr_of("(int *)p + 1"),
+		Expr::new_binary(
+			Expr::new_cast(TYPE_INT.into_pointer(), Expr::id("p")),
+			Punct::Add,
+			Expr::constant(Const::Integer("1".into()))
+		)
+	);
+	// (i) 不是类型, 仍然是括号表达式
+	assert_eq!(expr_of("(i) + 1"), Expr::new_binary(Expr::id("i"), Punct::Add, expr_of("1")));
+
+	assert_eq!(expr_of("sizeof(int (*)(void))"), ExprKind::SizeOfType(TYPE_INT.into_function().into_pointer()).into());
+	assert_eq!(expr_of("sizeof i"), Expr::new_sizeof_expr(Expr::id("i")));
+	assert_eq!(expr_of("sizeof(char)").fold_const(), Some(1));
+	assert_eq!(expr_of("sizeof(int *[3])").fold_const(), Some(24));
+	assert_eq!(expr_of("sizeof \"hi\"").fold_const(), Some(3));
+	assert_eq!(expr_of("sizeof ((char)1)").fold_const(), Some(1));
+	assert_eq!(expr_of("sizeof i").fold_const(), None);
+
+	assert_eq!(type_name_of("int [sizeof(int)]").size(), 16);
+}
+
+fn data_of(input: &str) -> crate::compile::data::DataSegment {
+	Parser::from_str(input)
+		.and_then(|mut p| {
+			p.parse()?;
+			p.check()?;
+			p.data_segment()
+		})
+		.unwrap()
+}
+
+fn int_at(bytes: &[u8], offset: usize) -> i32 {
+	i32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+#[test]
+fn test_initializer() {
+	let data = data_of("int a[3] = {1, 2, 3};");
+	assert_eq!((0..3).map(|i| int_at(&data.bytes, i * 4)).collect::<Vec<_>>(), [1, 2, 3]);
+
+	// 长度由初始化器确定
+	let data = data_of("char s[] = \"hi\"; int b[] = {[4] = 1, 2};");
+	assert_eq!(&data.bytes[..3], b"hi\0");
+	assert_eq!(data.symbols[1], ("b".into(), 4));
+	assert_eq!(data.offset_of(SymbolId(1)), Some(4));
+	assert_eq!(data.bytes.len(), 4 + 6 * 4);
+	assert_eq!(int_at(&data.bytes, 4 + 4 * 4), 1);
+	assert_eq!(int_at(&data.bytes, 4 + 5 * 4), 2);
+
+	// 花括号省略
+	let data = data_of("int m[2][2] = {1, 2, 3, 4}; int n[2][2] = {{1}, 3, 4};");
+	assert_eq!((0..4).map(|i| int_at(&data.bytes, i * 4)).collect::<Vec<_>>(), [1, 2, 3, 4]);
+	assert_eq!((4..8).map(|i| int_at(&data.bytes, i * 4)).collect::<Vec<_>>(), [1, 0, 3, 4]);
+
+	// 结构体和指示符
+	let data =
+		data_of("struct p { char c; int x; int y; }; struct p q = {.x = 1, 2}, r[2] = {[1].y = 3, [0] = {'a'}};");
+	assert_eq!(data.symbols[1], ("r".into(), 12));
+	assert_eq!(int_at(&data.bytes, 4), 1);
+	assert_eq!(int_at(&data.bytes, 8), 2);
+	assert_eq!(data.bytes[12], b'a');
+	assert_eq!(int_at(&data.bytes, 12 + 12 + 8), 3);
+
+	// 地址常量, 函数按名字解析得到的符号重定位
+	let data = data_of("int x; int *p = &x; char *s = \"ab\"; int f(void) { return 0; } int (*pf)(void) = f;");
+	use crate::compile::data::{RelocTarget, Relocation};
+	assert_eq!(
+		data.relocations,
+		[
+			Relocation { offset: 8, target: RelocTarget::Data(0) },
+			Relocation { offset: 16, target: RelocTarget::Data(32) },
+			Relocation { offset: 24, target: RelocTarget::Function(SymbolId(4)) },
+		]
+	);
+	assert_eq!(&data.bytes[32..], b"ab\0");
+
+	assert_eq!(
+		Parser::from_str("int x; int y = x;").and_then(|mut p| {
+			p.parse()?;
+			p.data_segment()
+		}),
+		Err(ParseError::NotConstant("y".into()))
+	);
+	// 溢出和除以0报告常量表达式本身的错误
+	assert_eq!(check_result("int g = 2147483647 + 1;").err(), Some(ParseError::ConstError(ConstError::Overflow)));
+	assert_eq!(
+		check_result("int a[2]; int *p = a + 1 / 0;").err(),
+		Some(ParseError::ConstError(ConstError::DivisionByZero))
+	);
+	assert!(Parser::from_str("int a[2] = {1, 2, 3};").and_then(|mut p| p.parse()).is_err());
+	// 只能丢掉结尾的0
+	assert_eq!(&data_of("char s[2] = \"hi\";").bytes, b"hi");
+	assert_eq!(
+		parse_result("char s[1] = \"hi\";").err(),
+		Some(ParseError::General("initializer-string for char array is too long"))
+	);
+}
+
+#[test]
+fn test_compound_literal() {
+	let expr = expr_of("(int[]){1, 2}");
+	let list = Initializer::List(vec![
+		Some(Initializer::Expr(Expr::constant(Const::Integer("1".into())))),
+		Some(Initializer::Expr(Expr::constant(Const::Integer("2".into())))),
+	]);
+	assert_eq!(expr, ExprKind::CompoundLiteral(TYPE_INT.into_array(None).with_array_length(2), Box::new(list)).into());
+	assert_eq!(expr_of("sizeof (int[]){1, 2, 3}").fold_const(), Some(12));
+
+	// 块作用域中的声明
+	assert!(Parser::from_str("int main() { struct p { int x; }; struct p q = {1}; int a[] = {1, 2}, i = 0; }")
+		.and_then(|mut p| p.parse())
+		.is_ok());
+}
+
+fn parse_result(input: &str) -> Result<Parser, ParseError> {
+	Parser::from_str(input).and_then(|mut p| {
+		p.parse()?;
+		Ok(p)
+	})
+}
+
+#[test]
+fn test_redeclaration() {
+	assert_eq!(
+		type_name_of("int (const char *, ...)"),
+		Type::Func(Func {
+			return_type: Box::new(TYPE_INT),
+			param_list: vec![TypeIdentifier::new(TYPE_CHAR.into_pointer(), None)],
+			is_variadic: true,
+			is_unspecified: false,
+		})
+	);
+	// 数组参数调整为指针
+	assert_eq!(type_name_of("int (int a[])"), type_name_of("int (int *a)"));
+
+	// 参数之间缺少逗号
+	assert!(parse_result("int f(int a int b);").is_err());
+	assert!(parse_result("int main(void) { f(1 2); }").is_err());
+
+	assert!(parse_result("int f(int); int f(int x) { return x; } int f(int y);").is_ok());
+	assert!(parse_result("int a[]; int a[3]; extern int a[3]; int a[];").is_ok());
+	assert!(parse_result("int i; int i = 1; int i;").is_ok());
+
+	assert_eq!(parse_result("int f(int); char f(int);").err(), Some(ParseError::ConflictingTypes("f".into())));
+	assert_eq!(parse_result("int f(int); int f(int, ...);").err(), Some(ParseError::ConflictingTypes("f".into())));
+	assert_eq!(parse_result("int a[2]; int a[3];").err(), Some(ParseError::ConflictingTypes("a".into())));
+	assert_eq!(parse_result("int i; char i;").err(), Some(ParseError::ConflictingTypes("i".into())));
+	assert_eq!(parse_result("int i; int i(void);").err(), Some(ParseError::ConflictingTypes("i".into())));
+	assert_eq!(parse_result("int i = 1; int i = 2;").err(), Some(ParseError::Redefinition("i".into())));
+	assert_eq!(
+		parse_result("int f(void) { return 0; } int f(void) { return 1; }").err(),
+		Some(ParseError::Redefinition("f".into()))
+	);
+
+	// () 没有原型, 合成类型取原型的参数
+	let unit = parse_result("int f(); int f(int a) { return a; } int g(void); int g();").unwrap().into_unit();
+	assert_eq!(unit.functions[0].ctype.param_list, [TypeIdentifier::new(TYPE_INT, Some("a".into()))]);
+	assert!(!unit.functions[0].ctype.is_unspecified);
+	assert_eq!(Type::Func(unit.functions[1].ctype.clone()), TYPE_INT.into_function());
+	for input in ["int f(char); int f();", "int f(int, ...); int f();", "int f(int); int f() { return 0; }"] {
+		assert_eq!(parse_result(input).err(), Some(ParseError::ConflictingTypes("f".into())), "{}", input);
+	}
+	assert_eq!(check_result("int g(void); int f(void) { return g(1); }").err(), Some(ParseError::ArgumentCount(0, 1)));
+	assert!(check_result("int g(); int f(void) { return g(1) + g(); }").is_ok());
+	// 在后一个声明符的位置报告
+	let (_, diagnostics) = recover("int f(int);\nchar  f(int);\nint x = 1;\nint x = 2;\n");
+	assert_eq!(diagnostics, ["2:7: error: conflicting types for f", "4:5: error: redefinition of x"]);
+
+	let data = data_of("int a[]; int a[3]; int t; int i; int i = 2;");
+	assert_eq!(data.symbols, [("a".into(), 0), ("t".into(), 12), ("i".into(), 16)]);
+	assert_eq!(int_at(&data.bytes, 16), 2);
+}
+
+fn recover(input: &str) -> (TranslationUnit, Vec<String>) {
+	let mut p = Parser::from_str(input).unwrap();
+	let _ = p.parse();
+	let diagnostics = p.diagnostics().iter().map(|d| d.to_string()).collect();
+	(p.into_unit(), diagnostics)
+}
+
+#[test]
+fn test_error_recovery() {
+	let (unit, diagnostics) = recover(
+		r##"int a = ;
+int b;
+int f(int x) {
+	x = 1 +;
+	return x;
+}
+int g(void) { if (1) { ] } return 0; }
+int c;
+"##,
+	);
+	assert_eq!(unit.global_variables.iter().map(|v| v.name.as_str()).collect::<Vec<_>>(), ["a", "b", "c"]);
+	assert_eq!(unit.functions.iter().map(|f| f.name.as_str()).collect::<Vec<_>>(), ["f", "g"]);
+	assert_eq!(unit.global_variables[0].init_value, Some(Initializer::Expr(Expr::error())));
+	let Statement::CompoundStmt(stmts) = &unit.functions[0].stmts else { panic!() };
+	assert_eq!(stmts[0], Statement::ExprStmt(Expr::error()));
+	assert!(matches!(&stmts[1], Statement::ReturnStmt(Some(x), _) if *x == Expr::id("x")));
+	assert_eq!(diagnostics.len(), 3, "{:?}", diagnostics);
+	assert!(diagnostics[0].starts_with("1:9: error:"), "{:?}", diagnostics);
+	assert!(diagnostics[1].starts_with("4:9: error:"), "{:?}", diagnostics);
+	assert!(diagnostics[2].starts_with("7:24: error:"), "{:?}", diagnostics);
+
+	// 同步之后的错误照常报告, 包括同一个函数中相邻的语句
+	let (_, diagnostics) = recover("int main(void){\n int a = 1\n int b = 2;\n return a\n}\nint c = ;\n");
+	assert_eq!(
+		diagnostics,
+		["3:2: error: expecting ;, but int", "5:1: error: expecting ;, but }", "6:9: error: expected expression"]
+	);
+}
+
+#[test]
+fn test_strict_entry() {
+	fn trailing(span: &str) -> String {
+		format!("unexpected trailing tokens at {}", span)
+	}
+	let err = |r: Result<Parser, ParseError>| r.err().map(|e| e.to_string());
+
+	// sizeof (char) 之后的 1 是多余的
+	let r = Parser::from_str("sizeof (char) 1").and_then(|mut p| p.parse_expression().map(|_| p));
+	assert_eq!(err(r), Some(trailing("1:15")));
+	let r = Parser::from_str("int *\n)").and_then(|mut p| p.parse_type_name().map(|_| p));
+	assert_eq!(err(r), Some(trailing("2:1")));
+	let r = Parser::from_str("int i, j").and_then(|mut p| p.parse_type_name().map(|_| p));
+	assert!(r.is_err());
+	let r = Parser::from_str("x = 1; y = 2;").and_then(|mut p| p.parse_statement().map(|_| p));
+	assert_eq!(err(r), Some(trailing("1:8")));
+
+	let mut p = Parser::from_str("if (x) { x = 1; } else return 2;").unwrap();
+	assert!(matches!(p.parse_statement(), Ok(Statement::IfStmt(..))));
+	// 恢复过的错误也算失败
+	let mut p = Parser::from_str("{ x = ; }").unwrap();
+	assert_eq!(p.parse_statement(), Err(ParseError::NoMoreExpr));
+
+	let mut p = Parser::from_str("int x; int f(void) { return x; }").unwrap();
+	assert_eq!(p.parse_translation_unit().map(|unit| unit.functions.len()), Ok(1));
+}
+
+fn check_result(input: &str) -> Result<TranslationUnit, ParseError> {
+	Parser::from_str(input).and_then(|mut p| {
+		p.parse()?;
+		p.check()?;
+		Ok(p.into_unit())
+	})
+}
+
+/// 最后一个函数体中每个表达式语句的表达式
+fn checked_exprs(input: &str) -> Vec<Expr> {
+	let unit = check_result(input).unwrap();
+	match &unit.functions.last().unwrap().stmts {
+		Statement::CompoundStmt(stmts) => stmts
+			.iter()
+			.filter_map(|stmt| match stmt {
+				Statement::ExprStmt(expr) | Statement::ReturnStmt(Some(expr), _) => Some(expr.clone()),
+				_ => None,
+			})
+			.collect(),
+		_ => vec![],
+	}
+}
+
+#[test]
+fn test_type_check() {
+	let exprs =
+		checked_exprs("int x; char c; int a[4]; int *p; int f(void) { c + x; p + 1; 1 + p; a[2]; p - p; c = 300; }");
+	assert!(exprs.iter().all(|e| e.ctype.is_some()));
+	// 整数提升
+	let ExprKind::BinOp(BinOp { left, .. }) = &exprs[0].kind else { panic!() };
+	assert!(matches!(&left.kind, ExprKind::Cast(Type::Int, _)));
+	assert_eq!(exprs[0].ctype, Some(TYPE_INT));
+	// 指针运算按元素大小缩放, 整数 + 指针交换了操作数
+	for e in &exprs[1..3] {
+		let ExprKind::BinOp(BinOp { left, op: Punct::Add, right }) = &e.kind else { panic!() };
+		assert_eq!(left.ctype, Some(TYPE_INT.into_pointer()));
+		assert_eq!(right.fold_const(), Some(4));
+	}
+	// a[2] 即 *(a + 2), 是左值
+	assert!(matches!(&exprs[3].kind, ExprKind::UnaryOp(UnaryOp { op: Punct::Mul, .. })));
+	assert_eq!((exprs[3].ctype.clone(), exprs[3].is_lvalue()), (Some(TYPE_INT), true));
+	// 指针相减的结果除以元素大小
+	assert!(matches!(&exprs[4].kind, ExprKind::BinOp(BinOp { op: Punct::Div, .. })));
+	assert_eq!(exprs[4].ctype, Some(TYPE_LONG));
+	// 赋值转换为左边的类型
+	let ExprKind::AssignExpr(AssignExpr { right, .. }) = &exprs[5].kind else { panic!() };
+	assert!(matches!(&right.kind, ExprKind::Cast(Type::Char, _)));
+
+	let exprs = checked_exprs(
+		"int a[4]; int g(int); int (*pg)(int); int f(void) { pg = g; return sizeof a + pg(1) + (*g)(2); }",
+	);
+	// sizeof的类型是unsigned long, 返回时转换为int
+	let ExprKind::Cast(Type::Int, sum) = &exprs[1].kind else { panic!() };
+	let ExprKind::BinOp(BinOp { left, .. }) = &sum.kind else { panic!() };
+	let ExprKind::BinOp(BinOp { left: size, .. }) = &left.kind else { panic!() };
+	assert_eq!((size.fold_const(), size.ctype.clone()), (Some(16), Some(TYPE_ULONG)));
+
+	// 赋值是右结合的, 条件表达式不是左值
+	assert!(check_result("int x, a, b; int f(void) { a = b = 2; x ? a : b; }").is_ok());
+	assert_eq!(
+		check_result("int x, a, b; int f(void) { x ? a : b = 2; }").err(),
+		Some(ParseError::NotLvalue("=".into()))
+	);
+	assert_eq!(check_result("int a[2]; int f(void) { a = 0; }").err(), Some(ParseError::NotLvalue("=".into())));
+	assert_eq!(check_result("int x; int f(void) { x(); }").err(), Some(ParseError::NotFunction));
+	assert_eq!(check_result("int f(void) { return y; }").err(), Some(ParseError::Undeclared("y".into())));
+	assert_eq!(check_result("int g(int); int f(void) { g(1, 2); }").err(), Some(ParseError::ArgumentCount(1, 2)));
+	assert_eq!(
+		check_result("struct s { int x; } v; int f(void) { return v.y; }").err(),
+		Some(ParseError::NoMember("y".into()))
+	);
+	assert!(check_result("struct s { int x; } v; int f(struct s *p) { return v.x + p->x; }").is_ok());
+	assert!(check_result("int *p; int f(void) { return *p + p[1] + !p; }").is_ok());
+	assert!(check_result("int *p; int f(void) { return p * 2; }").is_err());
+
+	// 指针赋值要求所指的类型兼容, void * 除外
+	assert!(check_result(
+		"struct s { int x; } a; int g(int *); int f(void) { char c, *q = &c; void *v = q; int *p = v; struct s *ps = &a; \
+		 char *r = \"x\"; return g(v) + g(0); }"
+	)
+	.is_ok());
+	for input in ["int f(void) { char c; char *q = &c; int *p = q; }", "int g(int *); int f(void) { char c; g(&c); }"] {
+		assert!(matches!(check_result(input).err(), Some(ParseError::InvalidOperands(_))), "{}", input);
+	}
+}
+
+/// 名字解析的警告和错误信息
+fn resolve_diagnostics(input: &str) -> Vec<String> {
+	let mut p = Parser::from_str(input).unwrap();
+	p.parse().unwrap();
+	let _ = p.check();
+	p.diagnostics().iter().map(|d| d.to_string()).collect()
+}
+
+#[test]
+fn test_resolve() {
+	let unit =
+		check_result("int x; int f(int a) { int x = a; { int y = x; } return x; } int g(void) { return x; }").unwrap();
+	let f = &unit.functions[0];
+	let names: Vec<_> = f.locals.iter().map(|id| (unit.symbol(*id).name.as_str(), unit.symbol(*id).kind)).collect();
+	assert_eq!(names, [("a", SymbolKind::Param), ("x", SymbolKind::Local), ("y", SymbolKind::Local)]);
+	// 局部的x隐藏全局的x, g中的x是全局变量
+	let Statement::CompoundStmt(stmts) = &f.stmts else { panic!() };
+	let Statement::ReturnStmt(Some(Expr { kind: ExprKind::Id(_, Some(local_x)), .. }), _) = &stmts[2] else { panic!() };
+	assert_eq!(*local_x, f.locals[1]);
+	let Statement::CompoundStmt(stmts) = &unit.functions[1].stmts else { panic!() };
+	let Statement::ReturnStmt(Some(global_x), _) = &stmts[0] else { panic!() };
+	assert!(matches!(&global_x.kind, ExprKind::Id(_, symbol) if *symbol == unit.global_variables[0].symbol));
+
+	// 枚举常量
+	let exprs = checked_exprs("enum e { A, B = 5, C }; int f(void) { enum { D = C * 2 }; return A + B + C + D; }");
+	assert_eq!(exprs[0].fold_const(), Some(23));
+	assert!(check_result("enum e { A }; int A;").is_err());
+	assert!(check_result("struct s { int x; }; enum s x;").is_err());
+
+	assert_eq!(
+		check_result("int f(void) { return y; } int y;").err(),
+		Some(ParseError::UsedBeforeDeclaration("y".into()))
+	);
+	assert_eq!(check_result("int f(void) { int a; int a; }").err(), Some(ParseError::Redefinition("a".into())));
+	assert_eq!(check_result("int f(int a) { int a; }").err(), Some(ParseError::Redefinition("a".into())));
+	assert_eq!(
+		resolve_diagnostics("int f(int a) { { int a; } g(); printf(\"\"); }"),
+		[
+			"1:22: warning: declaration of a shadows a previous declaration",
+			"1:27: warning: implicit declaration of function g"
+		]
+	);
+	// 隐式声明绑定到后面定义的函数
+	let unit = check_result("int f(void) { return g(); } int g(void) { return 1; }").unwrap();
+	let Statement::CompoundStmt(stmts) = &unit.functions[0].stmts else { panic!() };
+	let Statement::ReturnStmt(Some(Expr { kind: ExprKind::FunctionCall(callee, _), .. }), _) = &stmts[0] else {
+		panic!()
+	};
+	let ExprKind::Cast(_, callee) = &callee.kind else { panic!() };
+	assert!(matches!(&callee.kind, ExprKind::Id(_, symbol) if *symbol == unit.functions[1].symbol));
+}
+
+#[test]
+fn test_const_eval() {
+	// 数组长度可以是任意的整数常量表达式, 包括枚举常量, sizeof和类型转换
+	let unit = check_result(
+		"enum { N = 2 + 3 * 4, M = sizeof(int[N]) / sizeof(int), K = (char)300, L = 1 ? 4 : 1 / 0 };
+		int a[N], b[M << 1], c[K == 44 && L], d[sizeof a / sizeof a[0]];",
+	)
+	.unwrap();
+	let lengths: Vec<_> = unit.global_variables[4..].iter().map(|var| var.ctype.size()).collect();
+	assert_eq!(lengths, [56, 112, 4, 56]);
+
+	// 长度不是常量时是变长数组
+	assert!(!type_name_of("int[2 * 3]").is_vla());
+	let unit = check_result("int f(int n) { int v[n + 1]; int w[2][sizeof v]; }").unwrap();
+	let vla: Vec<_> =
+		unit.symbols.iter().filter(|s| ["v", "w"].contains(&s.name.as_str())).map(|s| s.ctype.is_vla()).collect();
+	assert_eq!(vla, [true, true]);
+
+	for (input, e) in [
+		("int a[2147483647 + 1];", ConstError::Overflow),
+		("int a[1 << 31];", ConstError::Overflow),
+		("int a[(-2147483647 - 1) / -1];", ConstError::Overflow),
+		("enum { A = 2147483647, B };", ConstError::Overflow),
+		("int a[1 % 0];", ConstError::DivisionByZero),
+		("int x; enum { A = x };", ConstError::NotConstant),
+	] {
+		assert_eq!(parse_result(input).err(), Some(ParseError::ConstError(e)), "{}", input);
+	}
+
+	// case标签
+	assert!(
+		check_result("enum { A = 1 }; int f(int x) { switch (x) { case A: case A + 1: break; default: ; } }").is_ok()
+	);
+	assert!(parse_result("int f(int x) { switch (x) { case 1: case 2 - 1: ; } }").is_err());
+	assert!(parse_result("int f(int x) { switch (x) { default: default: ; } }").is_err());
+	assert!(parse_result("int f(int x) { case 1: ; }").is_err());
+	assert!(parse_result("int f(int x) { switch (x) { case x: ; } }").is_err());
+}
+
+#[test]
+fn test_vla() {
+	// 变长维度的长度在声明处保存到隐藏变量中
+	let unit = check_result("int f(int n) { int v[n][2][n * 2]; n = 0; return sizeof v; }").unwrap();
+	let names: Vec<_> = unit.functions[0].locals.iter().map(|id| unit.symbol(*id).name.as_str()).collect();
+	assert_eq!(names, ["n", "v.len0", "v.len2", "v"]);
+	let Statement::CompoundStmt(stmts) = &unit.functions[0].stmts else { panic!() };
+	let Statement::DeclStmt(vars) = &stmts[0] else { panic!() };
+	let Type::Array(Array { size_expr: Some(len), .. }) = &vars[2].ctype else { panic!() };
+	assert!(matches!(&len.kind, ExprKind::Id(name, Some(_)) if name == "v.len0"));
+	// sizeof 变长数组不是常量
+	let Statement::ReturnStmt(Some(size), _) = &stmts[2] else { panic!() };
+	assert_eq!(size.fold_const(), None);
+
+	// 指向变长数组的指针运算按运行时的大小缩放
+	let exprs = checked_exprs("int f(int n, int a[n][n]) { a + 1; }");
+	let ExprKind::BinOp(BinOp { right, .. }) = &exprs[0].kind else { panic!() };
+	let ExprKind::BinOp(BinOp { op: Punct::Mul, right: size, .. }) = &right.kind else { panic!() };
+	assert!(matches!(&size.kind, ExprKind::SizeOfType(ctype) if ctype.is_vla()));
+
+	for input in [
+		"int n; int a[n];",
+		"int n; int (*p)[n];",
+		"int f(int n) { int a[n] = {1}; }",
+		"int f(int n) { struct s { int a[n]; } x; }",
+	] {
+		assert!(matches!(parse_result(input).err(), Some(ParseError::VariablyModified(_))), "{}", input);
+	}
+	assert!(check_result("int f(int *p) { int a[p]; }").is_err());
+}
+
+/// 编译必须成功, 用于测试运行时的行为
+fn compile_ok(input: &str) -> (super::eval::Env, super::eval::VM) {
+	let mut p = Parser::from_str(input).unwrap();
+	p.parse().unwrap();
+	p.check().unwrap_or_else(|e| panic!("{}: {:?}", e, p.diagnostics()));
+	p.into_vm()
+}
+
+/// 运行main, 返回main的返回值以及全局变量和main的局部变量的值
+fn eval_result(input: &str) -> Result<(i64, String), Trap> {
+	let (mut env, vm) = compile_ok(input);
+	let result = vm.eval(&mut env);
+	if let Some(bytecode) = eval_bytecode(input) {
+		let expected = result.as_ref().map(|code| *code).map_err(|trap| &trap.error);
+		let bytecode = bytecode.as_ref().map(|(code, _)| *code).map_err(|trap| &trap.error);
+		assert_eq!(bytecode, expected, "bytecode: {}", input);
+	}
+	if let Some(ir) = eval_ir(input) {
+		let expected = result.as_ref().map(|code| *code).map_err(|trap| &trap.error);
+		assert_eq!(ir.as_ref().map(|(code, _)| *code).map_err(|trap| &trap.error), expected, "ir: {}", input);
+	}
+	Ok((result?, vm.dump(&env)))
+}
+
+/// 翻译成字节码和中间表示时就报错的程序, 树遍历的解释器到运行时才报告. 其他程序都必须能翻译
+const TRANSLATION_ERRORS: [&str; 1] = ["int main(void) { return 99999999999999999999; }"];
+
+/// 翻译的结果, TRANSLATION_ERRORS中的程序必须翻译失败, 返回None
+fn translated<T>(input: &str, result: Result<T, ParseError>, what: &str) -> Option<T> {
+	match TRANSLATION_ERRORS.contains(&input) {
+		true => {
+			assert!(result.is_err(), "{} should fail: {}", what, input);
+			None
+		}
+		false => Some(result.unwrap_or_else(|e| panic!("{}: {}: {}", what, e, input))),
+	}
+}
+
+/// 翻译成字节码运行, 返回main的返回值和printf的输出
+fn eval_bytecode(input: &str) -> Option<Result<(i64, String), Trap>> {
+	let (mut env, vm) = compile_ok(input);
+	env.capture_output();
+	let program = translated(input, vm.compile(&env), "bytecode")?;
+	let result = super::vm::BytecodeVm::verified(&program).unwrap().run(&mut env, &[]);
+	let result = result.map(|code| (code, String::from_utf8_lossy(&env.take_output()).into_owned()));
+	// 寄存器虚拟机执行同一个程序, 结果和运行时错误都要和栈式的相同
+	let (mut env, _) = compile_ok(input);
+	env.capture_output();
+	let registers = super::regcode::RegisterProgram::translate(&program).unwrap();
+	let register_result = super::regvm::RegisterVm::new(&registers).run(&mut env, &[]);
+	let register_result = register_result.map(|code| (code, String::from_utf8_lossy(&env.take_output()).into_owned()));
+	assert_eq!(register_result, result, "register vm differs");
+	Some(result)
+}
+
+/// 翻译成中间表示解释执行, 返回main的返回值和printf的输出
+fn eval_ir(input: &str) -> Option<Result<(i64, String), Trap>> {
+	let (mut env, vm) = compile_ok(input);
+	env.capture_output();
+	let module = translated(input, vm.lower(&env), "ir")?;
+	let result = super::ireval::IrInterpreter::new(&module).run(&mut env, &[]);
+	Some(result.map(|code| (code, String::from_utf8_lossy(&env.take_output()).into_owned())))
+}
+
+#[test]
+fn test_eval_expr() {
+	let (_, dump) = eval_result(
+		r"
+int g = 3, h;
+_Bool flag;
+int main(void) {
+	int a = 7 / 2 + 7 % 3 * -2, b = (1 << 4) | 5 & ~1, c = 0;
+	int d = (c && g++) + (1 || h++), e = (c++, c++, c);
+	int i = 10, j = i++ + ++i, k = i-- - --i;
+	int m = 5;
+	m += 3; m <<= 2; m %= 7; m ^= 1;
+	int n = a > b ? a : b;
+	int *p = 0, q = sizeof(int[i]);
+	p++;
+	flag = 4;
+	h = g * 2;
+}
+",
+	)
+	.unwrap();
+	let lines: Vec<_> = dump.lines().collect();
+	assert_eq!(
+		lines,
+		[
+			"g = 3", "h = 6", "flag = 1", "a = 1", "b = 20", "c = 2", "d = 1", "e = 2", "i = 10", "j = 22", "k = 2",
+			"m = 5", "n = 20", "p = 4", "q = 40"
+		]
+	);
+	assert!(eval_result("int main(void) { int z = 0; z = 1 / z; }").is_err());
+	assert!(eval_result("int f(void); int main(void) { f(); }").is_err());
+}
+
+#[test]
+fn test_eval_call() {
+	let run = |input: &str| eval_result(input).map(|(code, _)| code).map_err(|trap| trap.error);
+	// 递归, 参数按位置绑定, return提前返回
+	assert_eq!(
+		run("int fib(int n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); } int main(void) { return fib(15); }"),
+		Ok(610)
+	);
+	assert_eq!(
+		run("int sub(int a, int b) { return a - b; } int main(void) { int a = 1; return sub(10, 3) * a; }"),
+		Ok(7)
+	);
+	// 被调用的函数有自己的栈帧
+	assert_eq!(run("int f(int n) { int x = n; return x; } int main(void) { int x = 5; f(1); return x; }"), Ok(5));
+	assert_eq!(run("int main(void) { }"), Ok(0));
+	// void函数中没有值的 return; 提前返回, 其它函数中是错误
+	let skip = "void set(int *p) { if (*p) return; *p = 5; }";
+	assert_eq!(
+		run(&format!("{} int main(void) {{ int x = 0, y = 1; set(&x); set(&y); return x * 10 + y; }}", skip)),
+		Ok(51)
+	);
+	// 在return语句的位置报告
+	assert_eq!(
+		resolve_diagnostics("int f(void) {\n\treturn;\n}\nvoid g(void) { return 1; }"),
+		["2:2: error: return with no value in non-void function", "4:16: error: return with a value in void function"]
+	);
+	// switch: 匹配的case, 贯穿, break和default
+	let classify = "int c(int x) { switch (x) { case 0: return 10; case 1: case 2: x = x * 100; break; default: x = -1; } return x; }";
+	for (arg, expected) in [(0, 10), (1, 100), (2, 200), (7, -1)] {
+		assert_eq!(run(&format!("{} int main(void) {{ return c({}); }}", classify, arg)), Ok(expected));
+	}
+	assert_eq!(run("int main(void) { int x = 1; switch (3) { case 1: x = 2; } return x; }"), Ok(1));
+
+	// 通过转换过的函数指针调用时参数个数在运行时检查
+	assert_eq!(
+		run("int f(int a) { return a; } int main(void) { int (*p)(int, int) = (int (*)(int, int))f; return p(1, 2); }"),
+		Err(RuntimeError::ArgumentCount(1, 2))
+	);
+	assert_eq!(run("int f(void); int main(void) { return f(); }"), Err(RuntimeError::UndefinedFunction("f".into())));
+}
+
+#[test]
+fn test_eval_memory() {
+	let run = |input: &str| eval_result(input).map(|(code, _)| code).map_err(|trap| trap.error);
+	// 指针, 取地址, 数组下标和指针运算
+	assert_eq!(
+		run("int main(void) { int a[4] = {1, 2, 3}; int *p = &a[1]; *p = 10; p[2] = 7; return a[0] + a[1] + a[2] + a[3] + (p - a); }"),
+		Ok(22)
+	);
+	// 字符串字面量, 全局变量中的地址常量
+	assert_eq!(run(r#"char *s = "hey"; int g = 5, *gp = &g; int main(void) { *gp += 1; return s[1] + g; }"#), Ok(107));
+	assert_eq!(
+		run(r#"int main(void) { char s[] = "abc"; char *t = "abc"; return s[2] == t[2] && sizeof s == 4; }"#),
+		Ok(1)
+	);
+	// 存储时截断, 读出时符号扩展
+	assert_eq!(run("int main(void) { char c = 300; char d = 255; _Bool b = 256; return c + d + b; }"), Ok(44 - 1 + 1));
+	assert_eq!(run("int main(void) { int x = 0; char *p = (char *)&x; p[1] = 1; return x; }"), Ok(256));
+	// 结构体: 成员访问, 赋值, 传参和返回
+	let (code, dump) = eval_result(
+		"struct pt { int x; char c; int *p; };
+		struct pt make(int x) { struct pt r = {x, 'a'}; r.p = &r.x; return r; }
+		int get(struct pt v) { return v.x; }
+		int main(void) { struct pt a = make(3), b; b = a; b.x++; struct pt *q = &b; q->c += 1; return get(b) * 100 + q->c; }",
+	)
+	.unwrap();
+	assert_eq!(code, 498);
+	assert!(dump.contains("b = {x: 4, c: 98, p: "), "{}", dump);
+	// 变长数组在栈上动态分配
+	assert_eq!(
+		run("int sum(int n) { int v[n][n]; v[n - 1][n - 1] = n; return sizeof v + v[n - 1][n - 1]; } int main(void) { return sum(3); }"),
+		Ok(39)
+	);
+
+	assert_eq!(run("int main(void) { int *p = 0; return *p; }"), Err(RuntimeError::NullPointer));
+	assert!(matches!(
+		run("int main(void) { int a[2]; int *p = a; return p[100000]; }"),
+		Err(RuntimeError::OutOfBounds(..))
+	));
+	assert!(matches!(
+		run("int *f(void) { int x = 1; return &x; } int main(void) { return *f(); }"),
+		Err(RuntimeError::OutOfBounds(..))
+	));
+}
+
+/// 运行main, 返回main的返回值和printf的输出
+fn eval_output(input: &str) -> Result<(i64, String), Trap> {
+	let (mut env, vm) = compile_ok(input);
+	env.capture_output();
+	let result = vm.eval(&mut env).map(|code| (code, String::from_utf8_lossy(&env.take_output()).into_owned()));
+	// 字节码的输出和运行时错误与树遍历的解释器相同
+	if let Some(bytecode) = eval_bytecode(input) {
+		let expected = result.as_ref().map_err(|trap| &trap.error);
+		assert_eq!(bytecode.as_ref().map_err(|trap| &trap.error), expected, "bytecode: {}", input);
+	}
+	if let Some(ir) = eval_ir(input) {
+		let expected = result.as_ref().map_err(|trap| &trap.error);
+		assert_eq!(ir.as_ref().map_err(|trap| &trap.error), expected, "ir: {}", input);
+	}
+	result
+}
+
+#[test]
+fn test_eval_builtins() {
+	let printf =
+		|args: &str| eval_output(&format!("int main(void) {{ return printf({}); }}", args)).map(|(_, out)| out);
+	for (args, expected) in [
+		(r#""%d %i %u|%%\n", -12, 34, -1"#, "-12 34 4294967295|%\n"),
+		(r#""[%5d][%-5d][%05d][%+d][% d]", 42, 42, -42, 7, 7"#, "[   42][42   ][-0042][+7][ 7]"),
+		(r#""[%.3d][%8.3d][%.0d][%*d][%-*d]", 5, -5, 0, 4, 1, 3, 2"#, "[005][    -005][][   1][2  ]"),
+		(r#""%x %X %#x %08x", 255, 255, 255, -1"#, "ff FF 0xff ffffffff"),
+		(
+			r#""[%c%c][%3c][%s][%.2s][%-4s][%5s]", 'h', 105, 'x', "abc", "abc", "ab", "ab""#,
+			"[hi][  x][abc][ab][ab  ][   ab]",
+		),
+		(r#""%p %ld", (void *)31, 10"#, "0x1f 10"),
+	] {
+		assert_eq!(printf(args).as_deref(), Ok(expected), "{}", args);
+	}
+	// printf返回输出的字节数
+	assert_eq!(eval_output(r#"int main(void) { return printf("%s!", "hello"); }"#).map(|r| r.0), Ok(6));
+	assert!(printf(r#""%d %d", 1"#).is_err());
+	assert!(printf(r#""%q", 1"#).is_err());
+	// 宽度和精度可以超过Rust格式化的上限, 超出int的范围报错而不是崩溃
+	assert_eq!(printf(r#""%.70000d", 1"#).map(|out| out.len()), Ok(70000));
+	assert_eq!(printf(r#""%-*d|", 70000, 1"#).map(|out| out.len()), Ok(70001));
+	for args in [r#""%99999999999999999999d", 1"#, r#""%.2147483648d", 1"#] {
+		assert!(matches!(printf(args).map_err(|trap| trap.error), Err(RuntimeError::Format(_))), "{}", args);
+	}
+
+	// 堆分配, memset, memcmp
+	let (code, out) = eval_output(
+		r#"int main(void) {
+			char *a = malloc(8), *b = malloc(8);
+			memset(a, 'x', 8);
+			memset(b, 'x', 8);
+			b[7] = 'y';
+			int r = memcmp(a, b, 7) * 10 + (memcmp(a, b, 8) < 0);
+			a[7] = 0;
+			printf("%s", a);
+			free(a);
+			free(0);
+			return r;
+		}"#,
+	)
+	.unwrap();
+	assert_eq!((code, out.as_str()), (1, "xxxxxxx"));
+	let mem_err = |input: &str| eval_output(input).err().map(|trap| trap.error);
+	assert!(matches!(
+		mem_err("int main(void) { char *p = malloc(4); return p[4]; }"),
+		Some(RuntimeError::OutOfBounds(..))
+	));
+	assert!(matches!(
+		mem_err("int main(void) { char *p = malloc(4); free(p); return *p; }"),
+		Some(RuntimeError::OutOfBounds(..))
+	));
+	assert!(matches!(
+		mem_err("int main(void) { char *p = malloc(4); free(p); free(p); }"),
+		Some(RuntimeError::InvalidFree(_))
+	));
+
+	// exit直接结束程序
+	assert_eq!(
+		eval_output(
+			r#"void f(int n) { printf("f"); exit(n); } int main(void) { f(3); printf("unreachable"); return 0; }"#
+		),
+		Ok((3, "f".into()))
+	);
+
+	// 读文件
+	let (code, out) = eval_output(
+		r#"int main(void) {
+			char buf[16];
+			void *fd = fopen("data/t0.c", "r");
+			if (fd == 0) return -1;
+			int n = fread(buf, 1, 15, fd);
+			buf[n] = 0;
+			printf("%s", buf);
+			return fclose(fd) + (fopen("no/such/file", "r") == 0);
+		}"#,
+	)
+	.unwrap();
+	assert_eq!((code, out), (1, std::fs::read_to_string("data/t0.c").unwrap().chars().take(15).collect()));
+}
+
+#[test]
+fn test_eval_args() {
+	let run = |input: &str, args: &[&str], stdin: &str| {
+		let (mut env, vm) = compile_ok(input);
+		env.capture_output();
+		env.set_input(stdin.as_bytes().to_vec());
+		let args: Vec<_> = args.iter().map(|arg| arg.to_string()).collect();
+		let code = vm.eval_with_args(&mut env, &args)?;
+		Ok::<_, Trap>((code, String::from_utf8_lossy(&env.take_output()).into_owned()))
+	};
+	let echo = r#"int main(int argc, char **argv) {
+		if (argc > 2) printf("%s %s %d|", argv[0], argv[2], argv[argc] == 0);
+		int c = getchar(), d = getchar();
+		printf("%d,%d", c, d);
+		return argc;
+	}"#;
+	assert_eq!(run(echo, &["prog", "a", "bc"], "x"), Ok((3, "prog bc 1|120,-1".into())));
+	assert_eq!(run(echo, &["prog"], ""), Ok((1, "-1,-1".into())));
+	// 没有参数的main也可以运行
+	assert_eq!(run("int main(void) { exit(7); }", &["prog", "a"], ""), Ok((7, "".into())));
+}
+
+#[test]
+fn test_eval_globals() {
+	let (code, dump) = eval_result(
+		r#"
+struct pt { char c; int x; };
+int zero, arr[3] = {1, [2] = 3}, t;
+char c = 300, s[] = "hi", *p = s + 1;
+struct pt q = {'a', -2};
+int t = 5;
+int f(void) { return 9; }
+int (*pf)(void) = f;
+int *ip = &arr[2];
+int main(void) { return pf() + *ip + p[0]; }
+"#,
+	)
+	.unwrap();
+	assert_eq!(code, 9 + 3 + 'i' as i64);
+	let lines: Vec<_> = dump.lines().filter(|line| !line.starts_with("p") && !line.starts_with("ip")).collect();
+	assert_eq!(lines, ["zero = 0", "arr = [1, 0, 3]", "t = 5", "c = 44", "s = [104, 105, 0]", "q = {c: 97, x: -2}"]);
+
+	// 文件作用域中的初始值必须是常量, 每个变量分别报告
+	let diagnostics = resolve_diagnostics("int x; int y = x; int f(void); int z = f() + 1; int *w = &x + 1;");
+	assert_eq!(
+		diagnostics,
+		["1:12: error: initializer element is not constant: y", "1:36: error: initializer element is not constant: z"]
+	);
+}
+
+#[test]
+fn test_eval_for() {
+	let run = |input: &str| eval_result(input).map(|(code, _)| code);
+	assert_eq!(run("int main(void) { int s = 0, i; for (i = 1; i <= 10; i++) s += i; return s * 100 + i; }"), Ok(5511));
+	// break, continue和嵌套的循环
+	assert_eq!(
+		run("int main(void) { int s = 0; for (int i = 0; ; i++) { if (i % 2) continue; if (i > 8) break; for (int j = 0; j < i; j++) s++; } return s; }"),
+		Ok(2 + 4 + 6 + 8)
+	);
+	// switch中的continue作用于外层的循环, break只跳出switch
+	assert_eq!(
+		run("int main(void) { int s = 0; for (int i = 0; i < 4; i++) { switch (i) { case 1: continue; case 2: break; default: s += 10; } s++; } return s; }"),
+		Ok(23)
+	);
+	// 循环中的return
+	assert_eq!(
+		run("int f(int n) { for (int i = 0; ; i++) if (i * i >= n) return i; } int main(void) { return f(50); }"),
+		Ok(8)
+	);
+	// for中声明的变量只在循环中可见, 每次进入循环体重新分配
+	assert_eq!(
+		run("int main(void) { int i = 7; for (int i = 0; i < 3; i++) { int v[i + 1]; v[i] = i; } return i; }"),
+		Ok(7)
+	);
+	assert!(check_result("int main(void) { for (int i = 0; i < 3; i++); return i; }").is_err());
+	assert!(matches!(
+		parse_result("int main(void) { continue; }").err(),
+		Some(ParseError::General("continue statement not within a loop"))
+	));
+	assert!(parse_result("int main(void) { switch (1) { continue; } }").is_err());
+}
+
+#[test]
+fn test_runtime_error() {
+	// 最内层出错的表达式的位置, 以及出错时的调用栈
+	let trap =
+		eval_result("int div(int a, int b) {\n\treturn a / b;\n}\nint main(void) { return div(1, 0); }").unwrap_err();
+	assert_eq!(trap.error, RuntimeError::DivisionByZero);
+	assert_eq!(trap.span.map(|span| (span.line, span.column)), Some((2, 9)));
+	assert_eq!(trap.to_string(), "2:9: runtime error: division by zero\n    in div, called at 4:25\n    in main");
+
+	let run = |input: &str| eval_result(input).map(|(code, _)| code).map_err(|trap| trap.error);
+	assert_eq!(run("int f(void) { return 0; }"), Err(RuntimeError::MissingMain));
+	assert_eq!(
+		run("int main(void) { return 99999999999999999999; }"),
+		Err(RuntimeError::InvalidConstant("99999999999999999999".into()))
+	);
+	assert_eq!(
+		run("int main(void) { int (*p)(void) = (int (*)(void))16; return p(); }"),
+		Err(RuntimeError::NotFunction(16))
+	);
+	// 栈空间用完
+	assert_eq!(
+		run("int f(int n) { char buf[1 << 20]; return f(n); } int main(void) { return f(0); }"),
+		Err(RuntimeError::StackOverflow)
+	);
+	// 调用太深, 递归的求值需要足够大的栈
+	let trap = std::thread::Builder::new()
+		.stack_size(1 << 30)
+		.spawn(|| eval_result("int f(int n) { return f(n + 1); } int main(void) { return f(0); }").unwrap_err())
+		.unwrap()
+		.join()
+		.unwrap();
+	assert_eq!(trap.error, RuntimeError::CallDepthLimit(super::eval::MAX_CALL_DEPTH));
+	assert_eq!(trap.backtrace.len(), super::eval::MAX_CALL_DEPTH);
+	let repeats: Vec<_> = trap.collapsed_backtrace().iter().map(|(_, _, n)| *n).collect();
+	assert_eq!(repeats, [super::eval::MAX_CALL_DEPTH - 2, 1, 1]);
+}
+
+#[test]
+fn test_eval_integers() {
+	let printf = |body: &str| eval_output(&format!("int main(void) {{ {} return 0; }}", body)).map(|(_, out)| out);
+	for (body, expected) in [
+		// 无符号数回绕, 转换时截断
+		("unsigned u = 0; u = u - 1; unsigned char uc = 250; uc += 10; printf(\"%u %d\", u, uc);", "4294967295 4"),
+		("char c = 200; short s = 40000; unsigned short us = -1; printf(\"%d %d %d\", c, s, us);", "-56 -25536 65535"),
+		(
+			"long l = 2147483647; l = l + 1; unsigned long ul = -1; printf(\"%ld %lu\", l, ul);",
+			"2147483648 18446744073709551615",
+		),
+		// 有符号数算术右移, 无符号数逻辑右移和无符号除法
+		("unsigned u = -1; printf(\"%d %d %u\", -8 >> 1, (int)(u >> 28), u / 3);", "-4 15 1431655765"),
+		("unsigned long ul = -1; printf(\"%ld %lu\", ul >> 60, ul / 7);", "15 2635249153387078802"),
+		// 寻常算术转换: int和unsigned int比较时按无符号
+		("printf(\"%d %d %d\", -1 < 0u, -1 < 0, (unsigned char)-1 > 0);", "0 1 1"),
+		("unsigned x = 5; x -= 10; char c = 127; c++; printf(\"%u %d\", x, c);", "4294967291 -128"),
+		// 整数常量的类型
+		("printf(\"%ld %ld %d %d\", 3000000000, 1L << 40, sizeof 1L, sizeof 1u);", "3000000000 1099511627776 8 4"),
+		("long long ll = 1; ll <<= 40; printf(\"%lld %d %hhd\", ll, (short)65535, 511);", "1099511627776 -1 -1"),
+		("int i = 2147483647; i++; printf(\"%d %d\", i, sizeof(unsigned long int));", "-2147483648 8"),
+	] {
+		assert_eq!(printf(body).as_deref(), Ok(expected), "{}", body);
+	}
+
+	// 打开溢出检查时, 有符号数溢出报错, 无符号数和比int小的类型不受影响
+	let trapv = |body: &str| {
+		let (mut env, vm) = compile_ok(&format!("int main(void) {{ {} return 0; }}", body));
+		env.trap_signed_overflow();
+		vm.eval(&mut env).map_err(|trap| trap.error)
+	};
+	assert_eq!(trapv("unsigned u = -1; u++; char c = 127; c++; c += 100;"), Ok(0));
+	for body in [
+		"int i = 2147483647; i++;",
+		"int i = -2147483647 - 1; i / -1;",
+		"long l = 1L << 62; l * 2;",
+		"int i = -2147483647 - 1; -i;",
+	] {
+		assert_eq!(trapv(body), Err(RuntimeError::SignedOverflow), "{}", body);
+	}
+
+	assert_eq!(parse_result("int main(void) { return 1lu + 2LL + 3ull; }").err(), None);
+	assert!(matches!(
+		parse_result("int main(void) { return 1uu; }"),
+		Err(ParseError::LexError(LexError::InvalidChar('u')))
+	));
+	assert!(check_result("unsigned long long x; short int y; long unsigned z; signed char w;").is_ok());
+	for decl in
+		["long char c;", "short long s;", "unsigned signed u;", "long long long l;", "unsigned struct s { int x; } v;"]
+	{
+		assert!(parse_result(decl).is_err(), "{}", decl);
+	}
+}
+
+#[test]
+fn test_eval_limits() {
+	use super::eval::Limits;
+	use std::time::Duration;
+
+	let limited = |input: &str, limits: Limits| {
+		let (mut env, vm) = compile_ok(input);
+		env.set_limits(limits);
+		vm.eval(&mut env).map_err(|trap| trap.error)
+	};
+	let forever = "int main(void) { for (;;); }";
+	assert_eq!(
+		limited(forever, Limits { max_steps: Some(1000), ..Limits::default() }),
+		Err(RuntimeError::StepLimit(1000))
+	);
+	let timeout = Limits { timeout: Some(Duration::from_millis(20)), ..Limits::default() };
+	assert!(
+		matches!(limited(forever, timeout), Err(RuntimeError::Timeout(elapsed, _)) if elapsed >= Duration::from_millis(20))
+	);
+	// 限制之内的程序正常结束
+	let sum = "int main(void) { int s = 0; for (int i = 0; i < 10; i++) s += i; return s; }";
+	assert_eq!(limited(sum, Limits { max_steps: Some(1000), ..Limits::default() }), Ok(45));
+
+	let recurse = "int f(int n) { return f(n + 1); } int main(void) { return f(0); }";
+	assert_eq!(
+		limited(recurse, Limits { max_call_depth: 20, ..Limits::default() }),
+		Err(RuntimeError::CallDepthLimit(20))
+	);
+
+	let heap = Limits { max_heap: Some(1000), ..Limits::default() };
+	assert_eq!(limited("int main(void) { for (;;) malloc(128); }", heap), Err(RuntimeError::HeapLimit(128, 896)));
+	assert_eq!(limited("int main(void) { malloc(1000); return 0; }", heap), Ok(0));
+	// 上限限制的是还没有释放的字节数, 不是分配过的总数
+	let reuse = "int main(void) { for (int i = 0; i < 100; i++) free(malloc(500)); return 0; }";
+	assert_eq!(limited(reuse, heap), Ok(0));
+}
+
+#[test]
+fn test_bytecode() {
+	use super::builtins::Builtin;
+	use super::codegen::{Opcode::*, Scalar};
+
+	// 和 c/src/vm.c 的指令序列一样: 实参压栈, 调用, ADJ弹出实参
+	let (env, vm) = compile_ok(r#"int main() { printf("hello %d\n", 1); return 0; }"#);
+	let program = vm.compile(&env).unwrap();
+	assert_eq!(
+		program.code,
+		[Ent(0), Lgb(0), Psh, Imm(1), Psh, Sys(Builtin::Printf, 2), Adj(2), Imm(0), Lev, Imm(0), Lev]
+	);
+	assert_eq!(program.data, b"hello %d\n\0");
+	let (env, vm) = compile_ok("int g; int main(void) { return g++; }");
+	let program = vm.compile(&env).unwrap();
+	let increment = [Lgb(0), Psh, Load(Scalar::I32), Psh, Imm(1), Add(Scalar::I32), Store(Scalar::I32)];
+	assert_eq!(program.code[1..8], increment);
+
+	for input in [
+		"int main(void) { _Bool b = 1; int x = b++; int y = b--; int z = b--; return x * 100 + y * 10 + z * 2 + b; }",
+		// 每次循环分配的变长数组在离开块, break和continue时释放
+		"int main(void) { int s = 0; for (int i = 0; i < 3000; i++) { int v[i % 50 + 1000]; v[0] = i;
+			if (i % 3 == 0) continue; if (i == 2990) break; s += v[0] % 7; } return s; }",
+		"int main(void) { int s = 0; for (int i = 0; i < 6; i++) {
+			switch (i) { case 1: continue; case 2: s += 10; break; default: s += 1; switch (i) { case 4: s += 100; } } }
+			return s; }",
+		r#"int twice(int x) { printf("%d|", x); return 2 * x; }
+		int main(void) { int (*p)(int) = twice; return p(p(21)) + (int[]){1, 2, 3}[2]; }"#,
+		"struct p { long a, b; }; struct p swap(struct p v) { struct p r = {v.b, v.a}; return r; }
+		int main(void) { struct p x = {1, 2}; x = swap(swap(swap(x))); return x.a * 10 + x.b; }",
+		"int main(void) { int i = 0; int a = i++ && i++; int b = i-- || i++;
+			unsigned u = 7; u -= 10; u >>= 28; return a + b * 2 + i * 4 + u * 8; }",
+	] {
+		let expected = eval_output(input);
+		assert!(expected.is_ok(), "{}: {:?}", input, expected);
+	}
+	// 例子程序在两种执行方式下输出相同
+	for file in ["data/expr.c", "data/simple.c", "../c/test/glb.c", "../c/test/hello.c", "../c/test/test.c"] {
+		let _ = eval_output(&std::fs::read_to_string(file).unwrap());
+	}
+}
+
+#[test]
+fn test_disassemble() {
+	let src = r#"int g;
+int inc(int x) {
+	return x + 1;
+}
+int main() {
+	printf("%d\n", inc(g));
+	return 0;
+}
+"#;
+	let (mut env, vm) = compile_ok(src);
+	let program = vm.compile(&env).unwrap();
+	let dis = program.disassemble(src);
+	let expected = [
+		"inc:",
+		"     0: ENT  16",
+		"       3 | return x + 1;",
+		"     5: LEA  0",
+		"",
+		"main:",
+		"    13: ENT  0",
+		"       6 | printf(\"%d\\n\", inc(g));",
+		"    14: LGB  4       ; \"%d\\n\"",
+		"    16: LGB  0       ; g",
+		"    19: JSR  0, 1    ; inc",
+		"    22: PRTF 2",
+		"       7 | return 0;",
+	];
+	let mut lines = dis.lines();
+	for line in expected {
+		assert!(lines.any(|l| l == line), "{} not found in\n{}", line, dis);
+	}
+
+	// 和 c/src/vm.c -d 一样的格式, 穿插在程序的输出中
+	env.capture_output();
+	let mut bytecode = super::vm::BytecodeVm::new(&program);
+	bytecode.trace_execution();
+	assert_eq!(bytecode.run(&mut env, &[]), Ok(0));
+	let output = String::from_utf8(env.take_output()).unwrap();
+	let lines: Vec<_> = output.lines().collect();
+	assert_eq!(lines[0], "1> 13: ENT  0       a = 0, top = -");
+	assert_eq!(lines[2], "3> 15: PSH          a = 1048580, top = -");
+	assert!(lines.contains(&"1"));
+	assert_eq!(lines.last(), Some(&format!("exit(0) cycle = {}", lines.len() - 2).as_str()));
+}
+
+#[test]
+fn test_object_file() {
+	use super::errors::ObjectError;
+	use super::object::{Object, MAGIC, VERSION};
+
+	for file in ["data/expr.c", "data/simple.c", "../c/test/glb.c", "../c/test/hello.c", "../c/test/test.c"] {
+		let src = std::fs::read_to_string(file).unwrap();
+		let (mut env, vm) = compile_ok(&src);
+		let object = Object::new(file, vm.compile(&env).unwrap());
+		let bytes = object.to_bytes();
+		assert_eq!(bytes[..4], MAGIC);
+		let loaded = Object::from_bytes(&bytes).unwrap();
+		assert_eq!(loaded, object, "{}", file);
+		// 同一个程序写出的文件相同
+		assert_eq!(loaded.to_bytes(), bytes);
+
+		// 不用重新解析, 执行的结果和直接翻译的相同
+		env.capture_output();
+		let expected = super::vm::BytecodeVm::new(&object.program).run(&mut env, &[]);
+		let expected = (expected, env.take_output());
+		let mut env = super::eval::Env::new(vec![]);
+		env.capture_output();
+		let result = super::vm::BytecodeVm::new(&loaded.program).run(&mut env, &[]);
+		assert_eq!((result, env.take_output()), expected, "{}", file);
+	}
+
+	let (env, vm) = compile_ok(r#"int g = 7; int main() { printf("%d\n", g << 2); return g; }"#);
+	let bytes = Object::new("g.c", vm.compile(&env).unwrap()).to_bytes();
+	assert_eq!(Object::from_bytes(b"int main() {}"), Err(ObjectError::BadMagic));
+	let mut newer = bytes.clone();
+	newer[4] = VERSION as u8 + 1;
+	assert_eq!(Object::from_bytes(&newer), Err(ObjectError::Version(VERSION + 1, VERSION)));
+	for len in [6, 20, bytes.len() - 1] {
+		assert_eq!(Object::from_bytes(&bytes[..len]), Err(ObjectError::Truncated));
+	}
+	let mut extra = bytes.clone();
+	extra.push(0);
+	assert!(matches!(Object::from_bytes(&extra), Err(ObjectError::Malformed(_))));
+	// 代码部分的第一条指令是 ENT
+	let metadata = u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize;
+	let mut unknown = bytes.clone();
+	unknown[12 + metadata + 4] = 0xff;
+	assert_eq!(Object::from_bytes(&unknown), Err(ObjectError::UnknownOpcode(0xff)));
+	// 行号部分在最后, 每条指令是 line column start end 四个u32
+	let mut reversed = bytes.clone();
+	let len = reversed.len();
+	reversed[len - 8..len - 4].fill(0xff);
+	assert!(matches!(Object::from_bytes(&reversed), Err(ObjectError::Malformed(_))));
+}
+
+#[test]
+fn test_verify() {
+	use super::builtins::Builtin;
+	use super::codegen::{FunctionInfo, Opcode, Opcode::*, Program, Scalar};
+	use super::errors::VerifyError;
+	use super::verify::verify;
+
+	let main =
+		FunctionInfo { name: "main".to_string(), entry: Some(0), params: 1, is_variadic: false, returns_struct: None };
+	let program = |code: Vec<Opcode>| Program {
+		spans: vec![None; code.len()],
+		code,
+		functions: vec![main.clone()],
+		data: vec![0; 8],
+		main: Some(0),
+		..Default::default()
+	};
+	let add = vec![Ent(16), Imm(1), Psh, Imm(2), Add(Scalar::I32), Lev];
+	assert_eq!(verify(&program(add.clone())), Ok(()));
+	for (i, op, error) in [
+		(0, Imm(0), VerifyError::MisplacedEnt(0)),
+		(3, Ent(0), VerifyError::MisplacedEnt(3)),
+		(5, Psh, VerifyError::FallsThrough(5)),
+		(2, Imm(0), VerifyError::StackUnderflow(4)),
+		(4, Cast(Scalar::I32), VerifyError::UnbalancedReturn(5, 1)),
+		(1, Jmp(6), VerifyError::JumpOutOfFunction(1, 6)),
+		(1, Lea(24), VerifyError::FrameOutOfBounds(1, 24)),
+		(1, Ssp(12), VerifyError::FrameOutOfBounds(1, 12)),
+		(1, Ssp(usize::MAX - 3), VerifyError::FrameOutOfBounds(1, usize::MAX - 3)),
+		(1, Lgb(8), VerifyError::DataOutOfBounds(1, 8)),
+		(1, Arg(1), VerifyError::ArgumentOutOfRange(1, 1)),
+		(3, Jsr(1, 0), VerifyError::UnknownFunction(3, 1)),
+		(3, Sys(Builtin::Printf, 2), VerifyError::StackUnderflow(3)),
+		(2, Adj(1), VerifyError::StackUnderflow(2)),
+		(3, Jsri(usize::MAX), VerifyError::StackUnderflow(3)),
+	] {
+		let mut code = add.clone();
+		code[i] = op;
+		assert_eq!(verify(&program(code)), Err(error), "{} {:?}", i, op);
+	}
+	// 两条路径到达 IMM 0 时操作数栈的深度不同
+	let branch = vec![Ent(0), Arg(0), Bz(4), Psh, Imm(0), Lev];
+	assert_eq!(verify(&program(branch)), Err(VerifyError::StackMismatch(4, 0, 1)));
+	// 没有校验时执行到错误的地方报错而不是崩溃
+	let mut underflow = add.clone();
+	underflow[2] = Add(Scalar::I32);
+	assert!(super::vm::BytecodeVm::verified(&program(underflow.clone())).is_err());
+	let mut env = super::eval::Env::new(vec![]);
+	let result = super::vm::BytecodeVm::new(&program(underflow)).run(&mut env, &[]);
+	assert!(matches!(result.map_err(|trap| trap.error), Err(RuntimeError::Unsupported(_))));
+	let mut unbalanced = program(add);
+	unbalanced.spans.pop();
+	assert!(matches!(verify(&unbalanced), Err(VerifyError::Malformed(_))));
+	// 校验不限制 ZERO 的大小, 执行时先检查范围, 超出栈帧报错而不是按大小分配内存
+	let zero = program(vec![Ent(16), Lea(0), Zero(1 << 60), Imm(0), Lev]);
+	let mut env = super::eval::Env::new(vec![]);
+	let result = super::vm::BytecodeVm::verified(&zero).unwrap().run(&mut env, &[]);
+	assert!(matches!(result.map_err(|trap| trap.error), Err(RuntimeError::OutOfBounds(..))));
+	let registers = super::regcode::RegisterProgram::translate(&zero).unwrap();
+	let result = super::regvm::RegisterVm::new(&registers).run(&mut env, &[]);
+	assert!(matches!(result.map_err(|trap| trap.error), Err(RuntimeError::OutOfBounds(..))));
+}
+
+#[test]
+fn test_register_vm() {
+	use super::codegen::Scalar;
+	use super::regcode::{Operand, RegOp, RegisterProgram};
+	let (env, vm) = compile_ok(
+		r"
+int fib(int n) {
+	if (n < 2)
+		return n;
+	return fib(n - 1) + fib(n - 2);
+}
+int main() {
+	int sum = 0;
+	for (int i = 0; i < 10; i++)
+		sum += fib(i);
+	return sum;
+}
+",
+	);
+	let program = vm.compile(&env).unwrap();
+	let registers = RegisterProgram::translate(&program).unwrap();
+	// fib的 LEA LD PSH IMM LT BZ 合并成读局部变量和一条比较跳转
+	let fib = registers.functions[0].entry.unwrap();
+	let branch = RegOp::Branch(Punct::Ge, Scalar::I32, Operand::Reg(1), Operand::Imm(2), fib + 7);
+	assert_eq!(registers.code[fib + 3..fib + 5], [RegOp::LoadLocal(1, 0, Scalar::I32), branch]);
+	assert!(registers.code.contains(&RegOp::StoreLocal(0, Operand::Imm(0), Scalar::I32)));
+	assert!(registers.code.len() < program.code.len() / 2);
+	assert_eq!(registers.spans.len(), registers.code.len());
+	assert_eq!(eval_bytecode("int main() { int a[3] = {1, 2, 3}; return a[0] + a[2] * 2; }").unwrap().unwrap().0, 7);
+	let mut env = super::eval::Env::new(vec![]);
+	assert_eq!(super::regvm::RegisterVm::new(&registers).run(&mut env, &[]), Ok(88));
+}
+
+#[test]
+fn test_ir() {
+	use super::ir::{BlockId, Terminator};
+	let (env, vm) = compile_ok(
+		r"
+int pick(int a, int b) {
+	int r = a && b;
+	return a > b ? a : r;
+}
+int main() {
+	int s = 0;
+	for (int i = 0; i < 4; i++) {
+		if (i == 2)
+			continue;
+		s += pick(i, s);
+	}
+	return s;
+}
+",
+	);
+	let module = vm.lower(&env).unwrap();
+	let pick = &module.functions[0];
+	// && 的两个操作数各一个分支, 两条路径写同一个结果寄存器后汇合; ?: 同样
+	assert_eq!(pick.blocks.len(), 8);
+	assert_eq!(pick.blocks[0].terminator.successors(), [BlockId(1), BlockId(3)]);
+	assert_eq!(pick.blocks[1].terminator.successors(), [BlockId(2), BlockId(3)]);
+	assert_eq!(pick.blocks[2].terminator, Terminator::Jump(BlockId(4)));
+	assert!(matches!(pick.blocks[7].terminator, Terminator::Return(_)));
+	// for 的条件, 循环体, continue 跳到的自增和出口各是一个块
+	let main = &module.functions[module.main.unwrap()];
+	assert!(main.blocks.iter().any(|block| block.terminator == Terminator::Jump(BlockId(1))));
+	let text = module.to_string();
+	assert!(text.contains("fn pick(params 2) frame 16:\nbb0:\n"), "{}", text);
+	for line in ["\t%15: i32 = gt i32 %12, %14\n", "\t%18: i32 = call pick(%15, %17)\n", "\tbr %7, bb2, bb6\n"] {
+		assert!(text.contains(line), "{}", line);
+	}
+	let mut env = super::eval::Env::new(vec![]);
+	assert_eq!(super::ireval::IrInterpreter::new(&module).run(&mut env, &[]), Ok(4));
+}
@@ -10,6 +10,17 @@ pub enum Token {
 #[derive(Debug, PartialEq, Eq)]
 pub struct TokenList {
 	pub data: Vec<Token>,
+	/// 与data一一对应
+	pub spans: Vec<Span>,
+}
+
+/// token在源码中的位置, start和end是字节偏移, line和column从1开始
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+	pub start: usize,
+	pub end: usize,
+	pub line: usize,
+	pub column: usize,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
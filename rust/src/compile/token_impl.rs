@@ -8,7 +8,7 @@ use console::style;
 use super::{
 	errors::LexError,
 	lex::TokenApi,
-	token::{Const, Keyword, Precedence, Punct, Token, TokenList},
+	token::{Const, Keyword, Precedence, Punct, Span, Token, TokenList},
 };
 
 impl Punct {
@@ -219,10 +219,7 @@ pub fn simple_unescape(c: &char) -> Option<&'static str> {
 impl FromStr for TokenList {
 	type Err = LexError;
 	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		match TokenApi::parse_all(s) {
-			Ok(data) => Ok(TokenList { data }),
-			Err(e) => Err(e),
-		}
+		TokenApi::parse_all_with_span(s).map(|(data, spans)| TokenList { data, spans })
 	}
 }
 
@@ -252,6 +249,12 @@ impl Display for TokenList {
 	}
 }
 
+impl Display for Span {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}:{}", self.line, self.column)
+	}
+}
+
 impl Precedence {
 	pub fn next_level(&self) -> Self {
 		match self {
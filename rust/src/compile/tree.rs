@@ -5,37 +5,38 @@ use console::style;
 use crate::compile::token::{Const, Token};
 
 use super::{
-	errors::ParseError,
+	errors::{ConstError, ParseError, RuntimeError},
 	token::{Punct, TokenList},
 };
 
-fn calc(op: &Punct, a: i64, b: i64) -> i64 {
-	match op {
-		Punct::Add => a + b,
-		Punct::Sub => a - b,
-		Punct::Mul => a * b,
-		Punct::Div => a / b,
+fn calc(op: &Punct, a: i64, b: i64) -> Result<i64, RuntimeError> {
+	Ok(match op {
+		Punct::Add => a.wrapping_add(b),
+		Punct::Sub => a.wrapping_sub(b),
+		Punct::Mul => a.wrapping_mul(b),
+		Punct::Div if b == 0 => return Err(RuntimeError::DivisionByZero),
+		Punct::Div => a.wrapping_div(b),
 		Punct::Xor => pow(a, b),
-		_ => 0,
-	}
+		_ => return Err(RuntimeError::Unsupported(format!("operator {}", op))),
+	})
 }
 
 fn pow(a: i64, b: i64) -> i64 {
-	let mut r = 1;
+	let mut r: i64 = 1;
 	for _ in 0..b {
-		r *= a
+		r = r.wrapping_mul(a)
 	}
 	r
 }
 
-fn op_info(op: &Punct) -> i8 {
+fn op_info(op: &Punct) -> Result<i8, ParseError> {
 	match op {
-		Punct::Add => 1,
-		Punct::Sub => 1,
-		Punct::Mul => 2,
-		Punct::Div => 2,
-		Punct::Xor => 3,
-		_ => panic!("bad input"),
+		Punct::Add => Ok(1),
+		Punct::Sub => Ok(1),
+		Punct::Mul => Ok(2),
+		Punct::Div => Ok(2),
+		Punct::Xor => Ok(3),
+		_ => Err(ParseError::Unexpected(format!("operator {}", op))),
 	}
 }
 
@@ -159,7 +160,7 @@ impl ExprTree {
 		}
 	}
 
-	pub fn eval_stack(&self) -> i64 {
+	pub fn eval_stack(&self) -> Result<i64, RuntimeError> {
 		fn po(this: &ExprTree, stack: &mut Vec<Token>) {
 			match this {
 				ExprTree::Leaf(v) => stack.push(Token::Const(Const::Integer(v.to_string()))),
@@ -174,25 +175,28 @@ impl ExprTree {
 		po(self, &mut list);
 
 		let mut stack = VecDeque::<i64>::new();
+		let empty = || RuntimeError::Unsupported("operator without operands".into());
 		for token in list.iter() {
 			match token {
-				Token::Const(Const::Integer(v)) => stack.push_back(v.parse::<i64>().unwrap()),
+				Token::Const(Const::Integer(v)) => {
+					stack.push_back(v.parse::<i64>().map_err(|_| RuntimeError::InvalidConstant(v.clone()))?)
+				}
 				Token::Punct(p) => {
-					let lhs = stack.pop_back().unwrap();
-					let rhs = stack.pop_back().unwrap();
+					let rhs = stack.pop_back().ok_or_else(empty)?;
+					let lhs = stack.pop_back().ok_or_else(empty)?;
 					println!("calc: {} {} {}", p, lhs, rhs);
-					stack.push_back(calc(p, lhs, rhs));
+					stack.push_back(calc(p, lhs, rhs)?);
 				}
-				_ => unreachable!(),
+				tk => return Err(RuntimeError::Unsupported(tk.to_string())),
 			}
 		}
-		stack.pop_back().unwrap()
+		stack.pop_back().ok_or_else(empty)
 	}
 
-	pub fn eval(&self) -> i64 {
+	pub fn eval(&self) -> Result<i64, RuntimeError> {
 		match self {
-			Self::Leaf(v) => *v,
-			Self::Branch(Branch { op, left, right }) => calc(op, left.eval(), right.eval()),
+			Self::Leaf(v) => Ok(*v),
+			Self::Branch(Branch { op, left, right }) => calc(op, left.eval()?, right.eval()?),
 		}
 	}
 }
@@ -268,8 +272,8 @@ fn test_expr_tree() {
 	println!("─────");
 	tree.print(&VisitOrder::Post);
 	println!("─────");
-	println!("eval tree: {}", tree.eval());
-	println!("eval tree with stack: {}", tree.eval_stack());
+	assert_eq!(tree.eval(), Ok(5));
+	assert_eq!(tree.eval_stack(), Ok(5));
 	println!("tree is\n{:#}", tree);
 	println!("tree is\n{}", tree);
 	let tree = ExprTree::tree(
@@ -277,7 +281,11 @@ fn test_expr_tree() {
 		ExprTree::branch(Punct::Mul, 1, 2),
 		ExprTree::tree(Punct::Mul, ExprTree::Leaf(3), ExprTree::branch(Punct::Xor, 4, 5)),
 	);
-	println!("tree:\n{}eval to {}", tree, tree.eval());
+	println!("tree:\n{}eval to {:?}", tree, tree.eval());
+	assert_eq!(build_tree("(1 - 3) / 2").map(|tree| tree.eval_stack()), Ok(Ok(-1)));
+	assert_eq!(build_tree("1 / (2 - 2)").map(|tree| tree.eval()), Ok(Err(RuntimeError::DivisionByZero)));
+	assert_eq!(build_tree("1 % 2").err(), Some(ParseError::Unexpected("operator %".into())));
+	assert!(matches!(build_tree("99999999999999999999"), Err(ParseError::ConstError(ConstError::Overflow))));
 }
 
 #[test]
@@ -293,7 +301,9 @@ type EvalResultTree = Result<ExprTree, ParseError>;
 fn parse_leaf<'a>(iter: &mut impl Iterator<Item = &'a Token>, cop: &mut Option<Punct>) -> EvalResultTree {
 	if let Some(tk) = iter.next() {
 		match tk {
-			Token::Const(Const::Integer(lhs)) => Ok(ExprTree::leaf(lhs.parse::<i64>().unwrap())),
+			Token::Const(Const::Integer(lhs)) => {
+				Ok(ExprTree::leaf(lhs.parse::<i64>().map_err(|_| ParseError::ConstError(ConstError::Overflow))?))
+			}
 			Token::Punct(Punct::ParentheseL) => Ok(parse_expr_tree(iter, 1, cop)?),
 			_ => Err(ParseError::Unexpected("".into())),
 		}
@@ -312,12 +322,12 @@ fn parse_expr_tree<'a>(
 	*maybe_current_op = match iter.next() {
 		Some(Token::Punct(mop)) => Some(*mop),
 		None => None,
-		_ => unreachable!(),
+		Some(tk) => return Err(ParseError::Unexpected(tk.to_string())),
 	};
 
 	while let Some(current_op) = *maybe_current_op {
-		if Punct::ParentheseR != current_op && op_info(&current_op) >= mp {
-			let next_mp = op_info(&current_op) + if current_op == Punct::Xor { 0 } else { 1 };
+		if Punct::ParentheseR != current_op && op_info(&current_op)? >= mp {
+			let next_mp = op_info(&current_op)? + if current_op == Punct::Xor { 0 } else { 1 };
 			lhs = ExprTree::tree(current_op, lhs, parse_expr_tree(iter, next_mp, maybe_current_op)?);
 		} else {
 			break;
@@ -344,7 +354,7 @@ fn test_tree() {
 	fn tp(i: &str) {
 		match build_tree(i) {
 			Ok(tree) => {
-				println!("{} = {}\n{}------\n", i, tree.eval(), tree)
+				println!("{} = {:?}\n{}------\n", i, tree.eval(), tree)
 			}
 			Err(err) => println!("err: {}", err),
 		}
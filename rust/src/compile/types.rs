@@ -1,42 +1,116 @@
-use super::token::{Const, Punct};
+use std::{
+	cell::{Ref, RefCell},
+	rc::Rc,
+};
+
+use super::{
+	builtins::Builtin,
+	consteval,
+	token::{Const, Punct, Span},
+};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Variable {
 	pub name: String,
 	pub ctype: Type,
-	pub init_value: Option<Expr>,
+	pub init_value: Option<Initializer>,
 
 	pub is_local: bool,
 	pub is_tentative: bool,
+	/// 枚举常量也是普通标识符, 值是init_value, 不占存储空间
+	pub is_enum_constant: bool,
+	/// 第一次声明的位置
+	pub span: Span,
+	/// 名字解析之后绑定的符号
+	pub symbol: Option<SymbolId>,
+}
+
+/// 一个源文件解析的结果, 按照声明出现的顺序保存
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TranslationUnit {
+	pub global_variables: Vec<Variable>,
+	pub functions: Vec<Function>,
+	/// 名字解析得到的符号表, 用SymbolId索引
+	pub symbols: Vec<Symbol>,
+}
+
+impl TranslationUnit {
+	pub fn symbol(&self, id: SymbolId) -> &Symbol {
+		&self.symbols[id.0]
+	}
+}
+
+/// 符号表中的下标
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SymbolId(pub usize);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+	pub name: String,
+	pub kind: SymbolKind,
+	pub ctype: Type,
+	/// 声明的位置, 内置函数没有位置
+	pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+	Global,
+	Local,
+	Param,
+	Function,
+	EnumConst(i64),
+	Builtin(Builtin),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Function {
 	pub name: String,
 	pub ctype: Func,
-	pub locals: Vec<Variable>,
+	/// 参数和局部变量的符号, 参数在前, 由名字解析填入
+	pub locals: Vec<SymbolId>,
 	pub stmts: Statement,
 	pub stack_size: usize,
 	pub is_definition: bool,
+	/// 第一次声明的位置
+	pub decl_span: Span,
+	/// 定义的位置, 没有定义时和decl_span相同
+	pub def_span: Span,
+	pub symbol: Option<SymbolId>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Type {
 	Void,
 	Bool,
+	/// char是有符号的, 和signed char相同
 	Char,
+	Short,
 	Int,
+	/// long和long long都是8字节
+	Long,
+	UChar,
+	UShort,
+	UInt,
+	ULong,
 	Ptr(Ptr),
 	Array(Array),
 	Func(Func),
-	// Enum(Enum), // Struct(Struct), // Union(Union),
-	// Short(Short), // Long(Long), // Float(Float), // Double(Double),
+	Struct(Struct),
+	// Enum(Enum), // Union(Union),
+	// Float(Float), // Double(Double),
 }
 
 pub const TYPE_VOID: Type = Type::Void;
 pub const TYPE_BOOL: Type = Type::Bool;
 pub const TYPE_CHAR: Type = Type::Char;
+pub const TYPE_SHORT: Type = Type::Short;
 pub const TYPE_INT: Type = Type::Int;
+pub const TYPE_LONG: Type = Type::Long;
+pub const TYPE_UCHAR: Type = Type::UChar;
+pub const TYPE_USHORT: Type = Type::UShort;
+pub const TYPE_UINT: Type = Type::UInt;
+pub const TYPE_ULONG: Type = Type::ULong;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TypeIdentifier {
@@ -50,7 +124,7 @@ impl TypeIdentifier {
 	}
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
 pub struct VarAttr {
 	pub is_typedef: bool,
 	pub is_static: bool,
@@ -59,6 +133,105 @@ pub struct VarAttr {
 }
 
 impl Type {
+	pub fn is_integer(&self) -> bool {
+		matches!(
+			self,
+			Type::Bool
+				| Type::Char | Type::Short
+				| Type::Int | Type::Long
+				| Type::UChar
+				| Type::UShort
+				| Type::UInt | Type::ULong
+		)
+	}
+
+	/// _Bool也是无符号整数类型
+	pub fn is_unsigned(&self) -> bool {
+		matches!(self, Type::Bool | Type::UChar | Type::UShort | Type::UInt | Type::ULong)
+	}
+
+	/// 整数提升: 比int小的整数类型都能用int表示
+	pub fn promoted(&self) -> Type {
+		match self {
+			Type::Bool | Type::Char | Type::Short | Type::UChar | Type::UShort => TYPE_INT,
+			ctype => ctype.clone(),
+		}
+	}
+
+	/// 寻常算术转换 (6.3.1.8) 的共同类型: 提升之后大的类型优先, 一样大时无符号的优先
+	pub fn common(&self, other: &Type) -> Type {
+		let (a, b) = (self.promoted(), other.promoted());
+		match a.size().cmp(&b.size()) {
+			std::cmp::Ordering::Greater => a,
+			std::cmp::Ordering::Less => b,
+			std::cmp::Ordering::Equal if b.is_unsigned() => b,
+			std::cmp::Ordering::Equal => a,
+		}
+	}
+
+	/// 把整数值转换为这个类型: 截断到类型的宽度, 有符号类型符号扩展, 无符号类型零扩展.
+	/// unsigned long的值保存为相同位模式的i64. 其他类型的值不变
+	pub fn convert(&self, value: i64) -> i64 {
+		match self {
+			Type::Bool => (value != 0) as i64,
+			Type::Char => value as i8 as i64,
+			Type::UChar => value as u8 as i64,
+			Type::Short => value as i16 as i64,
+			Type::UShort => value as u16 as i64,
+			Type::Int => value as i32 as i64,
+			Type::UInt => value as u32 as i64,
+			_ => value,
+		}
+	}
+
+	/// 目前只有整数类型
+	pub fn is_arithmetic(&self) -> bool {
+		self.is_integer()
+	}
+
+	pub fn is_pointer(&self) -> bool {
+		matches!(self, Type::Ptr(_))
+	}
+
+	pub fn is_scalar(&self) -> bool {
+		self.is_arithmetic() || self.is_pointer()
+	}
+
+	/// 指针指向的类型
+	pub fn pointee(&self) -> Option<&Type> {
+		match self {
+			Type::Ptr(Ptr { base_type }) => Some(base_type),
+			_ => None,
+		}
+	}
+
+	pub fn is_char_array(&self) -> bool {
+		matches!(self, Type::Array(Array { base_type, .. }) if **base_type == TYPE_CHAR)
+	}
+
+	/// 未指定长度的数组, 如 int a[], 长度由初始化器确定
+	pub fn is_incomplete_array(&self) -> bool {
+		matches!(self, Type::Array(Array { size_expr: None, .. }))
+	}
+
+	/// 变长数组, 长度不是常量表达式. 多维数组中任何一维是变长的都算
+	pub fn is_vla(&self) -> bool {
+		match self {
+			Type::Array(Array { base_type, size_expr, .. }) => {
+				size_expr.as_ref().is_some_and(|expr| !matches!(expr.kind, ExprKind::Const(_))) || base_type.is_vla()
+			}
+			_ => false,
+		}
+	}
+
+	/// 变长数组或者指向变长数组的指针 (6.7.5), 只能出现在块作用域和函数原型中
+	pub fn is_variably_modified(&self) -> bool {
+		match self {
+			Type::Ptr(Ptr { base_type }) => base_type.is_variably_modified(),
+			ctype => ctype.is_vla(),
+		}
+	}
+
 	pub fn get_func(&self) -> Option<Func> {
 		match self {
 			Type::Func(f) => Some(f.clone()),
@@ -70,20 +243,75 @@ impl Type {
 		Type::Ptr(Ptr { base_type: Box::new(self) })
 	}
 
+	/// 长度是常量表达式时, size_expr折叠为整数常量; 否则是变长数组, length为0
 	pub fn into_array(self, expr: Option<Expr>) -> Self {
-		let length = match expr {
-			Some(Expr::Const(Const::Integer(ref i))) => i.parse().unwrap_or_default(),
-			_ => 0,
-		};
-		Type::Array(Array { base_type: Box::new(self), length, size_expr: expr })
+		match expr.as_ref().map(Expr::fold_const) {
+			Some(Some(n)) if n >= 0 => self.into_array(None).with_array_length(n as usize),
+			_ => Type::Array(Array { base_type: Box::new(self), length: 0, size_expr: expr.map(Box::new) }),
+		}
 	}
 
+	/// 用初始化器的元素个数补全 int a[] 这样的数组类型
+	pub fn with_array_length(self, length: usize) -> Self {
+		match self {
+			Type::Array(Array { base_type, .. }) => Type::Array(Array {
+				base_type,
+				length,
+				size_expr: Some(Box::new(Expr::constant(Const::Integer(length.to_string())))),
+			}),
+			other => other,
+		}
+	}
+
+	/// 没有参数的函数 (void)
 	pub fn into_function(self) -> Self {
-		Type::Func(Func { return_type: Box::new(self), param_list: vec![], is_variadic: false })
+		Type::Func(Func { return_type: Box::new(self), param_list: vec![], is_variadic: false, is_unspecified: false })
 	}
 
-	pub fn into_function_with_param(self, param_list: Vec<TypeIdentifier>) -> Self {
-		Type::Func(Func { return_type: Box::new(self), param_list, is_variadic: false })
+	/// 参数列表未指定的函数 ()
+	pub fn into_unspecified_function(self) -> Self {
+		Type::Func(Func { return_type: Box::new(self), param_list: vec![], is_variadic: false, is_unspecified: true })
+	}
+
+	/// 两个兼容类型的合成类型 (6.2.7), 不兼容时返回None. 函数参数名优先取self中的
+	pub fn composite(&self, other: &Type) -> Option<Type> {
+		match (self, other) {
+			(Type::Ptr(a), Type::Ptr(b)) => Some(a.base_type.composite(&b.base_type)?.into_pointer()),
+			(Type::Array(a), Type::Array(b)) => {
+				let base_type = Box::new(a.base_type.composite(&b.base_type)?);
+				let (length, size_expr) = match (&a.size_expr, &b.size_expr) {
+					(Some(_), Some(_)) if a.length != b.length => return None,
+					(None, Some(_)) => (b.length, b.size_expr.clone()),
+					_ => (a.length, a.size_expr.clone()),
+				};
+				Some(Type::Array(Array { length, size_expr, base_type }))
+			}
+			(Type::Func(a), Type::Func(b)) => {
+				let return_type = Box::new(a.return_type.composite(&b.return_type)?);
+				// () 与参数类型都不受默认实参提升影响的原型兼容, 合成类型取原型的参数
+				if a.is_unspecified || b.is_unspecified {
+					let proto = if a.is_unspecified { b } else { a };
+					if proto.is_variadic || proto.param_list.iter().any(|p| p.ctype.promoted() != p.ctype) {
+						return None;
+					}
+					return Some(Type::Func(Func { return_type, ..proto.clone() }));
+				}
+				if a.param_list.len() != b.param_list.len() || a.is_variadic != b.is_variadic {
+					return None;
+				}
+				let param_list = a
+					.param_list
+					.iter()
+					.zip(b.param_list.iter())
+					.map(|(pa, pb)| {
+						Some(TypeIdentifier::new(pa.ctype.composite(&pb.ctype)?, pa.name.clone().or(pb.name.clone())))
+					})
+					.collect::<Option<Vec<_>>>()?;
+				Some(Type::Func(Func { return_type, param_list, is_variadic: a.is_variadic, is_unspecified: false }))
+			}
+			_ if self == other => Some(self.clone()),
+			_ => None,
+		}
 	}
 }
 
@@ -97,22 +325,28 @@ impl TypeSizeAlign for Type {
 		match self {
 			Self::Void => 0,
 			Self::Bool => 1,
-			Self::Char => 1,
-			Self::Int => 4,
+			Self::Char | Self::UChar => 1,
+			Self::Short | Self::UShort => 2,
+			Self::Int | Self::UInt => 4,
+			Self::Long | Self::ULong => 8,
 			Self::Array(Array { base_type, length, size_expr: _ }) => base_type.size() * length,
 			Self::Ptr(_) => 8,
 			Self::Func(_) => 8,
+			Self::Struct(st) => st.0.borrow().size,
 		}
 	}
 	fn align(&self) -> usize {
 		match self {
 			Self::Void => 1,
 			Self::Bool => 1,
-			Self::Char => 1,
-			Self::Int => 4,
+			Self::Char | Self::UChar => 1,
+			Self::Short | Self::UShort => 2,
+			Self::Int | Self::UInt => 4,
+			Self::Long | Self::ULong => 8,
 			Self::Array(Array { base_type, length: _, size_expr: _ }) => base_type.align(),
 			Self::Ptr(_) => 8,
 			Self::Func(_) => 8,
+			Self::Struct(st) => st.0.borrow().align,
 		}
 	}
 }
@@ -124,30 +358,140 @@ pub struct Ptr {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Array {
 	pub length: usize,
-	pub size_expr: Option<Expr>,
+	pub size_expr: Option<Box<Expr>>,
 	pub base_type: Box<Type>,
 }
+
+/// 结构体类型在定义完成之前就可能被引用(如 struct node { struct node *next; }),
+/// 所以所有引用共享同一份定义, 类型相等按定义的同一性判断
+#[derive(Clone)]
+pub struct Struct(pub Rc<RefCell<StructDef>>);
+
+#[derive(Debug, Default)]
+pub struct StructDef {
+	pub tag: Option<String>,
+	pub members: Vec<Member>,
+	pub size: usize,
+	pub align: usize,
+	pub is_complete: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+	pub name: String,
+	pub ctype: Type,
+	pub offset: usize,
+}
+
+impl Struct {
+	pub fn new_incomplete(tag: Option<String>) -> Self {
+		Struct(Rc::new(RefCell::new(StructDef { tag, align: 1, ..Default::default() })))
+	}
+
+	/// 按顺序排列成员, 计算偏移, 大小和对齐
+	pub fn complete(&self, members: Vec<TypeIdentifier>) -> Result<(), String> {
+		let mut def = self.0.borrow_mut();
+		let mut offset = 0;
+		let mut align = 1;
+		def.members.clear();
+		for TypeIdentifier { name, ctype } in members {
+			let name = name.ok_or_else(|| "struct member should have a name".to_string())?;
+			if def.members.iter().any(|m| m.name == name) {
+				return Err(format!("duplicate member: {}", name));
+			}
+			offset = align_to(offset, ctype.align());
+			align = align.max(ctype.align());
+			let size = ctype.size();
+			def.members.push(Member { name, ctype, offset });
+			offset += size;
+		}
+		def.size = align_to(offset, align);
+		def.align = align;
+		def.is_complete = true;
+		Ok(())
+	}
+
+	pub fn tag(&self) -> Option<String> {
+		self.0.borrow().tag.clone()
+	}
+
+	pub fn is_complete(&self) -> bool {
+		self.0.borrow().is_complete
+	}
+
+	pub fn members(&self) -> Vec<Member> {
+		self.0.borrow().members.clone()
+	}
+
+	/// 按名字查找成员, 不复制成员列表
+	pub fn member(&self, name: &str) -> Option<Ref<'_, Member>> {
+		Ref::filter_map(self.0.borrow(), |def| def.members.iter().find(|m| m.name == name)).ok()
+	}
+}
+
+impl PartialEq for Struct {
+	fn eq(&self, other: &Self) -> bool {
+		Rc::ptr_eq(&self.0, &other.0)
+	}
+}
+
+impl Eq for Struct {}
+
+impl std::fmt::Debug for Struct {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "Struct({})", self.tag().unwrap_or_default())
+	}
+}
+
+pub fn align_to(n: usize, align: usize) -> usize {
+	n.div_ceil(align) * align
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Func {
 	pub return_type: Box<Type>,
 	pub param_list: Vec<TypeIdentifier>,
 	pub is_variadic: bool,
+	/// 空的 () 而不是 (void): 没有原型, 不检查参数
+	pub is_unspecified: bool,
 }
 
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Statement {
 	Empty,
 	ExprStmt(Expr),
-	ReturnStmt(Expr),
+	/// 没有值的 return; 只能出现在void函数中. 位置是整个语句, 用于报告返回值和函数类型不符
+	ReturnStmt(Option<Expr>, Span),
 	IfStmt(Expr, Box<Statement>, Option<Box<Statement>>),
 	ForStmt(Option<Expr>, Expr, Option<Expr>, Box<Statement>),
+	SwitchStmt(Expr, Box<Statement>),
+	/// case标签的值在解析时已经求出
+	CaseStmt(i64, Box<Statement>),
+	DefaultStmt(Box<Statement>),
+	BreakStmt,
+	ContinueStmt,
 	CompoundStmt(Vec<Statement>),
+	/// 块作用域中的声明
+	DeclStmt(Vec<Variable>),
+	/// 解析出错的语句, 错误已经记录在诊断信息中
+	Error,
 }
 
+/// 初始化器按照被初始化对象的类型展开: 花括号省略和指示符在解析时就已经处理,
+/// 聚合类型的每个子对象对应List中的一项, None或者超出List长度的部分初始化为0
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub enum Expr {
+pub enum Initializer {
+	Expr(Expr),
+	List(Vec<Option<Initializer>>),
+}
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExprKind {
 	Const(Const),
-	Id(String),
+	/// 标识符, 以及名字解析之后绑定的符号
+	Id(String, Option<SymbolId>),
 	StringLiteral(String),
 
 	MemberAccess(Box<Expr>, String),
@@ -155,48 +499,163 @@ pub enum Expr {
 	Postfix(PostfixOP),
 	FunctionCall(Box<Expr>, Vec<Expr>),
 
+	/// (type-name) cast-expression
+	Cast(Type, Box<Expr>),
+	/// sizeof (type-name)
+	SizeOfType(Type),
+	/// sizeof unary-expression
+	SizeOfExpr(Box<Expr>),
+	/// (type-name) { initializer-list }
+	CompoundLiteral(Type, Box<Initializer>),
+
 	UnaryOp(UnaryOp),
 	BinOp(BinOp),
 	CondExpr(CondExpr),
 	AssignExpr(AssignExpr),
 	CommaExpr(CommaExpr),
+
+	/// 解析出错的表达式, 错误已经记录在诊断信息中
+	Error,
+}
+
+/// 表达式节点. 解析时只有kind和span, 类型检查之后填入类型和值类别
+#[derive(Debug, Clone)]
+pub struct Expr {
+	pub kind: ExprKind,
+	pub ctype: Option<Type>,
+	pub category: ValueCategory,
+	pub span: Span,
+}
+
+/// 值类别: 左值指代一个对象, 可以取地址和赋值
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ValueCategory {
+	LValue,
+	#[default]
+	RValue,
+}
+
+// 比较时忽略位置, 方便测试中直接构造表达式
+impl PartialEq for Expr {
+	fn eq(&self, other: &Self) -> bool {
+		self.kind == other.kind && self.ctype == other.ctype && self.category == other.category
+	}
+}
+
+impl Eq for Expr {}
+
+impl From<ExprKind> for Expr {
+	fn from(kind: ExprKind) -> Self {
+		Expr { kind, ctype: None, category: ValueCategory::RValue, span: Span::default() }
+	}
+}
+
+impl Const {
+	/// 常量的类型. 字符常量的类型是int (6.4.4.4)
+	pub fn ctype(&self) -> Type {
+		match self {
+			Const::Integer(literal) => Const::integer(literal).map_or(TYPE_LONG, |(_, ctype)| ctype),
+			_ => TYPE_INT,
+		}
+	}
+
+	/// 整数常量的值和类型 (6.4.4.1): 按后缀依次尝试int, long, 有u后缀时是无符号的.
+	/// 超出所有候选类型的范围时返回None
+	pub fn integer(literal: &str) -> Option<(i64, Type)> {
+		let digits = literal.trim_end_matches(['u', 'U', 'l', 'L']);
+		let suffix = literal[digits.len()..].to_ascii_lowercase();
+		let value: u64 = digits.parse().ok()?;
+		let candidates: &[Type] = match (suffix.contains('u'), suffix.contains('l')) {
+			(false, false) => &[TYPE_INT, TYPE_LONG],
+			(false, true) => &[TYPE_LONG],
+			(true, false) => &[TYPE_UINT, TYPE_ULONG],
+			(true, true) => &[TYPE_ULONG],
+		};
+		let fits = |ctype: &&Type| match ctype {
+			Type::Long => i64::try_from(value).is_ok(),
+			ctype => ctype.convert(value as i64) as u64 == value,
+		};
+		Some((value as i64, candidates.iter().find(fits)?.clone()))
+	}
 }
 
 impl Expr {
+	pub fn constant(c: Const) -> Self {
+		ExprKind::Const(c).into()
+	}
+
+	pub fn id(name: &str) -> Self {
+		ExprKind::Id(name.to_owned(), None).into()
+	}
+
+	pub fn error() -> Self {
+		ExprKind::Error.into()
+	}
+
+	pub fn is_lvalue(&self) -> bool {
+		self.category == ValueCategory::LValue
+	}
+
 	pub fn new_member_access(expr: Self, id: String) -> Self {
-		Expr::MemberAccess(Box::new(expr), id)
+		ExprKind::MemberAccess(Box::new(expr), id).into()
 	}
 
 	pub fn new_member_access_p(expr: Self, id: String) -> Self {
-		Expr::MemberAccessP(Box::new(expr), id)
+		ExprKind::MemberAccessP(Box::new(expr), id).into()
 	}
 
 	pub fn new_func_call(expr: Self, args: Vec<Expr>) -> Self {
-		Expr::FunctionCall(Box::new(expr), args)
+		ExprKind::FunctionCall(Box::new(expr), args).into()
 	}
 
 	pub fn new_assign(left: Self, op: Punct, right: Self) -> Self {
-		Expr::AssignExpr(AssignExpr { left: Box::new(left), assign: op, right: Box::new(right) })
+		ExprKind::AssignExpr(AssignExpr { left: Box::new(left), assign: op, right: Box::new(right) }).into()
 	}
 
 	pub fn new_binary(left: Self, op: Punct, right: Self) -> Self {
-		Expr::BinOp(BinOp { left: Box::new(left), op, right: Box::new(right) })
+		ExprKind::BinOp(BinOp { left: Box::new(left), op, right: Box::new(right) }).into()
 	}
 
 	pub fn new_comma(left: Self, right: Self) -> Self {
-		Expr::CommaExpr(CommaExpr { left: Box::new(left), right: Box::new(right) })
+		ExprKind::CommaExpr(CommaExpr { left: Box::new(left), right: Box::new(right) }).into()
 	}
 
 	pub fn new_cond(cond: Self, left: Self, right: Self) -> Self {
-		Expr::CondExpr(CondExpr { cond: Box::new(cond), left: Box::new(left), right: Box::new(right) })
+		ExprKind::CondExpr(CondExpr { cond: Box::new(cond), left: Box::new(left), right: Box::new(right) }).into()
 	}
 
 	pub fn new_unary(op: Punct, expr: Self) -> Self {
-		Expr::UnaryOp(UnaryOp { op, expr: Box::new(expr) })
+		ExprKind::UnaryOp(UnaryOp { op, expr: Box::new(expr) }).into()
 	}
 
 	pub fn new_postfix(op: Punct, expr: Self) -> Self {
-		Expr::Postfix(PostfixOP { op, expr: Box::new(expr) })
+		ExprKind::Postfix(PostfixOP { op, expr: Box::new(expr) }).into()
+	}
+
+	pub fn new_cast(ctype: Type, expr: Self) -> Self {
+		ExprKind::Cast(ctype, Box::new(expr)).into()
+	}
+
+	pub fn new_sizeof_expr(expr: Self) -> Self {
+		ExprKind::SizeOfExpr(Box::new(expr)).into()
+	}
+
+	/// 不依赖符号表就能确定的表达式类型: 字面量, 类型转换
+	pub fn literal_type(&self) -> Option<Type> {
+		match &self.kind {
+			ExprKind::Const(c) => Some(c.ctype()),
+			ExprKind::StringLiteral(s) => {
+				Some(Type::Array(Array { base_type: Box::new(TYPE_CHAR), length: s.len() + 1, size_expr: None }))
+			}
+			ExprKind::Cast(ctype, _) | ExprKind::CompoundLiteral(ctype, _) => Some(ctype.clone()),
+			ExprKind::SizeOfType(_) | ExprKind::SizeOfExpr(_) => Some(TYPE_ULONG),
+			_ => None,
+		}
+	}
+
+	/// 折叠整数常量表达式, 得不到常量(或者溢出, 除以0)时返回None
+	pub fn fold_const(&self) -> Option<i64> {
+		consteval::eval(self, &|_| None).ok()
 	}
 }
 
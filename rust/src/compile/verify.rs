@@ -0,0 +1,113 @@
+//! 执行之前校验字节码, 不论是刚翻译的还是从 .tbc 文件读入的.
+//! 对每个函数沿着所有跳转遍历一遍, 算出每条指令执行前操作数栈的深度. 通过校验的程序执行时
+//! pc 不会离开代码, 弹栈不会弹空, 读实参不会越界, BytecodeVm 可以省掉这些检查
+
+use super::{
+	codegen::{Callee, FunctionInfo, Opcode, Program},
+	errors::VerifyError,
+};
+
+/// 校验整个程序: 函数表, 函数地址表, 以及每个函数的代码
+pub fn verify(program: &Program) -> Result<(), VerifyError> {
+	stack_depths(program).map(|_| ())
+}
+
+/// 校验程序, 返回每条指令执行前操作数栈的深度(从所在函数的入口算起), 到达不了的指令是None
+pub fn stack_depths(program: &Program) -> Result<Vec<Option<usize>>, VerifyError> {
+	let malformed = |what: String| Err(VerifyError::Malformed(what));
+	if program.spans.len() != program.code.len() {
+		return malformed(format!("{} lines for {} instructions", program.spans.len(), program.code.len()));
+	}
+	if let Some(main) = program.main {
+		match program.functions.get(main) {
+			Some(func) if func.entry.is_some() && func.params <= 2 => (),
+			_ => return malformed("main must be a defined function with at most 2 parameters".to_string()),
+		}
+	}
+	for (id, callee) in program.callees.iter() {
+		if matches!(callee, Callee::Function(index) if *index >= program.functions.len()) {
+			return malformed(format!("function address {} is not in the function table", id));
+		}
+	}
+
+	// 函数的代码按入口排列, 一个函数到下一个函数的入口为止
+	let mut entries: Vec<(usize, &FunctionInfo)> =
+		program.functions.iter().filter_map(|func| Some((func.entry?, func))).collect();
+	entries.sort_by_key(|(entry, _)| *entry);
+	let mut depths = vec![None; program.code.len()];
+	for (i, (entry, func)) in entries.iter().enumerate() {
+		let end = entries.get(i + 1).map_or(program.code.len(), |(next, _)| *next);
+		if *entry >= end {
+			return malformed(format!("{} has no code", func.name));
+		}
+		verify_function(program, func, *entry, &mut depths[*entry..end])?;
+	}
+	Ok(depths)
+}
+
+/// 校验从entry开始的一个函数, depths是这个函数的每条指令的深度.
+/// 到达每条指令时操作数栈的深度必须唯一, 返回时必须回到0
+fn verify_function(
+	program: &Program,
+	func: &FunctionInfo,
+	entry: usize,
+	depths: &mut [Option<usize>],
+) -> Result<(), VerifyError> {
+	let end = entry + depths.len();
+	let frame = match program.code[entry] {
+		Opcode::Ent(size) => size,
+		_ => return Err(VerifyError::MisplacedEnt(entry)),
+	};
+	if !matches!(program.code[end - 1], Opcode::Jmp(_) | Opcode::Lev) {
+		return Err(VerifyError::FallsThrough(end - 1));
+	}
+
+	depths[0] = Some(0);
+	let mut pending = vec![entry];
+	while let Some(pc) = pending.pop() {
+		let depth = depths[pc - entry].unwrap();
+		let pop = |n: usize| depth.checked_sub(n).ok_or(VerifyError::StackUnderflow(pc));
+		// 执行之后的深度, 跳转的目标, 是否可能执行下一条指令
+		let (after, target, falls) = match program.code[pc] {
+			Opcode::Ent(_) if pc != entry => return Err(VerifyError::MisplacedEnt(pc)),
+			Opcode::Lea(n) if n > frame => return Err(VerifyError::FrameOutOfBounds(pc, n)),
+			Opcode::Ssp(n) | Opcode::Rsp(n) if n.checked_add(8).is_none_or(|end| end > frame) => {
+				return Err(VerifyError::FrameOutOfBounds(pc, n))
+			}
+			Opcode::Lgb(n) if n >= program.data.len() => return Err(VerifyError::DataOutOfBounds(pc, n)),
+			Opcode::Arg(n) if n >= func.params => return Err(VerifyError::ArgumentOutOfRange(pc, n)),
+			Opcode::Jsr(function, _) if function >= program.functions.len() => {
+				return Err(VerifyError::UnknownFunction(pc, function))
+			}
+			Opcode::Jmp(target) => (depth, Some(target), false),
+			Opcode::Bz(target) | Opcode::Bnz(target) => (depth, Some(target), true),
+			// 实参留在栈上, 由调用者之后的 ADJ 弹出
+			Opcode::Jsr(_, argc) | Opcode::Sys(_, argc) => (pop(argc).map(|_| depth)?, None, true),
+			Opcode::Jsri(argc) => (pop(argc.saturating_add(1)).map(|_| depth)?, None, true),
+			Opcode::Adj(n) => (pop(n)?, None, true),
+			Opcode::Lev if depth > 0 => return Err(VerifyError::UnbalancedReturn(pc, depth)),
+			Opcode::Lev => (depth, None, false),
+			Opcode::Psh => (depth + 1, None, true),
+			Opcode::Store(_) | Opcode::Copy(_) => (pop(1)?, None, true),
+			op if op.operator().is_some() => (pop(1)?, None, true),
+			_ => (depth, None, true),
+		};
+		if let Some(target) = target {
+			if !(entry..end).contains(&target) {
+				return Err(VerifyError::JumpOutOfFunction(pc, target));
+			}
+		}
+		// 最后一条指令是 JMP 或者 LEV, 所以下一条指令还在函数中
+		for next in target.into_iter().chain(falls.then_some(pc + 1)) {
+			match depths[next - entry] {
+				None => {
+					depths[next - entry] = Some(after);
+					pending.push(next);
+				}
+				Some(depth) if depth != after => return Err(VerifyError::StackMismatch(next, depth, after)),
+				Some(_) => (),
+			}
+		}
+	}
+	Ok(())
+}
@@ -0,0 +1,256 @@
+//! 执行 codegen 生成的字节码. 和 c/src/vm.c 一样是一个取指令, 分派的循环;
+//! 内存, 内置函数和资源限制与树遍历的解释器共用 Env, 所以两者的输出和运行时错误相同
+
+use super::{
+	codegen::{Callee, Opcode, Program, Scalar},
+	errors::{RuntimeError, Trap, VerifyError},
+	eval::{binary, finish, Env},
+	memory::{address, Memory, DATA_BASE, FUNCTION_BASE},
+	token::Span,
+	verify::verify,
+};
+
+/// 一次调用: 返回地址, 栈帧的基地址, 实参在操作数栈中的位置
+#[derive(Debug)]
+struct CallFrame {
+	function: usize,
+	return_pc: usize,
+	bp: usize,
+	/// 调用之前内存的栈顶, 返回时释放之后的空间
+	sp: usize,
+	args: usize,
+	call_site: Option<Span>,
+}
+
+/// 虚拟机的寄存器和栈
+#[derive(Debug, Default)]
+struct State {
+	pc: usize,
+	a: i64,
+	stack: Vec<i64>,
+	frames: Vec<CallFrame>,
+	/// 执行过的指令数
+	cycle: u64,
+}
+
+pub struct BytecodeVm<'a> {
+	program: &'a Program,
+	/// 和程序的输出一起打印执行的每条指令
+	trace: bool,
+	/// 通过了校验, 执行时省掉取指令, 弹栈和读实参的检查
+	verified: bool,
+}
+
+/// 字节码本身有错, 正常翻译出来的代码不会出现
+fn malformed(what: &str) -> RuntimeError {
+	RuntimeError::Unsupported(what.to_string())
+}
+
+impl State {
+	fn pop<const CHECKED: bool>(&mut self) -> Result<i64, RuntimeError> {
+		match CHECKED {
+			true => self.stack.pop().ok_or_else(|| malformed("operand stack underflow")),
+			// 安全: 校验保证弹栈的指令执行时操作数栈中有足够的值
+			false => Ok(unsafe { self.stack.pop().unwrap_unchecked() }),
+		}
+	}
+
+	/// 栈顶的n个值在操作数栈中的开始位置
+	fn top<const CHECKED: bool>(&self, n: usize) -> Result<usize, RuntimeError> {
+		match CHECKED {
+			true => self.stack.len().checked_sub(n).ok_or_else(|| malformed("operand stack underflow")),
+			false => Ok(self.stack.len() - n),
+		}
+	}
+
+	/// 操作数栈中第i个值
+	fn get<const CHECKED: bool>(&self, i: usize) -> Result<i64, RuntimeError> {
+		match CHECKED {
+			true => self.stack.get(i).copied().ok_or_else(|| malformed("operand stack underflow")),
+			// 安全: 校验保证读的实参和函数指针都在栈中
+			false => Ok(unsafe { *self.stack.get_unchecked(i) }),
+		}
+	}
+
+	fn bp(&self) -> usize {
+		self.frames.last().map_or(0, |frame| frame.bp)
+	}
+}
+
+impl<'a> BytecodeVm<'a> {
+	pub fn new(program: &'a Program) -> Self {
+		BytecodeVm { program, trace: false, verified: false }
+	}
+
+	/// 校验之后执行, 省掉执行时的检查
+	pub fn verified(program: &'a Program) -> Result<Self, VerifyError> {
+		verify(program)?;
+		Ok(BytecodeVm { verified: true, ..Self::new(program) })
+	}
+
+	/// 执行每条指令之前打印 `周期> pc: 指令`, 以及a和操作数栈顶, 结束时打印 `exit(退出码) cycle = 周期`,
+	/// 格式和 c/src/vm.c 的 -d 一样
+	pub fn trace_execution(&mut self) {
+		self.trace = true;
+	}
+
+	/// 初始化数据段之后执行main, 返回main的返回值或者exit的参数
+	pub fn run(&self, env: &mut Env, args: &[String]) -> Result<i64, Trap> {
+		env.start(Memory::new(self.program.data.clone()));
+		let mut state = State::default();
+		let result = match self.program.main {
+			Some(main) => {
+				let main_args = env.enter_main(args, self.program.functions[main].params);
+				state.stack.extend(main_args);
+				self.call(main, state.stack.len() - self.program.functions[main].params, None, env, &mut state)
+					.and_then(|_| match self.verified {
+						true => self.execute::<false>(env, &mut state),
+						false => self.execute::<true>(env, &mut state),
+					})
+			}
+			None => Err(RuntimeError::MissingMain),
+		};
+		let calls =
+			state.frames.iter().map(|frame| (self.program.functions[frame.function].name.as_str(), frame.call_site));
+		let code = finish(result, self.span(state.pc), calls)?;
+		if self.trace {
+			env.write(format!("exit({}) cycle = {}\n", code, state.cycle).as_bytes())?;
+		}
+		Ok(code)
+	}
+
+	/// 出错的指令对应的位置, pc已经指向下一条指令
+	fn span(&self, pc: usize) -> Option<Span> {
+		pc.checked_sub(1).and_then(|pc| self.program.spans.get(pc).copied().flatten())
+	}
+
+	/// 进入函数, 实参已经在操作数栈上
+	fn call(
+		&self,
+		function: usize,
+		args: usize,
+		call_site: Option<Span>,
+		env: &mut Env,
+		state: &mut State,
+	) -> Result<(), RuntimeError> {
+		let func = self.program.functions.get(function).ok_or_else(|| malformed("unknown function"))?;
+		let entry = func.entry.ok_or_else(|| RuntimeError::UndefinedFunction(func.name.clone()))?;
+		env.check_call(func.params, func.is_variadic, state.stack.len() - args, state.frames.len())?;
+		let sp = env.memory.stack_pointer();
+		state.frames.push(CallFrame { function, return_pc: state.pc, bp: 0, sp, args, call_site });
+		state.pc = entry;
+		Ok(())
+	}
+
+	/// 取指令, 分派, 直到main返回. CHECKED为false时程序已经通过校验
+	fn execute<const CHECKED: bool>(&self, env: &mut Env, state: &mut State) -> Result<i64, RuntimeError> {
+		let code = &self.program.code;
+		loop {
+			env.tick()?;
+			let op = match CHECKED {
+				true => code.get(state.pc).copied().ok_or_else(|| malformed("pc out of code"))?,
+				// 安全: 校验保证跳转的目标在函数中, 函数的最后一条指令是 JMP 或者 LEV
+				false => unsafe { *code.get_unchecked(state.pc) },
+			};
+			state.cycle += 1;
+			if self.trace {
+				let top = state.stack.last().map_or("-".to_string(), |top| top.to_string());
+				let line = format!("{}> {}: {:<12} a = {}, top = {}\n", state.cycle, state.pc, op, state.a, top);
+				env.write(line.as_bytes())?;
+			}
+			state.pc += 1;
+			match op {
+				Opcode::Lea(n) => state.a = (state.bp() + n) as i64,
+				Opcode::Imm(n) => state.a = n,
+				Opcode::Jmp(target) => state.pc = target,
+				Opcode::Jsr(function, argc) => {
+					let span = self.span(state.pc);
+					let args = state.top::<CHECKED>(argc)?;
+					self.call(function, args, span, env, state)?;
+				}
+				Opcode::Jsri(argc) => {
+					let args = state.top::<CHECKED>(argc.saturating_add(1))? + 1;
+					let callee = state.get::<CHECKED>(args - 1)?;
+					let id = address(callee).wrapping_sub(FUNCTION_BASE);
+					match self.program.callees.get(&id) {
+						Some(Callee::Function(function)) => {
+							let span = self.span(state.pc);
+							self.call(*function, args, span, env, state)?;
+						}
+						Some(Callee::Builtin(builtin)) => {
+							state.a = env.call_builtin(*builtin, &state.stack[args..])?;
+						}
+						None => return Err(RuntimeError::NotFunction(callee)),
+					}
+				}
+				Opcode::Bz(target) => {
+					if state.a == 0 {
+						state.pc = target;
+					}
+				}
+				Opcode::Bnz(target) => {
+					if state.a != 0 {
+						state.pc = target;
+					}
+				}
+				Opcode::Ent(size) => {
+					let bp = env.memory.push(size, 16)?;
+					if let Some(frame) = state.frames.last_mut() {
+						frame.bp = bp;
+					}
+				}
+				Opcode::Adj(n) => {
+					let len = state.stack.len().saturating_sub(n);
+					state.stack.truncate(len);
+				}
+				Opcode::Lgb(n) => state.a = (DATA_BASE + n) as i64,
+				Opcode::Lev => {
+					let frame = state.frames.pop().ok_or_else(|| malformed("return from no function"))?;
+					let returns_struct = self.program.functions[frame.function].returns_struct;
+					state.a = env.return_value(state.a, returns_struct, frame.sp)?;
+					if state.frames.is_empty() {
+						return Ok(state.a);
+					}
+					state.pc = frame.return_pc;
+				}
+				Opcode::Arg(n) => {
+					let args = state.frames.last().map_or(0, |frame| frame.args);
+					state.a = state.get::<CHECKED>(args + n)?;
+				}
+				Opcode::Load(scalar) => state.a = env.memory.load(address(state.a), &scalar.ctype())?,
+				Opcode::Store(scalar) => {
+					let addr = state.pop::<CHECKED>()?;
+					let ctype = scalar.ctype();
+					env.memory.store(address(addr), &ctype, state.a)?;
+					state.a = ctype.convert(state.a);
+				}
+				Opcode::Copy(size) => {
+					let dst = state.pop::<CHECKED>()?;
+					env.memory.copy(address(dst), address(state.a), size)?;
+					state.a = dst;
+				}
+				Opcode::Zero(size) => env.memory.fill(address(state.a), size, 0)?,
+				Opcode::Alloca(align) => state.a = env.memory.push(address(state.a), align)? as i64,
+				Opcode::Ssp(n) => {
+					let sp = env.memory.stack_pointer() as i64;
+					env.memory.store(state.bp() + n, &Scalar::U64.ctype(), sp)?;
+				}
+				Opcode::Rsp(n) => {
+					let sp = env.memory.load(state.bp() + n, &Scalar::U64.ctype())?;
+					env.memory.pop_to(address(sp));
+				}
+				Opcode::Cast(scalar) => state.a = scalar.ctype().convert(state.a),
+				Opcode::Psh => state.stack.push(state.a),
+				Opcode::Sys(builtin, argc) => {
+					let args = state.top::<CHECKED>(argc)?;
+					state.a = env.call_builtin(builtin, &state.stack[args..])?;
+				}
+				op => {
+					let (operator, scalar) = op.operator().ok_or_else(|| malformed(&format!("{:?}", op)))?;
+					let left = state.pop::<CHECKED>()?;
+					state.a = binary(operator, &scalar.ctype(), left, state.a, env.trap_overflow)?;
+				}
+			}
+		}
+	}
+}
@@ -2,12 +2,23 @@ mod compile;
 
 use std::error::Error;
 use std::fs;
-use std::time::Duration;
+use std::path::Path;
+use std::time::{Duration, Instant};
 
 use clap::Parser;
+use console::style;
 
+use crate::compile::codegen::Program;
+use crate::compile::errors::{ObjectError, ParseError, Trap};
+use crate::compile::eval::{Env, Limits, VM};
+use crate::compile::ir;
+use crate::compile::ireval::IrInterpreter;
+use crate::compile::object::{self, Object};
+use crate::compile::regcode::RegisterProgram;
+use crate::compile::regvm::RegisterVm;
 use crate::compile::token::{Punct, TokenList};
 use crate::compile::tree::{build_tree, ExprTree, VisitOrder};
+use crate::compile::vm::BytecodeVm;
 
 #[derive(clap::Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
@@ -40,11 +51,102 @@ enum SubCommand {
 
 		#[clap(short, long)]
 		debug: bool,
+
+		/// 把整个输入作为一个表达式, 语句或者类型名来解析
+		#[clap(short, long, value_enum, default_value = "unit")]
+		as_: Construct,
+	},
+	/// 运行C程序或者编译好的 .tbc 文件, main的返回值或者exit的参数作为退出码
+	Run {
+		#[clap(value_parser)]
+		file: String,
+
+		/// 从文件读程序的标准输入
+		#[clap(short, long)]
+		input: Option<String>,
+
+		/// 有符号整数运算溢出时报错
+		#[clap(long)]
+		trapv: bool,
+
+		/// 遍历语法树求值, 翻译成字节码用栈式或者寄存器虚拟机执行, 或者解释执行三地址的中间表示.
+		/// .tbc 文件总是执行字节码
+		#[clap(long, value_enum, default_value = "tree")]
+		engine: Engine,
+
+		/// 打印执行的每条指令, 使用栈式字节码执行
+		#[clap(long)]
+		trace: bool,
+
+		/// 最多执行的步数, 每个语句和表达式算一步
+		#[clap(long)]
+		max_steps: Option<u64>,
+
+		/// 最大的调用深度
+		#[clap(long, default_value_t = compile::eval::MAX_CALL_DEPTH)]
+		max_depth: usize,
+
+		/// 堆最多占用的字节数
+		#[clap(long)]
+		max_heap: Option<usize>,
+
+		/// 最长的运行时间, 单位是秒
+		#[clap(long)]
+		timeout: Option<f64>,
+
+		/// 传给main的argv, 写在 -- 之后
+		#[clap(value_parser, last = true)]
+		args: Vec<String>,
+	},
+	/// 把C程序编译成字节码文件, 之后用 toy run 执行而不用重新解析
+	Build {
+		#[clap(value_parser)]
+		file: String,
+
+		/// 输出的文件, 默认把源文件的后缀换成 .tbc
+		#[clap(short, long)]
+		output: Option<String>,
+	},
+	/// 打印C程序或者 .tbc 文件的字节码, 按函数分组, 穿插对应的源码行
+	Dis {
+		#[clap(value_parser)]
+		file: String,
+	},
+	/// 打印C程序的三地址中间表示: 每个函数的基本块和有类型的虚拟寄存器
+	Ir {
+		#[clap(value_parser)]
+		file: String,
+	},
+	/// 用每种执行方式运行基准程序, 比较输出和用时
+	Bench {
+		/// 基准程序, 默认是 data/bench 中的 fib sieve nbody
+		#[clap(value_parser)]
+		files: Vec<String>,
+
+		/// 每种执行方式运行的次数, 取最短的用时
+		#[clap(short, long, default_value_t = 3)]
+		repeat: usize,
 	},
 	Http,
 	Term,
 }
 
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum Engine {
+	Tree,
+	Bytecode,
+	Register,
+	Ir,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum Construct {
+	Unit,
+	Expr,
+	Stmt,
+	Type,
+}
+
 #[test]
 fn test_progress_bar() {
 	let total = 64 << 10;
@@ -67,9 +169,217 @@ fn test_progress_bar() {
 	pb.finish_with_message("done");
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-	let args = Args::parse();
+/// toy run 的解释器线程的栈大小, 调用深度的上限更大时按每层调用的大小增加
+const RUN_STACK_SIZE: usize = 1 << 30;
+const RUN_STACK_PER_CALL: usize = 100 << 10;
+
+/// 运行时错误的报告: 位置和错误, 出错的源码行, 然后是调用栈
+fn report_trap(file: &str, src: &str, trap: &Trap) -> String {
+	let mut s = match trap.span {
+		Some(span) => format!("{}:{}: {}: {}\n", file, span, style("runtime error").red().bold(), trap.error),
+		None => format!("{}: {}: {}\n", file, style("runtime error").red().bold(), trap.error),
+	};
+	// 执行 .tbc 文件时源文件可能已经不存在
+	if let Some((span, line)) = trap.span.and_then(|span| Some((span, src.lines().nth(span.line.checked_sub(1)?)?))) {
+		// .tbc 文件中的位置没有和源文件核对过, 缩进和 ^ 的个数限制在这一行之内
+		let columns = line.chars().count();
+		let indent = span.column.saturating_sub(1).min(columns);
+		let width = span.end.saturating_sub(span.start).min(columns - indent).max(1);
+		let carets = style("^".repeat(width)).red();
+		s += &format!("{:>5} | {}\n      | {:indent$}{}\n", span.line, line, "", carets);
+	}
+	for (function, call_site, times) in trap.collapsed_backtrace() {
+		s += &match call_site {
+			Some(span) => format!("    in {}, called at {}:{}", function, file, span),
+			None => format!("    in {}", function),
+		};
+		s += &match times {
+			1 => "\n".to_string(),
+			n => format!(" ({} times)\n", n),
+		};
+	}
+	s
+}
+
+/// 错误已经作为诊断信息打印过, 只需要以非0的退出码结束
+#[derive(Debug)]
+struct Reported;
+
+impl std::fmt::Display for Reported {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("compilation failed")
+	}
+}
+
+impl Error for Reported {}
+
+/// 打印诊断信息. 有错误时返回Reported, 不再重复报告
+fn report_diagnostics(
+	p: &compile::parse::Parser,
+	result: Result<(), ParseError>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	for diagnostic in p.diagnostics() {
+		eprintln!("{}", diagnostic);
+	}
+	match result {
+		Err(_) if p.diagnostics().iter().any(|d| d.is_error()) => Err(Reported.into()),
+		result => Ok(result?),
+	}
+}
+
+/// 解析和检查C程序, 打印诊断信息
+fn load(src: &str) -> Result<(Env, VM), Box<dyn Error + Send + Sync>> {
+	let mut p = compile::parse::Parser::from_str(src)?;
+	let result = p.parse_translation_unit().map(|_| ()).and_then(|_| p.check());
+	report_diagnostics(&p, result)?;
+	Ok(p.into_vm())
+}
+
+/// 读取 .tbc 文件, 以及它记录的源文件. 源文件不存在时报告运行时错误不显示源码
+fn load_object(bytes: &[u8]) -> Result<(Object, String), ObjectError> {
+	let object = Object::from_bytes(bytes)?;
+	let src = fs::read_to_string(&object.source).unwrap_or_default();
+	Ok((object, src))
+}
+
+/// 要执行的程序: 语法树, 翻译好的字节码, 或者中间表示
+enum Executable {
+	Tree(VM),
+	Bytecode(Program),
+	Ir(ir::Module),
+}
+
+/// 编译并运行C程序, 返回进程的退出码. 运行时错误报告之后退出码是1
+fn run(
+	file: String,
+	input: Option<String>,
+	trapv: bool,
+	limits: Limits,
+	engine: Engine,
+	trace: bool,
+	args: Vec<String>,
+) -> Result<i32, Box<dyn Error + Send + Sync>> {
+	let bytes = fs::read(&file)?;
+	// 编译好的 .tbc 文件直接执行字节码, 运行时错误的位置指向它记录的源文件
+	let (source, src, mut env, executable) = match bytes.starts_with(&object::MAGIC) {
+		true => {
+			let (object, src) = load_object(&bytes)?;
+			(object.source, src, Env::new(vec![]), Executable::Bytecode(object.program))
+		}
+		false => {
+			let src = String::from_utf8(bytes)?;
+			let (env, vm) = load(&src)?;
+			let executable = match engine {
+				Engine::Tree if !trace => Executable::Tree(vm),
+				Engine::Ir if !trace => Executable::Ir(vm.lower(&env)?),
+				_ => Executable::Bytecode(vm.compile(&env)?),
+			};
+			(file.clone(), src, env, executable)
+		}
+	};
+	if let Some(input) = input {
+		env.set_input(fs::read(input)?);
+	}
+	if trapv {
+		env.trap_signed_overflow();
+	}
+	env.set_limits(limits);
+	let argv: Vec<_> = std::iter::once(file.clone()).chain(args).collect();
+	let result = match &executable {
+		Executable::Tree(vm) => vm.eval_with_args(&mut env, &argv),
+		Executable::Ir(module) => IrInterpreter::new(module).run(&mut env, &argv),
+		Executable::Bytecode(program) if matches!(engine, Engine::Register) && !trace => {
+			let program = RegisterProgram::translate(program)?;
+			RegisterVm::new(&program).run(&mut env, &argv)
+		}
+		Executable::Bytecode(program) => {
+			let mut bytecode = BytecodeVm::verified(program)?;
+			if trace {
+				bytecode.trace_execution();
+			}
+			bytecode.run(&mut env, &argv)
+		}
+	};
+	match result {
+		Ok(code) => Ok(code as i32),
+		Err(trap) => {
+			eprint!("{}", report_trap(&source, &src, &trap));
+			Ok(1)
+		}
+	}
+}
+
+/// 用一种执行方式运行一次, 返回输出和用时. 编译和翻译不计入用时
+fn bench_once(src: &str, engine: Engine) -> Result<(Vec<u8>, Duration), Box<dyn Error + Send + Sync>> {
+	let (mut env, vm) = load(src)?;
+	env.capture_output();
+	let (result, elapsed) = match engine {
+		Engine::Tree => {
+			let start = Instant::now();
+			(vm.eval(&mut env), start.elapsed())
+		}
+		Engine::Bytecode => {
+			let program = vm.compile(&env)?;
+			let bytecode = BytecodeVm::verified(&program)?;
+			let start = Instant::now();
+			(bytecode.run(&mut env, &[]), start.elapsed())
+		}
+		Engine::Register => {
+			let program = RegisterProgram::translate(&vm.compile(&env)?)?;
+			let start = Instant::now();
+			(RegisterVm::new(&program).run(&mut env, &[]), start.elapsed())
+		}
+		Engine::Ir => {
+			let module = vm.lower(&env)?;
+			let start = Instant::now();
+			(IrInterpreter::new(&module).run(&mut env, &[]), start.elapsed())
+		}
+	};
+	result.map_err(|trap| trap.error.to_string())?;
+	Ok((env.take_output(), elapsed))
+}
+
+/// 每个程序在四种执行方式下各运行repeat次, 输出必须相同, 打印最短用时和寄存器虚拟机相对栈式的加速比
+fn bench(files: &[String], repeat: usize) -> Result<(), Box<dyn Error + Send + Sync>> {
+	let engines = [Engine::Tree, Engine::Bytecode, Engine::Register, Engine::Ir];
+	println!("{:<24}{:>10}{:>10}{:>10}{:>10}{:>10}", "program", "tree", "bytecode", "register", "ir", "speedup");
+	for file in files {
+		let src = fs::read_to_string(file)?;
+		let mut times = vec![];
+		let mut expected: Option<Vec<u8>> = None;
+		for engine in engines {
+			let mut best = Duration::MAX;
+			for _ in 0..repeat {
+				let (output, elapsed) = bench_once(&src, engine)?;
+				match &expected {
+					Some(expected) if *expected != output => {
+						return Err(format!("{}: {:?} printed different output", file, engine).into())
+					}
+					Some(_) => (),
+					None => expected = Some(output),
+				}
+				best = best.min(elapsed);
+			}
+			times.push(best);
+		}
+		let secs: Vec<String> = times.iter().map(|time| format!("{:.3}s", time.as_secs_f64())).collect();
+		let speedup = times[1].as_secs_f64() / times[2].as_secs_f64();
+		println!("{:<24}{:>10}{:>10}{:>10}{:>10}{:>9.2}x", file, secs[0], secs[1], secs[2], secs[3], speedup);
+	}
+	Ok(())
+}
 
+fn main() {
+	// 诊断信息和运行时错误已经打印过, 其他错误在这里报告
+	if let Err(e) = run_command(Args::parse()) {
+		if !e.is::<Reported>() {
+			eprintln!("{}: {}", style("error").red().bold(), e);
+		}
+		std::process::exit(1);
+	}
+}
+
+fn run_command(args: Args) -> Result<(), Box<dyn Error>> {
 	match args.command {
 		SubCommand::Dev { expr, debug } => {
 			let tree = build_tree(&expr)?;
@@ -80,12 +390,12 @@ fn main() -> Result<(), Box<dyn Error>> {
 				tree.print(&VisitOrder::In);
 				println!("─────");
 				tree.print(&VisitOrder::Post);
-				println!("eval stack to {}", tree.eval_stack());
+				println!("eval stack to {}", tree.eval_stack()?);
 				let tree = ExprTree::tree(Punct::Add, ExprTree::branch(Punct::Mul, 1, 2), ExprTree::leaf(3));
 				tree.print(&VisitOrder::Pre);
 			}
 
-			println!("──────\n{}\n{}= {}", expr, tree, tree.eval());
+			println!("──────\n{}\n{}= {}", expr, tree, tree.eval()?);
 		}
 
 		SubCommand::Term => {
@@ -108,7 +418,7 @@ fn main() -> Result<(), Box<dyn Error>> {
 				println!("lex: {}\n{:#}{}", input, r, r);
 			}
 		}
-		SubCommand::Parse { file, debug } => {
+		SubCommand::Parse { file, debug, as_ } => {
 			let src = fs::read_to_string(file)?;
 
 			if debug {
@@ -122,12 +432,95 @@ fn main() -> Result<(), Box<dyn Error>> {
 				println!("------\n{}: {}", data.name.unwrap_or_default(), data.ctype);
 			}
 
-			compile::parse::Parser::from_str(src.as_str()).and_then(|mut p| {
-				p.parse()?;
-				p.display();
-				let (mut env, vm) = p.into_vm();
-				vm.eval(&mut env)
-			})?;
+			let mut p = compile::parse::Parser::from_str(src.as_str())?;
+			let single = match as_ {
+				Construct::Expr => Some(p.parse_expression()?.to_string()),
+				Construct::Stmt => Some(p.parse_statement()?.to_string()),
+				Construct::Type => Some(p.parse_type_name()?.to_string()),
+				Construct::Unit => None,
+			};
+			if let Some(tree) = single {
+				println!("{}", tree);
+				return Ok(());
+			}
+			let result = p.parse_translation_unit().map(|_| ()).and_then(|_| p.check());
+			p.display();
+			report_diagnostics(&p, result).map_err(|e| e as Box<dyn Error>)?;
+			let (mut env, vm) = p.into_vm();
+			env.capture_output();
+			let code = vm.eval(&mut env)?;
+			println!("output:\n{}", String::from_utf8_lossy(&env.take_output()));
+			print!("{}", vm.dump(&env));
+			println!("main returned {}", code);
+		}
+		SubCommand::Run { file, input, trapv, engine, trace, max_steps, max_depth, max_heap, timeout, args } => {
+			let timeout = match timeout {
+				Some(secs) => Some(Duration::try_from_secs_f64(secs)?),
+				None => None,
+			};
+			let limits = Limits { max_steps, max_call_depth: max_depth, max_heap, timeout };
+			// 求值是递归的, 在栈足够大的线程中运行. 调用深度的上限太大时可能分配不了这么大的栈
+			let stack_size = RUN_STACK_SIZE.max(max_depth.saturating_mul(RUN_STACK_PER_CALL));
+			let code = std::thread::Builder::new()
+				.stack_size(stack_size)
+				.spawn(move || run(file, input, trapv, limits, engine, trace, args))
+				.map_err(|e| {
+					format!(
+						"--max-depth {} needs a {} MiB interpreter stack, which is not available: {}",
+						max_depth,
+						stack_size >> 20,
+						e
+					)
+				})?
+				.join()
+				.map_err(|_| "interpreter panicked")?;
+			match code {
+				Ok(code) => std::process::exit(code),
+				Err(e) => return Err(e),
+			}
+		}
+
+		SubCommand::Bench { files, repeat } => {
+			let files = match files.is_empty() {
+				true => ["fib", "sieve", "nbody"].iter().map(|name| format!("data/bench/{}.c", name)).collect(),
+				false => files,
+			};
+			let result = std::thread::Builder::new()
+				.stack_size(RUN_STACK_SIZE)
+				.spawn(move || bench(&files, repeat.max(1)))?
+				.join()
+				.map_err(|_| "interpreter panicked")?;
+			result.map_err(|e| e as Box<dyn Error>)?;
+		}
+
+		SubCommand::Build { file, output } => {
+			let src = fs::read_to_string(&file)?;
+			let (env, vm) = load(&src).map_err(|e| e as Box<dyn Error>)?;
+			let object = Object::new(&file, vm.compile(&env)?);
+			let output = output.unwrap_or_else(|| Path::new(&file).with_extension("tbc").display().to_string());
+			fs::write(output, object.to_bytes())?;
+		}
+
+		SubCommand::Dis { file } => {
+			let bytes = fs::read(&file)?;
+			let (program, src) = match bytes.starts_with(&object::MAGIC) {
+				true => {
+					let (object, src) = load_object(&bytes)?;
+					(object.program, src)
+				}
+				false => {
+					let src = String::from_utf8(bytes)?;
+					let (env, vm) = load(&src).map_err(|e| e as Box<dyn Error>)?;
+					(vm.compile(&env)?, src)
+				}
+			};
+			print!("{}", program.disassemble(&src));
+		}
+
+		SubCommand::Ir { file } => {
+			let src = fs::read_to_string(&file)?;
+			let (env, vm) = load(&src).map_err(|e| e as Box<dyn Error>)?;
+			print!("{}", vm.lower(&env)?);
 		}
 
 		SubCommand::Http => {